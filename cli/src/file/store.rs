@@ -13,6 +13,7 @@ use sp_ipld::{
   Ipld,
 };
 use std::{
+  convert::TryFrom,
   sync::{
     Arc,
     Mutex
@@ -81,12 +82,27 @@ pub fn hashspace_directory() -> PathBuf {
   PathBuf::from(path)
 }
 
-pub fn fs_get(link: Cid) -> Option<Ipld> {
+pub fn fs_read(link: Cid) -> Option<Vec<u8>> {
   let dir = hashspace_directory();
   let path = dir.as_path().join(Path::new(&link.to_string()));
-  let file: Vec<u8> = fs::read(path).ok()?;
-  let res: Ipld = DagCborCodec.decode(ByteCursor::new(file)).expect("valid cbor bytes");
-  Some(res)
+  fs::read(path).ok()
+}
+
+/// Get a block from the hashspace, which is `Ok(None)` if it isn't there and
+/// an error if it can't be decoded
+pub fn fs_get(link: Cid) -> Result<Option<Ipld>, String> {
+  let file = match fs_read(link) {
+    Some(file) => file,
+    None => return Ok(None),
+  };
+  match DagCborCodec.decode(ByteCursor::new(file)) {
+    Ok(res) => Ok(Some(res)),
+    Err(_) => Err(format!(
+      "Block {} in the hashspace is not valid dag-cbor. Run `yatima fsck` \
+       to find and quarantine corrupt blocks",
+      link
+    )),
+  }
 }
 
 pub fn fs_put(expr: Ipld) -> Cid {
@@ -104,6 +120,116 @@ pub fn fs_put(expr: Ipld) -> Cid {
   link
 }
 
+pub fn fs_list() -> Vec<Cid> {
+  match fs::read_dir(hashspace_directory()) {
+    // The memo directory, the registry and the pins aren't named by CIDs
+    Ok(entries) => entries
+      .filter_map(|e| Cid::try_from(e.ok()?.file_name().to_str()?).ok())
+      .collect(),
+    Err(_) => vec![],
+  }
+}
+
+fn memo_directory() -> PathBuf {
+  let dir = hashspace_directory().join("memo");
+  fs::create_dir_all(&dir).unwrap_or_else(|_| {
+    panic!(
+    "Error: cannot create memo path {}, likely due to lacking \
+     sufficient filesystem permissions.",
+    dir.to_string_lossy())
+  });
+  dir
+}
+
+pub fn fs_get_memo(key: Cid) -> Option<Cid> {
+  let path = memo_directory().join(Path::new(&key.to_string()));
+  let value = fs::read_to_string(path).ok()?;
+  Cid::try_from(value.trim()).ok()
+}
+
+pub fn fs_put_memo(key: Cid, value: Cid) {
+  let path = memo_directory().join(Path::new(&key.to_string()));
+  // A memo is only a cache, so failing to write it isn't an error
+  fs::write(path, value.to_string()).ok();
+}
+
+pub fn fs_list_memos() -> Vec<(Cid, Cid)> {
+  match fs::read_dir(memo_directory()) {
+    Ok(entries) => entries
+      .filter_map(|e| {
+        let key = Cid::try_from(e.ok()?.file_name().to_str()?).ok()?;
+        Some((key, fs_get_memo(key)?))
+      })
+      .collect(),
+    Err(_) => vec![],
+  }
+}
+
+pub fn fs_delete_memo(key: Cid) -> bool {
+  fs::remove_file(memo_directory().join(Path::new(&key.to_string()))).is_ok()
+}
+
+// The current package registry is kept next to the hashspace, as the CID of
+// the registry in it
+fn registry_path() -> PathBuf { hashspace_directory().join("registry") }
+
+pub fn fs_get_registry() -> Option<Cid> {
+  let value = fs::read_to_string(registry_path()).ok()?;
+  Cid::try_from(value.trim()).ok()
+}
+
+pub fn fs_put_registry(registry: Cid) {
+  fs::write(registry_path(), registry.to_string()).unwrap_or_else(|_| {
+    panic!(
+    "Error: cannot write the registry to hashspace path {}.",
+    registry_path().to_string_lossy())
+  });
+}
+
+pub fn fs_delete(link: Cid) -> bool {
+  fs::remove_file(hashspace_directory().join(Path::new(&link.to_string())))
+    .is_ok()
+}
+
+// Corrupt blocks are moved out of the hashspace into a directory that isn't
+// named by a CID, so `fs_list` skips them
+fn quarantine_directory() -> PathBuf { hashspace_directory().join("quarantine") }
+
+/// Move a block out of the hashspace into quarantine, returning whether it
+/// was there
+pub fn fs_quarantine(link: Cid) -> bool {
+  let dir = quarantine_directory();
+  fs::create_dir_all(&dir).unwrap_or_else(|_| {
+    panic!(
+    "Error: cannot create quarantine path {}, likely due to lacking \
+     sufficient filesystem permissions.",
+    dir.to_string_lossy())
+  });
+  let name = link.to_string();
+  fs::rename(hashspace_directory().join(&name), dir.join(&name)).is_ok()
+}
+
+// The pinned CIDs are kept next to the hashspace, one on each line
+fn pins_path() -> PathBuf { hashspace_directory().join("pins") }
+
+pub fn fs_get_pins() -> Vec<Cid> {
+  match fs::read_to_string(pins_path()) {
+    Ok(pins) => {
+      pins.lines().filter_map(|pin| Cid::try_from(pin.trim()).ok()).collect()
+    }
+    Err(_) => vec![],
+  }
+}
+
+pub fn fs_put_pins(pins: &[Cid]) {
+  let pins: Vec<String> = pins.iter().map(|pin| pin.to_string()).collect();
+  fs::write(pins_path(), pins.join("\n")).unwrap_or_else(|_| {
+    panic!(
+    "Error: cannot write the pins to hashspace path {}.",
+    pins_path().to_string_lossy())
+  });
+}
+
 #[derive(Debug, Clone)]
 pub struct FileStoreOpts {
   /// Put and get data from the local IPFS daemon
@@ -119,10 +245,24 @@ pub struct FileStore {
   pub opts: FileStoreOpts,
   /// This is used when use_file_store is false
   mem_store: Arc<Mutex<HashMap<Cid, Ipld>>>,
+  /// The memo table used when use_file_store is false
+  mem_memo: Arc<Mutex<HashMap<Cid, Cid>>>,
+  /// The current registry used when use_file_store is false
+  mem_registry: Arc<Mutex<Option<Cid>>>,
+  /// The pinned CIDs used when use_file_store is false
+  mem_pins: Arc<Mutex<Vec<Cid>>>,
 }
 
 impl FileStore {
-  pub fn new(opts: FileStoreOpts) -> Self { FileStore { opts, mem_store: Default::default() } }
+  pub fn new(opts: FileStoreOpts) -> Self {
+    FileStore {
+      opts,
+      mem_store: Default::default(),
+      mem_memo: Default::default(),
+      mem_registry: Default::default(),
+      mem_pins: Default::default(),
+    }
+  }
 }
 
 impl Store for FileStore {
@@ -145,11 +285,19 @@ impl Store for FileStore {
   }
 
   fn get(&self, link: Cid) -> Option<Ipld> {
+    self.try_get(link).unwrap_or_else(|e| {
+      eprintln!("{}", e);
+      None
+    })
+  }
+
+  fn try_get(&self, link: Cid) -> Result<Option<Ipld>, String> {
     if !self.opts.use_file_store {
-      self.mem_store.lock().unwrap().get(&link).map(|ipld| ipld.clone())
+      Ok(self.mem_store.lock().unwrap().get(&link).map(|ipld| ipld.clone()))
     }
     else {
-      fs_get(link).or_else(|| {
+      let local = fs_get(link)?;
+      Ok(local.or_else(|| {
         if self.opts.use_ipfs_daemon {
           task::block_in_place(move || {
             Handle::current().block_on(async move { ipfs::dag_get(link.to_string()).await.ok() })
@@ -158,7 +306,17 @@ impl Store for FileStore {
         else {
           None
         }
-      })
+      }))
+    }
+  }
+
+  fn read(&self, link: Cid) -> Option<Vec<u8>> {
+    if !self.opts.use_file_store {
+      let ipld = self.mem_store.lock().unwrap().get(&link)?.clone();
+      Some(DagCborCodec.encode(&ipld).ok()?.into_inner())
+    }
+    else {
+      fs_read(link)
     }
   }
 
@@ -180,4 +338,99 @@ impl Store for FileStore {
       fs_put(expr)
     }
   }
+
+  fn get_memo(&self, key: Cid) -> Option<Cid> {
+    if !self.opts.use_file_store {
+      self.mem_memo.lock().unwrap().get(&key).copied()
+    }
+    else {
+      fs_get_memo(key)
+    }
+  }
+
+  fn put_memo(&self, key: Cid, value: Cid) {
+    if !self.opts.use_file_store {
+      self.mem_memo.lock().unwrap().insert(key, value);
+    }
+    else {
+      fs_put_memo(key, value)
+    }
+  }
+
+  fn list_memos(&self) -> Vec<(Cid, Cid)> {
+    if !self.opts.use_file_store {
+      self.mem_memo.lock().unwrap().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+    else {
+      fs_list_memos()
+    }
+  }
+
+  fn delete_memo(&self, key: Cid) -> bool {
+    if !self.opts.use_file_store {
+      self.mem_memo.lock().unwrap().remove(&key).is_some()
+    }
+    else {
+      fs_delete_memo(key)
+    }
+  }
+
+  fn get_registry(&self) -> Option<Cid> {
+    if !self.opts.use_file_store {
+      *self.mem_registry.lock().unwrap()
+    }
+    else {
+      fs_get_registry()
+    }
+  }
+
+  fn put_registry(&self, registry: Cid) {
+    if !self.opts.use_file_store {
+      *self.mem_registry.lock().unwrap() = Some(registry);
+    }
+    else {
+      fs_put_registry(registry)
+    }
+  }
+
+  fn list(&self) -> Vec<Cid> {
+    if !self.opts.use_file_store {
+      self.mem_store.lock().unwrap().keys().copied().collect()
+    }
+    else {
+      fs_list()
+    }
+  }
+
+  fn delete(&self, link: Cid) -> bool {
+    if !self.opts.use_file_store {
+      self.mem_store.lock().unwrap().remove(&link).is_some()
+    }
+    else {
+      fs_delete(link)
+    }
+  }
+
+  // Blocks in memory are always the IPLD they're addressed by
+  fn quarantine(&self, link: Cid) -> bool {
+    self.opts.use_file_store && fs_quarantine(link)
+  }
+
+  fn get_pins(&self) -> Vec<Cid> {
+    if !self.opts.use_file_store {
+      self.mem_pins.lock().unwrap().clone()
+    }
+    else {
+      fs_get_pins()
+    }
+  }
+
+  fn put_pins(&self, pins: &[Cid]) {
+    if !self.opts.use_file_store {
+      *self.mem_pins.lock().unwrap() = pins.to_vec();
+    }
+    else {
+      fs_put_pins(pins)
+    }
+  }
 }
@@ -2,7 +2,9 @@ use nom::{
   Finish,
 };
 use sp_cid::Cid;
+use sp_ipld::Ipld;
 use std::{
+  collections::BTreeMap,
   path::PathBuf,
   rc::Rc,
 };
@@ -14,12 +16,45 @@ use yatima_cli::{
   },
   repl,
 };
-use yatima_core::name::Name;
+use yatima_core::{
+  check,
+  codegen::{
+    self,
+    Program,
+  },
+  defs::Defs,
+  dll::DLL,
+  eval::Strategy,
+  memory,
+  name::Name,
+  package::{
+    Index,
+    Package,
+  },
+  position::Pos,
+  runtime,
+  term::Term,
+};
 use yatima_utils::{
+  car,
+  compat::{
+    self,
+    Compat,
+  },
+  diff::Diff,
   file,
+  fsck,
+  gc,
+  registry::{
+    parse_reference,
+    Registry,
+    Version,
+  },
+  rename::rename,
   store::{
     show,
     Store,
+    StoreCache,
   },
 };
 
@@ -39,6 +74,20 @@ struct Cli {
   #[structopt(long, help = "The root directory we are reading files relative to.")]
   root: Option<PathBuf>,
 
+  #[structopt(
+    long,
+    default_value = "nbe",
+    possible_values = &["nbe", "dag"],
+    help = "How the type checker compares types: by normalization by evaluation, falling back to the graph when it gives up, or only on the graph."
+  )]
+  conversion: String,
+
+  #[structopt(
+    long,
+    help = "Share identical closed subterms between the types built while type checking a definition."
+  )]
+  hash_cons: bool,
+
   /// Command to execute
   #[structopt(subcommand)]
   command: Command,
@@ -54,16 +103,204 @@ enum Command {
   Check {
     #[structopt(parse(from_os_str))]
     path: PathBuf,
+    #[structopt(
+      long,
+      help = "Fail instead of updating yatima.lock if an import isn't locked to the package it resolves to, or there is no yatima.lock."
+    )]
+    frozen: bool,
+    #[structopt(
+      long,
+      conflicts_with = "frozen",
+      help = "Lock imports anew in yatima.lock when they resolve to a different package than they lock, instead of failing."
+    )]
+    update: bool,
   },
   Show {
     #[structopt(subcommand)]
     typ: ShowType,
   },
   Run {
+    #[structopt(parse(from_os_str), required_unless = "resume")]
+    path: Option<PathBuf>,
+    #[structopt(
+      long,
+      default_value = "main",
+      help = "The definition to evaluate."
+    )]
+    entry: String,
+    #[structopt(
+      long,
+      help = "Evaluate with the typed evaluator instead of the runtime, reusing normal forms of definitions cached in the store, and caching new ones. Limits and snapshots are only supported by the runtime, so this can't be combined with --gas, --max-nodes, --max-literal-bytes or --resume."
+    )]
+    cache: bool,
+    #[structopt(
+      long,
+      help = "Reduce arguments and let bindings before substituting them, as definitions marked `strict` always do."
+    )]
+    strict: bool,
+    #[structopt(
+      long,
+      conflicts_with = "cache",
+      help = "Stop evaluating after using this much gas, and report the gas used. Every evaluation step costs gas, so the same package and entry always use the same gas."
+    )]
+    gas: Option<u64>,
+    #[structopt(
+      long,
+      conflicts_with = "cache",
+      help = "Stop evaluating with an error once this many graph nodes are live."
+    )]
+    max_nodes: Option<u64>,
+    #[structopt(
+      long,
+      conflicts_with = "cache",
+      help = "Stop evaluating with an error before primitive operations build more than this many bytes of literals."
+    )]
+    max_literal_bytes: Option<u64>,
+    #[structopt(
+      long,
+      conflicts_with = "cache",
+      parse(try_from_str = parse_cid),
+      help = "Continue the evaluation saved in this snapshot when a run stopped on a limit."
+    )]
+    resume: Option<Cid>,
+  },
+  Compile {
     #[structopt(parse(from_os_str))]
     path: PathBuf,
+    #[structopt(
+      long,
+      default_value = "js",
+      possible_values = &["js", "c"],
+      help = "The language to compile the package to."
+    )]
+    target: String,
+    #[structopt(
+      short,
+      long,
+      parse(from_os_str),
+      help = "The file to write the compiled program to, instead of standard output."
+    )]
+    output: Option<PathBuf>,
+    #[structopt(
+      long,
+      default_value = "main",
+      help = "The definition a compiled C program evaluates and prints."
+    )]
+    entry: String,
+  },
+  /// Classify the changes to the exported definitions of a package as
+  /// compatible, breaking or additive, and check the packages in the store
+  /// that import the old version against the new one
+  Compat {
+    #[structopt(parse(try_from_str = parse_cid))]
+    old: Cid,
+    #[structopt(parse(try_from_str = parse_cid))]
+    new: Cid,
+  },
+  /// Rename a definition of a package without changing the anonymous terms
+  /// of any of its definitions, printing the CID of the renamed package
+  Rename {
+    #[structopt(parse(try_from_str = parse_cid))]
+    package: Cid,
+    old: String,
+    new: String,
+    #[structopt(
+      short,
+      long,
+      parse(from_os_str),
+      help = "The file to write the renamed source of the package to."
+    )]
+    output: Option<PathBuf>,
+  },
+  /// Compare the definitions of two versions of a package
+  Diff {
+    #[structopt(parse(try_from_str = parse_cid))]
+    old: Cid,
+    #[structopt(parse(try_from_str = parse_cid))]
+    new: Cid,
+    #[structopt(long, help = "Print the differences as JSON.")]
+    json: bool,
   },
   Repl,
+  Registry {
+    #[structopt(subcommand)]
+    command: RegistryCommand,
+  },
+  Pin {
+    #[structopt(subcommand)]
+    command: PinCommand,
+  },
+  /// Delete everything in the hashspace that isn't reachable from a pin, the
+  /// registry or a cached normal form
+  Gc {
+    #[structopt(long, help = "Only report what would be deleted.")]
+    dry_run: bool,
+    #[structopt(
+      long,
+      help = "Also delete the normal forms cached by `run --cache`, and forget them."
+    )]
+    drop_cache: bool,
+  },
+  /// Write a package and everything it links to as a CAR file
+  Export {
+    #[structopt(parse(try_from_str = parse_cid))]
+    cid: Cid,
+    #[structopt(
+      short,
+      long,
+      parse(from_os_str),
+      help = "The file to write the archive to."
+    )]
+    output: PathBuf,
+  },
+  /// Read the blocks of a CAR file into the hashspace
+  Import {
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+    #[structopt(
+      long,
+      help = "Don't pin the roots of the archive, leaving what was imported to the next garbage collection."
+    )]
+    no_pin: bool,
+  },
+  /// Check that every block in the hashspace matches its CID and that no
+  /// block links to a missing one
+  Fsck {
+    #[structopt(long, help = "Move corrupt blocks out of the hashspace.")]
+    quarantine: bool,
+  },
+}
+
+#[derive(Debug, StructOpt)]
+enum PinCommand {
+  /// Pin a CID, keeping it and everything it links to from garbage collection
+  Add {
+    #[structopt(parse(try_from_str = parse_cid))]
+    cid: Cid,
+  },
+  /// Unpin a CID
+  Rm {
+    #[structopt(parse(try_from_str = parse_cid))]
+    cid: Cid,
+  },
+  /// List the pinned CIDs
+  Ls,
+}
+
+#[derive(Debug, StructOpt)]
+enum RegistryCommand {
+  /// Check a package and register it under its name and a version
+  Add {
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+    #[structopt(help = "The version to register, like 1.2.")]
+    version: Version,
+  },
+  /// List every registered version of every package
+  List,
+  /// Print the CID of the latest version of a package matching a reference
+  /// like `bool@1.2`, or of any version for a bare name
+  Resolve { package: String },
 }
 
 #[derive(Debug, StructOpt)]
@@ -105,11 +342,75 @@ fn parse_cid(
   result
 }
 
+// Typecheck the definitions of a package before running or compiling it,
+// since both erase their types
+fn check_package(
+  defs: &Rc<Defs>,
+  index: &Index,
+  conversion: check::Conversion,
+  hash_cons: bool,
+) -> std::io::Result<()> {
+  for (n, _) in &index.0 {
+    let checked =
+      check::check_def(defs.clone(), n, false, conversion, hash_cons);
+    if let Err(e) = checked {
+      eprintln!("✕ {}: {}", n, e);
+      return Err(std::io::Error::from(std::io::ErrorKind::Other));
+    }
+  }
+  Ok(())
+}
+
+fn load_package(store: &dyn Store, cid: Cid) -> std::io::Result<Package> {
+  let ipld = store.get(cid).ok_or_else(|| {
+    eprintln!("Package {} not found", cid);
+    std::io::Error::from(std::io::ErrorKind::NotFound)
+  })?;
+  Package::from_ipld(&ipld).map_err(|e| {
+    eprintln!("{:?}", e);
+    std::io::Error::from(std::io::ErrorKind::InvalidData)
+  })
+}
+
+// Report whether an evaluation ran out of memory or gas, and how much gas it
+// used, ending the limits set for it
+fn report_limits(gas: Option<u64>) -> std::io::Result<()> {
+  let out_of_memory = memory::report();
+  memory::unlimit();
+  let used = match gas {
+    Some(_) => {
+      let report = yatima_core::gas::report();
+      yatima_core::gas::unmeter();
+      report.map(Some)
+    }
+    None => Ok(None),
+  };
+  let report = match (out_of_memory, used) {
+    (Err(err), _) => Err(err.to_string()),
+    (Ok(()), Err(out)) => Err(out.to_string()),
+    (Ok(()), Ok(used)) => {
+      if let Some(used) = used {
+        eprintln!("Gas used: {}", used);
+      }
+      Ok(())
+    }
+  };
+  report.map_err(|err| {
+    eprintln!("{}", err);
+    std::io::Error::from(std::io::ErrorKind::Other)
+  })
+}
+
 //   Test,
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
   let cli = Cli::from_args();
   let root = cli.root.unwrap_or_else(|| std::env::current_dir().unwrap());
+  let conversion = match cli.conversion.as_str() {
+    "dag" => check::Conversion::Dag,
+    _ => check::Conversion::Nbe,
+  };
+  let hash_cons = cli.hash_cons;
   let store = Rc::new(FileStore::new(FileStoreOpts {
     use_ipfs_daemon: cli.use_ipfs_daemon,
     use_file_store: !cli.no_file_store,
@@ -202,24 +503,346 @@ async fn main() -> std::io::Result<()> {
       println!("{}", d);
       Ok(())
     }
-    Command::Check { path } => {
-      file::check_all_in_file(root, path, store)?;
+    Command::Check { path, frozen, update } => {
+      file::check_all_in_file(
+        root,
+        path,
+        store,
+        frozen,
+        update,
+        conversion,
+        hash_cons,
+      )?;
+      Ok(())
+    }
+    Command::Run {
+      path,
+      entry,
+      cache,
+      strict,
+      gas,
+      max_nodes,
+      max_literal_bytes,
+      resume,
+    } => {
+      let set_limits = || {
+        if let Some(limit) = gas {
+          yatima_core::gas::meter(limit);
+        }
+        memory::limit(memory::Limits {
+          nodes: max_nodes,
+          lit_bytes: max_literal_bytes,
+        });
+      };
+      let mut dag = match resume {
+        Some(cid) => {
+          let ipld = store.get(cid).ok_or_else(|| {
+            eprintln!("Snapshot {} not found in the store", cid);
+            std::io::Error::from(std::io::ErrorKind::NotFound)
+          })?;
+          set_limits();
+          let root = runtime::alloc_val(DLL::singleton(runtime::ParentPtr::Root));
+          runtime::snapshot::from_ipld(&ipld, Some(root)).map_err(|e| {
+            eprintln!("Invalid snapshot {}: {:?}", cid, e);
+            std::io::Error::from(std::io::ErrorKind::InvalidData)
+          })?
+        }
+        None => {
+          // `path` is required without `--resume`
+          let path = path.unwrap();
+          let env =
+            file::parse::PackageEnv::new(root, path.clone(), store.clone());
+          let (_, p, defs) = file::parse::parse_file(env).map_err(|e| {
+            eprintln!("{}", e);
+            std::io::Error::from(std::io::ErrorKind::Other)
+          })?;
+
+          let _cid = store.put(p.to_ipld());
+          let defs = Rc::new(defs);
+          check_package(&defs, &p.index, conversion, hash_cons)?;
+          let name = Name::from(entry.as_str());
+          let def = defs.get(&name).ok_or_else(|| {
+            eprintln!(
+              "No `{}` definition in package {} from file {:?}",
+              entry, p.name, path
+            );
+            std::io::Error::from(std::io::ErrorKind::NotFound)
+          })?;
+          let strategy = if strict { Strategy::Strict } else { Strategy::Lazy };
+          // Evaluate a reference to the entry, so that its normal form is
+          // cached and its own strictness is respected
+          let main = Term::Ref(Pos::None, name, def.def_cid, def.ast_cid);
+          // Only the runtime runs within limits and can be snapshotted, so
+          // `--cache` conflicts with all of their options
+          if cache {
+            let mut dag = yatima_core::dag::DAG::from_term(&main);
+            let cache = StoreCache(store.clone());
+            dag.norm_with(&defs, Some(&cache), strategy, false);
+            println!("{}", dag);
+            return Ok(());
+          }
+          set_limits();
+          let root = runtime::alloc_val(DLL::singleton(runtime::ParentPtr::Root));
+          runtime::from_term(&defs, &main, strategy, Some(root))
+        }
+      };
+      runtime::norm(&mut dag, false);
+      // A run stopped on a limit leaves the graph part way through its
+      // reduction, so it's saved for `--resume` to continue
+      if let Err(e) = report_limits(gas) {
+        let cid = store.put(runtime::snapshot::to_ipld(dag));
+        // Pinned, so that garbage collection doesn't invalidate it
+        gc::pin(&*store, cid).ok();
+        eprintln!("Snapshot of the stopped run: {}", cid);
+        eprintln!("Continue it with `yatima run --resume {}`", cid);
+        eprintln!("and unpin it with `yatima pin rm {}` when done", cid);
+        return Err(e);
+      }
+      // Types are erased, so the runtime reads the result back untyped
+      println!("{}", runtime::to_term(dag));
+      Ok(())
+    }
+    Command::Registry { command: RegistryCommand::Add { path, version } } => {
+      let env = file::parse::PackageEnv::new(root, path, store.clone());
+      let (cid, p, defs) = file::parse::parse_file(env).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::Other)
+      })?;
+      check_package(&Rc::new(defs), &p.index, conversion, hash_cons)?;
+      let mut registry = Registry::load(&*store).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::InvalidData)
+      })?;
+      let name = p.name.to_string();
+      registry.add(name.clone(), version.clone(), cid).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::AlreadyExists)
+      })?;
+      registry.save(&*store);
+      println!("Registered {}@{} as {}", name, version, cid);
+      Ok(())
+    }
+    Command::Registry { command: RegistryCommand::List } => {
+      let registry = Registry::load(&*store).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::InvalidData)
+      })?;
+      print!("{}", registry);
+      Ok(())
+    }
+    Command::Registry { command: RegistryCommand::Resolve { package } } => {
+      let (name, version) = parse_reference(&package).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::InvalidInput)
+      })?;
+      let registry = Registry::load(&*store).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::InvalidData)
+      })?;
+      match registry.resolve(&name, version.as_ref()) {
+        Some((version, cid)) => {
+          println!("{}@{} {}", name, version, cid);
+          Ok(())
+        }
+        None => {
+          eprintln!("Package {} is not in the registry", package);
+          Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+        }
+      }
+    }
+    Command::Compat { old, new } => {
+      let old_package = load_package(&*store, old)?;
+      let new_package = load_package(&*store, new)?;
+      let compat = Compat::new(&old_package, &new_package, &*store)
+        .map_err(|e| {
+          eprintln!("{}", e);
+          std::io::Error::from(std::io::ErrorKind::NotFound)
+        })?;
+      print!("{}", compat);
+      let mut broken = false;
+      // Dependents that import the package through others are rechecked
+      // against those others rebuilt against the upgrade
+      let mut upgrades = BTreeMap::new();
+      upgrades.insert(old, new);
+      for (cid, dependent) in compat::dependents(&*store, old) {
+        println!("Checking dependent {} at {}", dependent.name, cid);
+        let (upgraded, errors) = compat::recheck(
+          store.clone(),
+          &dependent,
+          &upgrades,
+          conversion,
+          hash_cons,
+        )
+        .map_err(|e| {
+          eprintln!("{}", e);
+          std::io::Error::from(std::io::ErrorKind::NotFound)
+        })?;
+        upgrades.insert(cid, upgraded);
+        if errors.is_empty() {
+          println!("✓ {}", dependent.name);
+        }
+        for (name, error) in errors {
+          println!("✕ {}: {}", name, error);
+          broken = true;
+        }
+      }
+      if broken {
+        eprintln!("Upgrading to {} breaks packages that import {}", new, old);
+        return Err(std::io::Error::from(std::io::ErrorKind::Other));
+      }
+      Ok(())
+    }
+    Command::Rename { package, old, new, output } => {
+      let p = load_package(&*store, package)?;
+      let (cid, renamed) =
+        rename(&*store, &p, &Name::from(old), &Name::from(new)).map_err(|e| {
+          eprintln!("{}", e);
+          std::io::Error::from(std::io::ErrorKind::Other)
+        })?;
+      if let (Some(output), Pos::Some(pos)) = (output, renamed.pos) {
+        if let Some(Ipld::String(source)) = store.get(pos.input) {
+          std::fs::write(output, source)?;
+        }
+      }
+      println!("Renamed package {} at {}", renamed.name, cid);
+      Ok(())
+    }
+    Command::Diff { old, new, json } => {
+      let old = load_package(&*store, old)?;
+      let new = load_package(&*store, new)?;
+      let diff = Diff::new(&old, &new, &*store).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+      })?;
+      if json {
+        println!("{}", diff.to_json());
+      }
+      else {
+        print!("{}", diff);
+      }
       Ok(())
     }
-    Command::Run { path } => {
-      let env = file::parse::PackageEnv::new(root, path.clone(), store.clone());
+    Command::Pin { command: PinCommand::Add { cid } } => {
+      let pinned = gc::pin(&*store, cid).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+      })?;
+      if pinned {
+        println!("Pinned {}", cid);
+      }
+      else {
+        println!("{} is already pinned", cid);
+      }
+      Ok(())
+    }
+    Command::Pin { command: PinCommand::Rm { cid } } => {
+      if gc::unpin(&*store, cid) {
+        println!("Unpinned {}", cid);
+        Ok(())
+      }
+      else {
+        eprintln!("{} is not pinned", cid);
+        Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+      }
+    }
+    Command::Pin { command: PinCommand::Ls } => {
+      for pin in store.get_pins() {
+        println!("{}", pin);
+      }
+      Ok(())
+    }
+    Command::Gc { dry_run, drop_cache } => {
+      let collection =
+        gc::collect(&*store, dry_run, drop_cache).map_err(|e| {
+          eprintln!("{}", e);
+          std::io::Error::from(std::io::ErrorKind::InvalidData)
+        })?;
+      let total = collection.live + collection.garbage.len();
+      if dry_run {
+        for cid in &collection.garbage {
+          println!("{}", cid);
+        }
+        println!("Would delete {} of {} blocks", collection.garbage.len(), total);
+        println!("Would forget {} cached normal forms", collection.memos.len());
+      }
+      else {
+        println!("Deleted {} of {} blocks", collection.garbage.len(), total);
+        println!("Forgot {} cached normal forms", collection.memos.len());
+      }
+      Ok(())
+    }
+    Command::Export { cid, output } => {
+      load_package(&*store, cid)?;
+      let car = car::write_car(&*store, &[cid]).map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+      })?;
+      std::fs::write(&output, car)?;
+      println!("Exported {} to {:?}", cid, output);
+      Ok(())
+    }
+    Command::Import { path, no_pin } => {
+      let bytes = std::fs::read(&path)?;
+      let (roots, count) =
+        car::read_car(&*store, &bytes, !no_pin).map_err(|e| {
+          eprintln!("{}", e);
+          std::io::Error::from(std::io::ErrorKind::InvalidData)
+        })?;
+      println!("Imported {} blocks from {:?}", count, path);
+      for root in roots {
+        println!("{}", root);
+      }
+      Ok(())
+    }
+    Command::Fsck { quarantine } => {
+      let blocks =
+        store.list().into_iter().map(|link| (link, store.read(link)));
+      let report = fsck::fsck(blocks);
+      for problem in &report.problems {
+        println!("{}", problem);
+      }
+      if quarantine {
+        for link in report.corrupt() {
+          if store.quarantine(link) {
+            println!("Quarantined {}", link);
+          }
+        }
+      }
+      println!(
+        "Checked {} blocks, found {} problems",
+        report.checked,
+        report.problems.len()
+      );
+      if report.is_clean() {
+        Ok(())
+      }
+      else {
+        Err(std::io::Error::from(std::io::ErrorKind::InvalidData))
+      }
+    }
+    Command::Compile { path, target, output, entry } => {
+      let env = file::parse::PackageEnv::new(root, path, store.clone());
       let (_, p, defs) = file::parse::parse_file(env).map_err(|e| {
         eprintln!("{}", e);
         std::io::Error::from(std::io::ErrorKind::Other)
       })?;
-
-      let _cid = store.put(p.to_ipld());
-      let def = defs
-        .get(&Name::from("main"))
-        .expect(&format!("No `main` expression in package {} from file {:?}", p.name, path));
-      let mut dag = yatima_core::dag::DAG::from_term(&def.to_owned().term);
-      dag.norm(&defs, false);
-      println!("{}", dag);
+      store.put(p.to_ipld());
+      let defs = Rc::new(defs);
+      check_package(&defs, &p.index, conversion, hash_cons)?;
+      let program = Program::new(&defs, &p.index, conversion).and_then(
+        |prog| match target.as_str() {
+          "c" => codegen::c::emit(&prog, &entry),
+          _ => codegen::js::emit(&prog),
+        },
+      );
+      let program = program.map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::from(std::io::ErrorKind::Other)
+      })?;
+      match output {
+        Some(output) => std::fs::write(output, program)?,
+        None => print!("{}", program),
+      }
       Ok(())
     }
   }
@@ -0,0 +1,82 @@
+#![feature(test)]
+
+extern crate test;
+extern crate yatima_core;
+
+use nom_locate::LocatedSpan;
+use std::rc::Rc;
+use test::Bencher;
+use yatima_core::{
+  check::{
+    check_def,
+    Conversion,
+  },
+  defs::Defs,
+  parse::term::input_cid,
+};
+
+// A package whose definitions lean on conversion: functions over datatypes,
+// proofs by reflexivity that only hold after unfolding, and an indexed family
+const PACKAGE: &str = "
+  type Bool { True, False }
+  type Nat { Z, S Nat }
+  type List (A: Type) { Nil, Cons A (List A) }
+  type Equal (A: Type) (a: A): ∀ (b: A) -> Type { Refl: Equal A a a }
+  type Vector (A: Type): ∀ (k: #Nat) -> Type {
+    Nil: Vector A 0,
+    Cons (0 k: #Nat) (x: A) (xs: Vector A k): Vector A (#Nat.suc k),
+  }
+
+  def Bool.not (b: Bool): Bool = (case b) (λ _ => Bool) Bool.False Bool.True
+  def Bool.and (a: Bool) (b: Bool): Bool = (case a) (λ _ => Bool) b Bool.False
+
+  def Nat.add (n: Nat) (m: Nat): Nat =
+    (case n) (λ _ => Nat) m (λ p => Nat.S (Nat.add p m))
+  def Nat.double (n: Nat): Nat = Nat.add n n
+  def two: Nat = Nat.S (Nat.S Nat.Z)
+  def four: Nat = Nat.double two
+
+  def List.map (A B: Type) (f: ∀ A -> B) (xs: List A): List B =
+    (case xs) (λ _ => List B) (List.Nil B)
+      (λ y ys => List.Cons B (f y) (List.map A B f ys))
+  def List.foldr (A B: Type) (f: ∀ A B -> B) (z: B) (xs: List A): B =
+    (case xs) (λ _ => B) z (λ y ys => f y (List.foldr A B f z ys))
+  def List.all (xs: List Bool): Bool =
+    List.foldr Bool Bool Bool.and Bool.True xs
+  def bools: List Bool =
+    List.Cons Bool Bool.False (List.Cons Bool Bool.True (List.Nil Bool))
+
+  def not_not_true: Equal Bool (Bool.not (Bool.not Bool.True)) Bool.True =
+    Equal.Refl Bool Bool.True
+  def two_plus_two: Equal Nat (Nat.add two two) four = Equal.Refl Nat four
+  def all_not: Equal Bool (List.all (List.map Bool Bool Bool.not bools))
+    Bool.False = Equal.Refl Bool Bool.False
+  def add_lits: Equal #Nat (#Nat.add 20 22) 42 = Equal.Refl #Nat 42
+
+  def Vector.head (A: Type) (0 k: #Nat) (v: Vector A (#Nat.suc k)): A =
+    (case v) (λ k _ => A) (λ x => x) (λ _ x _ => x)
+  def three: Vector #Nat 3 =
+    Vector.Cons #Nat 2 1 (Vector.Cons #Nat 1 2 (Vector.Cons #Nat 0 3
+      (Vector.Nil #Nat)))
+";
+
+fn bench_check(conversion: Conversion, b: &mut Bencher) {
+  let (_, (defs, _)) = yatima_core::parse::package::parse_defs(
+    input_cid(PACKAGE),
+    Defs::new(),
+  )(LocatedSpan::from(PACKAGE))
+  .unwrap();
+  let defs = Rc::new(defs);
+  let names: Vec<String> = defs.names.keys().map(|n| n.to_string()).collect();
+  b.iter(|| {
+    for name in &names {
+      let _ = check_def(defs.clone(), name, false, conversion, false);
+    }
+  });
+}
+
+#[bench]
+fn check_nbe(b: &mut Bencher) { bench_check(Conversion::Nbe, b); }
+
+#[bench]
+fn check_dag(b: &mut Bencher) { bench_check(Conversion::Dag, b); }
@@ -7,7 +7,14 @@ use core::sync::atomic::Ordering;
 use nom_locate::LocatedSpan;
 use test::Bencher;
 use yatima_core::{
+  arena,
+  dag::{
+    alloc_val,
+    free_val,
+    Lit,
+  },
   defs::Defs,
+  literal::Literal,
   parse::term::input_cid,
   upcopy::UPCOPY_COUNT,
 };
@@ -41,3 +48,37 @@ fn fact4(b: &mut Bencher) { bench_fact("fact 4", b); }
 
 #[bench]
 fn fact5(b: &mut Bencher) { bench_fact("fact 5", b); }
+
+#[bench]
+fn fact10(b: &mut Bencher) { bench_fact("fact 10", b); }
+
+#[bench]
+fn fact20(b: &mut Bencher) { bench_fact("fact 20", b); }
+
+// Allocation churn of the size `fact` produces, through a node arena and
+// through the system allocator
+const CHURN: u64 = 1000;
+
+#[bench]
+fn alloc_arena(b: &mut Bencher) {
+  arena::scope(|| {
+    b.iter(|| {
+      let nodes: Vec<_> = (0..CHURN)
+        .map(|n| alloc_val(Lit { lit: Literal::U64(n), parents: None }))
+        .collect();
+      for node in nodes {
+        free_val(node);
+      }
+    })
+  });
+}
+
+#[bench]
+fn alloc_box(b: &mut Bencher) {
+  b.iter(|| {
+    let nodes: Vec<_> = (0..CHURN)
+      .map(|n| Box::new(Lit { lit: Literal::U64(n), parents: None }))
+      .collect();
+    test::black_box(nodes);
+  });
+}
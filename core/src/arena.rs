@@ -0,0 +1,410 @@
+// Slab allocation for λ-DAG nodes. Nodes are created and destroyed at a very
+// high rate during reduction, so instead of boxing each one they are handed
+// out from fixed-size chunks, and freed slots are recycled through an
+// intrusive free list.
+//
+// An `Arena` holds one slab per node type and is owned by the graphs built in
+// it: every `DAG` keeps a reference to its arena, and when the last graph (or
+// scope) using an arena is dropped, the values still alive in it are dropped
+// and its chunks are given back to the system allocator. Graphs freely share
+// nodes with the graphs they are spliced into (see `DAG::from_ref` and
+// hash-consing), so the graphs of one computation, say checking a definition,
+// all live in the same arena.
+//
+// Nodes are allocated in the current arena of the thread, which `enter` sets
+// for the extent of a computation. `DAG` methods enter the arena of their
+// graph, and `scope` creates an arena for computations that build graphs from
+// scratch. Like the gas meter, the current arena is per thread; without `std`
+// there is only one.
+//
+// With the `debug-arena` feature, slabs also keep track of their live slots:
+// freeing a slot twice (or freeing a pointer the slab never handed out)
+// panics, and `DAG::free` reports the nodes of a graph that survive it.
+
+use crate::{
+  dag::{
+    All,
+    Ann,
+    App,
+    Cse,
+    Dat,
+    Fix,
+    LTy,
+    Lam,
+    Let,
+    Lit,
+    Opr,
+    Parents,
+    Ref,
+    Slf,
+    Typ,
+    Var,
+  },
+  memory,
+};
+
+use core::{
+  cell::RefCell,
+  mem::{
+    self,
+    ManuallyDrop,
+  },
+  ptr::{
+    self,
+    NonNull,
+  },
+};
+
+use sp_std::{
+  collections::btree_set::BTreeSet,
+  rc::Rc,
+  vec::Vec,
+};
+
+// The number of slots in a chunk
+pub const CHUNK_SIZE: usize = 1024;
+
+// A slot holds either a value or, once freed, the next free slot
+#[repr(C)]
+union Slot<T> {
+  val: ManuallyDrop<T>,
+  next: Option<NonNull<Slot<T>>>,
+}
+
+pub struct Slab<T> {
+  chunks: Vec<Vec<Slot<T>>>,
+  free: Option<NonNull<Slot<T>>>,
+  live: usize,
+  #[cfg(feature = "debug-arena")]
+  slots: Option<BTreeSet<usize>>,
+}
+
+impl<T> Slab<T> {
+  pub const fn new() -> Self {
+    Slab {
+      chunks: Vec::new(),
+      free: None,
+      live: 0,
+      #[cfg(feature = "debug-arena")]
+      slots: None,
+    }
+  }
+
+  pub fn alloc(&mut self, val: T) -> NonNull<T> {
+    let slot = match self.free {
+      Some(slot) => {
+        self.free = unsafe { (*slot.as_ptr()).next };
+        slot
+      }
+      None => {
+        let full = self.chunks.last().map_or(true, |c| c.len() == c.capacity());
+        if full {
+          self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+        }
+        // Pushing within capacity never moves the chunk, so the slot pointer
+        // stays valid for the lifetime of the arena
+        let chunk = self.chunks.last_mut().unwrap();
+        chunk.push(Slot { next: None });
+        NonNull::from(chunk.last_mut().unwrap())
+      }
+    };
+    unsafe {
+      ptr::write(slot.as_ptr(), Slot { val: ManuallyDrop::new(val) });
+    }
+    self.live += 1;
+    #[cfg(feature = "debug-arena")]
+    self.slots.get_or_insert_with(BTreeSet::new).insert(slot.as_ptr() as usize);
+    slot.cast()
+  }
+
+  /// Drops the value of a slot and recycles it.
+  ///
+  /// # Safety
+  ///
+  /// The pointer must have been returned by `alloc` on this slab and not freed
+  /// since.
+  pub unsafe fn free(&mut self, link: NonNull<T>) {
+    #[cfg(feature = "debug-arena")]
+    {
+      let addr = link.as_ptr() as usize;
+      if !self.slots.as_mut().map_or(false, |slots| slots.remove(&addr)) {
+        panic!(
+          "double free of {} node at {:#x}",
+          core::any::type_name::<T>(),
+          addr
+        );
+      }
+    }
+    ptr::drop_in_place(link.as_ptr());
+    let slot: NonNull<Slot<T>> = link.cast();
+    (*slot.as_ptr()).next = self.free;
+    self.free = Some(slot);
+    self.live -= 1;
+  }
+
+  // The number of allocated slots that haven't been freed
+  pub fn live(&self) -> usize { self.live }
+
+  // Whether a pointer refers to a slot that is currently allocated
+  #[cfg(feature = "debug-arena")]
+  pub fn is_live(&self, link: NonNull<T>) -> bool {
+    let addr = link.as_ptr() as usize;
+    self.slots.as_ref().map_or(false, |slots| slots.contains(&addr))
+  }
+}
+
+impl<T> Default for Slab<T> {
+  fn default() -> Self { Self::new() }
+}
+
+// Dropping a slab drops the values still alive in it, and gives its chunks
+// back to the system allocator
+impl<T> Drop for Slab<T> {
+  fn drop(&mut self) {
+    if self.live == 0 {
+      return;
+    }
+    let mut free = BTreeSet::new();
+    let mut next = self.free;
+    while let Some(slot) = next {
+      free.insert(slot.as_ptr() as usize);
+      next = unsafe { (*slot.as_ptr()).next };
+    }
+    for chunk in &mut self.chunks {
+      for slot in chunk.iter_mut() {
+        if !free.contains(&(&*slot as *const Slot<T> as usize)) {
+          unsafe { ManuallyDrop::drop(&mut slot.val) }
+        }
+      }
+    }
+  }
+}
+
+// A node type and the slab of an arena it is allocated in
+pub trait Node: Sized + 'static {
+  fn slab(arena: &Arena) -> &RefCell<Slab<Self>>;
+}
+
+// Declares the arena, with one slab for each of the given node types
+macro_rules! arena_nodes {
+  ($($field:ident: $typ:ty),* $(,)?) => {
+    pub struct Arena {
+      $($field: RefCell<Slab<$typ>>,)*
+    }
+
+    impl Arena {
+      pub fn new() -> Self {
+        Arena { $($field: RefCell::new(Slab::new()),)* }
+      }
+
+      // The number of nodes allocated in the arena that haven't been freed
+      pub fn live(&self) -> usize {
+        0 $(+ self.$field.borrow().live())*
+      }
+    }
+
+    $(
+      impl Node for $typ {
+        #[inline]
+        fn slab(arena: &Arena) -> &RefCell<Slab<Self>> { &arena.$field }
+      }
+    )*
+  };
+}
+
+arena_nodes!(
+  var: Var,
+  lam: Lam,
+  app: App,
+  all: All,
+  slf: Slf,
+  fix: Fix,
+  dat: Dat,
+  cse: Cse,
+  ann: Ann,
+  let_: Let,
+  ref_: Ref,
+  typ: Typ,
+  lit: Lit,
+  lty: LTy,
+  opr: Opr,
+  parents: Parents,
+);
+
+impl Default for Arena {
+  fn default() -> Self { Self::new() }
+}
+
+// The nodes left in a dropped arena no longer count towards the memory limit
+impl Drop for Arena {
+  fn drop(&mut self) { memory::free_nodes(self.live() as u64) }
+}
+
+#[cfg(any(feature = "std", test))]
+std::thread_local! {
+  static CURRENT: RefCell<Option<Rc<Arena>>> = RefCell::new(None);
+}
+
+#[cfg(any(feature = "std", test))]
+fn with_current<A>(f: impl FnOnce(&mut Option<Rc<Arena>>) -> A) -> A {
+  CURRENT.with(|current| f(&mut current.borrow_mut()))
+}
+
+#[cfg(not(any(feature = "std", test)))]
+mod global {
+  use super::Arena;
+  use core::cell::UnsafeCell;
+  use sp_std::rc::Rc;
+
+  struct Current(UnsafeCell<Option<Rc<Arena>>>);
+
+  // Without `std` there is only one thread
+  unsafe impl Sync for Current {}
+
+  static CURRENT: Current = Current(UnsafeCell::new(None));
+
+  pub fn with_current<A>(f: impl FnOnce(&mut Option<Rc<Arena>>) -> A) -> A {
+    f(unsafe { &mut *CURRENT.0.get() })
+  }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+use global::with_current;
+
+// The arena new nodes are allocated in on this thread, if any
+pub fn current() -> Option<Rc<Arena>> {
+  with_current(|current| current.clone())
+}
+
+// Run `f` with `arena` as the current arena of this thread, or in the current
+// arena if `arena` is `None`
+pub fn enter<A>(arena: Option<&Rc<Arena>>, f: impl FnOnce() -> A) -> A {
+  let arena = match arena {
+    Some(arena) => arena.clone(),
+    None => return f(),
+  };
+  let prev = with_current(|current| current.replace(arena));
+  // Restored on drop, so that a panic doesn't leave the arena entered
+  let _restore = Restore(prev);
+  f()
+}
+
+// Run `f` in the current arena, or in a new one if there is none. A new arena
+// lives on in the graphs `f` returns, and is dropped with them.
+pub fn scope<A>(f: impl FnOnce() -> A) -> A {
+  if current().is_some() {
+    f()
+  }
+  else {
+    enter(Some(&Rc::new(Arena::new())), f)
+  }
+}
+
+struct Restore(Option<Rc<Arena>>);
+
+impl Drop for Restore {
+  fn drop(&mut self) {
+    let prev = self.0.take();
+    // Dropped once the current arena is no longer borrowed, since dropping it
+    // may drop the arena
+    let _left = with_current(|current| mem::replace(current, prev));
+  }
+}
+
+// Run `f` on the slab of a node type in the current arena
+#[inline]
+pub fn with_slab<T: Node, A>(f: impl FnOnce(&mut Slab<T>) -> A) -> A {
+  with_current(|current| {
+    let arena = current.as_ref().expect("No arena to allocate DAG nodes in");
+    f(&mut T::slab(arena).borrow_mut())
+  })
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+
+  #[test]
+  fn slots_are_recycled() {
+    let mut slab = Slab::new();
+    let x = slab.alloc(1u64);
+    let y = slab.alloc(2u64);
+    assert_eq!(unsafe { *x.as_ptr() + *y.as_ptr() }, 3);
+    unsafe { slab.free(x) };
+    assert_eq!(slab.live(), 1);
+    let z = slab.alloc(3u64);
+    assert_eq!(z, x);
+    assert_eq!(unsafe { *z.as_ptr() }, 3);
+    assert_eq!(slab.live(), 2);
+  }
+
+  #[test]
+  fn chunks_never_move() {
+    let mut slab = Slab::new();
+    let first = slab.alloc(0usize);
+    let ptrs: Vec<NonNull<usize>> =
+      (1..3 * CHUNK_SIZE).map(|i| slab.alloc(i)).collect();
+    assert_eq!(unsafe { *first.as_ptr() }, 0);
+    for (i, ptr) in ptrs.iter().enumerate() {
+      assert_eq!(unsafe { *ptr.as_ptr() }, i + 1);
+    }
+    assert_eq!(slab.chunks.len(), 3);
+  }
+
+  #[test]
+  fn free_drops_values() {
+    let mut slab = Slab::new();
+    let rc = Rc::new(());
+    let x = slab.alloc(rc.clone());
+    assert_eq!(Rc::strong_count(&rc), 2);
+    unsafe { slab.free(x) };
+    assert_eq!(Rc::strong_count(&rc), 1);
+  }
+
+  #[test]
+  fn drop_drops_live_values() {
+    let rc = Rc::new(());
+    let mut slab = Slab::new();
+    let x = slab.alloc(rc.clone());
+    let _y = slab.alloc(rc.clone());
+    unsafe { slab.free(x) };
+    assert_eq!(Rc::strong_count(&rc), 2);
+    drop(slab);
+    assert_eq!(Rc::strong_count(&rc), 1);
+  }
+
+  #[test]
+  fn scope_drops_its_arena() {
+    let arena = scope(|| {
+      assert!(current().is_some());
+      Rc::downgrade(&current().unwrap())
+    });
+    assert!(current().is_none());
+    assert!(arena.upgrade().is_none());
+  }
+
+  #[test]
+  fn enter_restores_the_arena() {
+    let outer = Rc::new(Arena::new());
+    let inner = Rc::new(Arena::new());
+    enter(Some(&outer), || {
+      enter(Some(&inner), || {
+        assert!(Rc::ptr_eq(&current().unwrap(), &inner));
+      });
+      assert!(Rc::ptr_eq(&current().unwrap(), &outer));
+    });
+    assert!(current().is_none());
+  }
+
+  #[cfg(feature = "debug-arena")]
+  #[test]
+  #[should_panic(expected = "double free")]
+  fn double_free_panics() {
+    let mut slab = Slab::new();
+    let x = slab.alloc(1u64);
+    unsafe {
+      slab.free(x);
+      slab.free(x);
+    }
+  }
+}
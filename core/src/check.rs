@@ -1,14 +1,25 @@
 pub mod ctx;
 pub mod error;
+pub mod nbe;
 
 use ctx::*;
 use error::CheckError;
 
 use crate::{
-  dag::*,
-  defs::Defs,
+  arena,
+  dag::{
+    hashcons::HashCons,
+    *,
+  },
+  defs::{
+    Def,
+    Defs,
+  },
   dll::*,
-  literal::Literal,
+  literal::{
+    LitType,
+    Literal,
+  },
   name::Name,
   position::Pos,
   term::Term,
@@ -31,72 +42,156 @@ use sp_std::{
   rc::Rc,
 };
 
+// The literal types `case` eliminates in a definition, by the address of the
+// expression each `case` is applied to, which the backends need to expand
+// literals that share a representation
+pub type Cases = BTreeMap<*const Term, LitType>;
+
 pub fn hash(dag: DAGPtr, dep: u64) -> Cid {
   let mut map = BTreeMap::new();
   DAG::dag_ptr_to_term(&dag, &mut map, dep, true).embed().0.cid()
 }
 
-pub fn equal(defs: &Defs, a: &mut DAG, b: &mut DAG, dep: u64, should_count: bool) -> bool {
+// How `check` decides whether two types are convertible
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conversion {
+  // Evaluate both types into closures and values and compare those, falling
+  // back to `Dag` whenever that gives up
+  Nbe,
+  // Reduce both graphs to weak head normal form and compare the hashes of
+  // their subterms
+  Dag,
+}
+
+pub fn equal(
+  defs: &Defs,
+  a: &mut DAG,
+  b: &mut DAG,
+  dep: u64,
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+) -> bool {
+  if conversion == Conversion::Nbe {
+    if let Ok(eq) = nbe::equal(defs, a, b, dep, nbe::FUEL) {
+      return eq;
+    }
+  }
+  equal_dag(defs, a, b, dep, should_count, cons)
+}
+
+pub fn equal_dag(
+  defs: &Defs,
+  a: &mut DAG,
+  b: &mut DAG,
+  dep: u64,
+  should_count: bool,
+  cons: &mut Option<HashCons>,
+) -> bool {
   a.whnf(defs, should_count);
   b.whnf(defs, should_count);
-  let mut triples = vec![(a.head, b.head, dep)];
+  // Sharing the nodes below the ones being compared may merge any other closed
+  // node away, so `a`, `b` and the nodes waiting to be compared are rooted
+  // until they're done with
+  let heads = (pin(a.head), pin(b.head));
+  let mut triples = vec![(pin(a.head), pin(b.head), dep)];
   let mut set: BTreeSet<(Cid, Cid)> = BTreeSet::new();
-  while let Some((a, b, dep)) = triples.pop() {
-    let mut a = DAG::new(a);
-    let mut b = DAG::new(b);
-    a.whnf(defs, should_count);
-    b.whnf(defs, should_count);
-    let hash_a = hash(a.head, dep);
-    let hash_b = hash(b.head, dep);
-    let eq =
-      hash_a == hash_b || set.contains(&(hash_a, hash_b)) || set.contains(&(hash_b, hash_a));
-    set.insert((hash_a, hash_b));
-    if !eq {
-      match (a.head, b.head) {
-        (DAGPtr::Lam(a_link), DAGPtr::Lam(b_link)) => unsafe {
-          let Lam { bod: a_bod, .. } = *a_link.as_ptr();
-          let Lam { bod: b_bod, .. } = *b_link.as_ptr();
-          triples.push((a_bod, b_bod, dep + 1));
-        },
-        (DAGPtr::Slf(a_link), DAGPtr::Slf(b_link)) => unsafe {
-          let Slf { bod: a_bod, .. } = *a_link.as_ptr();
-          let Slf { bod: b_bod, .. } = *b_link.as_ptr();
-          triples.push((a_bod, b_bod, dep + 1));
-        },
-        (DAGPtr::Cse(a_link), DAGPtr::Cse(b_link)) => unsafe {
-          let Cse { bod: a_bod, .. } = *a_link.as_ptr();
-          let Cse { bod: b_bod, .. } = *b_link.as_ptr();
-          triples.push((a_bod, b_bod, dep));
-        },
-        (DAGPtr::Dat(a_link), DAGPtr::Dat(b_link)) => unsafe {
-          let Dat { bod: a_bod, .. } = *a_link.as_ptr();
-          let Dat { bod: b_bod, .. } = *b_link.as_ptr();
-          triples.push((a_bod, b_bod, dep));
-        },
-        (DAGPtr::All(a_link), DAGPtr::All(b_link)) => unsafe {
-          let All { uses: a_uses, dom: a_dom, img: a_img, .. } = *a_link.as_ptr();
-          let All { uses: b_uses, dom: b_dom, img: b_img, .. } = *b_link.as_ptr();
-          let a_img = DAGPtr::Lam(a_img);
-          let b_img = DAGPtr::Lam(b_img);
-          if a_uses != b_uses {
-            return false;
-          }
-          triples.push((a_dom, b_dom, dep));
-          triples.push((a_img, b_img, dep + 1));
-        },
-        (DAGPtr::App(a_link), DAGPtr::App(b_link)) => unsafe {
-          let App { fun: a_fun, arg: a_arg, .. } = *a_link.as_ptr();
-          let App { fun: b_fun, arg: b_arg, .. } = *b_link.as_ptr();
-          triples.push((a_fun, b_fun, dep));
-          triples.push((a_arg, b_arg, dep));
-        },
-        _ => return false,
-      }
+  let mut eq = true;
+  while let Some((mut a, mut b, dep)) = triples.pop() {
+    if eq {
+      eq = equal_step(defs, &mut a, &mut b, dep, should_count, cons, &mut set, &mut triples);
+    }
+    a.free();
+    b.free();
+  }
+  heads.0.free();
+  heads.1.free();
+  eq
+}
+
+// Compare the heads of two graphs reduced to weak head normal form, pushing
+// their children onto `triples` if they may still be equal
+#[inline]
+fn equal_step(
+  defs: &Defs,
+  a: &mut DAG,
+  b: &mut DAG,
+  dep: u64,
+  should_count: bool,
+  cons: &mut Option<HashCons>,
+  set: &mut BTreeSet<(Cid, Cid)>,
+  triples: &mut Vec<(DAG, DAG, u64)>,
+) -> bool {
+  // Hash-consed graphs share their closed subterms
+  if a.head == b.head {
+    return true;
+  }
+  a.whnf(defs, should_count);
+  b.whnf(defs, should_count);
+  if let Some(cons) = cons {
+    cons.share(a.head);
+    cons.share(b.head);
+    if a.head == b.head {
+      return true;
+    }
+  }
+  let hash_a = hash(a.head, dep);
+  let hash_b = hash(b.head, dep);
+  let eq =
+    hash_a == hash_b || set.contains(&(hash_a, hash_b)) || set.contains(&(hash_b, hash_a));
+  set.insert((hash_a, hash_b));
+  if !eq {
+    match (a.head, b.head) {
+      (DAGPtr::Lam(a_link), DAGPtr::Lam(b_link)) => unsafe {
+        let Lam { bod: a_bod, .. } = *a_link.as_ptr();
+        let Lam { bod: b_bod, .. } = *b_link.as_ptr();
+        triples.push((pin(a_bod), pin(b_bod), dep + 1));
+      },
+      (DAGPtr::Slf(a_link), DAGPtr::Slf(b_link)) => unsafe {
+        let Slf { bod: a_bod, .. } = *a_link.as_ptr();
+        let Slf { bod: b_bod, .. } = *b_link.as_ptr();
+        triples.push((pin(a_bod), pin(b_bod), dep + 1));
+      },
+      (DAGPtr::Cse(a_link), DAGPtr::Cse(b_link)) => unsafe {
+        let Cse { bod: a_bod, .. } = *a_link.as_ptr();
+        let Cse { bod: b_bod, .. } = *b_link.as_ptr();
+        triples.push((pin(a_bod), pin(b_bod), dep));
+      },
+      (DAGPtr::Dat(a_link), DAGPtr::Dat(b_link)) => unsafe {
+        let Dat { bod: a_bod, .. } = *a_link.as_ptr();
+        let Dat { bod: b_bod, .. } = *b_link.as_ptr();
+        triples.push((pin(a_bod), pin(b_bod), dep));
+      },
+      (DAGPtr::All(a_link), DAGPtr::All(b_link)) => unsafe {
+        let All { uses: a_uses, dom: a_dom, img: a_img, .. } = *a_link.as_ptr();
+        let All { uses: b_uses, dom: b_dom, img: b_img, .. } = *b_link.as_ptr();
+        let a_img = DAGPtr::Lam(a_img);
+        let b_img = DAGPtr::Lam(b_img);
+        if a_uses != b_uses {
+          return false;
+        }
+        triples.push((pin(a_dom), pin(b_dom), dep));
+        triples.push((pin(a_img), pin(b_img), dep + 1));
+      },
+      (DAGPtr::App(a_link), DAGPtr::App(b_link)) => unsafe {
+        let App { fun: a_fun, arg: a_arg, .. } = *a_link.as_ptr();
+        let App { fun: b_fun, arg: b_arg, .. } = *b_link.as_ptr();
+        triples.push((pin(a_fun), pin(b_fun), dep));
+        triples.push((pin(a_arg), pin(b_arg), dep));
+      },
+      _ => return false,
     }
   }
   true
 }
 
+// Root a node of a graph, to be unrooted with `DAG::free`
+fn pin(node: DAGPtr) -> DAG {
+  let root = alloc_val(DLL::singleton(ParentPtr::Root));
+  add_to_parents(node, root);
+  DAG::new(node)
+}
+
 pub fn check(
   rec: &Option<(Name, Cid, Cid)>,
   defs: &Defs,
@@ -105,15 +200,18 @@ pub fn check(
   term: &Term,
   typ: &mut DAG,
   should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<(), CheckError> {
   match term {
-    Term::Lam(pos, _, bod) => check_lam(rec, defs, ctx, uses, term, typ, pos, &**bod, should_count),
-    Term::Dat(pos, bod) => check_dat(rec, defs, ctx, uses, term, typ, pos, &**bod, should_count),
+    Term::Lam(pos, _, bod) => check_lam(rec, defs, ctx, uses, term, typ, pos, &**bod, should_count, conversion, cons, cases),
+    Term::Dat(pos, bod) => check_dat(rec, defs, ctx, uses, term, typ, pos, &**bod, should_count, conversion, cons, cases),
     _ => {
       let depth = ctx.len();
       // TODO Should we clone ctx?
-      let mut detected_typ = infer(rec, defs, ctx, uses, term, should_count)?;
-      if equal(defs, typ, &mut detected_typ, depth as u64, should_count) {
+      let mut detected_typ = infer(rec, defs, ctx, uses, term, should_count, conversion, cons, cases)?;
+      if equal(defs, typ, &mut detected_typ, depth as u64, should_count, conversion, cons) {
         detected_typ.free();
         Ok(())
       }
@@ -137,7 +235,10 @@ pub fn check_lam(
   typ: &mut DAG,
   pos: &Pos,
   bod: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<(), CheckError> {
   // To check whether a lambda is well typed, its type must reduce to a forall;
   // otherwise we fail
@@ -157,7 +258,7 @@ pub fn check_lam(
       let rest_ctx = div_ctx(uses, ctx);
       ctx.push((all_var.nam.to_string(), *lam_uses, dom));
       let mut img = DAG::new(*img);
-      check(rec, defs, ctx, Uses::Once, bod, &mut img, should_count)?;
+      check(rec, defs, ctx, Uses::Once, bod, &mut img, should_count, conversion, cons, cases)?;
       // Check whether the rest 'contains' zero (i.e., zero is less than or
       // equal to the rest), otherwise the variable was not used enough
       let (_, rest, _) = ctx.last().unwrap();
@@ -197,6 +298,9 @@ pub fn check_dat(
   pos: &Pos,
   bod: &Term,
   should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<(), CheckError> {
   // To check whether data is well typed, its type must reduce to a self type;
   // otherwise we fail
@@ -218,7 +322,8 @@ pub fn check_dat(
       }
       let root = alloc_val(DLL::singleton(ParentPtr::Root));
       let mut unrolled_typ = DAG::new(DAG::from_subdag(*slf_bod, &mut map, Some(root)));
-      check(rec, defs, ctx, uses, bod, &mut unrolled_typ, should_count)?;
+      share(cons, &unrolled_typ);
+      check(rec, defs, ctx, uses, bod, &mut unrolled_typ, should_count, conversion, cons, cases)?;
       // We must free the newly created type as to not leak
       unrolled_typ.free();
       Ok(())
@@ -237,22 +342,25 @@ pub fn infer(
   ctx: &mut Ctx,
   uses: Uses,
   term: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
-  match term {
+  let typ = match term {
     Term::Rec(_) => infer_rec(rec, defs),
     Term::Var(pos, nam, idx) => infer_var(rec, defs, ctx, uses, pos, nam, idx),
     Term::Ref(pos, nam, def_link, _) => infer_ref(defs, pos, nam, def_link),
-    Term::App(pos, fun_arg) => infer_app(rec, defs, ctx, uses, pos, &fun_arg.0, &fun_arg.1, should_count),
-    Term::Cse(pos, exp) => infer_cse(rec, defs, ctx, uses, pos, exp, should_count),
-    Term::All(_, _, nam, dom_img) => infer_all(rec, defs, ctx, nam, &dom_img.0, &dom_img.1, should_count),
-    Term::Slf(_, nam, bod) => infer_slf(rec, defs, ctx, term, nam, bod, should_count),
-    Term::Ann(_, typ_exp) => infer_ann(rec, defs, ctx, uses, &typ_exp.0, &typ_exp.1, should_count),
+    Term::App(pos, fun_arg) => infer_app(rec, defs, ctx, uses, pos, &fun_arg.0, &fun_arg.1, should_count, conversion, cons, cases),
+    Term::Cse(pos, exp) => infer_cse(rec, defs, ctx, uses, pos, exp, should_count, conversion, cons, cases),
+    Term::All(_, _, nam, dom_img) => infer_all(rec, defs, ctx, nam, &dom_img.0, &dom_img.1, should_count, conversion, cons, cases),
+    Term::Slf(_, nam, bod) => infer_slf(rec, defs, ctx, term, nam, bod, should_count, conversion, cons, cases),
+    Term::Ann(_, typ_exp) => infer_ann(rec, defs, ctx, uses, &typ_exp.0, &typ_exp.1, should_count, conversion, cons, cases),
     Term::Let(pos, false, exp_uses, nam, triple) => {
-      infer_let(rec, defs, ctx, uses, pos, *exp_uses, nam, &triple.0, &triple.1, &triple.2, should_count)
+      infer_let(rec, defs, ctx, uses, pos, *exp_uses, nam, &triple.0, &triple.1, &triple.2, should_count, conversion, cons, cases)
     }
     Term::Let(pos, true, exp_uses, nam, triple) => {
-      infer_letrec(rec, defs, ctx, uses, pos, *exp_uses, nam, &triple.0, &triple.1, &triple.2, should_count)
+      infer_letrec(rec, defs, ctx, uses, pos, *exp_uses, nam, &triple.0, &triple.1, &triple.2, should_count, conversion, cons, cases)
     }
     Term::Typ(_) => {
       let typ = DAG::from_term(&Term::Typ(Pos::None));
@@ -263,6 +371,16 @@ pub fn infer(
     Term::Opr(_, opr) => Ok(DAG::from_term(&opr.type_of())),
     Term::Lam(..) => Err(CheckError::UntypedLambda(term.pos(), error_context(&ctx))),
     Term::Dat(..) => Err(CheckError::UntypedData(term.pos(), error_context(&ctx))),
+  }?;
+  share(cons, &typ);
+  Ok(typ)
+}
+
+// Share the closed subterms of a type built while checking with the ones of
+// the types built before, when hash-consing
+fn share(cons: &mut Option<HashCons>, typ: &DAG) {
+  if let Some(cons) = cons {
+    cons.share(typ.head);
   }
 }
 
@@ -328,14 +446,17 @@ pub fn infer_app(
   fun: &Term,
   arg: &Term,
   should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
-  let mut fun_typ = infer(rec, defs, ctx, uses, fun, should_count)?;
+  let mut fun_typ = infer(rec, defs, ctx, uses, fun, should_count, conversion, cons, cases)?;
   fun_typ.whnf(defs, should_count);
   match fun_typ.head {
     DAGPtr::All(link) => {
       let All { uses: lam_uses, dom, img, .. } = unsafe { &mut *link.as_ptr() };
       let Lam { var, bod: img, .. } = unsafe { &mut *img.as_ptr() };
-      check(rec, defs, ctx, *lam_uses * uses, arg, &mut DAG::new(*dom), should_count)?;
+      check(rec, defs, ctx, *lam_uses * uses, arg, &mut DAG::new(*dom), should_count, conversion, cons, cases)?;
       let mut map = BTreeMap::new();
       if var.parents.is_some() {
         map.insert(
@@ -365,9 +486,12 @@ pub fn infer_cse(
   uses: Uses,
   pos: &Pos,
   exp: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
-  let mut exp_typ = infer(rec, defs, ctx, uses, exp, should_count)?;
+  let mut exp_typ = infer(rec, defs, ctx, uses, exp, should_count, conversion, cons, cases)?;
   exp_typ.whnf(defs, should_count);
   match exp_typ.head {
     DAGPtr::Slf(link) => {
@@ -386,6 +510,9 @@ pub fn infer_cse(
     }
     DAGPtr::LTy(link) => {
       let LTy { lty, .. } = unsafe { &mut *link.as_ptr() };
+      if let Some(cases) = cases {
+        cases.insert(exp as *const Term, *lty);
+      }
       let root = alloc_val(DLL::singleton(ParentPtr::Root));
       match lty.induction(exp.clone()) {
         None => Err(CheckError::NonInductiveLitType(*pos, error_context(&ctx), *lty)),
@@ -414,13 +541,16 @@ pub fn infer_all(
   dom: &Term,
   img: &Term,
   should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
   let mut typ = DAG::from_term(&Term::Typ(Pos::None));
-  check(rec, defs, ctx, Uses::None, dom, &mut typ, should_count)?;
+  check(rec, defs, ctx, Uses::None, dom, &mut typ, should_count, conversion, cons, cases)?;
   let mut dom_dag =
     DAG::from_term_inner(dom, ctx.len() as u64, BTreeMap::new(), None, rec.clone());
   ctx.push((nam.to_string(), Uses::None, &mut dom_dag));
-  check(rec, defs, ctx, Uses::None, img, &mut typ, should_count)?;
+  check(rec, defs, ctx, Uses::None, img, &mut typ, should_count, conversion, cons, cases)?;
   ctx.pop();
   free_dead_node(dom_dag);
   Ok(typ)
@@ -434,13 +564,16 @@ pub fn infer_slf(
   term: &Term,
   nam: &Name,
   bod: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
   let mut typ = DAG::from_term(&Term::Typ(Pos::None));
   let mut term_dag =
     DAG::from_term_inner(term, ctx.len() as u64, BTreeMap::new(), None, rec.clone());
   ctx.push((nam.to_string(), Uses::None, &mut term_dag));
-  check(rec, defs, ctx, Uses::None, bod, &mut typ, should_count)?;
+  check(rec, defs, ctx, Uses::None, bod, &mut typ, should_count, conversion, cons, cases)?;
   ctx.pop();
   free_dead_node(term_dag);
   Ok(typ)
@@ -458,7 +591,10 @@ pub fn infer_let(
   exp_typ: &Term,
   exp: &Term,
   bod: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
   let exp_dag =
     &mut DAG::new(DAG::from_term_inner(exp, ctx.len() as u64, BTreeMap::new(), None, rec.clone()));
@@ -470,10 +606,10 @@ pub fn infer_let(
     Some(root),
     rec.clone(),
   ));
-  check(rec, defs, ctx, exp_uses * uses, exp, exp_typ_dag, should_count)?;
+  check(rec, defs, ctx, exp_uses * uses, exp, exp_typ_dag, should_count, conversion, cons, cases)?;
   let rest_ctx = div_ctx(uses, ctx);
   ctx.push((nam.to_string(), exp_uses, &mut exp_typ_dag.head));
-  let mut bod_typ = infer(rec, defs, ctx, Uses::Once, bod, should_count)?;
+  let mut bod_typ = infer(rec, defs, ctx, Uses::Once, bod, should_count, conversion, cons, cases)?;
   let (_, rest, _) = ctx.last().unwrap();
   // Have to check whether the rest 'contains' zero (i.e., zero is less than or
   // equal to the rest), otherwise the variable was not used enough
@@ -501,7 +637,10 @@ pub fn infer_letrec(
   exp_typ: &Term,
   exp: &Term,
   bod: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
   unsafe {
     // Allocates exp as a DAG, must be rootless
@@ -528,13 +667,13 @@ pub fn infer_letrec(
     // Check exp, noting it is a recursive definition
     let rest_ctx = div_ctx(Uses::Many, ctx);
     ctx.push((nam.to_string(), Uses::Many, &mut exp_typ_dag.head));
-    check(rec, defs, ctx, Uses::Many, exp, exp_typ_dag, should_count)?; // TODO better error message
+    check(rec, defs, ctx, Uses::Many, exp, exp_typ_dag, should_count, conversion, cons, cases)?; // TODO better error message
     ctx.pop();
     // Check bod
     add_ctx(ctx, rest_ctx);
     let rest_ctx = div_ctx(uses, ctx);
     ctx.push((nam.to_string(), exp_uses, &mut exp_typ_dag.head));
-    let mut bod_typ = infer(rec, defs, ctx, Uses::Once, bod, should_count)?;
+    let mut bod_typ = infer(rec, defs, ctx, Uses::Once, bod, should_count, conversion, cons, cases)?;
     let (_, rest, _) = ctx.last().unwrap();
     // Have to check whether the rest 'contains' zero (i.e., zero is less than
     // or equal to the rest), otherwise the variable was not used enough
@@ -565,7 +704,10 @@ pub fn infer_ann(
   uses: Uses,
   exp: &Term,
   typ: &Term,
-  should_count: bool
+  should_count: bool,
+  conversion: Conversion,
+  cons: &mut Option<HashCons>,
+  cases: &mut Option<Cases>,
 ) -> Result<DAG, CheckError> {
   let root = alloc_val(DLL::singleton(ParentPtr::Root));
   let mut typ_dag = DAG::new(DAG::from_term_inner(
@@ -575,7 +717,7 @@ pub fn infer_ann(
     Some(root),
     rec.clone(),
   ));
-  check(rec, defs, ctx, uses, exp, &mut typ_dag, should_count)?;
+  check(rec, defs, ctx, uses, exp, &mut typ_dag, should_count, conversion, cons, cases)?;
   Ok(typ_dag)
 }
 
@@ -601,14 +743,28 @@ pub fn infer_lit(lit: Literal) -> Term {
   }
 }
 
-pub fn infer_term(defs: &Defs, term: Term, should_count: bool) -> Result<Term, CheckError> {
-  let typ_dag = infer(&None, &defs, &mut vec![].into(), Uses::Once, &term, should_count)?;
-  let typ = DAG::to_term(&typ_dag, true);
-  typ_dag.free();
-  Ok(typ)
+pub fn infer_term(
+  defs: &Defs,
+  term: Term,
+  should_count: bool,
+  conversion: Conversion,
+) -> Result<Term, CheckError> {
+  // The graphs built while checking live in one arena, dropped at the end
+  arena::scope(|| {
+    let typ_dag = infer(&None, &defs, &mut vec![].into(), Uses::Once, &term, should_count, conversion, &mut None, &mut None)?;
+    let typ = DAG::to_term(&typ_dag, true);
+    typ_dag.free();
+    Ok(typ)
+  })
 }
 
-pub fn check_def(defs: Rc<Defs>, name: &str, should_count: bool) -> Result<Term, CheckError> {
+pub fn check_def(
+  defs: Rc<Defs>,
+  name: &str,
+  should_count: bool,
+  conversion: Conversion,
+  hash_cons: bool,
+) -> Result<Term, CheckError> {
   let def = defs
     .get(&Name::from(name))
     .ok_or_else(|| CheckError::UndefinedReference(Pos::None, name.to_owned()))?;
@@ -616,8 +772,34 @@ pub fn check_def(defs: Rc<Defs>, name: &str, should_count: bool) -> Result<Term,
   let def_cid = d.cid();
   let ast_cid = a.cid();
   let rec = Some((Name::from(name), def_cid, ast_cid));
-  let mut typ = DAG::from_term(&def.typ_);
-  check(&rec, &defs, &mut vec![].into(), Uses::Once, &def.term, &mut typ, should_count)?;
-  typ.free();
-  Ok(def.typ_.clone())
+  arena::scope(|| {
+    // One table shares the types built while checking the definition with
+    // each other
+    let cons = &mut if hash_cons { Some(HashCons::new()) } else { None };
+    let mut typ = DAG::from_term(&def.typ_);
+    share(cons, &typ);
+    check(&rec, &defs, &mut vec![].into(), Uses::Once, &def.term, &mut typ, should_count, conversion, cons, &mut None)?;
+    typ.free();
+    Ok(def.typ_.clone())
+  })
+}
+
+// Check a definition, collecting the literal types its `case`s eliminate. The
+// keys point into `def.term`, so they are only valid while it is borrowed.
+pub fn check_cases(
+  defs: &Defs,
+  name: &Name,
+  def: &Def,
+  conversion: Conversion,
+) -> Result<Cases, CheckError> {
+  let (d, _, a) = def.embed();
+  let rec = Some((name.clone(), d.cid(), a.cid()));
+  let cases = &mut Some(Cases::new());
+  arena::scope(|| -> Result<(), CheckError> {
+    let mut typ = DAG::from_term(&def.typ_);
+    check(&rec, defs, &mut vec![].into(), Uses::Once, &def.term, &mut typ, false, conversion, &mut None, cases)?;
+    typ.free();
+    Ok(())
+  })?;
+  Ok(cases.take().unwrap_or_default())
 }
@@ -0,0 +1,717 @@
+// Conversion checking by normalization by evaluation. The two types are read
+// out of the graph into shared syntax, evaluated into a semantic domain of
+// closures and neutral values, and compared, instantiating the binders of both
+// sides with the same fresh variable. Variables in values are de Bruijn
+// levels, so comparing them needs no shifting, and nothing is ever
+// substituted into a term.
+//
+// Definitions are kept folded for as long as possible: two applications of
+// the same definition are equal when their arguments are, and only otherwise
+// are they unfolded. Evaluation is bounded by fuel and by nesting depth, and
+// fixpoints outside of a `let` can't be read, so `equal` may give up, in which
+// case the caller falls back to comparing graphs.
+
+use crate::{
+  dag::*,
+  defs::Defs,
+  literal::{
+    LitType,
+    Literal,
+  },
+  name::Name,
+  prim::Op,
+  term::Term,
+  uses::Uses,
+};
+
+use sp_cid::Cid;
+
+use sp_std::{
+  collections::btree_map::BTreeMap,
+  fmt,
+  rc::Rc,
+  vec::Vec,
+};
+
+// The number of evaluation and comparison steps a single conversion check may
+// take before giving up
+pub const FUEL: u64 = 1_000_000;
+
+// Evaluation and comparison recurse into subterms, so their nesting is bounded
+// to give up before the stack overflows
+pub const MAX_DEPTH: u64 = 128;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ConvError {
+  OutOfFuel,
+  TooDeep,
+  // A fixpoint that isn't the expression of a recursive `let`, or the
+  // recursion variable of an open term
+  UnreadableFix,
+  UndefinedReference(Name, Cid),
+}
+
+impl fmt::Display for ConvError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::OutOfFuel => write!(f, "Conversion check ran out of fuel"),
+      Self::TooDeep => {
+        write!(f, "Conversion check nested deeper than {} levels", MAX_DEPTH)
+      }
+      Self::UnreadableFix => write!(f, "Unreadable fixpoint in type"),
+      Self::UndefinedReference(nam, cid) => {
+        write!(f, "Undefined reference {} ({})", nam, cid)
+      }
+    }
+  }
+}
+
+// Terms with shared subterms, so that closures can hold on to them without
+// copying. Bound variables are de Bruijn indices, while the free variables of
+// the checking context keep their levels. Annotations and the types of `let`
+// bindings are dropped, since they don't take part in reduction.
+pub enum Syn {
+  Var(u64),
+  Free(u64),
+  Lam(Rc<Syn>),
+  Slf(Rc<Syn>),
+  All(Uses, Rc<Syn>, Rc<Syn>),
+  App(Rc<Syn>, Rc<Syn>),
+  Dat(Rc<Syn>),
+  Cse(Rc<Syn>),
+  Ref(Name, Cid),
+  Let(bool, Rc<Syn>, Rc<Syn>),
+  Typ,
+  LTy(LitType),
+  Lit(Literal),
+  Opr(Op),
+}
+
+impl Syn {
+  // Convert a term, where `Term::Rec` refers to the definition `rec`
+  pub fn from_term(
+    term: &Term,
+    rec: &Option<(Name, Cid)>,
+  ) -> Result<Rc<Syn>, ConvError> {
+    let go = |term: &Term| Syn::from_term(term, rec);
+    let syn = match term {
+      Term::Var(_, _, idx) => Syn::Var(*idx),
+      Term::Rec(_) => match rec {
+        Some((nam, def)) => Syn::Ref(nam.clone(), *def),
+        None => return Err(ConvError::UnreadableFix),
+      },
+      Term::Lam(_, _, bod) => Syn::Lam(go(bod)?),
+      Term::Slf(_, _, bod) => Syn::Slf(go(bod)?),
+      Term::All(_, uses, _, dom_img) => {
+        Syn::All(*uses, go(&dom_img.0)?, go(&dom_img.1)?)
+      }
+      Term::App(_, fun_arg) => Syn::App(go(&fun_arg.0)?, go(&fun_arg.1)?),
+      Term::Dat(_, bod) => Syn::Dat(go(bod)?),
+      Term::Cse(_, bod) => Syn::Cse(go(bod)?),
+      Term::Ref(_, nam, def, _) => Syn::Ref(nam.clone(), *def),
+      Term::Let(_, rec, _, _, typ_exp_bod) => {
+        Syn::Let(*rec, go(&typ_exp_bod.1)?, go(&typ_exp_bod.2)?)
+      }
+      Term::Ann(_, typ_exp) => return go(&typ_exp.1),
+      Term::Typ(_) => Syn::Typ,
+      Term::LTy(_, lty) => Syn::LTy(*lty),
+      Term::Lit(_, lit) => Syn::Lit(lit.clone()),
+      Term::Opr(_, opr) => Syn::Opr(*opr),
+    };
+    Ok(Rc::new(syn))
+  }
+
+  // Read a graph `depth` binders deep. Variables whose binders are outside of
+  // the graph are free, and keep the level the checker gave them.
+  pub fn from_dag(
+    node: DAGPtr,
+    levels: &mut BTreeMap<*mut Var, u64>,
+    depth: u64,
+  ) -> Result<Rc<Syn>, ConvError> {
+    let syn = match node {
+      DAGPtr::Var(link) => {
+        let Var { dep, rec, .. } = unsafe { link.as_ref() };
+        match levels.get(&link.as_ptr()) {
+          Some(level) => Syn::Var(depth - level - 1),
+          None if *rec => return Err(ConvError::UnreadableFix),
+          None => Syn::Free(*dep),
+        }
+      }
+      DAGPtr::Lam(link) => unsafe {
+        let Lam { var, bod, .. } = &mut *link.as_ptr();
+        levels.insert(var, depth);
+        Syn::Lam(Syn::from_dag(*bod, levels, depth + 1)?)
+      },
+      DAGPtr::Slf(link) => unsafe {
+        let Slf { var, bod, .. } = &mut *link.as_ptr();
+        levels.insert(var, depth);
+        Syn::Slf(Syn::from_dag(*bod, levels, depth + 1)?)
+      },
+      DAGPtr::All(link) => unsafe {
+        let All { uses, dom, img, .. } = &mut *link.as_ptr();
+        let Lam { var, bod, .. } = &mut *img.as_ptr();
+        let dom = Syn::from_dag(*dom, levels, depth)?;
+        levels.insert(var, depth);
+        Syn::All(*uses, dom, Syn::from_dag(*bod, levels, depth + 1)?)
+      },
+      DAGPtr::App(link) => {
+        let App { fun, arg, .. } = unsafe { link.as_ref() };
+        let fun = Syn::from_dag(*fun, levels, depth)?;
+        Syn::App(fun, Syn::from_dag(*arg, levels, depth)?)
+      }
+      DAGPtr::Dat(link) => {
+        let Dat { bod, .. } = unsafe { link.as_ref() };
+        Syn::Dat(Syn::from_dag(*bod, levels, depth)?)
+      }
+      DAGPtr::Cse(link) => {
+        let Cse { bod, .. } = unsafe { link.as_ref() };
+        Syn::Cse(Syn::from_dag(*bod, levels, depth)?)
+      }
+      DAGPtr::Ann(link) => {
+        let Ann { exp, .. } = unsafe { link.as_ref() };
+        return Syn::from_dag(*exp, levels, depth);
+      }
+      DAGPtr::Let(link) => unsafe {
+        let Let { exp, bod, .. } = &mut *link.as_ptr();
+        let Lam { var, bod, .. } = &mut *bod.as_ptr();
+        let (rec, exp) = match exp {
+          DAGPtr::Fix(link) => {
+            let Fix { var, bod, .. } = &mut *link.as_ptr();
+            levels.insert(var, depth);
+            (true, Syn::from_dag(*bod, levels, depth + 1)?)
+          }
+          _ => (false, Syn::from_dag(*exp, levels, depth)?),
+        };
+        levels.insert(var, depth);
+        Syn::Let(rec, exp, Syn::from_dag(*bod, levels, depth + 1)?)
+      },
+      DAGPtr::Ref(link) => {
+        let Ref { nam, exp, .. } = unsafe { link.as_ref() };
+        Syn::Ref(nam.clone(), *exp)
+      }
+      DAGPtr::Typ(_) => Syn::Typ,
+      DAGPtr::LTy(link) => Syn::LTy(unsafe { link.as_ref().lty }),
+      DAGPtr::Lit(link) => Syn::Lit(unsafe { link.as_ref().lit.clone() }),
+      DAGPtr::Opr(link) => Syn::Opr(unsafe { link.as_ref().opr }),
+      DAGPtr::Fix(_) => return Err(ConvError::UnreadableFix),
+    };
+    Ok(Rc::new(syn))
+  }
+}
+
+struct Bind {
+  val: Value,
+  next: Env,
+}
+
+// The values of the bound variables in scope, innermost first
+#[derive(Clone, Default)]
+pub struct Env(Option<Rc<Bind>>);
+
+impl Env {
+  pub fn bind(&self, val: Value) -> Self {
+    Env(Some(Rc::new(Bind { val, next: self.clone() })))
+  }
+
+  pub fn get(&self, idx: u64) -> Option<&Value> {
+    let mut bind = self.0.as_ref()?;
+    for _ in 0..idx {
+      bind = bind.next.0.as_ref()?;
+    }
+    Some(&bind.val)
+  }
+}
+
+#[derive(Clone)]
+pub struct Closure {
+  bod: Rc<Syn>,
+  env: Env,
+}
+
+impl Closure {
+  fn new(bod: &Rc<Syn>, env: &Env) -> Self {
+    Closure { bod: bod.clone(), env: env.clone() }
+  }
+}
+
+// Weak head normal forms, except that definitions and recursive `let`
+// bindings in head position stay folded until they are forced
+#[derive(Clone)]
+pub enum Value {
+  Lam(Closure),
+  Slf(Closure),
+  All(Uses, Rc<Value>, Closure),
+  Dat(Rc<Value>),
+  Typ,
+  LTy(LitType),
+  Lit(Literal),
+  // A head applied to arguments, in application order
+  Neu(Head, Vec<Value>),
+}
+
+#[derive(Clone)]
+pub enum Head {
+  // A variable, by level
+  Var(u64),
+  Ref(Name, Cid),
+  // The expression of a recursive `let`, bound to itself when unfolded
+  Fix(Closure),
+  // A `case` of a value that is neither data nor an expandable literal
+  Cse(Rc<Value>),
+  // A primitive operation whose arguments aren't all literals
+  Opr(Op),
+  // Any other value that can't consume arguments, like a literal
+  Val(Rc<Value>),
+}
+
+pub struct Nbe<'a> {
+  defs: &'a Defs,
+  unfolded: BTreeMap<Cid, Rc<Syn>>,
+  fuel: u64,
+  depth: u64,
+}
+
+impl<'a> Nbe<'a> {
+  pub fn new(defs: &'a Defs, fuel: u64) -> Self {
+    Nbe { defs, unfolded: BTreeMap::new(), fuel, depth: 0 }
+  }
+
+  fn nested<A>(
+    &mut self,
+    f: impl FnOnce(&mut Self) -> Result<A, ConvError>,
+  ) -> Result<A, ConvError> {
+    if self.depth >= MAX_DEPTH {
+      return Err(ConvError::TooDeep);
+    }
+    self.depth += 1;
+    let res = f(self);
+    self.depth -= 1;
+    res
+  }
+
+  fn tick(&mut self) -> Result<(), ConvError> {
+    match self.fuel.checked_sub(1) {
+      Some(fuel) => {
+        self.fuel = fuel;
+        Ok(())
+      }
+      None => Err(ConvError::OutOfFuel),
+    }
+  }
+
+  pub fn eval(&mut self, syn: &Rc<Syn>, env: &Env) -> Result<Value, ConvError> {
+    self.tick()?;
+    self.nested(|this| this.eval_syn(syn, env))
+  }
+
+  fn eval_syn(&mut self, syn: &Rc<Syn>, env: &Env) -> Result<Value, ConvError> {
+    match &**syn {
+      // Indices are in scope by construction
+      Syn::Var(idx) => Ok(env.get(*idx).unwrap().clone()),
+      Syn::Free(lvl) => Ok(Value::Neu(Head::Var(*lvl), Vec::new())),
+      Syn::Lam(bod) => Ok(Value::Lam(Closure::new(bod, env))),
+      Syn::Slf(bod) => Ok(Value::Slf(Closure::new(bod, env))),
+      Syn::All(uses, dom, img) => {
+        let dom = self.eval(dom, env)?;
+        Ok(Value::All(*uses, Rc::new(dom), Closure::new(img, env)))
+      }
+      Syn::App(fun, arg) => {
+        let fun = self.eval(fun, env)?;
+        let arg = self.eval(arg, env)?;
+        self.apply(fun, arg)
+      }
+      Syn::Dat(bod) => Ok(Value::Dat(Rc::new(self.eval(bod, env)?))),
+      Syn::Cse(bod) => {
+        let val = self.eval(bod, env)?;
+        match self.force(val)? {
+          Value::Dat(bod) => Ok((*bod).clone()),
+          Value::Lit(lit) => match lit.clone().expand() {
+            Some(expand) => {
+              let expand = Syn::from_term(&expand, &None)?;
+              self.eval(&expand, &Env::default())
+            }
+            None => {
+              Ok(Value::Neu(Head::Cse(Rc::new(Value::Lit(lit))), Vec::new()))
+            }
+          },
+          val => Ok(Value::Neu(Head::Cse(Rc::new(val)), Vec::new())),
+        }
+      }
+      Syn::Ref(nam, def) => {
+        Ok(Value::Neu(Head::Ref(nam.clone(), *def), Vec::new()))
+      }
+      Syn::Let(false, exp, bod) => {
+        let exp = self.eval(exp, env)?;
+        self.eval(bod, &env.bind(exp))
+      }
+      Syn::Let(true, exp, bod) => {
+        let fix = Value::Neu(Head::Fix(Closure::new(exp, env)), Vec::new());
+        self.eval(bod, &env.bind(fix))
+      }
+      Syn::Typ => Ok(Value::Typ),
+      Syn::LTy(lty) => Ok(Value::LTy(*lty)),
+      Syn::Lit(lit) => Ok(Value::Lit(lit.clone())),
+      Syn::Opr(opr) => self.operate(*opr, Vec::new()),
+    }
+  }
+
+  fn inst(&mut self, clo: &Closure, arg: Value) -> Result<Value, ConvError> {
+    self.eval(&clo.bod, &clo.env.bind(arg))
+  }
+
+  pub fn apply(&mut self, fun: Value, arg: Value) -> Result<Value, ConvError> {
+    match fun {
+      Value::Lam(clo) => self.inst(&clo, arg),
+      Value::Neu(Head::Opr(opr), mut args) => {
+        args.push(arg);
+        self.operate(opr, args)
+      }
+      Value::Neu(head, mut args) => {
+        args.push(arg);
+        Ok(Value::Neu(head, args))
+      }
+      val => Ok(Value::Neu(Head::Val(Rc::new(val)), vec![arg])),
+    }
+  }
+
+  // Apply a primitive operation once it has all of its arguments, if they are
+  // all literals
+  fn operate(
+    &mut self,
+    opr: Op,
+    args: Vec<Value>,
+  ) -> Result<Value, ConvError> {
+    let arity = opr.arity() as usize;
+    if arity == 0 {
+      if let Some(res) = opr.apply0() {
+        return Ok(Value::Lit(res));
+      }
+    }
+    else if args.len() == arity {
+      let mut forced = Vec::new();
+      for arg in args {
+        forced.push(self.force(arg)?);
+      }
+      let lits: Vec<&Literal> = forced
+        .iter()
+        .filter_map(|arg| match arg {
+          Value::Lit(lit) => Some(lit),
+          _ => None,
+        })
+        .collect();
+      let res = match lits.as_slice() {
+        [x] if arity == 1 => opr.apply1(x),
+        [x, y] if arity == 2 => opr.apply2(x, y),
+        [x, y, z] if arity == 3 => opr.apply3(x, y, z),
+        _ => None,
+      };
+      return match res {
+        Some(res) => Ok(Value::Lit(res)),
+        None => Ok(Value::Neu(Head::Opr(opr), forced)),
+      };
+    }
+    Ok(Value::Neu(Head::Opr(opr), args))
+  }
+
+  // Unfold the definition or recursive `let` in head position, until there is
+  // none
+  pub fn force(&mut self, mut val: Value) -> Result<Value, ConvError> {
+    loop {
+      let (head, args) = match val {
+        Value::Neu(Head::Ref(nam, def), args) => {
+          let syn = self.unfold(nam, def)?;
+          (self.eval(&syn, &Env::default())?, args)
+        }
+        Value::Neu(Head::Fix(clo), args) => {
+          let fix = Value::Neu(Head::Fix(clo.clone()), Vec::new());
+          (self.inst(&clo, fix)?, args)
+        }
+        val => return Ok(val),
+      };
+      val = head;
+      for arg in args {
+        val = self.apply(val, arg)?;
+      }
+    }
+  }
+
+  fn unfold(&mut self, nam: Name, def: Cid) -> Result<Rc<Syn>, ConvError> {
+    if let Some(syn) = self.unfolded.get(&def) {
+      return Ok(syn.clone());
+    }
+    let term = match self.defs.defs.get(&def) {
+      Some(d) => &d.term,
+      None => return Err(ConvError::UndefinedReference(nam, def)),
+    };
+    let syn = Syn::from_term(term, &Some((nam, def)))?;
+    self.unfolded.insert(def, syn.clone());
+    Ok(syn)
+  }
+
+  // Decide whether two values are convertible, `lvl` binders deep
+  pub fn conv(
+    &mut self,
+    a: Value,
+    b: Value,
+    lvl: u64,
+  ) -> Result<bool, ConvError> {
+    self.tick()?;
+    self.nested(|this| this.conv_values(a, b, lvl))
+  }
+
+  fn conv_values(
+    &mut self,
+    a: Value,
+    b: Value,
+    lvl: u64,
+  ) -> Result<bool, ConvError> {
+    // The same definition applied to convertible arguments needs no unfolding
+    if let (
+      Value::Neu(Head::Ref(_, a_def), a_args),
+      Value::Neu(Head::Ref(_, b_def), b_args),
+    ) = (&a, &b)
+    {
+      if a_def == b_def
+        && a_args.len() == b_args.len()
+        && self.conv_args(a_args, b_args, lvl)?
+      {
+        return Ok(true);
+      }
+    }
+    let a = self.force(a)?;
+    let b = self.force(b)?;
+    match (a, b) {
+      (Value::Lam(a_clo), Value::Lam(b_clo))
+      | (Value::Slf(a_clo), Value::Slf(b_clo)) => {
+        self.conv_under(&a_clo, &b_clo, lvl)
+      }
+      (
+        Value::All(a_uses, a_dom, a_img),
+        Value::All(b_uses, b_dom, b_img),
+      ) => Ok(
+        a_uses == b_uses
+          && self.conv((*a_dom).clone(), (*b_dom).clone(), lvl)?
+          && self.conv_under(&a_img, &b_img, lvl)?,
+      ),
+      (Value::Dat(a_bod), Value::Dat(b_bod)) => {
+        self.conv((*a_bod).clone(), (*b_bod).clone(), lvl)
+      }
+      (Value::Typ, Value::Typ) => Ok(true),
+      (Value::LTy(a_lty), Value::LTy(b_lty)) => Ok(a_lty == b_lty),
+      (Value::Lit(a_lit), Value::Lit(b_lit)) => Ok(a_lit == b_lit),
+      (Value::Neu(a_head, a_args), Value::Neu(b_head, b_args)) => Ok(
+        a_args.len() == b_args.len()
+          && self.conv_heads(a_head, b_head, lvl)?
+          && self.conv_args(&a_args, &b_args, lvl)?,
+      ),
+      _ => Ok(false),
+    }
+  }
+
+  fn conv_under(
+    &mut self,
+    a: &Closure,
+    b: &Closure,
+    lvl: u64,
+  ) -> Result<bool, ConvError> {
+    let var = Value::Neu(Head::Var(lvl), Vec::new());
+    let a = self.inst(a, var.clone())?;
+    let b = self.inst(b, var)?;
+    self.conv(a, b, lvl + 1)
+  }
+
+  fn conv_heads(
+    &mut self,
+    a: Head,
+    b: Head,
+    lvl: u64,
+  ) -> Result<bool, ConvError> {
+    match (a, b) {
+      (Head::Var(a_lvl), Head::Var(b_lvl)) => Ok(a_lvl == b_lvl),
+      (Head::Opr(a_opr), Head::Opr(b_opr)) => Ok(a_opr == b_opr),
+      (Head::Cse(a_val), Head::Cse(b_val))
+      | (Head::Val(a_val), Head::Val(b_val)) => {
+        self.conv((*a_val).clone(), (*b_val).clone(), lvl)
+      }
+      _ => Ok(false),
+    }
+  }
+
+  fn conv_args(
+    &mut self,
+    a_args: &[Value],
+    b_args: &[Value],
+    lvl: u64,
+  ) -> Result<bool, ConvError> {
+    for (a, b) in a_args.iter().zip(b_args) {
+      if !self.conv(a.clone(), b.clone(), lvl)? {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+}
+
+// Decide whether two graphs are convertible, under `dep` variables of the
+// checking context
+pub fn equal(
+  defs: &Defs,
+  a: &DAG,
+  b: &DAG,
+  dep: u64,
+  fuel: u64,
+) -> Result<bool, ConvError> {
+  let a = Syn::from_dag(a.head, &mut BTreeMap::new(), dep)?;
+  let b = Syn::from_dag(b.head, &mut BTreeMap::new(), dep)?;
+  let mut nbe = Nbe::new(defs, fuel);
+  let a = nbe.eval(&a, &Env::default())?;
+  let b = nbe.eval(&b, &Env::default())?;
+  nbe.conv(a, b, dep)
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    arena,
+    check::{
+      check_def,
+      equal_dag,
+      Conversion,
+    },
+    eval::test::parse_defs,
+    parse::term::parse,
+    term::tests::{
+      affine,
+      test_defs,
+    },
+  };
+  use quickcheck::TestResult;
+
+  const SRC: &str = "
+    type Bool { True, False }
+    type Nat { Z, S Nat }
+    type List (A: Type) { Nil, Cons A (List A) }
+    type Equal (A: Type) (a: A): ∀ (b: A) -> Type { Refl: Equal A a a }
+    def Bool.not (b: Bool): Bool = (case b) (λ _ => Bool) Bool.False Bool.True
+    def Bool.and (a: Bool) (b: Bool): Bool =
+      (case a) (λ _ => Bool) b Bool.False
+    def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+      #Nat.mul x (fact x'))
+    def not_true: Equal Bool (Bool.not Bool.True) Bool.False =
+      Equal.Refl Bool Bool.False
+    def fact_three: Equal #Nat (fact 3) 6 = Equal.Refl #Nat 6
+    def wrong: Equal Bool Bool.True Bool.False = Equal.Refl Bool Bool.True
+  ";
+
+  fn nbe_equal(
+    defs: &Defs,
+    a: &Term,
+    b: &Term,
+    fuel: u64,
+  ) -> Result<bool, ConvError> {
+    let mut a = DAG::from_term(a);
+    let mut b = DAG::from_term(b);
+    let res = equal(defs, &a, &b, 0, fuel);
+    a.free();
+    b.free();
+    res
+  }
+
+  fn dag_equal(defs: &Defs, a: &Term, b: &Term) -> bool {
+    arena::scope(|| {
+      let mut a = DAG::from_term(a);
+      let mut b = DAG::from_term(b);
+      let res = equal_dag(defs, &mut a, &mut b, 0, false, &mut None);
+      a.free();
+      b.free();
+      res
+    })
+  }
+
+  #[test]
+  fn agrees_with_dag() {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    let cases = [
+      ("λ x => x", "λ y => (λ z => z) y", true),
+      ("Bool.not (Bool.not Bool.True)", "Bool.True", true),
+      ("Bool.True", "Bool.False", false),
+      ("Bool.and Bool.False", "λ b => Bool.False", true),
+      ("List Bool", "List Bool", true),
+      ("List Bool", "List Nat", false),
+      (
+        "∀ (x: Bool) -> Equal Bool x x",
+        "∀ (y: Bool) -> Equal Bool y y",
+        true,
+      ),
+      ("#Nat.add 1 2", "3", true),
+      ("λ n => #Nat.add n 1", "λ m => #Nat.add m 2", false),
+      ("fact 4", "24", true),
+    ];
+    for (a, b, eq) in cases.iter() {
+      let (_, a_term) = parse(a, defs.clone()).unwrap();
+      let (_, b_term) = parse(b, defs.clone()).unwrap();
+      let nbe = nbe_equal(&defs, &a_term, &b_term, FUEL);
+      assert_eq!(nbe, Ok(*eq), "{} = {}", a, b);
+      assert_eq!(dag_equal(&defs, &a_term, &b_term), *eq, "{} = {}", a, b);
+    }
+  }
+
+  #[test]
+  fn gives_up_on_fuel() {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    let (_, a) = parse("fact 10", defs.clone()).unwrap();
+    let (_, b) = parse("3628800", defs.clone()).unwrap();
+    assert_eq!(nbe_equal(&defs, &a, &b, 10), Err(ConvError::OutOfFuel));
+    assert_eq!(nbe_equal(&defs, &a, &b, FUEL), Ok(true));
+  }
+
+  // Checking a definition must succeed or fail the same way whichever
+  // conversion is used, with or without hash-consing
+  #[test]
+  fn checks_like_dag() {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    let defs = Rc::new(defs);
+    let check = |nam: &str, conversion, hash_cons| {
+      check_def(defs.clone(), nam, false, conversion, hash_cons).is_ok()
+    };
+    for nam in defs.names.keys() {
+      let nam = nam.to_string();
+      let dag = check(&nam, Conversion::Dag, false);
+      let nbe = check(&nam, Conversion::Nbe, false);
+      let shared = check(&nam, Conversion::Dag, true);
+      assert_eq!(nbe, dag, "{}", nam);
+      assert_eq!(shared, dag, "{}", nam);
+    }
+    assert!(check("fact_three", Conversion::Nbe, false));
+    assert!(!check("wrong", Conversion::Nbe, false));
+  }
+
+  // The graph reducer's normal forms are trusted on affine terms, see
+  // `eval::reference`, so a term must be convertible with its normal form, and
+  // terms with the same normal form with each other
+  #[quickcheck]
+  fn conversion_matches_norm(x: Term, y: Term) -> TestResult {
+    if !affine(&x, &mut vec![]) || !affine(&y, &mut vec![]) {
+      return TestResult::discard();
+    }
+    let defs = test_defs();
+    let norm = |term: &Term| {
+      let mut dag = DAG::from_term(term);
+      dag.norm(&defs, false);
+      let term = dag.to_term(false);
+      dag.free();
+      term
+    };
+    let (x_norm, y_norm) = (norm(&x), norm(&y));
+    match (
+      nbe_equal(&defs, &x, &x_norm, FUEL),
+      nbe_equal(&defs, &x, &y, FUEL),
+    ) {
+      (Ok(norm_eq), Ok(eq)) => {
+        TestResult::from_bool(norm_eq && (x_norm != y_norm || eq))
+      }
+      _ => TestResult::discard(),
+    }
+  }
+}
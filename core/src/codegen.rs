@@ -0,0 +1,256 @@
+pub mod c;
+pub mod js;
+
+use sp_cid::Cid;
+
+use alloc::string::{
+  String,
+  ToString,
+};
+use sp_std::{
+  boxed::Box,
+  collections::btree_set::BTreeSet,
+  fmt,
+  vec::Vec,
+};
+
+use crate::{
+  check::{
+    check_cases,
+    Cases,
+    Conversion,
+  },
+  defs::Defs,
+  literal::{
+    LitType,
+    Literal,
+  },
+  name::Name,
+  package::Index,
+  prim::Op,
+  term::Term,
+};
+
+// A term with all of its type level structure removed. Annotations, `data`
+// and types are dropped, leaving an untyped lambda calculus with literals and
+// primitive operations, which is what the backends compile. A `case` keeps the
+// literal type it eliminates, if any, since several literal types can share a
+// representation in the compiled code.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Erased {
+  Var(Name, u64),
+  Lam(Name, Box<Erased>),
+  App(Box<(Erased, Erased)>),
+  Ref(Name, Cid),
+  Rec,
+  Let(bool, Name, Box<(Erased, Erased)>),
+  Cse(Option<LitType>, Box<Erased>),
+  Lit(Literal),
+  Opr(Op),
+  // An irrelevant (type level) argument. It is never inspected at runtime
+  Irr,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum CodegenError {
+  UndefinedReference(Name, Cid),
+  FreeVariable(Name, u64),
+  UnknownEntry(Name),
+  IllTyped(Name, String),
+}
+
+impl fmt::Display for CodegenError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::UndefinedReference(nam, cid) => {
+        write!(f, "Undefined reference {} ({})", nam, cid)
+      }
+      Self::FreeVariable(nam, idx) => {
+        write!(f, "Free variable {}^{} in compiled term", nam, idx)
+      }
+      Self::UnknownEntry(nam) => {
+        write!(f, "Entry point {} is not defined in the package", nam)
+      }
+      Self::IllTyped(nam, err) => {
+        write!(f, "Definition {} does not type check: {}", nam, err)
+      }
+    }
+  }
+}
+
+impl Erased {
+  pub fn from_term(term: &Term, cases: &Cases) -> Self {
+    match term {
+      Term::Var(_, nam, idx) => Self::Var(nam.clone(), *idx),
+      Term::Lam(_, nam, bod) => {
+        Self::Lam(nam.clone(), Box::new(Self::from_term(bod, cases)))
+      }
+      Term::App(_, fun_arg) => {
+        let (fun, arg) = &**fun_arg;
+        let fun = Self::from_term(fun, cases);
+        let arg = Self::from_term(arg, cases);
+        Self::App(Box::new((fun, arg)))
+      }
+      Term::Ref(_, nam, def, _) => Self::Ref(nam.clone(), *def),
+      Term::Rec(_) => Self::Rec,
+      Term::Let(_, rec, _, nam, typ_exp_bod) => {
+        let (_, exp, bod) = &**typ_exp_bod;
+        let exp = Self::from_term(exp, cases);
+        let bod = Self::from_term(bod, cases);
+        Self::Let(*rec, nam.clone(), Box::new((exp, bod)))
+      }
+      Term::Ann(_, typ_exp) => Self::from_term(&typ_exp.1, cases),
+      Term::Dat(_, bod) => Self::from_term(bod, cases),
+      Term::Cse(_, bod) => {
+        let lty = cases.get(&(&**bod as *const Term)).copied();
+        Self::Cse(lty, Box::new(Self::from_term(bod, cases)))
+      }
+      Term::Lit(_, lit) => Self::Lit(lit.clone()),
+      Term::Opr(_, opr) => Self::Opr(*opr),
+      Term::Typ(_) | Term::All(..) | Term::Slf(..) | Term::LTy(..) => {
+        Self::Irr
+      }
+    }
+  }
+
+  // Collects the definitions this term refers to, in order of appearance
+  pub fn refs(&self, acc: &mut Vec<(Name, Cid)>) {
+    match self {
+      Self::Ref(nam, cid) => acc.push((nam.clone(), *cid)),
+      Self::Lam(_, bod) | Self::Cse(_, bod) => bod.refs(acc),
+      Self::App(fun_arg) => {
+        fun_arg.0.refs(acc);
+        fun_arg.1.refs(acc);
+      }
+      Self::Let(_, _, exp_bod) => {
+        exp_bod.0.refs(acc);
+        exp_bod.1.refs(acc);
+      }
+      _ => (),
+    }
+  }
+}
+
+// The erased definitions reachable from a package's index. Each one is checked
+// again to find the literal types of its `case`s.
+#[derive(Clone, Debug)]
+pub struct Program {
+  pub defs: Vec<(Name, Cid, Erased)>,
+  pub exports: Vec<(Name, Cid)>,
+}
+
+impl Program {
+  pub fn new(
+    defs: &Defs,
+    index: &Index,
+    conversion: Conversion,
+  ) -> Result<Self, CodegenError> {
+    let mut seen = BTreeSet::new();
+    let mut todo: Vec<(Name, Cid)> = index.0.iter().rev().cloned().collect();
+    let mut erased = Vec::new();
+    while let Some((nam, cid)) = todo.pop() {
+      if !seen.insert(cid) {
+        continue;
+      }
+      let def = defs
+        .defs
+        .get(&cid)
+        .ok_or_else(|| CodegenError::UndefinedReference(nam.clone(), cid))?;
+      let cases = check_cases(defs, &nam, def, conversion)
+        .map_err(|e| CodegenError::IllTyped(nam.clone(), e.to_string()))?;
+      let term = Erased::from_term(&def.term, &cases);
+      let mut refs = Vec::new();
+      term.refs(&mut refs);
+      todo.extend(refs.into_iter().rev());
+      erased.push((nam, cid, term));
+    }
+    Ok(Program { defs: erased, exports: index.0.clone() })
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use sp_ipld::Ipld;
+
+  use crate::parse::{
+    package::parse_defs,
+    span::Span,
+    term::input_cid,
+  };
+
+  pub fn program(src: &str) -> Program {
+    let (_, (defs, index)) =
+      parse_defs(input_cid(src), Defs::new())(Span::new(src)).unwrap();
+    Program::new(&defs, &index, Conversion::Nbe).unwrap()
+  }
+
+  // Every primitive operation, found by decoding their IPLD tags
+  pub fn every_op() -> Vec<Op> {
+    let mut ops = Vec::new();
+    for typ in 0..=16 {
+      for tag in 0..64 {
+        let ipld = Ipld::List(vec![Ipld::Integer(typ), Ipld::Integer(tag)]);
+        if let Ok(op) = Op::from_ipld(&ipld) {
+          ops.push(op);
+        }
+      }
+    }
+    ops
+  }
+
+  #[test]
+  fn erase_types() {
+    let prog = program(
+      "def id (A: Type) (x: A): A = x
+       def two: #Nat = id #Nat 2",
+    );
+    assert_eq!(prog.exports.len(), 2);
+    let (_, _, two) = &prog.defs[1];
+    match two {
+      Erased::App(fun_arg) => {
+        assert_eq!(fun_arg.1, Erased::Lit(Literal::Nat(2u64.into())));
+        match &fun_arg.0 {
+          Erased::App(fun_arg) => assert_eq!(fun_arg.1, Erased::Irr),
+          x => panic!("unexpected {:?}", x),
+        }
+      }
+      x => panic!("unexpected {:?}", x),
+    }
+  }
+
+  #[test]
+  fn keeps_case_literal_types() {
+    let prog = program(
+      "def sign (x: #Int): #Bool = (case x) (λ _ => #Bool) (λ s n => s)",
+    );
+    match &prog.defs[0].2 {
+      Erased::Lam(_, bod) => match &**bod {
+        Erased::App(fun_arg) => match &fun_arg.0 {
+          Erased::App(fun_arg) => {
+            assert!(matches!(fun_arg.0, Erased::Cse(Some(LitType::Int), _)))
+          }
+          x => panic!("unexpected {:?}", x),
+        },
+        x => panic!("unexpected {:?}", x),
+      },
+      x => panic!("unexpected {:?}", x),
+    }
+    // `#Char` has no induction principle, so `case` on it doesn't check
+    let src = "def c: #Bool = (case 'a') (λ _ => #Bool) #Bool.true";
+    let (_, (defs, index)) =
+      parse_defs(input_cid(src), Defs::new())(Span::new(src)).unwrap();
+    assert!(matches!(
+      Program::new(&defs, &index, Conversion::Nbe),
+      Err(CodegenError::IllTyped(..))
+    ));
+  }
+
+  #[test]
+  fn every_op_found() {
+    let ops = every_op();
+    assert!(ops.contains(&Op::Nat(crate::prim::nat::NatOp::Mod)));
+    assert!(ops.contains(&Op::I64(crate::prim::i64::I64Op::ToBytes)));
+    assert!(ops.contains(&Op::Text(crate::prim::text::TextOp::ToBytes)));
+  }
+}
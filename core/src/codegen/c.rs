@@ -0,0 +1,612 @@
+use sp_cid::Cid;
+
+use sp_std::{
+  collections::{
+    btree_map::BTreeMap,
+    btree_set::BTreeSet,
+  },
+  vec::Vec,
+};
+
+use alloc::string::{
+  String,
+  ToString,
+};
+
+use crate::{
+  codegen::{
+    CodegenError,
+    Erased,
+    Program,
+  },
+  literal::Literal,
+};
+
+pub const RUNTIME: &str = include_str!("c/runtime.c");
+
+pub const HEADER: &str = "/* Generated by yatima. Do not edit. */\n";
+
+// Definitions become globals named `d<n>_<name>`, with everything outside of
+// `[A-Za-z0-9_]` in the name escaped as `_<hex codepoint>_`. The number keeps
+// globals unique even when escaped names collide.
+pub fn mangle(nam: &str) -> String {
+  let mut res = String::new();
+  for c in nam.chars() {
+    if c.is_ascii_alphanumeric() || c == '_' {
+      res.push(c);
+    }
+    else {
+      res.push_str(&format!("_{:x}_", c as u32));
+    }
+  }
+  res
+}
+
+// C string literals, with every byte outside of printable ASCII written as an
+// octal escape, which unlike `\x` never runs into the following character
+pub fn string(bytes: &[u8]) -> String {
+  let mut res = String::from("\"");
+  for b in bytes {
+    match b {
+      b'"' => res.push_str("\\\""),
+      b'\\' => res.push_str("\\\\"),
+      b'?' => res.push_str("\\?"),
+      0x20..=0x7e => res.push(*b as char),
+      _ => res.push_str(&format!("\\{:03o}", b)),
+    }
+  }
+  res.push('"');
+  res
+}
+
+fn fixed(tag: &str, x: u64) -> String {
+  format!("yt_fixed({}, UINT64_C({}))", tag, x)
+}
+
+pub fn literal(lit: &Literal) -> String {
+  match lit {
+    Literal::Nat(x) => format!("yt_nat_lit(\"{}\")", x),
+    Literal::Int(x) => format!("yt_int_lit(\"{}\")", x),
+    Literal::Bits(xs) => {
+      let xs: String = xs.iter().map(|x| if *x { '1' } else { '0' }).collect();
+      format!("yt_bits_lit(\"{}\")", xs)
+    }
+    Literal::Bytes(xs) => {
+      format!("yt_bytes_lit({}, {})", xs.len(), string(xs))
+    }
+    Literal::Text(x) => {
+      let x = x.to_string();
+      format!("yt_text_lit({}, {})", x.len(), string(x.as_bytes()))
+    }
+    Literal::Char(x) => format!("yt_char(0x{:x})", *x as u32),
+    Literal::Bool(x) => format!("yt_bool({})", *x as u8),
+    Literal::U8(x) => fixed("YT_U8", *x as u64),
+    Literal::U16(x) => fixed("YT_U16", *x as u64),
+    Literal::U32(x) => fixed("YT_U32", *x as u64),
+    Literal::U64(x) => fixed("YT_U64", *x),
+    Literal::I8(x) => fixed("YT_I8", *x as i64 as u64),
+    Literal::I16(x) => fixed("YT_I16", *x as i64 as u64),
+    Literal::I32(x) => fixed("YT_I32", *x as i64 as u64),
+    Literal::I64(x) => fixed("YT_I64", *x as u64),
+    Literal::U128(x) => format!("yt_num_lit(YT_U128, \"{}\")", x),
+    Literal::I128(x) => format!("yt_num_lit(YT_I128, \"{}\")", x),
+  }
+}
+
+// The de Bruijn levels of the variables bound outside of `term`, where `dep`
+// is the number of binders around it
+fn free_levels(term: &Erased, dep: u64, acc: &mut BTreeSet<u64>) {
+  match term {
+    Erased::Var(_, idx) => {
+      if *idx < dep {
+        acc.insert(dep - 1 - idx);
+      }
+    }
+    Erased::Lam(_, bod) => free_levels(bod, dep + 1, acc),
+    Erased::App(fun_arg) => {
+      free_levels(&fun_arg.0, dep, acc);
+      free_levels(&fun_arg.1, dep, acc);
+    }
+    Erased::Let(rec, _, exp_bod) => {
+      free_levels(&exp_bod.0, if *rec { dep + 1 } else { dep }, acc);
+      free_levels(&exp_bod.1, dep + 1, acc);
+    }
+    Erased::Cse(_, bod) => free_levels(bod, dep, acc),
+    _ => (),
+  }
+}
+
+// Maps the levels of the variables in scope to the C expressions holding them
+type Frame = BTreeMap<u64, String>;
+
+struct Emitter<'a> {
+  idents: &'a BTreeMap<Cid, String>,
+  this: String,
+  consts: Vec<(String, String)>,
+  const_idents: BTreeMap<String, String>,
+  protos: Vec<String>,
+  funs: Vec<String>,
+}
+
+impl<'a> Emitter<'a> {
+  // Literals and primitives are allocated once, when the program starts
+  fn constant(&mut self, init: String) -> String {
+    if let Some(ident) = self.const_idents.get(&init) {
+      return format!("yt_dup({})", ident);
+    }
+    let ident = format!("k{}", self.consts.len());
+    self.const_idents.insert(init.clone(), ident.clone());
+    self.consts.push((ident.clone(), init));
+    format!("yt_dup({})", ident)
+  }
+
+  // The captured variables of a closure or thunk over `term`, in the order of
+  // its environment, with the expressions that capture them from `frame`
+  fn captures(
+    &self,
+    term: &Erased,
+    inner: u64,
+    outer: u64,
+    frame: &Frame,
+  ) -> (Vec<u64>, Vec<String>) {
+    let mut levels = BTreeSet::new();
+    free_levels(term, inner, &mut levels);
+    let levels: Vec<u64> = levels.into_iter().filter(|l| *l < outer).collect();
+    let exprs =
+      levels.iter().map(|l| format!("yt_dup({})", frame[l])).collect();
+    (levels, exprs)
+  }
+
+  // Reserves a prototype, so that functions are numbered in the order they
+  // appear in the source, while their definitions follow their dependencies
+  fn reserve(&mut self, prefix: &str) -> (usize, String) {
+    let slot = self.protos.len();
+    self.protos.push(String::new());
+    (slot, format!("{}{}", prefix, slot))
+  }
+
+  fn function(
+    &mut self,
+    slot: usize,
+    name: &str,
+    sig: &str,
+    env: bool,
+    body: String,
+  ) {
+    let env = if env { "Val *env = self->u.clo.env;" } else { "(void)self;" };
+    self.protos[slot] = format!("static Val {}({});\n", name, sig);
+    self.funs.push(format!(
+      "static Val {}({}) {{\n  {}\n{}}}\n",
+      name, sig, env, body
+    ));
+  }
+
+  fn lam(
+    &mut self,
+    bod: &Erased,
+    dep: u64,
+    frame: &Frame,
+  ) -> Result<String, CodegenError> {
+    let (slot, name) = self.reserve("lam");
+    let (levels, caps) = self.captures(bod, dep + 1, dep, frame);
+    let mut inner = Frame::new();
+    for (i, l) in levels.iter().enumerate() {
+      inner.insert(*l, format!("env[{}]", i));
+    }
+    inner.insert(dep, "x".to_string());
+    let bod = self.term(bod, dep + 1, &inner)?;
+    let body = format!("  Val r = {};\n  yt_drop(x);\n  return r;\n", bod);
+    self.function(slot, &name, "Val self, Val x", !caps.is_empty(), body);
+    Ok(closure("yt_closure", &name, caps))
+  }
+
+  // A suspended computation. Recursive thunks find themselves in `env[0]`
+  fn thunk(
+    &mut self,
+    term: &Erased,
+    dep: u64,
+    frame: &Frame,
+    rec: bool,
+  ) -> Result<String, CodegenError> {
+    let (slot, name) = self.reserve("thk");
+    let inner_dep = if rec { dep + 1 } else { dep };
+    let (levels, caps) = self.captures(term, inner_dep, dep, frame);
+    let offset = if rec { 1 } else { 0 };
+    let mut inner = Frame::new();
+    for (i, l) in levels.iter().enumerate() {
+      inner.insert(*l, format!("env[{}]", i + offset));
+    }
+    if rec {
+      inner.insert(dep, "env[0]".to_string());
+    }
+    let mut used = BTreeSet::new();
+    free_levels(term, inner_dep, &mut used);
+    let env = used.contains(&dep) || !caps.is_empty();
+    let term = self.term(term, inner_dep, &inner)?;
+    let body = format!("  return {};\n", term);
+    self.function(slot, &name, "Val self", env, body);
+    let make = if rec { "yt_letrec" } else { "yt_thunk" };
+    Ok(closure(make, &name, caps))
+  }
+
+  fn term(
+    &mut self,
+    term: &Erased,
+    dep: u64,
+    frame: &Frame,
+  ) -> Result<String, CodegenError> {
+    match term {
+      Erased::Var(nam, idx) => {
+        if *idx >= dep {
+          Err(CodegenError::FreeVariable(nam.clone(), *idx))
+        }
+        else {
+          Ok(format!("yt_dup({})", frame[&(dep - 1 - idx)]))
+        }
+      }
+      Erased::Lam(_, bod) => self.lam(bod, dep, frame),
+      Erased::App(fun_arg) => {
+        let (fun, arg) = &**fun_arg;
+        let fun = self.term(fun, dep, frame)?;
+        let arg = self.arg(arg, dep, frame)?;
+        Ok(format!("yt_apply({}, {})", fun, arg))
+      }
+      Erased::Ref(nam, cid) => match self.idents.get(cid) {
+        Some(ident) => Ok(format!("yt_dup({})", ident)),
+        None => Err(CodegenError::UndefinedReference(nam.clone(), *cid)),
+      },
+      Erased::Rec => Ok(format!("yt_dup({})", self.this)),
+      // A let is the application of its body to the bound expression
+      Erased::Let(rec, _, exp_bod) => {
+        let (exp, bod) = &**exp_bod;
+        let fun = self.lam(bod, dep, frame)?;
+        let arg = if *rec {
+          self.thunk(exp, dep, frame, true)?
+        }
+        else {
+          self.arg(exp, dep, frame)?
+        };
+        Ok(format!("yt_apply({}, {})", fun, arg))
+      }
+      Erased::Cse(_, bod) => {
+        Ok(format!("yt_case({})", self.term(bod, dep, frame)?))
+      }
+      Erased::Lit(lit) => Ok(self.constant(literal(lit))),
+      Erased::Opr(opr) => {
+        let sym = opr.symbol();
+        Ok(self.constant(format!("yt_prim({})", string(sym.as_bytes()))))
+      }
+      Erased::Irr => Ok("yt_irr()".to_string()),
+    }
+  }
+
+  // Arguments are passed by need, so anything that could do work when
+  // evaluated is delayed in a thunk
+  fn arg(
+    &mut self,
+    term: &Erased,
+    dep: u64,
+    frame: &Frame,
+  ) -> Result<String, CodegenError> {
+    match term {
+      Erased::App(..) | Erased::Let(..) | Erased::Cse(..) => {
+        self.thunk(term, dep, frame, false)
+      }
+      _ => self.term(term, dep, frame),
+    }
+  }
+
+  // Definitions are initialized in order, so the ones that refer to other
+  // definitions are delayed until they are first used
+  fn top(&mut self, term: &Erased) -> Result<String, CodegenError> {
+    match term {
+      Erased::Lam(..) | Erased::Lit(..) | Erased::Opr(..) | Erased::Irr => {
+        self.term(term, 0, &Frame::new())
+      }
+      _ => self.thunk(term, 0, &Frame::new(), false),
+    }
+  }
+}
+
+fn closure(make: &str, name: &str, caps: Vec<String>) -> String {
+  let mut res = format!("{}({}, {}", make, name, caps.len());
+  for cap in caps {
+    res.push_str(", ");
+    res.push_str(&cap);
+  }
+  res.push(')');
+  res
+}
+
+// The globals, functions and initializer of a program, without the runtime
+pub fn emit_defs(program: &Program) -> Result<String, CodegenError> {
+  let mut idents = BTreeMap::new();
+  for (i, (nam, cid, _)) in program.defs.iter().enumerate() {
+    idents.insert(*cid, format!("d{}_{}", i, mangle(nam)));
+  }
+  let mut emitter = Emitter {
+    idents: &idents,
+    this: String::new(),
+    consts: Vec::new(),
+    const_idents: BTreeMap::new(),
+    protos: Vec::new(),
+    funs: Vec::new(),
+  };
+  let mut inits = Vec::new();
+  for (_, cid, term) in &program.defs {
+    emitter.this = idents[cid].clone();
+    inits.push((idents[cid].clone(), emitter.top(term)?));
+  }
+  let mut res = String::new();
+  for (ident, _) in &emitter.consts {
+    res.push_str(&format!("static Val {};\n", ident));
+  }
+  for (ident, _) in &inits {
+    res.push_str(&format!("static Val {};\n", ident));
+  }
+  res.push('\n');
+  for proto in &emitter.protos {
+    res.push_str(proto);
+  }
+  for fun in &emitter.funs {
+    res.push('\n');
+    res.push_str(fun);
+  }
+  res.push_str("\nstatic void yt_init(void) {\n");
+  for (ident, init) in emitter.consts.iter().chain(inits.iter()) {
+    res.push_str(&format!("  {} = {};\n", ident, init));
+  }
+  res.push_str("}\n");
+  Ok(res)
+}
+
+// Compiles a program to a single C file, whose `main` evaluates the `entry`
+// definition and prints its value as a Yatima literal:
+//
+//   yatima compile --target c -o fact.c fact.ya
+//   cc -O2 -o fact fact.c && ./fact
+pub fn emit(program: &Program, entry: &str) -> Result<String, CodegenError> {
+  let cid = program
+    .exports
+    .iter()
+    .find(|(nam, _)| &**nam == entry)
+    .map(|(_, cid)| *cid)
+    .ok_or_else(|| CodegenError::UnknownEntry(entry.into()))?;
+  let index = program.defs.iter().position(|(_, c, _)| *c == cid).unwrap();
+  let ident = format!("d{}_{}", index, mangle(&program.defs[index].0));
+  Ok(format!(
+    "{}\n{}\n{}\nint main(void) {{\n  Val r;\n  yt_init();\n  r = \
+     yt_force(yt_dup({}));\n  yt_print(stdout, r);\n  putchar('\\n');\n  \
+     yt_drop(r);\n  return 0;\n}}\n",
+    HEADER,
+    RUNTIME,
+    emit_defs(program)?,
+    ident
+  ))
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    codegen::tests::{
+      every_op,
+      program,
+    },
+    prim::Op,
+  };
+  use ropey::Rope;
+  use std::{
+    fs,
+    process::Command,
+  };
+
+  #[test]
+  fn mangle_names() {
+    assert_eq!(mangle("fact"), "fact");
+    assert_eq!(mangle("Nat.add"), "Nat_2e_add");
+    assert_eq!(mangle("x'"), "x_27_");
+    assert_eq!(mangle("is_zero"), "is_zero");
+  }
+
+  #[test]
+  fn literals() {
+    assert_eq!(literal(&Literal::Nat(10u64.into())), "yt_nat_lit(\"10\")");
+    assert_eq!(literal(&Literal::Int((-3i64).into())), "yt_int_lit(\"-3\")");
+    assert_eq!(
+      literal(&Literal::Bits(vec![true, false])),
+      "yt_bits_lit(\"10\")"
+    );
+    assert_eq!(
+      literal(&Literal::Bytes(vec![0, 255])),
+      "yt_bytes_lit(2, \"\\000\\377\")"
+    );
+    assert_eq!(
+      literal(&Literal::Text(Rope::from_str("a\"λ\n"))),
+      "yt_text_lit(5, \"a\\\"\\316\\273\\012\")"
+    );
+    assert_eq!(literal(&Literal::Char('λ')), "yt_char(0x3bb)");
+    assert_eq!(literal(&Literal::Bool(true)), "yt_bool(1)");
+    assert_eq!(literal(&Literal::U8(7)), "yt_fixed(YT_U8, UINT64_C(7))");
+    assert_eq!(
+      literal(&Literal::I8(-1)),
+      "yt_fixed(YT_I8, UINT64_C(18446744073709551615))"
+    );
+  }
+
+  #[test]
+  fn golden_fact() {
+    let prog = program(
+      "def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+       #Nat.mul x (fact x'))",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "static Val k0;\n\
+       static Val k1;\n\
+       static Val d0_fact;\n\
+       \n\
+       static Val lam0(Val self, Val x);\n\
+       static Val lam1(Val self, Val x);\n\
+       static Val lam2(Val self, Val x);\n\
+       static Val thk3(Val self);\n\
+       \n\
+       static Val lam1(Val self, Val x) {\n  \
+         (void)self;\n  \
+         Val r = yt_irr();\n  \
+         yt_drop(x);\n  \
+         return r;\n\
+       }\n\
+       \n\
+       static Val thk3(Val self) {\n  \
+         Val *env = self->u.clo.env;\n  \
+         return yt_apply(yt_dup(d0_fact), yt_dup(env[0]));\n\
+       }\n\
+       \n\
+       static Val lam2(Val self, Val x) {\n  \
+         Val *env = self->u.clo.env;\n  \
+         Val r = yt_apply(yt_apply(yt_dup(k1), yt_dup(env[0])), \
+       yt_thunk(thk3, 1, yt_dup(x)));\n  \
+         yt_drop(x);\n  \
+         return r;\n\
+       }\n\
+       \n\
+       static Val lam0(Val self, Val x) {\n  \
+         (void)self;\n  \
+         Val r = yt_apply(yt_apply(yt_apply(yt_case(yt_dup(x)), \
+       yt_closure(lam1, 0)), yt_dup(k0)), yt_closure(lam2, 1, yt_dup(x)));\n  \
+         yt_drop(x);\n  \
+         return r;\n\
+       }\n\
+       \n\
+       static void yt_init(void) {\n  \
+         k0 = yt_nat_lit(\"1\");\n  \
+         k1 = yt_prim(\"#Nat.mul\");\n  \
+         d0_fact = yt_closure(lam0, 0);\n\
+       }\n"
+    );
+  }
+
+  #[test]
+  fn golden_letrec() {
+    let prog = program(
+      "def f: #Bool = letrec g: #Bool = #Bool.not g; #Bool.true",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "static Val k0;\n\
+       static Val k1;\n\
+       static Val d0_f;\n\
+       \n\
+       static Val thk0(Val self);\n\
+       static Val lam1(Val self, Val x);\n\
+       static Val thk2(Val self);\n\
+       \n\
+       static Val lam1(Val self, Val x) {\n  \
+         (void)self;\n  \
+         Val r = yt_dup(k0);\n  \
+         yt_drop(x);\n  \
+         return r;\n\
+       }\n\
+       \n\
+       static Val thk2(Val self) {\n  \
+         Val *env = self->u.clo.env;\n  \
+         return yt_apply(yt_dup(k1), yt_dup(env[0]));\n\
+       }\n\
+       \n\
+       static Val thk0(Val self) {\n  \
+         (void)self;\n  \
+         return yt_apply(yt_closure(lam1, 0), yt_letrec(thk2, 0));\n\
+       }\n\
+       \n\
+       static void yt_init(void) {\n  \
+         k0 = yt_bool(1);\n  \
+         k1 = yt_prim(\"#Bool.not\");\n  \
+         d0_f = yt_thunk(thk0, 0);\n\
+       }\n"
+    );
+  }
+
+  #[test]
+  fn unknown_entry() {
+    let prog = program("def a: #U8 = 1u8");
+    assert_eq!(
+      emit(&prog, "main"),
+      Err(CodegenError::UnknownEntry("main".into()))
+    );
+  }
+
+  #[test]
+  fn runtime_implements_every_op() {
+    for op in every_op() {
+      let sym = op.symbol();
+      let (typ, nam) = sym[1..].split_once('.').unwrap();
+      let row = format!("{{\"{}\", {}, ", sym, op.arity());
+      let generated = match op {
+        Op::Bits(_) | Op::Bytes(_) => {
+          RUNTIME.contains(&format!("YT_SEQ_TABLE({}, ", typ))
+            && RUNTIME.contains(&format!(
+              "{{\"#\" #T \".{}\", {}, ",
+              nam,
+              op.arity()
+            ))
+        }
+        Op::U8(_)
+        | Op::U16(_)
+        | Op::U32(_)
+        | Op::U64(_)
+        | Op::I8(_)
+        | Op::I16(_)
+        | Op::I32(_)
+        | Op::I64(_) => {
+          RUNTIME.contains(&format!("YT_FIXED_TABLE({}, ", typ))
+            && RUNTIME.contains(&format!(
+              "{{\"#\" #T \".{}\", {}, ",
+              nam,
+              op.arity()
+            ))
+        }
+        _ => false,
+      };
+      assert!(
+        RUNTIME.contains(&row) || generated,
+        "missing primitive {} in the C runtime",
+        sym
+      );
+    }
+  }
+
+  // Compiles the factorial of `benches/runtime.rs` with the system's C
+  // compiler and checks the output of the executable
+  #[test]
+  #[ignore = "needs a C compiler"]
+  fn compile_and_run_fact() {
+    let prog = program(
+      "def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+       #Nat.mul x (fact x'))
+       def main: #Nat = fact 25",
+    );
+    // A directory of its own, so concurrent runs don't clobber each other
+    let dir = std::env::temp_dir()
+      .join(format!("yatima_codegen_c_fact_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let src = dir.join("fact.c");
+    let exe = dir.join("fact");
+    fs::write(&src, emit(&prog, "main").unwrap()).unwrap();
+    let status = Command::new("cc")
+      .arg("-O2")
+      .arg("-o")
+      .arg(&exe)
+      .arg(&src)
+      .status()
+      .unwrap();
+    assert!(status.success());
+    let out = Command::new(&exe).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+      String::from_utf8(out.stdout).unwrap(),
+      "15511210043330985984000000\n"
+    );
+    fs::remove_dir_all(&dir).ok();
+  }
+}
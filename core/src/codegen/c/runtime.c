@@ -0,0 +1,2281 @@
+/* Yatima runtime for generated C programs.
+ *
+ * Every value is a reference counted heap object. Functions are closures over
+ * an environment of captured values, and arguments are passed lazily as
+ * memoized thunks, so evaluation is call-by-need like the reference
+ * evaluator. Reference counts do not collect the cycles created by `letrec`.
+ *
+ * Ownership: every `Val` returned by a function is owned by the caller and
+ * every `Val` passed to `yt_apply`, `yt_force`, `yt_case` and `yt_drop` is
+ * consumed. Closure environments are borrowed by the code that runs in them.
+ */
+
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <wctype.h>
+
+/* Programs only use the parts of the runtime they need */
+#if defined(__GNUC__)
+#pragma GCC diagnostic ignored "-Wunused-function"
+#endif
+
+typedef struct yt_obj yt_obj;
+typedef yt_obj *Val;
+typedef Val (*yt_code)(Val self, Val arg);
+typedef Val (*yt_thunk_code)(Val self);
+
+enum yt_tag {
+  YT_CLO,
+  YT_THK,
+  YT_PRIM,
+  YT_IRR,
+  YT_NAT,
+  YT_INT,
+  YT_U128,
+  YT_I128,
+  YT_BITS,
+  YT_BYTES,
+  YT_TEXT,
+  YT_CHAR,
+  YT_BOOL,
+  YT_U8,
+  YT_U16,
+  YT_U32,
+  YT_U64,
+  YT_I8,
+  YT_I16,
+  YT_I32,
+  YT_I64
+};
+
+#define YT_BLACKHOLE 1
+#define YT_WEAK_SELF 2
+
+typedef struct {
+  uint32_t len;
+  uint32_t *digits; /* little endian base 2^32, no leading zeros */
+} yt_nat;
+
+typedef struct yt_op yt_op;
+
+struct yt_obj {
+  uint32_t rc;
+  uint8_t tag;
+  uint8_t flags;
+  union {
+    struct {
+      yt_code fun;
+      yt_thunk_code thk;
+      uint32_t len;
+      Val *env;
+      Val val;
+    } clo;
+    struct {
+      const yt_op *op;
+      uint32_t len;
+      Val args[3];
+    } prim;
+    struct {
+      int neg;
+      yt_nat mag;
+    } num;
+    struct {
+      size_t len;
+      uint8_t *data;
+    } buf;
+    uint32_t chr;
+    int bool_;
+    uint64_t fixed;
+  } u;
+};
+
+typedef Val (*yt_op_fn)(Val *args);
+
+struct yt_op {
+  const char *name;
+  int arity;
+  yt_op_fn fn;
+};
+
+static void yt_panic(const char *msg, const char *arg) {
+  fprintf(stderr, "yatima: %s%s\n", msg, arg ? arg : "");
+  exit(1);
+}
+
+static void *yt_alloc(size_t size) {
+  void *ptr = malloc(size ? size : 1);
+  if (!ptr) {
+    yt_panic("out of memory", NULL);
+  }
+  return ptr;
+}
+
+static Val yt_new(uint8_t tag) {
+  Val v = (Val)yt_alloc(sizeof(yt_obj));
+  v->rc = 1;
+  v->tag = tag;
+  v->flags = 0;
+  return v;
+}
+
+static Val yt_dup(Val v) {
+  v->rc++;
+  return v;
+}
+
+static void yt_drop(Val v);
+
+static void yt_drop_env(Val v) {
+  uint32_t i;
+  for (i = 0; i < v->u.clo.len; i++) {
+    if (i == 0 && (v->flags & YT_WEAK_SELF)) {
+      continue;
+    }
+    yt_drop(v->u.clo.env[i]);
+  }
+  free(v->u.clo.env);
+  v->u.clo.env = NULL;
+  v->u.clo.len = 0;
+}
+
+static void yt_drop(Val v) {
+  uint32_t i;
+  if (--v->rc > 0) {
+    return;
+  }
+  switch (v->tag) {
+    case YT_CLO:
+      yt_drop_env(v);
+      break;
+    case YT_THK:
+      yt_drop_env(v);
+      if (v->u.clo.val) {
+        yt_drop(v->u.clo.val);
+      }
+      break;
+    case YT_PRIM:
+      for (i = 0; i < v->u.prim.len; i++) {
+        yt_drop(v->u.prim.args[i]);
+      }
+      break;
+    case YT_NAT:
+    case YT_INT:
+    case YT_U128:
+    case YT_I128:
+      free(v->u.num.mag.digits);
+      break;
+    case YT_BITS:
+    case YT_BYTES:
+    case YT_TEXT:
+      free(v->u.buf.data);
+      break;
+    default:
+      break;
+  }
+  free(v);
+}
+
+/* Closures and thunks */
+
+static Val yt_closure_env(uint8_t tag, uint32_t len, va_list ap) {
+  uint32_t i;
+  Val v = yt_new(tag);
+  v->u.clo.fun = NULL;
+  v->u.clo.thk = NULL;
+  v->u.clo.len = len;
+  v->u.clo.env = (Val *)yt_alloc(len * sizeof(Val));
+  v->u.clo.val = NULL;
+  for (i = 0; i < len; i++) {
+    v->u.clo.env[i] = va_arg(ap, Val);
+  }
+  return v;
+}
+
+/* Takes ownership of the `len` captured values that follow */
+static Val yt_closure(yt_code code, uint32_t len, ...) {
+  Val v;
+  va_list ap;
+  va_start(ap, len);
+  v = yt_closure_env(YT_CLO, len, ap);
+  v->u.clo.fun = code;
+  va_end(ap);
+  return v;
+}
+
+static Val yt_thunk(yt_thunk_code code, uint32_t len, ...) {
+  Val v;
+  va_list ap;
+  va_start(ap, len);
+  v = yt_closure_env(YT_THK, len, ap);
+  v->u.clo.thk = code;
+  va_end(ap);
+  return v;
+}
+
+/* A recursive thunk, whose first environment slot is a weak reference to the
+ * thunk itself */
+static Val yt_letrec(yt_thunk_code code, uint32_t len, ...) {
+  uint32_t i;
+  va_list ap;
+  Val v = yt_new(YT_THK);
+  v->flags = YT_WEAK_SELF;
+  v->u.clo.fun = NULL;
+  v->u.clo.thk = code;
+  v->u.clo.len = len + 1;
+  v->u.clo.env = (Val *)yt_alloc((len + 1) * sizeof(Val));
+  v->u.clo.val = NULL;
+  v->u.clo.env[0] = v;
+  va_start(ap, len);
+  for (i = 0; i < len; i++) {
+    v->u.clo.env[i + 1] = va_arg(ap, Val);
+  }
+  va_end(ap);
+  return v;
+}
+
+static Val yt_force(Val v) {
+  while (v->tag == YT_THK) {
+    Val res;
+    if (v->u.clo.val) {
+      res = yt_dup(v->u.clo.val);
+      yt_drop(v);
+      v = res;
+      continue;
+    }
+    if (v->flags & YT_BLACKHOLE) {
+      yt_panic("thunk forced during its own evaluation", NULL);
+    }
+    v->flags |= YT_BLACKHOLE;
+    res = yt_force(v->u.clo.thk(v));
+    v->flags &= ~YT_BLACKHOLE;
+    v->u.clo.val = yt_dup(res);
+    yt_drop_env(v);
+    yt_drop(v);
+    v = res;
+  }
+  return v;
+}
+
+static Val yt_prim_apply(Val f, Val x);
+
+static Val yt_apply(Val f, Val x) {
+  Val res;
+  f = yt_force(f);
+  switch (f->tag) {
+    case YT_CLO:
+      res = f->u.clo.fun(f, x);
+      yt_drop(f);
+      return res;
+    case YT_PRIM:
+      return yt_prim_apply(f, x);
+    default:
+      yt_panic("application of a value that is not a function", NULL);
+      return NULL;
+  }
+}
+
+static yt_obj yt_irr_obj = {1, YT_IRR, 0, {{0}}};
+
+/* Type level arguments erased at compile time */
+static Val yt_irr(void) { return yt_dup(&yt_irr_obj); }
+
+/* Arbitrary precision naturals */
+
+static yt_nat nat_alloc(uint32_t len) {
+  yt_nat n;
+  n.len = len;
+  n.digits = (uint32_t *)yt_alloc(len * sizeof(uint32_t));
+  memset(n.digits, 0, len * sizeof(uint32_t));
+  return n;
+}
+
+static yt_nat nat_trim(yt_nat n) {
+  while (n.len > 0 && n.digits[n.len - 1] == 0) {
+    n.len--;
+  }
+  return n;
+}
+
+static yt_nat nat_copy(const yt_nat *a) {
+  yt_nat n = nat_alloc(a->len);
+  memcpy(n.digits, a->digits, a->len * sizeof(uint32_t));
+  return n;
+}
+
+static yt_nat nat_from_u64(uint64_t x) {
+  yt_nat n = nat_alloc(2);
+  n.digits[0] = (uint32_t)x;
+  n.digits[1] = (uint32_t)(x >> 32);
+  return nat_trim(n);
+}
+
+static int nat_cmp(const yt_nat *a, const yt_nat *b) {
+  uint32_t i;
+  if (a->len != b->len) {
+    return a->len < b->len ? -1 : 1;
+  }
+  for (i = a->len; i > 0; i--) {
+    if (a->digits[i - 1] != b->digits[i - 1]) {
+      return a->digits[i - 1] < b->digits[i - 1] ? -1 : 1;
+    }
+  }
+  return 0;
+}
+
+static yt_nat nat_add(const yt_nat *a, const yt_nat *b) {
+  uint32_t i;
+  uint64_t carry = 0;
+  uint32_t len = (a->len > b->len ? a->len : b->len) + 1;
+  yt_nat n = nat_alloc(len);
+  for (i = 0; i < len; i++) {
+    uint64_t s = carry;
+    s += i < a->len ? a->digits[i] : 0;
+    s += i < b->len ? b->digits[i] : 0;
+    n.digits[i] = (uint32_t)s;
+    carry = s >> 32;
+  }
+  return nat_trim(n);
+}
+
+/* Assumes a >= b */
+static yt_nat nat_sub(const yt_nat *a, const yt_nat *b) {
+  uint32_t i;
+  int64_t borrow = 0;
+  yt_nat n = nat_alloc(a->len);
+  for (i = 0; i < a->len; i++) {
+    int64_t d = (int64_t)a->digits[i] - borrow;
+    d -= i < b->len ? b->digits[i] : 0;
+    borrow = d < 0;
+    n.digits[i] = (uint32_t)(d + (borrow ? ((int64_t)1 << 32) : 0));
+  }
+  return nat_trim(n);
+}
+
+static yt_nat nat_mul(const yt_nat *a, const yt_nat *b) {
+  uint32_t i, j;
+  yt_nat n = nat_alloc(a->len + b->len);
+  for (i = 0; i < a->len; i++) {
+    uint64_t carry = 0;
+    for (j = 0; j < b->len; j++) {
+      uint64_t t = (uint64_t)a->digits[i] * b->digits[j];
+      t += n.digits[i + j];
+      t += carry;
+      n.digits[i + j] = (uint32_t)t;
+      carry = t >> 32;
+    }
+    n.digits[i + b->len] = (uint32_t)carry;
+  }
+  return nat_trim(n);
+}
+
+static uint64_t nat_bits(const yt_nat *a) {
+  uint64_t bits;
+  uint32_t top;
+  if (a->len == 0) {
+    return 0;
+  }
+  bits = (uint64_t)(a->len - 1) * 32;
+  top = a->digits[a->len - 1];
+  while (top) {
+    bits++;
+    top >>= 1;
+  }
+  return bits;
+}
+
+static int nat_bit(const yt_nat *a, uint64_t i) {
+  return (int)((a->digits[i / 32] >> (i % 32)) & 1);
+}
+
+/* Binary long division, assumes b != 0 */
+static void nat_divmod(const yt_nat *a, const yt_nat *b, yt_nat *q, yt_nat *r) {
+  uint64_t i = nat_bits(a);
+  yt_nat quo = nat_alloc(a->len);
+  yt_nat rem = nat_alloc(b->len + 1);
+  rem.len = 0;
+  while (i > 0) {
+    uint32_t j;
+    uint32_t carry;
+    i--;
+    /* rem = rem * 2 + bit */
+    carry = (uint32_t)nat_bit(a, i);
+    for (j = 0; j < rem.len; j++) {
+      uint32_t next = rem.digits[j] >> 31;
+      rem.digits[j] = (rem.digits[j] << 1) | carry;
+      carry = next;
+    }
+    if (carry) {
+      rem.digits[rem.len++] = carry;
+    }
+    if (nat_cmp(&rem, b) >= 0) {
+      yt_nat diff = nat_sub(&rem, b);
+      memset(rem.digits, 0, (b->len + 1) * sizeof(uint32_t));
+      memcpy(rem.digits, diff.digits, diff.len * sizeof(uint32_t));
+      rem.len = diff.len;
+      free(diff.digits);
+      quo.digits[i / 32] |= (uint32_t)1 << (i % 32);
+    }
+  }
+  *q = nat_trim(quo);
+  *r = nat_trim(rem);
+}
+
+static uint32_t nat_divmod_small(yt_nat *a, uint32_t d) {
+  uint32_t i;
+  uint64_t rem = 0;
+  for (i = a->len; i > 0; i--) {
+    uint64_t cur = (rem << 32) | a->digits[i - 1];
+    a->digits[i - 1] = (uint32_t)(cur / d);
+    rem = cur % d;
+  }
+  *a = nat_trim(*a);
+  return (uint32_t)rem;
+}
+
+static yt_nat nat_from_str(const char *s) {
+  yt_nat n = nat_alloc(0);
+  for (; *s; s++) {
+    uint32_t i;
+    uint64_t carry = (uint64_t)(*s - '0');
+    for (i = 0; i < n.len; i++) {
+      uint64_t t = (uint64_t)n.digits[i] * 10 + carry;
+      n.digits[i] = (uint32_t)t;
+      carry = t >> 32;
+    }
+    if (carry) {
+      n.digits = (uint32_t *)realloc(n.digits, (n.len + 1) * sizeof(uint32_t));
+      n.digits[n.len++] = (uint32_t)carry;
+    }
+  }
+  return n;
+}
+
+static void nat_print(FILE *out, const yt_nat *a) {
+  yt_nat n = nat_copy(a);
+  uint32_t *chunks = (uint32_t *)yt_alloc((a->len * 2 + 1) * sizeof(uint32_t));
+  uint32_t len = 0;
+  do {
+    chunks[len++] = nat_divmod_small(&n, 1000000000);
+  } while (n.len > 0);
+  fprintf(out, "%lu", (unsigned long)chunks[--len]);
+  while (len > 0) {
+    fprintf(out, "%09lu", (unsigned long)chunks[--len]);
+  }
+  free(chunks);
+  free(n.digits);
+}
+
+/* Returns 1 and sets `out` if the natural fits in 64 bits */
+static int nat_to_u64(const yt_nat *a, uint64_t *out) {
+  if (a->len > 2) {
+    return 0;
+  }
+  *out = a->len > 0 ? a->digits[0] : 0;
+  if (a->len > 1) {
+    *out |= (uint64_t)a->digits[1] << 32;
+  }
+  return 1;
+}
+
+/* Literal values */
+
+static Val yt_num(uint8_t tag, int neg, yt_nat mag) {
+  Val v = yt_new(tag);
+  v->u.num.neg = mag.len > 0 ? neg : 0;
+  v->u.num.mag = mag;
+  return v;
+}
+
+static Val yt_nat_val(yt_nat mag) { return yt_num(YT_NAT, 0, mag); }
+
+static Val yt_nat_u64(uint64_t x) { return yt_nat_val(nat_from_u64(x)); }
+
+static Val yt_num_lit(uint8_t tag, const char *s) {
+  int neg = *s == '-';
+  return yt_num(tag, neg, nat_from_str(neg ? s + 1 : s));
+}
+
+static Val yt_nat_lit(const char *s) { return yt_num_lit(YT_NAT, s); }
+
+static Val yt_int_lit(const char *s) { return yt_num_lit(YT_INT, s); }
+
+/* Copies `len` bytes of `data`, or leaves them uninitialized if it is NULL */
+static Val yt_buf(uint8_t tag, size_t len, const uint8_t *data) {
+  Val v = yt_new(tag);
+  v->u.buf.len = len;
+  v->u.buf.data = (uint8_t *)yt_alloc(len);
+  if (len > 0 && data) {
+    memcpy(v->u.buf.data, data, len);
+  }
+  return v;
+}
+
+/* Bits are stored one per byte, each either 0 or 1 */
+static Val yt_bits_lit(const char *bits) {
+  size_t i;
+  size_t len = strlen(bits);
+  Val v = yt_buf(YT_BITS, len, (const uint8_t *)bits);
+  for (i = 0; i < len; i++) {
+    v->u.buf.data[i] = bits[i] == '1';
+  }
+  return v;
+}
+
+static Val yt_bytes_lit(size_t len, const char *data) {
+  return yt_buf(YT_BYTES, len, (const uint8_t *)data);
+}
+
+static Val yt_text_lit(size_t len, const char *data) {
+  return yt_buf(YT_TEXT, len, (const uint8_t *)data);
+}
+
+static Val yt_char(uint32_t c) {
+  Val v = yt_new(YT_CHAR);
+  v->u.chr = c;
+  return v;
+}
+
+static Val yt_bool(int b) {
+  Val v = yt_new(YT_BOOL);
+  v->u.bool_ = b != 0;
+  return v;
+}
+
+/* Fixed width integers */
+
+static unsigned fx_width(uint8_t tag) {
+  switch (tag) {
+    case YT_U8:
+    case YT_I8:
+      return 8;
+    case YT_U16:
+    case YT_I16:
+      return 16;
+    case YT_U32:
+    case YT_I32:
+      return 32;
+    default:
+      return 64;
+  }
+}
+
+static int fx_signed(uint8_t tag) {
+  return tag == YT_I8 || tag == YT_I16 || tag == YT_I32 || tag == YT_I64;
+}
+
+static uint64_t fx_mask(unsigned w) {
+  return w == 64 ? ~(uint64_t)0 : (((uint64_t)1 << w) - 1);
+}
+
+/* Signed values are kept sign extended to 64 bits, unsigned ones masked */
+static uint64_t fx_norm(uint8_t tag, uint64_t x) {
+  unsigned w = fx_width(tag);
+  x &= fx_mask(w);
+  if (fx_signed(tag) && w < 64 && ((x >> (w - 1)) & 1)) {
+    x |= ~fx_mask(w);
+  }
+  return x;
+}
+
+static Val yt_fixed(uint8_t tag, uint64_t x) {
+  Val v = yt_new(tag);
+  v->u.fixed = fx_norm(tag, x);
+  return v;
+}
+
+static int64_t fx_int(uint64_t x) {
+  return x >> 63 ? -(int64_t)(~x) - 1 : (int64_t)x;
+}
+
+/* Checked conversion of a signed or unsigned 64 bit value into `tag`. The
+ * value is negative if `neg` is set, with `x` holding its two's complement */
+static Val fx_conv(uint8_t tag, int neg, uint64_t x) {
+  unsigned w = fx_width(tag);
+  if (tag == YT_U128 || tag == YT_I128) {
+    if (neg && tag == YT_U128) {
+      return NULL;
+    }
+    return yt_num(tag, neg, nat_from_u64(neg ? ~x + 1 : x));
+  }
+  if (fx_signed(tag)) {
+    int64_t min = w == 64 ? INT64_MIN : -((int64_t)1 << (w - 1));
+    int64_t max = w == 64 ? INT64_MAX : ((int64_t)1 << (w - 1)) - 1;
+    if (neg ? fx_int(x) < min : (x > (uint64_t)max)) {
+      return NULL;
+    }
+  }
+  else if (neg || x > fx_mask(w)) {
+    return NULL;
+  }
+  return yt_fixed(tag, x);
+}
+
+static int fx_neg(Val v) {
+  return fx_signed(v->tag) && fx_int(v->u.fixed) < 0;
+}
+
+static unsigned fx_ones(uint64_t x) {
+  unsigned n = 0;
+  while (x) {
+    n += (unsigned)(x & 1);
+    x >>= 1;
+  }
+  return n;
+}
+
+static Val yt_bits_of_bytes(size_t len, const uint8_t *bytes, size_t n) {
+  size_t i;
+  Val v = yt_buf(YT_BITS, 0, NULL);
+  free(v->u.buf.data);
+  if (len > n * 8) {
+    len = n * 8;
+  }
+  v->u.buf.len = len;
+  v->u.buf.data = (uint8_t *)yt_alloc(len);
+  for (i = 0; i < len; i++) {
+    v->u.buf.data[i] = (bytes[i / 8] >> (i % 8)) & 1;
+  }
+  return v;
+}
+
+static Val fx_bytes(Val x) {
+  unsigned i;
+  unsigned n = fx_width(x->tag) / 8;
+  uint8_t bytes[8];
+  for (i = 0; i < n; i++) {
+    bytes[n - 1 - i] = (uint8_t)(x->u.fixed >> (8 * i));
+  }
+  return yt_buf(YT_BYTES, n, bytes);
+}
+
+static Val fx_bits(Val x) {
+  Val bytes = fx_bytes(x);
+  Val res = yt_bits_of_bytes(fx_width(x->tag), bytes->u.buf.data, bytes->u.buf.len);
+  yt_drop(bytes);
+  return res;
+}
+
+static Val fx_char(uint64_t c) {
+  if (c > 0x10ffff || (c >= 0xd800 && c <= 0xdfff)) {
+    return NULL;
+  }
+  return yt_char((uint32_t)c);
+}
+
+static int fx_cmp(Val x, Val y) {
+  if (fx_signed(x->tag)) {
+    int64_t a = fx_int(x->u.fixed);
+    int64_t b = fx_int(y->u.fixed);
+    return a < b ? -1 : a > b;
+  }
+  return x->u.fixed < y->u.fixed ? -1 : x->u.fixed > y->u.fixed;
+}
+
+enum fx_op {
+  FX_MAX,
+  FX_MIN,
+  FX_ABS,
+  FX_SGN,
+  FX_EQL,
+  FX_LTE,
+  FX_LTH,
+  FX_GTH,
+  FX_GTE,
+  FX_NOT,
+  FX_AND,
+  FX_OR,
+  FX_XOR,
+  FX_ADD,
+  FX_SUB,
+  FX_MUL,
+  FX_DIV,
+  FX_MOD,
+  FX_POW,
+  FX_SHL,
+  FX_SHR,
+  FX_ROL,
+  FX_ROR,
+  FX_COUNT_ZEROS,
+  FX_COUNT_ONES,
+  FX_TO_U8,
+  FX_TO_U16,
+  FX_TO_U32,
+  FX_TO_U64,
+  FX_TO_U128,
+  FX_TO_I8,
+  FX_TO_I16,
+  FX_TO_I32,
+  FX_TO_I64,
+  FX_TO_I128,
+  FX_TO_NAT,
+  FX_TO_INT,
+  FX_TO_BITS,
+  FX_TO_BYTES,
+  FX_TO_CHAR
+};
+
+/* The operations shared by every fixed width type. Arguments are checked to
+ * have the expected types, returning NULL (a stuck application) otherwise */
+static Val fx_apply(uint8_t tag, enum fx_op op, Val *args) {
+  Val x = args[0];
+  Val y = args[1];
+  unsigned w = fx_width(tag);
+  uint64_t a;
+  uint64_t b;
+  switch (op) {
+    case FX_MAX:
+      return yt_fixed(tag, fx_signed(tag) ? fx_mask(w) >> 1 : fx_mask(w));
+    case FX_MIN:
+      return yt_fixed(tag, fx_signed(tag) ? ~(fx_mask(w) >> 1) : 0);
+    case FX_SHL:
+    case FX_SHR:
+    case FX_ROL:
+    case FX_ROR:
+      if (x->tag != YT_U32 || y->tag != tag) {
+        return NULL;
+      }
+      a = y->u.fixed & fx_mask(w);
+      b = x->u.fixed % w;
+      switch (op) {
+        case FX_SHL:
+          return yt_fixed(tag, a << b);
+        case FX_SHR:
+          if (fx_neg(y)) {
+            return yt_fixed(tag, ~(~y->u.fixed >> b));
+          }
+          return yt_fixed(tag, a >> b);
+        case FX_ROL:
+          return yt_fixed(tag, b ? (a << b) | (a >> (w - b)) : a);
+        default:
+          return yt_fixed(tag, b ? (a >> b) | (a << (w - b)) : a);
+      }
+    case FX_POW:
+      if (x->tag != tag || y->tag != YT_U32) {
+        return NULL;
+      }
+      else {
+        uint64_t res = 1;
+        uint64_t base = x->u.fixed;
+        uint64_t n = y->u.fixed;
+        while (n > 0) {
+          if (n & 1) {
+            res *= base;
+          }
+          base *= base;
+          n >>= 1;
+        }
+        return yt_fixed(tag, res);
+      }
+    default:
+      break;
+  }
+  if (x->tag != tag) {
+    return NULL;
+  }
+  a = x->u.fixed;
+  switch (op) {
+    case FX_ABS:
+      return yt_fixed(fx_signed(tag) ? tag - (YT_I8 - YT_U8) : tag,
+                      fx_neg(x) ? ~a + 1 : a);
+    case FX_SGN:
+      return yt_bool(fx_signed(tag) ? fx_int(a) > 0 : a > 0);
+    case FX_NOT:
+      return yt_fixed(tag, ~a);
+    case FX_COUNT_ONES:
+      return yt_fixed(YT_U32, fx_ones(a & fx_mask(w)));
+    case FX_COUNT_ZEROS:
+      return yt_fixed(YT_U32, w - fx_ones(a & fx_mask(w)));
+    case FX_TO_U8:
+      return fx_conv(YT_U8, fx_neg(x), a);
+    case FX_TO_U16:
+      return fx_conv(YT_U16, fx_neg(x), a);
+    case FX_TO_U32:
+      return fx_conv(YT_U32, fx_neg(x), a);
+    case FX_TO_U64:
+      return fx_conv(YT_U64, fx_neg(x), a);
+    case FX_TO_U128:
+      return fx_conv(YT_U128, fx_neg(x), a);
+    case FX_TO_I8:
+      return fx_conv(YT_I8, fx_neg(x), a);
+    case FX_TO_I16:
+      return fx_conv(YT_I16, fx_neg(x), a);
+    case FX_TO_I32:
+      return fx_conv(YT_I32, fx_neg(x), a);
+    case FX_TO_I64:
+      return fx_conv(YT_I64, fx_neg(x), a);
+    case FX_TO_I128:
+      return fx_conv(YT_I128, fx_neg(x), a);
+    case FX_TO_NAT:
+      return fx_neg(x) ? NULL : yt_nat_u64(a);
+    case FX_TO_INT:
+      return yt_num(YT_INT, fx_neg(x), nat_from_u64(fx_neg(x) ? ~a + 1 : a));
+    case FX_TO_BITS:
+      return fx_bits(x);
+    case FX_TO_BYTES:
+      return fx_bytes(x);
+    case FX_TO_CHAR:
+      return fx_char(a);
+    default:
+      break;
+  }
+  if (y->tag != tag) {
+    return NULL;
+  }
+  b = y->u.fixed;
+  switch (op) {
+    case FX_EQL:
+      return yt_bool(fx_cmp(x, y) == 0);
+    case FX_LTE:
+      return yt_bool(fx_cmp(x, y) <= 0);
+    case FX_LTH:
+      return yt_bool(fx_cmp(x, y) < 0);
+    case FX_GTH:
+      return yt_bool(fx_cmp(x, y) > 0);
+    case FX_GTE:
+      return yt_bool(fx_cmp(x, y) >= 0);
+    case FX_AND:
+      return yt_fixed(tag, a & b);
+    case FX_OR:
+      return yt_fixed(tag, a | b);
+    case FX_XOR:
+      return yt_fixed(tag, a ^ b);
+    case FX_ADD:
+      return yt_fixed(tag, a + b);
+    case FX_SUB:
+      return yt_fixed(tag, a - b);
+    case FX_MUL:
+      return yt_fixed(tag, a * b);
+    case FX_DIV:
+    case FX_MOD:
+      if (b == 0) {
+        return NULL;
+      }
+      if (fx_signed(tag)) {
+        int64_t p = fx_int(a);
+        int64_t q = fx_int(b);
+        if (q == -1) {
+          return yt_fixed(tag, op == FX_DIV ? ~a + 1 : 0);
+        }
+        return yt_fixed(tag, (uint64_t)(op == FX_DIV ? p / q : p % q));
+      }
+      return yt_fixed(tag, op == FX_DIV ? a / b : a % b);
+    default:
+      return NULL;
+  }
+}
+
+/* `#Nat` and `#Int` */
+
+static int yt_is(Val v, uint8_t tag) { return v->tag == tag; }
+
+static Val int_norm(int neg, yt_nat mag) { return yt_num(YT_INT, neg, mag); }
+
+/* Signed addition on sign and magnitude pairs */
+static Val int_add(int an, const yt_nat *a, int bn, const yt_nat *b) {
+  if (an == bn) {
+    return int_norm(an, nat_add(a, b));
+  }
+  if (nat_cmp(a, b) >= 0) {
+    return int_norm(an, nat_sub(a, b));
+  }
+  return int_norm(bn, nat_sub(b, a));
+}
+
+static int int_cmp(Val x, Val y) {
+  int c;
+  if (x->u.num.neg != y->u.num.neg) {
+    return x->u.num.neg ? -1 : 1;
+  }
+  c = nat_cmp(&x->u.num.mag, &y->u.num.mag);
+  return x->u.num.neg ? -c : c;
+}
+
+static Val num_cmp_op(Val *args, uint8_t tag, int lo, int hi) {
+  int c;
+  if (!yt_is(args[0], tag) || !yt_is(args[1], tag)) {
+    return NULL;
+  }
+  c = int_cmp(args[0], args[1]);
+  return yt_bool(c >= lo && c <= hi);
+}
+
+#define YT_NAT2(a, b)                                          \
+  if (!yt_is(args[0], YT_NAT) || !yt_is(args[1], YT_NAT)) {    \
+    return NULL;                                               \
+  }                                                            \
+  a = &args[0]->u.num.mag;                                     \
+  b = &args[1]->u.num.mag
+
+static Val nat_suc(Val *args) {
+  yt_nat one = nat_from_u64(1);
+  Val res;
+  if (!yt_is(args[0], YT_NAT)) {
+    free(one.digits);
+    return NULL;
+  }
+  res = yt_nat_val(nat_add(&args[0]->u.num.mag, &one));
+  free(one.digits);
+  return res;
+}
+
+static Val nat_pre(Val *args) {
+  yt_nat one = nat_from_u64(1);
+  Val res;
+  if (!yt_is(args[0], YT_NAT)) {
+    free(one.digits);
+    return NULL;
+  }
+  if (args[0]->u.num.mag.len == 0) {
+    res = yt_nat_u64(0);
+  }
+  else {
+    res = yt_nat_val(nat_sub(&args[0]->u.num.mag, &one));
+  }
+  free(one.digits);
+  return res;
+}
+
+static Val nat_eql(Val *args) { return num_cmp_op(args, YT_NAT, 0, 0); }
+static Val nat_lte(Val *args) { return num_cmp_op(args, YT_NAT, -1, 0); }
+static Val nat_lth(Val *args) { return num_cmp_op(args, YT_NAT, -1, -1); }
+static Val nat_gte(Val *args) { return num_cmp_op(args, YT_NAT, 0, 1); }
+static Val nat_gth(Val *args) { return num_cmp_op(args, YT_NAT, 1, 1); }
+
+static Val nat_add_op(Val *args) {
+  const yt_nat *a;
+  const yt_nat *b;
+  YT_NAT2(a, b);
+  return yt_nat_val(nat_add(a, b));
+}
+
+static Val nat_sub_op(Val *args) {
+  const yt_nat *a;
+  const yt_nat *b;
+  YT_NAT2(a, b);
+  return nat_cmp(a, b) < 0 ? NULL : yt_nat_val(nat_sub(a, b));
+}
+
+static Val nat_mul_op(Val *args) {
+  const yt_nat *a;
+  const yt_nat *b;
+  YT_NAT2(a, b);
+  return yt_nat_val(nat_mul(a, b));
+}
+
+static Val nat_div_op(Val *args) {
+  const yt_nat *a;
+  const yt_nat *b;
+  yt_nat q;
+  yt_nat r;
+  YT_NAT2(a, b);
+  if (b->len == 0) {
+    return NULL;
+  }
+  nat_divmod(a, b, &q, &r);
+  free(r.digits);
+  return yt_nat_val(q);
+}
+
+static Val nat_mod_op(Val *args) {
+  const yt_nat *a;
+  const yt_nat *b;
+  yt_nat q;
+  yt_nat r;
+  YT_NAT2(a, b);
+  if (b->len == 0) {
+    return NULL;
+  }
+  nat_divmod(a, b, &q, &r);
+  free(q.digits);
+  return yt_nat_val(r);
+}
+
+static Val int_new(Val *args) {
+  if (!yt_is(args[0], YT_BOOL) || !yt_is(args[1], YT_NAT)) {
+    return NULL;
+  }
+  return int_norm(!args[0]->u.bool_, nat_copy(&args[1]->u.num.mag));
+}
+
+static Val int_sgn(Val *args) {
+  if (!yt_is(args[0], YT_INT)) {
+    return NULL;
+  }
+  return yt_bool(!args[0]->u.num.neg && args[0]->u.num.mag.len > 0);
+}
+
+static Val int_abs(Val *args) {
+  if (!yt_is(args[0], YT_INT)) {
+    return NULL;
+  }
+  return yt_nat_val(nat_copy(&args[0]->u.num.mag));
+}
+
+static Val int_eql(Val *args) { return num_cmp_op(args, YT_INT, 0, 0); }
+static Val int_lte(Val *args) { return num_cmp_op(args, YT_INT, -1, 0); }
+static Val int_lth(Val *args) { return num_cmp_op(args, YT_INT, -1, -1); }
+static Val int_gte(Val *args) { return num_cmp_op(args, YT_INT, 0, 1); }
+static Val int_gth(Val *args) { return num_cmp_op(args, YT_INT, 1, 1); }
+
+#define YT_INT2(x, y)                                          \
+  if (!yt_is(args[0], YT_INT) || !yt_is(args[1], YT_INT)) {    \
+    return NULL;                                               \
+  }                                                            \
+  x = args[0];                                                 \
+  y = args[1]
+
+static Val int_add_op(Val *args) {
+  Val x;
+  Val y;
+  YT_INT2(x, y);
+  return int_add(x->u.num.neg, &x->u.num.mag, y->u.num.neg, &y->u.num.mag);
+}
+
+static Val int_sub_op(Val *args) {
+  Val x;
+  Val y;
+  YT_INT2(x, y);
+  return int_add(x->u.num.neg, &x->u.num.mag, !y->u.num.neg, &y->u.num.mag);
+}
+
+static Val int_mul_op(Val *args) {
+  Val x;
+  Val y;
+  YT_INT2(x, y);
+  return int_norm(
+    x->u.num.neg != y->u.num.neg, nat_mul(&x->u.num.mag, &y->u.num.mag));
+}
+
+/* Truncating division, with the remainder taking the sign of the dividend */
+static Val int_divmod(Val *args, int div) {
+  Val x;
+  Val y;
+  yt_nat q;
+  yt_nat r;
+  YT_INT2(x, y);
+  if (y->u.num.mag.len == 0) {
+    return NULL;
+  }
+  nat_divmod(&x->u.num.mag, &y->u.num.mag, &q, &r);
+  if (div) {
+    free(r.digits);
+    return int_norm(x->u.num.neg != y->u.num.neg, q);
+  }
+  free(q.digits);
+  return int_norm(x->u.num.neg, r);
+}
+
+static Val int_div_op(Val *args) { return int_divmod(args, 1); }
+static Val int_mod_op(Val *args) { return int_divmod(args, 0); }
+
+/* `#Bits` and `#Bytes`, stacks whose head is the last element */
+
+static Val buf_concat(uint8_t tag, const uint8_t *a, size_t an,
+                      const uint8_t *b, size_t bn) {
+  Val v = yt_buf(tag, an + bn, NULL);
+  if (an > 0) {
+    memcpy(v->u.buf.data, a, an);
+  }
+  if (bn > 0) {
+    memcpy(v->u.buf.data + an, b, bn);
+  }
+  return v;
+}
+
+/* Reads a `#Nat` index, returning 0 if it does not fit in a size_t */
+static int yt_index(Val n, size_t *idx) {
+  uint64_t x;
+  if (!yt_is(n, YT_NAT) || !nat_to_u64(&n->u.num.mag, &x) || x > SIZE_MAX) {
+    return 0;
+  }
+  *idx = (size_t)x;
+  return 1;
+}
+
+static Val seq_elem(uint8_t tag, uint8_t x) {
+  return tag == YT_BITS ? yt_bool(x) : yt_fixed(YT_U8, x);
+}
+
+static int seq_unelem(uint8_t tag, Val v, uint8_t *x) {
+  if (tag == YT_BITS && yt_is(v, YT_BOOL)) {
+    *x = (uint8_t)v->u.bool_;
+    return 1;
+  }
+  if (tag == YT_BYTES && yt_is(v, YT_U8)) {
+    *x = (uint8_t)v->u.fixed;
+    return 1;
+  }
+  return 0;
+}
+
+enum seq_op {
+  SEQ_CONS,
+  SEQ_LEN,
+  SEQ_HEAD,
+  SEQ_TAIL,
+  SEQ_TAKE,
+  SEQ_DROP,
+  SEQ_APPEND,
+  SEQ_INSERT,
+  SEQ_REMOVE,
+  SEQ_INDEX
+};
+
+static Val seq_apply(uint8_t tag, enum seq_op op, Val *args) {
+  Val xs;
+  uint8_t x;
+  size_t i;
+  size_t len;
+  switch (op) {
+    case SEQ_LEN:
+    case SEQ_HEAD:
+    case SEQ_TAIL:
+      xs = args[0];
+      if (!yt_is(xs, tag)) {
+        return NULL;
+      }
+      len = xs->u.buf.len;
+      if (op == SEQ_LEN) {
+        return yt_nat_u64(len);
+      }
+      if (op == SEQ_HEAD) {
+        return len == 0 ? NULL : seq_elem(tag, xs->u.buf.data[len - 1]);
+      }
+      return yt_buf(tag, len == 0 ? 0 : len - 1, xs->u.buf.data);
+    case SEQ_CONS:
+      xs = args[1];
+      if (!yt_is(xs, tag) || !seq_unelem(tag, args[0], &x)) {
+        return NULL;
+      }
+      return buf_concat(tag, xs->u.buf.data, xs->u.buf.len, &x, 1);
+    case SEQ_APPEND:
+      if (!yt_is(args[0], tag) || !yt_is(args[1], tag)) {
+        return NULL;
+      }
+      return buf_concat(tag, args[0]->u.buf.data, args[0]->u.buf.len,
+                        args[1]->u.buf.data, args[1]->u.buf.len);
+    case SEQ_INSERT:
+      xs = args[2];
+      if (!yt_is(args[0], YT_NAT) || !yt_is(xs, tag)
+          || !seq_unelem(tag, args[1], &x)) {
+        return NULL;
+      }
+      len = xs->u.buf.len;
+      if (!yt_index(args[0], &i) || i >= len) {
+        return yt_buf(tag, len, xs->u.buf.data);
+      }
+      else {
+        Val v = yt_buf(tag, len + 1, NULL);
+        memcpy(v->u.buf.data, xs->u.buf.data, i);
+        v->u.buf.data[i] = x;
+        memcpy(v->u.buf.data + i + 1, xs->u.buf.data + i, len - i);
+        return v;
+      }
+    default:
+      break;
+  }
+  xs = args[1];
+  if (!yt_is(args[0], YT_NAT) || !yt_is(xs, tag)) {
+    return NULL;
+  }
+  len = xs->u.buf.len;
+  if (!yt_index(args[0], &i)) {
+    i = SIZE_MAX;
+  }
+  switch (op) {
+    case SEQ_TAKE:
+      return yt_buf(tag, i <= len ? i : len, xs->u.buf.data);
+    case SEQ_DROP:
+      return i <= len ? yt_buf(tag, len - i, xs->u.buf.data + i)
+                      : yt_buf(tag, 0, NULL);
+    case SEQ_REMOVE:
+      if (i >= len) {
+        return yt_buf(tag, len, xs->u.buf.data);
+      }
+      return buf_concat(tag, xs->u.buf.data, i, xs->u.buf.data + i + 1,
+                        len - i - 1);
+    case SEQ_INDEX:
+      return i < len ? seq_elem(tag, xs->u.buf.data[i]) : NULL;
+    default:
+      return NULL;
+  }
+}
+
+static Val bits_to_bytes(Val *args) {
+  size_t i;
+  Val xs = args[0];
+  Val v;
+  if (!yt_is(xs, YT_BITS)) {
+    return NULL;
+  }
+  v = yt_buf(YT_BYTES, (xs->u.buf.len + 7) / 8, NULL);
+  memset(v->u.buf.data, 0, v->u.buf.len);
+  for (i = 0; i < xs->u.buf.len; i++) {
+    v->u.buf.data[i / 8] |= (uint8_t)(xs->u.buf.data[i] << (i % 8));
+  }
+  return v;
+}
+
+static Val bytes_to_bits(Val *args) {
+  size_t len;
+  if (!yt_is(args[1], YT_BYTES) || !yt_index(args[0], &len)) {
+    return NULL;
+  }
+  return yt_bits_of_bytes(len, args[1]->u.buf.data, args[1]->u.buf.len);
+}
+
+/* `#Text`, stored as UTF-8 */
+
+static size_t utf8_len(uint32_t c) {
+  return c < 0x80 ? 1 : c < 0x800 ? 2 : c < 0x10000 ? 3 : 4;
+}
+
+static size_t utf8_encode(uint32_t c, uint8_t *out) {
+  size_t n = utf8_len(c);
+  switch (n) {
+    case 1:
+      out[0] = (uint8_t)c;
+      break;
+    case 2:
+      out[0] = (uint8_t)(0xc0 | (c >> 6));
+      out[1] = (uint8_t)(0x80 | (c & 0x3f));
+      break;
+    case 3:
+      out[0] = (uint8_t)(0xe0 | (c >> 12));
+      out[1] = (uint8_t)(0x80 | ((c >> 6) & 0x3f));
+      out[2] = (uint8_t)(0x80 | (c & 0x3f));
+      break;
+    default:
+      out[0] = (uint8_t)(0xf0 | (c >> 18));
+      out[1] = (uint8_t)(0x80 | ((c >> 12) & 0x3f));
+      out[2] = (uint8_t)(0x80 | ((c >> 6) & 0x3f));
+      out[3] = (uint8_t)(0x80 | (c & 0x3f));
+      break;
+  }
+  return n;
+}
+
+/* Decodes the text into code points and the byte offset of each of them,
+ * with one extra offset for the end of the text */
+static size_t utf8_decode(Val t, uint32_t **chars, size_t **offsets) {
+  size_t i = 0;
+  size_t n = 0;
+  const uint8_t *s = t->u.buf.data;
+  *chars = (uint32_t *)yt_alloc((t->u.buf.len + 1) * sizeof(uint32_t));
+  *offsets = (size_t *)yt_alloc((t->u.buf.len + 1) * sizeof(size_t));
+  while (i < t->u.buf.len) {
+    uint32_t c = s[i];
+    size_t len = c < 0x80 ? 1 : c < 0xe0 ? 2 : c < 0xf0 ? 3 : 4;
+    size_t j;
+    c &= len == 1 ? 0x7f : len == 2 ? 0x1f : len == 3 ? 0x0f : 0x07;
+    for (j = 1; j < len; j++) {
+      c = (c << 6) | (s[i + j] & 0x3f);
+    }
+    (*chars)[n] = c;
+    (*offsets)[n] = i;
+    n++;
+    i += len;
+  }
+  (*offsets)[n] = i;
+  return n;
+}
+
+static int text_is_break(uint32_t c) {
+  return (c >= 0x0a && c <= 0x0d) || c == 0x85 || c == 0x2028 || c == 0x2029;
+}
+
+/* The char indices at which lines start, like ropey */
+static size_t text_lines(const uint32_t *cs, size_t n, size_t *starts) {
+  size_t i;
+  size_t lines = 1;
+  starts[0] = 0;
+  for (i = 0; i < n; i++) {
+    if (cs[i] == '\r' && i + 1 < n && cs[i + 1] == '\n') {
+      continue;
+    }
+    if (text_is_break(cs[i])) {
+      starts[lines++] = i + 1;
+    }
+  }
+  return lines;
+}
+
+enum text_op {
+  TEXT_CONS,
+  TEXT_APPEND,
+  TEXT_INSERT,
+  TEXT_REMOVE,
+  TEXT_TAKE,
+  TEXT_DROP,
+  TEXT_EQL,
+  TEXT_LTE,
+  TEXT_LTH,
+  TEXT_GTE,
+  TEXT_GTH,
+  TEXT_LEN_CHARS,
+  TEXT_LEN_BYTES,
+  TEXT_LEN_LINES,
+  TEXT_CHAR,
+  TEXT_BYTE,
+  TEXT_LINE,
+  TEXT_CHAR_AT_BYTE,
+  TEXT_BYTE_AT_CHAR,
+  TEXT_LINE_AT_BYTE,
+  TEXT_LINE_AT_CHAR,
+  TEXT_LINE_START_BYTE,
+  TEXT_LINE_START_CHAR,
+  TEXT_TO_BYTES
+};
+
+static int text_cmp(Val x, Val y) {
+  size_t n = x->u.buf.len < y->u.buf.len ? x->u.buf.len : y->u.buf.len;
+  int c = n > 0 ? memcmp(x->u.buf.data, y->u.buf.data, n) : 0;
+  if (c != 0) {
+    return c < 0 ? -1 : 1;
+  }
+  return x->u.buf.len < y->u.buf.len ? -1 : x->u.buf.len > y->u.buf.len;
+}
+
+static Val text_apply_idx(enum text_op op, size_t i, int ok, Val t) {
+  uint32_t *cs;
+  size_t *offs;
+  size_t *starts;
+  size_t n = utf8_decode(t, &cs, &offs);
+  size_t lines;
+  size_t k;
+  Val res = NULL;
+  starts = (size_t *)yt_alloc((n + 2) * sizeof(size_t));
+  lines = text_lines(cs, n, starts);
+  starts[lines] = n;
+  switch (op) {
+    case TEXT_TAKE:
+      res = yt_buf(YT_TEXT, ok && i <= n ? offs[i] : offs[n], t->u.buf.data);
+      break;
+    case TEXT_DROP:
+      res = ok && i <= n ? yt_buf(YT_TEXT, offs[n] - offs[i], t->u.buf.data + offs[i])
+                         : yt_buf(YT_TEXT, 0, NULL);
+      break;
+    case TEXT_CHAR:
+      res = ok && i < n ? yt_char(cs[i]) : NULL;
+      break;
+    case TEXT_BYTE:
+      res = ok && i < n ? yt_fixed(YT_U8, t->u.buf.data[i]) : NULL;
+      break;
+    case TEXT_LINE:
+      if (ok && i < lines) {
+        res = yt_buf(YT_TEXT, offs[starts[i + 1]] - offs[starts[i]],
+                     t->u.buf.data + offs[starts[i]]);
+      }
+      break;
+    case TEXT_CHAR_AT_BYTE:
+    case TEXT_LINE_AT_BYTE:
+      if (ok && i < t->u.buf.len) {
+        for (k = 0; offs[k + 1] <= i; k++) {
+        }
+        if (op == TEXT_CHAR_AT_BYTE) {
+          res = yt_nat_u64(k);
+        }
+        else {
+          i = k;
+          for (k = 0; k + 1 < lines && starts[k + 1] <= i; k++) {
+          }
+          res = yt_nat_u64(k);
+        }
+      }
+      break;
+    case TEXT_BYTE_AT_CHAR:
+      res = ok && i < n ? yt_nat_u64(offs[i]) : NULL;
+      break;
+    case TEXT_LINE_AT_CHAR:
+      if (ok && i < n) {
+        for (k = 0; k + 1 < lines && starts[k + 1] <= i; k++) {
+        }
+        res = yt_nat_u64(k);
+      }
+      break;
+    case TEXT_LINE_START_BYTE:
+      res = ok && i < lines ? yt_nat_u64(offs[starts[i]]) : NULL;
+      break;
+    case TEXT_LINE_START_CHAR:
+      res = ok && i < lines ? yt_nat_u64(starts[i]) : NULL;
+      break;
+    default:
+      break;
+  }
+  free(cs);
+  free(offs);
+  free(starts);
+  return res;
+}
+
+static Val text_apply(enum text_op op, Val *args) {
+  Val x = args[0];
+  Val y = args[1];
+  size_t i;
+  int ok;
+  switch (op) {
+    case TEXT_LEN_CHARS:
+    case TEXT_LEN_BYTES:
+    case TEXT_LEN_LINES:
+    case TEXT_TO_BYTES:
+      if (!yt_is(x, YT_TEXT)) {
+        return NULL;
+      }
+      if (op == TEXT_TO_BYTES) {
+        return yt_buf(YT_BYTES, x->u.buf.len, x->u.buf.data);
+      }
+      if (op == TEXT_LEN_BYTES) {
+        return yt_nat_u64(x->u.buf.len);
+      }
+      else {
+        uint32_t *cs;
+        size_t *offs;
+        size_t n = utf8_decode(x, &cs, &offs);
+        size_t *starts = (size_t *)yt_alloc((n + 2) * sizeof(size_t));
+        size_t lines = text_lines(cs, n, starts);
+        free(cs);
+        free(offs);
+        free(starts);
+        return yt_nat_u64(op == TEXT_LEN_CHARS ? n : lines);
+      }
+    case TEXT_CONS:
+      if (!yt_is(x, YT_CHAR) || !yt_is(y, YT_TEXT)) {
+        return NULL;
+      }
+      else {
+        uint8_t c[4];
+        size_t n = utf8_encode(x->u.chr, c);
+        return buf_concat(YT_TEXT, c, n, y->u.buf.data, y->u.buf.len);
+      }
+    case TEXT_APPEND:
+    case TEXT_EQL:
+    case TEXT_LTE:
+    case TEXT_LTH:
+    case TEXT_GTE:
+    case TEXT_GTH:
+      if (!yt_is(x, YT_TEXT) || !yt_is(y, YT_TEXT)) {
+        return NULL;
+      }
+      switch (op) {
+        case TEXT_APPEND:
+          return buf_concat(YT_TEXT, x->u.buf.data, x->u.buf.len,
+                            y->u.buf.data, y->u.buf.len);
+        case TEXT_EQL:
+          return yt_bool(text_cmp(x, y) == 0);
+        case TEXT_LTE:
+          return yt_bool(text_cmp(x, y) <= 0);
+        case TEXT_LTH:
+          return yt_bool(text_cmp(x, y) < 0);
+        case TEXT_GTE:
+          return yt_bool(text_cmp(x, y) >= 0);
+        default:
+          return yt_bool(text_cmp(x, y) > 0);
+      }
+    case TEXT_INSERT:
+    case TEXT_REMOVE: {
+      Val t = args[2];
+      uint32_t *cs;
+      size_t *offs;
+      size_t n;
+      size_t j;
+      Val res;
+      if (!yt_is(x, YT_NAT) || !yt_is(t, YT_TEXT)) {
+        return NULL;
+      }
+      if (op == TEXT_INSERT ? !yt_is(y, YT_TEXT) : !yt_is(y, YT_NAT)) {
+        return NULL;
+      }
+      n = utf8_decode(t, &cs, &offs);
+      ok = yt_index(x, &i) && i <= n;
+      if (op == TEXT_INSERT) {
+        res = !ok ? yt_buf(YT_TEXT, t->u.buf.len, t->u.buf.data)
+                  : buf_concat(YT_TEXT, t->u.buf.data, offs[i],
+                               y->u.buf.data, y->u.buf.len);
+        if (ok) {
+          Val full = buf_concat(YT_TEXT, res->u.buf.data, res->u.buf.len,
+                                t->u.buf.data + offs[i], offs[n] - offs[i]);
+          yt_drop(res);
+          res = full;
+        }
+      }
+      else {
+        ok = ok && yt_index(y, &j) && j <= n && j >= i;
+        res = !ok ? yt_buf(YT_TEXT, t->u.buf.len, t->u.buf.data)
+                  : buf_concat(YT_TEXT, t->u.buf.data, offs[i],
+                               t->u.buf.data + offs[j], offs[n] - offs[j]);
+      }
+      free(cs);
+      free(offs);
+      return res;
+    }
+    default:
+      if (!yt_is(x, YT_NAT) || !yt_is(y, YT_TEXT)) {
+        return NULL;
+      }
+      ok = yt_index(x, &i);
+      return text_apply_idx(op, i, ok, y);
+  }
+}
+
+/* `#Char`. Classification outside of ASCII uses the C library's wide
+ * character functions, and so depends on the locale */
+
+enum char_op {
+  CHAR_FROM_U32,
+  CHAR_TO_U32,
+  CHAR_IS_ALPHABETIC,
+  CHAR_IS_ALPHANUMERIC,
+  CHAR_IS_ASCII,
+  CHAR_IS_ASCII_ALPHABETIC,
+  CHAR_IS_ASCII_ALPHANUMERIC,
+  CHAR_IS_ASCII_CONTROL,
+  CHAR_IS_ASCII_DIGIT,
+  CHAR_IS_ASCII_GRAPHIC,
+  CHAR_IS_ASCII_HEXDIGIT,
+  CHAR_IS_ASCII_LOWERCASE,
+  CHAR_IS_ASCII_PUNCTUATION,
+  CHAR_IS_ASCII_UPPERCASE,
+  CHAR_IS_ASCII_WHITESPACE,
+  CHAR_IS_CONTROL,
+  CHAR_IS_DIGIT,
+  CHAR_IS_LOWERCASE,
+  CHAR_IS_NUMERIC,
+  CHAR_IS_UPPERCASE,
+  CHAR_IS_WHITESPACE,
+  CHAR_LEN_UTF8,
+  CHAR_LEN_UTF16,
+  CHAR_TO_ASCII_LOWERCASE,
+  CHAR_TO_ASCII_UPPERCASE,
+  CHAR_TO_LOWERCASE,
+  CHAR_TO_UPPERCASE,
+  CHAR_EQL,
+  CHAR_LTE,
+  CHAR_LTH,
+  CHAR_GTH,
+  CHAR_GTE
+};
+
+static int is_lower(uint32_t c) { return c >= 'a' && c <= 'z'; }
+static int is_upper(uint32_t c) { return c >= 'A' && c <= 'Z'; }
+static int is_digit(uint32_t c) { return c >= '0' && c <= '9'; }
+static int is_graphic(uint32_t c) { return c >= 0x21 && c <= 0x7e; }
+
+static Val char_text(uint32_t c) {
+  uint8_t buf[4];
+  size_t n = utf8_encode(c, buf);
+  return yt_buf(YT_TEXT, n, buf);
+}
+
+static Val char_apply(enum char_op op, Val *args) {
+  Val x = args[0];
+  Val y = args[1];
+  uint32_t c;
+  if (op == CHAR_FROM_U32) {
+    return yt_is(x, YT_U32) ? fx_char(x->u.fixed) : NULL;
+  }
+  if (!yt_is(x, YT_CHAR)) {
+    return NULL;
+  }
+  c = x->u.chr;
+  switch (op) {
+    case CHAR_TO_U32:
+      return yt_fixed(YT_U32, c);
+    case CHAR_IS_ALPHABETIC:
+      return yt_bool(c < 0x80 ? is_lower(c | 0x20) : iswalpha((wint_t)c));
+    case CHAR_IS_ALPHANUMERIC:
+      return yt_bool(
+        c < 0x80 ? is_lower(c | 0x20) || is_digit(c) : iswalnum((wint_t)c));
+    case CHAR_IS_ASCII:
+      return yt_bool(c < 0x80);
+    case CHAR_IS_ASCII_ALPHABETIC:
+      return yt_bool(is_lower(c) || is_upper(c));
+    case CHAR_IS_ASCII_ALPHANUMERIC:
+      return yt_bool(is_lower(c) || is_upper(c) || is_digit(c));
+    case CHAR_IS_ASCII_CONTROL:
+      return yt_bool(c < 0x20 || c == 0x7f);
+    case CHAR_IS_ASCII_DIGIT:
+      return yt_bool(is_digit(c));
+    case CHAR_IS_ASCII_GRAPHIC:
+      return yt_bool(is_graphic(c));
+    case CHAR_IS_ASCII_HEXDIGIT:
+      return yt_bool(is_digit(c) || (c | 0x20) - 'a' < 6);
+    case CHAR_IS_ASCII_LOWERCASE:
+      return yt_bool(is_lower(c));
+    case CHAR_IS_ASCII_PUNCTUATION:
+      return yt_bool(
+        is_graphic(c) && !is_lower(c) && !is_upper(c) && !is_digit(c));
+    case CHAR_IS_ASCII_UPPERCASE:
+      return yt_bool(is_upper(c));
+    case CHAR_IS_ASCII_WHITESPACE:
+      return yt_bool(c == ' ' || c == '\t' || c == '\n' || c == '\f' || c == '\r');
+    case CHAR_IS_CONTROL:
+      return yt_bool(c < 0x20 || (c >= 0x7f && c < 0xa0));
+    case CHAR_IS_DIGIT:
+      if (!yt_is(y, YT_U32) || y->u.fixed > 36) {
+        return NULL;
+      }
+      else {
+        uint64_t d = is_digit(c) ? c - '0'
+                   : is_lower(c | 0x20) ? (c | 0x20) - 'a' + 10 : 36;
+        return yt_bool(d < y->u.fixed);
+      }
+    case CHAR_IS_LOWERCASE:
+      return yt_bool(c < 0x80 ? is_lower(c) : iswlower((wint_t)c));
+    case CHAR_IS_NUMERIC:
+      return yt_bool(c < 0x80 ? is_digit(c) : iswdigit((wint_t)c));
+    case CHAR_IS_UPPERCASE:
+      return yt_bool(c < 0x80 ? is_upper(c) : iswupper((wint_t)c));
+    case CHAR_IS_WHITESPACE:
+      return yt_bool(
+        (c >= 0x09 && c <= 0x0d) || c == ' ' || c == 0x85
+        || (c >= 0x80 && iswspace((wint_t)c)));
+    case CHAR_LEN_UTF8:
+      return yt_nat_u64(utf8_len(c));
+    case CHAR_LEN_UTF16:
+      return yt_nat_u64(c < 0x10000 ? 1 : 2);
+    case CHAR_TO_ASCII_LOWERCASE:
+      return yt_char(is_upper(c) ? c | 0x20 : c);
+    case CHAR_TO_ASCII_UPPERCASE:
+      return yt_char(is_lower(c) ? c & ~(uint32_t)0x20 : c);
+    case CHAR_TO_LOWERCASE:
+      return char_text(c < 0x80 ? (is_upper(c) ? c | 0x20 : c)
+                                : (uint32_t)towlower((wint_t)c));
+    case CHAR_TO_UPPERCASE:
+      return char_text(c < 0x80 ? (is_lower(c) ? c & ~(uint32_t)0x20 : c)
+                                : (uint32_t)towupper((wint_t)c));
+    default:
+      break;
+  }
+  if (!yt_is(y, YT_CHAR)) {
+    return NULL;
+  }
+  switch (op) {
+    case CHAR_EQL:
+      return yt_bool(c == y->u.chr);
+    case CHAR_LTE:
+      return yt_bool(c <= y->u.chr);
+    case CHAR_LTH:
+      return yt_bool(c < y->u.chr);
+    case CHAR_GTH:
+      return yt_bool(c > y->u.chr);
+    default:
+      return yt_bool(c >= y->u.chr);
+  }
+}
+
+/* `#Bool` */
+
+enum bool_op {
+  BOOL_EQL,
+  BOOL_LTE,
+  BOOL_LTH,
+  BOOL_GTE,
+  BOOL_GTH,
+  BOOL_AND,
+  BOOL_OR,
+  BOOL_XOR,
+  BOOL_NOT
+};
+
+static Val bool_apply(enum bool_op op, Val *args) {
+  int a;
+  int b;
+  if (!yt_is(args[0], YT_BOOL)) {
+    return NULL;
+  }
+  a = args[0]->u.bool_;
+  if (op == BOOL_NOT) {
+    return yt_bool(!a);
+  }
+  if (!yt_is(args[1], YT_BOOL)) {
+    return NULL;
+  }
+  b = args[1]->u.bool_;
+  switch (op) {
+    case BOOL_EQL:
+      return yt_bool(a == b);
+    case BOOL_LTE:
+      return yt_bool(a <= b);
+    case BOOL_LTH:
+      return yt_bool(a < b);
+    case BOOL_GTE:
+      return yt_bool(a >= b);
+    case BOOL_GTH:
+      return yt_bool(a > b);
+    case BOOL_AND:
+      return yt_bool(a && b);
+    case BOOL_OR:
+      return yt_bool(a || b);
+    default:
+      return yt_bool(a != b);
+  }
+}
+
+/* The table of primitive operations, by their Yatima symbol */
+
+#define YT_SEQ_OPS(T, t, TAG)                                                \
+  static Val t##_cons(Val *a) { return seq_apply(TAG, SEQ_CONS, a); }        \
+  static Val t##_len(Val *a) { return seq_apply(TAG, SEQ_LEN, a); }          \
+  static Val t##_head(Val *a) { return seq_apply(TAG, SEQ_HEAD, a); }        \
+  static Val t##_tail(Val *a) { return seq_apply(TAG, SEQ_TAIL, a); }        \
+  static Val t##_take(Val *a) { return seq_apply(TAG, SEQ_TAKE, a); }        \
+  static Val t##_drop(Val *a) { return seq_apply(TAG, SEQ_DROP, a); }        \
+  static Val t##_append(Val *a) { return seq_apply(TAG, SEQ_APPEND, a); }    \
+  static Val t##_insert(Val *a) { return seq_apply(TAG, SEQ_INSERT, a); }    \
+  static Val t##_remove(Val *a) { return seq_apply(TAG, SEQ_REMOVE, a); }    \
+  static Val t##_index(Val *a) { return seq_apply(TAG, SEQ_INDEX, a); }
+
+YT_SEQ_OPS(Bits, bits, YT_BITS)
+YT_SEQ_OPS(Bytes, bytes, YT_BYTES)
+
+#define YT_SEQ_TABLE(T, t)                                                   \
+  {"#" #T ".cons", 2, t##_cons}, {"#" #T ".len", 1, t##_len},                \
+  {"#" #T ".head", 1, t##_head}, {"#" #T ".tail", 1, t##_tail},              \
+  {"#" #T ".take", 2, t##_take}, {"#" #T ".drop", 2, t##_drop},              \
+  {"#" #T ".append", 2, t##_append}, {"#" #T ".insert", 3, t##_insert},      \
+  {"#" #T ".remove", 2, t##_remove}, {"#" #T ".index", 2, t##_index}
+
+#define YT_TEXT_OP(name, op) \
+  static Val text_##name(Val *a) { return text_apply(op, a); }
+
+YT_TEXT_OP(cons, TEXT_CONS)
+YT_TEXT_OP(append, TEXT_APPEND)
+YT_TEXT_OP(insert, TEXT_INSERT)
+YT_TEXT_OP(remove, TEXT_REMOVE)
+YT_TEXT_OP(take, TEXT_TAKE)
+YT_TEXT_OP(drop, TEXT_DROP)
+YT_TEXT_OP(eql, TEXT_EQL)
+YT_TEXT_OP(lte, TEXT_LTE)
+YT_TEXT_OP(lth, TEXT_LTH)
+YT_TEXT_OP(gte, TEXT_GTE)
+YT_TEXT_OP(gth, TEXT_GTH)
+YT_TEXT_OP(len_chars, TEXT_LEN_CHARS)
+YT_TEXT_OP(len_bytes, TEXT_LEN_BYTES)
+YT_TEXT_OP(len_lines, TEXT_LEN_LINES)
+YT_TEXT_OP(char, TEXT_CHAR)
+YT_TEXT_OP(byte, TEXT_BYTE)
+YT_TEXT_OP(line, TEXT_LINE)
+YT_TEXT_OP(char_at_byte, TEXT_CHAR_AT_BYTE)
+YT_TEXT_OP(byte_at_char, TEXT_BYTE_AT_CHAR)
+YT_TEXT_OP(line_at_byte, TEXT_LINE_AT_BYTE)
+YT_TEXT_OP(line_at_char, TEXT_LINE_AT_CHAR)
+YT_TEXT_OP(line_start_byte, TEXT_LINE_START_BYTE)
+YT_TEXT_OP(line_start_char, TEXT_LINE_START_CHAR)
+YT_TEXT_OP(to_bytes, TEXT_TO_BYTES)
+
+#define YT_CHAR_OP(name, op) \
+  static Val char_##name(Val *a) { return char_apply(op, a); }
+
+YT_CHAR_OP(from_U32, CHAR_FROM_U32)
+YT_CHAR_OP(to_U32, CHAR_TO_U32)
+YT_CHAR_OP(is_alphabetic, CHAR_IS_ALPHABETIC)
+YT_CHAR_OP(is_alphanumeric, CHAR_IS_ALPHANUMERIC)
+YT_CHAR_OP(is_ascii, CHAR_IS_ASCII)
+YT_CHAR_OP(is_ascii_alphabetic, CHAR_IS_ASCII_ALPHABETIC)
+YT_CHAR_OP(is_ascii_alphanumeric, CHAR_IS_ASCII_ALPHANUMERIC)
+YT_CHAR_OP(is_ascii_control, CHAR_IS_ASCII_CONTROL)
+YT_CHAR_OP(is_ascii_digit, CHAR_IS_ASCII_DIGIT)
+YT_CHAR_OP(is_ascii_graphic, CHAR_IS_ASCII_GRAPHIC)
+YT_CHAR_OP(is_ascii_hexdigit, CHAR_IS_ASCII_HEXDIGIT)
+YT_CHAR_OP(is_ascii_lowercase, CHAR_IS_ASCII_LOWERCASE)
+YT_CHAR_OP(is_ascii_punctuation, CHAR_IS_ASCII_PUNCTUATION)
+YT_CHAR_OP(is_ascii_uppercase, CHAR_IS_ASCII_UPPERCASE)
+YT_CHAR_OP(is_ascii_whitespace, CHAR_IS_ASCII_WHITESPACE)
+YT_CHAR_OP(is_control, CHAR_IS_CONTROL)
+YT_CHAR_OP(is_digit, CHAR_IS_DIGIT)
+YT_CHAR_OP(is_lowercase, CHAR_IS_LOWERCASE)
+YT_CHAR_OP(is_numeric, CHAR_IS_NUMERIC)
+YT_CHAR_OP(is_uppercase, CHAR_IS_UPPERCASE)
+YT_CHAR_OP(is_whitespace, CHAR_IS_WHITESPACE)
+YT_CHAR_OP(len_utf8, CHAR_LEN_UTF8)
+YT_CHAR_OP(len_utf16, CHAR_LEN_UTF16)
+YT_CHAR_OP(to_ascii_lowercase, CHAR_TO_ASCII_LOWERCASE)
+YT_CHAR_OP(to_ascii_uppercase, CHAR_TO_ASCII_UPPERCASE)
+YT_CHAR_OP(to_lowercase, CHAR_TO_LOWERCASE)
+YT_CHAR_OP(to_uppercase, CHAR_TO_UPPERCASE)
+YT_CHAR_OP(eql, CHAR_EQL)
+YT_CHAR_OP(lte, CHAR_LTE)
+YT_CHAR_OP(lth, CHAR_LTH)
+YT_CHAR_OP(gth, CHAR_GTH)
+YT_CHAR_OP(gte, CHAR_GTE)
+
+#define YT_BOOL_OP(name, op) \
+  static Val bool_##name(Val *a) { return bool_apply(op, a); }
+
+YT_BOOL_OP(eql, BOOL_EQL)
+YT_BOOL_OP(lte, BOOL_LTE)
+YT_BOOL_OP(lth, BOOL_LTH)
+YT_BOOL_OP(gte, BOOL_GTE)
+YT_BOOL_OP(gth, BOOL_GTH)
+YT_BOOL_OP(and, BOOL_AND)
+YT_BOOL_OP(or, BOOL_OR)
+YT_BOOL_OP(xor, BOOL_XOR)
+YT_BOOL_OP(not, BOOL_NOT)
+
+#define YT_FIXED_OPS(t, TAG)                                                 \
+  static Val t##_max(Val *a) { return fx_apply(TAG, FX_MAX, a); }            \
+  static Val t##_min(Val *a) { return fx_apply(TAG, FX_MIN, a); }            \
+  static Val t##_abs(Val *a) { return fx_apply(TAG, FX_ABS, a); }            \
+  static Val t##_sgn(Val *a) { return fx_apply(TAG, FX_SGN, a); }            \
+  static Val t##_eql(Val *a) { return fx_apply(TAG, FX_EQL, a); }            \
+  static Val t##_lte(Val *a) { return fx_apply(TAG, FX_LTE, a); }            \
+  static Val t##_lth(Val *a) { return fx_apply(TAG, FX_LTH, a); }            \
+  static Val t##_gth(Val *a) { return fx_apply(TAG, FX_GTH, a); }            \
+  static Val t##_gte(Val *a) { return fx_apply(TAG, FX_GTE, a); }            \
+  static Val t##_not(Val *a) { return fx_apply(TAG, FX_NOT, a); }            \
+  static Val t##_and(Val *a) { return fx_apply(TAG, FX_AND, a); }            \
+  static Val t##_or(Val *a) { return fx_apply(TAG, FX_OR, a); }              \
+  static Val t##_xor(Val *a) { return fx_apply(TAG, FX_XOR, a); }            \
+  static Val t##_add(Val *a) { return fx_apply(TAG, FX_ADD, a); }            \
+  static Val t##_sub(Val *a) { return fx_apply(TAG, FX_SUB, a); }            \
+  static Val t##_mul(Val *a) { return fx_apply(TAG, FX_MUL, a); }            \
+  static Val t##_div(Val *a) { return fx_apply(TAG, FX_DIV, a); }            \
+  static Val t##_mod(Val *a) { return fx_apply(TAG, FX_MOD, a); }            \
+  static Val t##_pow(Val *a) { return fx_apply(TAG, FX_POW, a); }            \
+  static Val t##_shl(Val *a) { return fx_apply(TAG, FX_SHL, a); }            \
+  static Val t##_shr(Val *a) { return fx_apply(TAG, FX_SHR, a); }            \
+  static Val t##_rol(Val *a) { return fx_apply(TAG, FX_ROL, a); }            \
+  static Val t##_ror(Val *a) { return fx_apply(TAG, FX_ROR, a); }            \
+  static Val t##_count_zeros(Val *a) {                                       \
+    return fx_apply(TAG, FX_COUNT_ZEROS, a);                                 \
+  }                                                                          \
+  static Val t##_count_ones(Val *a) { return fx_apply(TAG, FX_COUNT_ONES, a); } \
+  static Val t##_to_U8(Val *a) { return fx_apply(TAG, FX_TO_U8, a); }        \
+  static Val t##_to_U16(Val *a) { return fx_apply(TAG, FX_TO_U16, a); }      \
+  static Val t##_to_U32(Val *a) { return fx_apply(TAG, FX_TO_U32, a); }      \
+  static Val t##_to_U64(Val *a) { return fx_apply(TAG, FX_TO_U64, a); }      \
+  static Val t##_to_U128(Val *a) { return fx_apply(TAG, FX_TO_U128, a); }    \
+  static Val t##_to_I8(Val *a) { return fx_apply(TAG, FX_TO_I8, a); }        \
+  static Val t##_to_I16(Val *a) { return fx_apply(TAG, FX_TO_I16, a); }      \
+  static Val t##_to_I32(Val *a) { return fx_apply(TAG, FX_TO_I32, a); }      \
+  static Val t##_to_I64(Val *a) { return fx_apply(TAG, FX_TO_I64, a); }      \
+  static Val t##_to_I128(Val *a) { return fx_apply(TAG, FX_TO_I128, a); }    \
+  static Val t##_to_Nat(Val *a) { return fx_apply(TAG, FX_TO_NAT, a); }      \
+  static Val t##_to_Int(Val *a) { return fx_apply(TAG, FX_TO_INT, a); }      \
+  static Val t##_to_Bits(Val *a) { return fx_apply(TAG, FX_TO_BITS, a); }    \
+  static Val t##_to_Bytes(Val *a) { return fx_apply(TAG, FX_TO_BYTES, a); }  \
+  static Val t##_to_Char(Val *a) { return fx_apply(TAG, FX_TO_CHAR, a); }
+
+YT_FIXED_OPS(u8, YT_U8)
+YT_FIXED_OPS(u16, YT_U16)
+YT_FIXED_OPS(u32, YT_U32)
+YT_FIXED_OPS(u64, YT_U64)
+YT_FIXED_OPS(i8, YT_I8)
+YT_FIXED_OPS(i16, YT_I16)
+YT_FIXED_OPS(i32, YT_I32)
+YT_FIXED_OPS(i64, YT_I64)
+
+#define YT_FIXED_TABLE(T, t)                                                 \
+  {"#" #T ".max", 0, t##_max}, {"#" #T ".min", 0, t##_min},                  \
+  {"#" #T ".abs", 1, t##_abs}, {"#" #T ".sgn", 1, t##_sgn},                  \
+  {"#" #T ".eql", 2, t##_eql}, {"#" #T ".lte", 2, t##_lte},                  \
+  {"#" #T ".lth", 2, t##_lth}, {"#" #T ".gth", 2, t##_gth},                  \
+  {"#" #T ".gte", 2, t##_gte}, {"#" #T ".not", 1, t##_not},                  \
+  {"#" #T ".and", 2, t##_and}, {"#" #T ".or", 2, t##_or},                    \
+  {"#" #T ".xor", 2, t##_xor}, {"#" #T ".add", 2, t##_add},                  \
+  {"#" #T ".sub", 2, t##_sub}, {"#" #T ".mul", 2, t##_mul},                  \
+  {"#" #T ".div", 2, t##_div}, {"#" #T ".mod", 2, t##_mod},                  \
+  {"#" #T ".pow", 2, t##_pow}, {"#" #T ".shl", 2, t##_shl},                  \
+  {"#" #T ".shr", 2, t##_shr}, {"#" #T ".rol", 2, t##_rol},                  \
+  {"#" #T ".ror", 2, t##_ror}, {"#" #T ".count_zeros", 1, t##_count_zeros},  \
+  {"#" #T ".count_ones", 1, t##_count_ones},                                 \
+  {"#" #T ".to_U8", 1, t##_to_U8}, {"#" #T ".to_U16", 1, t##_to_U16},        \
+  {"#" #T ".to_U32", 1, t##_to_U32}, {"#" #T ".to_U64", 1, t##_to_U64},      \
+  {"#" #T ".to_U128", 1, t##_to_U128}, {"#" #T ".to_I8", 1, t##_to_I8},      \
+  {"#" #T ".to_I16", 1, t##_to_I16}, {"#" #T ".to_I32", 1, t##_to_I32},      \
+  {"#" #T ".to_I64", 1, t##_to_I64}, {"#" #T ".to_I128", 1, t##_to_I128},    \
+  {"#" #T ".to_Nat", 1, t##_to_Nat}, {"#" #T ".to_Int", 1, t##_to_Int},      \
+  {"#" #T ".to_Bits", 1, t##_to_Bits}, {"#" #T ".to_Bytes", 1, t##_to_Bytes}, \
+  {"#" #T ".to_Char", 1, t##_to_Char}
+
+static const yt_op yt_ops[] = {
+  {"#Nat.suc", 1, nat_suc},
+  {"#Nat.pre", 1, nat_pre},
+  {"#Nat.eql", 2, nat_eql},
+  {"#Nat.lte", 2, nat_lte},
+  {"#Nat.lth", 2, nat_lth},
+  {"#Nat.gte", 2, nat_gte},
+  {"#Nat.gth", 2, nat_gth},
+  {"#Nat.add", 2, nat_add_op},
+  {"#Nat.sub", 2, nat_sub_op},
+  {"#Nat.mul", 2, nat_mul_op},
+  {"#Nat.div", 2, nat_div_op},
+  {"#Nat.mod", 2, nat_mod_op},
+  {"#Int.new", 2, int_new},
+  {"#Int.sgn", 1, int_sgn},
+  {"#Int.abs", 1, int_abs},
+  {"#Int.eql", 2, int_eql},
+  {"#Int.lte", 2, int_lte},
+  {"#Int.lth", 2, int_lth},
+  {"#Int.gte", 2, int_gte},
+  {"#Int.gth", 2, int_gth},
+  {"#Int.add", 2, int_add_op},
+  {"#Int.sub", 2, int_sub_op},
+  {"#Int.mul", 2, int_mul_op},
+  {"#Int.div", 2, int_div_op},
+  {"#Int.mod", 2, int_mod_op},
+  YT_SEQ_TABLE(Bits, bits),
+  {"#Bits.to_Bytes", 1, bits_to_bytes},
+  YT_SEQ_TABLE(Bytes, bytes),
+  {"#Bytes.to_Bits", 2, bytes_to_bits},
+  {"#Text.cons", 2, text_cons},
+  {"#Text.append", 2, text_append},
+  {"#Text.insert", 3, text_insert},
+  {"#Text.remove", 3, text_remove},
+  {"#Text.take", 2, text_take},
+  {"#Text.drop", 2, text_drop},
+  {"#Text.eql", 2, text_eql},
+  {"#Text.lte", 2, text_lte},
+  {"#Text.lth", 2, text_lth},
+  {"#Text.gte", 2, text_gte},
+  {"#Text.gth", 2, text_gth},
+  {"#Text.len_chars", 1, text_len_chars},
+  {"#Text.len_bytes", 1, text_len_bytes},
+  {"#Text.len_lines", 1, text_len_lines},
+  {"#Text.char", 2, text_char},
+  {"#Text.byte", 2, text_byte},
+  {"#Text.line", 2, text_line},
+  {"#Text.char_at_byte", 2, text_char_at_byte},
+  {"#Text.byte_at_char", 2, text_byte_at_char},
+  {"#Text.line_at_byte", 2, text_line_at_byte},
+  {"#Text.line_at_char", 2, text_line_at_char},
+  {"#Text.line_start_byte", 2, text_line_start_byte},
+  {"#Text.line_start_char", 2, text_line_start_char},
+  {"#Text.to_bytes", 1, text_to_bytes},
+  {"#Char.from_U32", 1, char_from_U32},
+  {"#Char.to_U32", 1, char_to_U32},
+  {"#Char.is_alphabetic", 1, char_is_alphabetic},
+  {"#Char.is_alphanumeric", 1, char_is_alphanumeric},
+  {"#Char.is_ascii", 1, char_is_ascii},
+  {"#Char.is_ascii_alphabetic", 1, char_is_ascii_alphabetic},
+  {"#Char.is_ascii_alphanumeric", 1, char_is_ascii_alphanumeric},
+  {"#Char.is_ascii_control", 1, char_is_ascii_control},
+  {"#Char.is_ascii_digit", 1, char_is_ascii_digit},
+  {"#Char.is_ascii_graphic", 1, char_is_ascii_graphic},
+  {"#Char.is_ascii_hexdigit", 1, char_is_ascii_hexdigit},
+  {"#Char.is_ascii_lowercase", 1, char_is_ascii_lowercase},
+  {"#Char.is_ascii_punctuation", 1, char_is_ascii_punctuation},
+  {"#Char.is_ascii_uppercase", 1, char_is_ascii_uppercase},
+  {"#Char.is_ascii_whitespace", 1, char_is_ascii_whitespace},
+  {"#Char.is_control", 1, char_is_control},
+  {"#Char.is_digit", 2, char_is_digit},
+  {"#Char.is_lowercase", 1, char_is_lowercase},
+  {"#Char.is_numeric", 1, char_is_numeric},
+  {"#Char.is_uppercase", 1, char_is_uppercase},
+  {"#Char.is_whitespace", 1, char_is_whitespace},
+  {"#Char.len_utf8", 1, char_len_utf8},
+  {"#Char.len_utf16", 1, char_len_utf16},
+  {"#Char.to_ascii_lowercase", 1, char_to_ascii_lowercase},
+  {"#Char.to_ascii_uppercase", 1, char_to_ascii_uppercase},
+  {"#Char.to_lowercase", 1, char_to_lowercase},
+  {"#Char.to_uppercase", 1, char_to_uppercase},
+  {"#Char.eql", 2, char_eql},
+  {"#Char.lte", 2, char_lte},
+  {"#Char.lth", 2, char_lth},
+  {"#Char.gth", 2, char_gth},
+  {"#Char.gte", 2, char_gte},
+  {"#Bool.eql", 2, bool_eql},
+  {"#Bool.lte", 2, bool_lte},
+  {"#Bool.lth", 2, bool_lth},
+  {"#Bool.gte", 2, bool_gte},
+  {"#Bool.gth", 2, bool_gth},
+  {"#Bool.and", 2, bool_and},
+  {"#Bool.or", 2, bool_or},
+  {"#Bool.xor", 2, bool_xor},
+  {"#Bool.not", 1, bool_not},
+  YT_FIXED_TABLE(U8, u8),
+  YT_FIXED_TABLE(U16, u16),
+  YT_FIXED_TABLE(U32, u32),
+  YT_FIXED_TABLE(U64, u64),
+  YT_FIXED_TABLE(I8, i8),
+  YT_FIXED_TABLE(I16, i16),
+  YT_FIXED_TABLE(I32, i32),
+  YT_FIXED_TABLE(I64, i64),
+};
+
+/* Looks up a primitive by symbol. Nullary primitives are values */
+static Val yt_prim(const char *name) {
+  size_t i;
+  for (i = 0; i < sizeof(yt_ops) / sizeof(yt_ops[0]); i++) {
+    if (strcmp(yt_ops[i].name, name) == 0) {
+      Val v;
+      if (yt_ops[i].arity == 0) {
+        Val none[3] = {NULL, NULL, NULL};
+        return yt_ops[i].fn(none);
+      }
+      v = yt_new(YT_PRIM);
+      v->u.prim.op = &yt_ops[i];
+      v->u.prim.len = 0;
+      return v;
+    }
+  }
+  yt_panic("unknown primitive ", name);
+  return NULL;
+}
+
+/* Primitives only run once fully applied, forcing all of their arguments */
+static Val yt_prim_apply(Val f, Val x) {
+  uint32_t i;
+  Val res;
+  Val p = yt_new(YT_PRIM);
+  p->u.prim.op = f->u.prim.op;
+  p->u.prim.len = f->u.prim.len + 1;
+  memset(p->u.prim.args, 0, sizeof(p->u.prim.args));
+  for (i = 0; i < f->u.prim.len; i++) {
+    p->u.prim.args[i] = yt_dup(f->u.prim.args[i]);
+  }
+  p->u.prim.args[f->u.prim.len] = x;
+  yt_drop(f);
+  if ((int)p->u.prim.len < p->u.prim.op->arity) {
+    return p;
+  }
+  for (i = 0; i < p->u.prim.len; i++) {
+    p->u.prim.args[i] = yt_force(p->u.prim.args[i]);
+  }
+  res = p->u.prim.op->fn(p->u.prim.args);
+  if (!res) {
+    yt_panic("stuck primitive application ", p->u.prim.op->name);
+  }
+  yt_drop(p);
+  return res;
+}
+
+/* Case analysis. Literals are expanded into their inductive encodings, like
+ * `Literal::expand`, everything else is already its own eliminator. The
+ * eliminators skip the motive, take two branches and apply the selected one
+ * to the fields kept in the environment after the `#Bool` selector. */
+
+static Val elim_b(Val self, Val b) {
+  Val *env = self->u.clo.env;
+  uint32_t len = self->u.clo.len;
+  uint32_t i;
+  Val res;
+  if (env[0]->u.bool_) {
+    res = b;
+  }
+  else {
+    yt_drop(b);
+    res = yt_dup(env[len - 1]);
+  }
+  for (i = 1; i + 1 < len; i++) {
+    res = yt_apply(res, yt_dup(env[i]));
+  }
+  return res;
+}
+
+static Val elim_a(Val self, Val a) {
+  uint32_t i;
+  Val v = yt_new(YT_CLO);
+  v->u.clo.fun = elim_b;
+  v->u.clo.thk = NULL;
+  v->u.clo.len = self->u.clo.len + 1;
+  v->u.clo.env = (Val *)yt_alloc(v->u.clo.len * sizeof(Val));
+  v->u.clo.val = NULL;
+  for (i = 0; i < self->u.clo.len; i++) {
+    v->u.clo.env[i] = yt_dup(self->u.clo.env[i]);
+  }
+  v->u.clo.env[self->u.clo.len] = a;
+  return v;
+}
+
+static Val elim_p(Val self, Val p) {
+  Val *env = self->u.clo.env;
+  yt_drop(p);
+  switch (self->u.clo.len) {
+    case 1:
+      return yt_closure(elim_a, 1, yt_dup(env[0]));
+    case 2:
+      return yt_closure(elim_a, 2, yt_dup(env[0]), yt_dup(env[1]));
+    default:
+      return yt_closure(
+        elim_a, 3, yt_dup(env[0]), yt_dup(env[1]), yt_dup(env[2]));
+  }
+}
+
+/* An `#Int` has a single branch, applied to its sign and magnitude */
+static Val elim_i(Val self, Val i) {
+  Val *env = self->u.clo.env;
+  return yt_apply(yt_apply(i, yt_dup(env[0])), yt_dup(env[1]));
+}
+
+static Val elim_int(Val self, Val p) {
+  Val *env = self->u.clo.env;
+  yt_drop(p);
+  return yt_closure(elim_i, 2, yt_dup(env[0]), yt_dup(env[1]));
+}
+
+static Val yt_case(Val v) {
+  Val res;
+  size_t len;
+  v = yt_force(v);
+  switch (v->tag) {
+    case YT_BOOL:
+      res = yt_closure(elim_p, 1, yt_bool(!v->u.bool_));
+      break;
+    case YT_NAT:
+      if (v->u.num.mag.len == 0) {
+        res = yt_closure(elim_p, 1, yt_bool(0));
+      }
+      else {
+        yt_nat one = nat_from_u64(1);
+        res = yt_closure(
+          elim_p, 2, yt_bool(1), yt_nat_val(nat_sub(&v->u.num.mag, &one)));
+        free(one.digits);
+      }
+      break;
+    case YT_INT:
+      res = yt_closure(elim_int, 2, yt_bool(!v->u.num.neg),
+                       yt_nat_val(nat_copy(&v->u.num.mag)));
+      break;
+    case YT_TEXT:
+      if (v->u.buf.len == 0) {
+        res = yt_closure(elim_p, 1, yt_bool(0));
+      }
+      else {
+        uint32_t *cs;
+        size_t *offs;
+        utf8_decode(v, &cs, &offs);
+        res = yt_closure(elim_p, 3, yt_bool(1), yt_char(cs[0]),
+                         yt_buf(YT_TEXT, v->u.buf.len - offs[1],
+                                v->u.buf.data + offs[1]));
+        free(cs);
+        free(offs);
+      }
+      break;
+    case YT_BITS:
+    case YT_BYTES:
+      len = v->u.buf.len;
+      if (len == 0) {
+        res = yt_closure(elim_p, 1, yt_bool(0));
+      }
+      else {
+        res = yt_closure(elim_p, 3, yt_bool(1),
+                         seq_elem(v->tag, v->u.buf.data[len - 1]),
+                         yt_buf(v->tag, len - 1, v->u.buf.data));
+      }
+      break;
+    case YT_CLO:
+    case YT_PRIM:
+      return v;
+    default:
+      yt_panic("case on a literal without an inductive structure", NULL);
+      return NULL;
+  }
+  yt_drop(v);
+  return res;
+}
+
+/* Printing, in the syntax of Yatima literals */
+
+static void yt_print_text(FILE *out, Val t, char quote) {
+  uint32_t *cs;
+  size_t *offs;
+  size_t i;
+  size_t n = utf8_decode(t, &cs, &offs);
+  fputc(quote, out);
+  for (i = 0; i < n; i++) {
+    uint32_t c = cs[i];
+    if (c == '\\' || c == '"' || c == '\'') {
+      fprintf(out, "\\%c", (char)c);
+    }
+    else if (c == '\n') {
+      fputs("\\n", out);
+    }
+    else if (c == '\r') {
+      fputs("\\r", out);
+    }
+    else if (c == '\t') {
+      fputs("\\t", out);
+    }
+    else if (c < 0x20 || c >= 0x7f) {
+      fprintf(out, "\\u{%lx}", (unsigned long)c);
+    }
+    else {
+      fputc((int)c, out);
+    }
+  }
+  fputc(quote, out);
+  free(cs);
+  free(offs);
+}
+
+static void yt_print(FILE *out, Val v) {
+  size_t i;
+  static const char *fixed[] = {"u8", "u16", "u32", "u64",
+                                "i8", "i16", "i32", "i64"};
+  switch (v->tag) {
+    case YT_NAT:
+      nat_print(out, &v->u.num.mag);
+      break;
+    case YT_INT:
+    case YT_I128:
+      fputc(v->u.num.neg ? '-' : '+', out);
+      nat_print(out, &v->u.num.mag);
+      if (v->tag == YT_I128) {
+        fputs("i128", out);
+      }
+      break;
+    case YT_U128:
+      nat_print(out, &v->u.num.mag);
+      fputs("u128", out);
+      break;
+    case YT_BITS:
+      if (v->u.buf.len % 4 == 0) {
+        Val bytes = bits_to_bytes(&v);
+        fputs("#x", out);
+        for (i = bytes->u.buf.len; i > 0; i--) {
+          fprintf(out, "%02x", bytes->u.buf.data[i - 1]);
+        }
+        yt_drop(bytes);
+        break;
+      }
+      fputs("#b", out);
+      for (i = v->u.buf.len; i > 0; i--) {
+        fputc(v->u.buf.data[i - 1] ? '1' : '0', out);
+      }
+      break;
+    case YT_BYTES:
+      fputs("x'", out);
+      for (i = v->u.buf.len; i > 0; i--) {
+        fprintf(out, "%02x", v->u.buf.data[i - 1]);
+      }
+      fputc('\'', out);
+      break;
+    case YT_TEXT:
+      yt_print_text(out, v, '"');
+      break;
+    case YT_CHAR: {
+      uint8_t buf[4];
+      Val t = yt_buf(YT_TEXT, utf8_encode(v->u.chr, buf), buf);
+      yt_print_text(out, t, '\'');
+      yt_drop(t);
+      break;
+    }
+    case YT_BOOL:
+      fputs(v->u.bool_ ? "#Bool.true" : "#Bool.false", out);
+      break;
+    case YT_U8:
+    case YT_U16:
+    case YT_U32:
+    case YT_U64:
+      fprintf(out, "%llu%s", (unsigned long long)v->u.fixed,
+              fixed[v->tag - YT_U8]);
+      break;
+    case YT_I8:
+    case YT_I16:
+    case YT_I32:
+    case YT_I64:
+      fprintf(out, "%s%lld%s", fx_int(v->u.fixed) < 0 ? "" : "+",
+              (long long)fx_int(v->u.fixed), fixed[v->tag - YT_U8]);
+      break;
+    case YT_IRR:
+      fputs("Type", out);
+      break;
+    default:
+      fputs("<function>", out);
+      break;
+  }
+}
@@ -0,0 +1,388 @@
+use sp_cid::Cid;
+
+use sp_std::{
+  collections::{
+    btree_map::BTreeMap,
+    btree_set::BTreeSet,
+  },
+  vec::Vec,
+};
+
+use alloc::string::{
+  String,
+  ToString,
+};
+
+use crate::{
+  codegen::{
+    CodegenError,
+    Erased,
+    Program,
+  },
+  literal::Literal,
+};
+
+pub const PRELUDE: &str = include_str!("js/prelude.js");
+
+pub const HEADER: &str = "// Generated by yatima. Do not edit.\n";
+
+// Yatima names may contain almost any character, so everything outside of
+// `[A-Za-z0-9_]` is escaped as `$<hex codepoint>$`. The leading underscore
+// keeps definitions apart from the prelude's `$` names and from locals.
+pub fn mangle(nam: &str) -> String {
+  let mut res = String::from("_");
+  for c in nam.chars() {
+    if c.is_ascii_alphanumeric() || c == '_' {
+      res.push(c);
+    }
+    else {
+      res.push_str(&format!("${:x}$", c as u32));
+    }
+  }
+  res
+}
+
+pub fn string(s: &str) -> String {
+  let mut res = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => res.push_str("\\\""),
+      '\\' => res.push_str("\\\\"),
+      '\n' => res.push_str("\\n"),
+      '\r' => res.push_str("\\r"),
+      '\t' => res.push_str("\\t"),
+      c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => {
+        res.push_str(&format!("\\u{{{:x}}}", c as u32))
+      }
+      c => res.push(c),
+    }
+  }
+  res.push('"');
+  res
+}
+
+pub fn literal(lit: &Literal) -> String {
+  match lit {
+    Literal::Nat(x) => format!("{}n", x),
+    Literal::Int(x) => format!("{}n", x),
+    Literal::Bits(xs) => {
+      let xs: Vec<String> = xs.iter().map(|x| x.to_string()).collect();
+      format!("[{}]", xs.join(", "))
+    }
+    Literal::Bytes(xs) => {
+      let xs: Vec<String> = xs.iter().map(|x| x.to_string()).collect();
+      format!("new Uint8Array([{}])", xs.join(", "))
+    }
+    Literal::Text(x) => string(&x.to_string()),
+    Literal::Char(x) => string(&x.to_string()),
+    Literal::Bool(x) => x.to_string(),
+    Literal::U8(x) => x.to_string(),
+    Literal::U16(x) => x.to_string(),
+    Literal::U32(x) => x.to_string(),
+    Literal::I8(x) => x.to_string(),
+    Literal::I16(x) => x.to_string(),
+    Literal::I32(x) => x.to_string(),
+    Literal::U64(x) => format!("{}n", x),
+    Literal::U128(x) => format!("{}n", x),
+    Literal::I64(x) => format!("{}n", x),
+    Literal::I128(x) => format!("{}n", x),
+  }
+}
+
+struct Emitter<'a> {
+  idents: &'a BTreeMap<Cid, String>,
+  this: &'a str,
+}
+
+impl<'a> Emitter<'a> {
+  fn term(&self, term: &Erased, dep: u64) -> Result<String, CodegenError> {
+    match term {
+      Erased::Var(nam, idx) => {
+        if *idx >= dep {
+          Err(CodegenError::FreeVariable(nam.clone(), *idx))
+        }
+        else {
+          Ok(format!("x{}", dep - 1 - idx))
+        }
+      }
+      Erased::Lam(_, bod) => {
+        Ok(format!("(x{} => {})", dep, self.term(bod, dep + 1)?))
+      }
+      Erased::App(fun_arg) => {
+        let (fun, arg) = &**fun_arg;
+        let fun = self.term(fun, dep)?;
+        let arg = self.arg(arg, dep)?;
+        Ok(format!("$app({}, {})", fun, arg))
+      }
+      Erased::Ref(nam, cid) => match self.idents.get(cid) {
+        Some(ident) => Ok(ident.clone()),
+        None => Err(CodegenError::UndefinedReference(nam.clone(), *cid)),
+      },
+      Erased::Rec => Ok(self.this.to_string()),
+      Erased::Let(rec, _, exp_bod) => {
+        let (exp, bod) = &**exp_bod;
+        let exp = self.arg(exp, if *rec { dep + 1 } else { dep })?;
+        let bod = self.term(bod, dep + 1)?;
+        Ok(format!(
+          "(() => {{ const x{} = {}; return {}; }})()",
+          dep, exp, bod
+        ))
+      }
+      Erased::Cse(None, bod) => Ok(format!("$case({})", self.term(bod, dep)?)),
+      Erased::Cse(Some(lty), bod) => Ok(format!(
+        "$cases[{}]({})",
+        string(&lty.to_string()),
+        self.term(bod, dep)?
+      )),
+      Erased::Lit(lit) => Ok(literal(lit)),
+      Erased::Opr(opr) => Ok(format!("$ops[{}]", string(&opr.symbol()))),
+      Erased::Irr => Ok("null".to_string()),
+    }
+  }
+
+  // Arguments are passed by need, so anything that could do work when
+  // evaluated is delayed in a thunk
+  fn arg(&self, term: &Erased, dep: u64) -> Result<String, CodegenError> {
+    match term {
+      Erased::App(..) | Erased::Let(..) | Erased::Cse(..) => {
+        Ok(format!("$lazy(() => {})", self.term(term, dep)?))
+      }
+      _ => self.term(term, dep),
+    }
+  }
+
+  // Top level values must not touch other definitions while the module is
+  // loading, since those may not have been initialized yet
+  fn top(&self, term: &Erased) -> Result<String, CodegenError> {
+    match term {
+      Erased::Lam(..) | Erased::Lit(..) | Erased::Opr(..) | Erased::Irr => {
+        self.term(term, 0)
+      }
+      _ => Ok(format!("$lazy(() => {})", self.term(term, 0)?)),
+    }
+  }
+}
+
+// The definitions and exports of a program, without the prelude
+pub fn emit_defs(program: &Program) -> Result<String, CodegenError> {
+  let mut idents = BTreeMap::new();
+  let mut used = BTreeSet::new();
+  for (nam, cid, _) in &program.defs {
+    let base = mangle(nam);
+    let mut ident = base.clone();
+    let mut n = 0;
+    while used.contains(&ident) {
+      n += 1;
+      ident = format!("{}${}", base, n);
+    }
+    used.insert(ident.clone());
+    idents.insert(*cid, ident);
+  }
+  let mut res = String::new();
+  for (_, cid, term) in &program.defs {
+    let this = &idents[cid];
+    let emitter = Emitter { idents: &idents, this };
+    res.push_str(&format!("const {} = {};\n", this, emitter.top(term)?));
+  }
+  res.push_str("\nexport {\n");
+  for (nam, cid) in &program.exports {
+    res.push_str(&format!("  {} as {},\n", idents[cid], string(nam)));
+  }
+  res.push_str("};\n");
+  Ok(res)
+}
+
+// Compiles a program to a self-contained ES module. Every index entry is
+// exported under its Yatima name, along with the prelude's `force` and
+// `call` helpers for evaluating values from JavaScript:
+//
+//   import * as bool from "./bool.js";
+//   bool.call(bool["Bool.and"], true, false);
+pub fn emit(program: &Program) -> Result<String, CodegenError> {
+  Ok(format!("{}\n{}\n{}", HEADER, PRELUDE, emit_defs(program)?))
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    codegen::tests::{
+      every_op,
+      program,
+    },
+    prim::Op,
+  };
+  use ropey::Rope;
+  use std::{
+    fs,
+    process::Command,
+  };
+
+  #[test]
+  fn mangle_names() {
+    assert_eq!(mangle("fact"), "_fact");
+    assert_eq!(mangle("Nat.add"), "_Nat$2e$add");
+    assert_eq!(mangle("x'"), "_x$27$");
+    assert_eq!(mangle("λ"), "_$3bb$");
+  }
+
+  #[test]
+  fn literals() {
+    assert_eq!(literal(&Literal::Nat(10u64.into())), "10n");
+    assert_eq!(literal(&Literal::Int((-3i64).into())), "-3n");
+    assert_eq!(literal(&Literal::Bits(vec![true, false])), "[true, false]");
+    assert_eq!(
+      literal(&Literal::Bytes(vec![0, 255])),
+      "new Uint8Array([0, 255])"
+    );
+    assert_eq!(
+      literal(&Literal::Text(Rope::from_str("a\"b\n"))),
+      "\"a\\\"b\\n\""
+    );
+    assert_eq!(literal(&Literal::Char('\u{7}')), "\"\\u{7}\"");
+    assert_eq!(literal(&Literal::Bool(true)), "true");
+    assert_eq!(literal(&Literal::U8(7)), "7");
+    assert_eq!(literal(&Literal::I32(-7)), "-7");
+    assert_eq!(literal(&Literal::U64(u64::MAX)), "18446744073709551615n");
+    assert_eq!(literal(&Literal::I64(-1)), "-1n");
+  }
+
+  #[test]
+  fn golden_id() {
+    let prog = program(
+      "def id (A: Type) (x: A): A = x
+       def two: #Nat = id #Nat 2",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "const _id = (x0 => (x1 => x1));\n\
+       const _two = $lazy(() => $app($app(_id, null), 2n));\n\
+       \n\
+       export {\n  \
+         _id as \"id\",\n  \
+         _two as \"two\",\n\
+       };\n"
+    );
+  }
+
+  #[test]
+  fn golden_fact() {
+    let prog = program(
+      "def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+       #Nat.mul x (fact x'))",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "const _fact = (x0 => $app($app($app($cases[\"#Nat\"](x0), (x1 => \
+       null)), 1n), \
+       (x1 => $app($app($ops[\"#Nat.mul\"], x0), $lazy(() => $app(_fact, \
+       x1))))));\n\
+       \n\
+       export {\n  \
+         _fact as \"fact\",\n\
+       };\n"
+    );
+  }
+
+  #[test]
+  fn golden_let() {
+    let prog = program(
+      "def f: #Bool = let x: #Bool = #Bool.not #Bool.true; #Bool.and x x",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "const _f = $lazy(() => (() => { const x0 = $lazy(() => \
+       $app($ops[\"#Bool.not\"], true)); return \
+       $app($app($ops[\"#Bool.and\"], x0), x0); })());\n\
+       \n\
+       export {\n  \
+         _f as \"f\",\n\
+       };\n"
+    );
+  }
+
+  #[test]
+  fn golden_shared_names() {
+    let prog = program(
+      "def a: #U8 = 1u8
+       def b: #U8 = #U8.add a a",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "const _a = 1;\n\
+       const _b = $lazy(() => $app($app($ops[\"#U8.add\"], _a), _a));\n\
+       \n\
+       export {\n  \
+         _a as \"a\",\n  \
+         _b as \"b\",\n\
+       };\n"
+    );
+  }
+
+  #[test]
+  fn golden_case_int() {
+    let prog = program(
+      "def sign (x: #Int): #Bool = (case x) (λ _ => #Bool) (λ s n => s)",
+    );
+    assert_eq!(
+      emit_defs(&prog).unwrap(),
+      "const _sign = (x0 => $app($app($cases[\"#Int\"](x0), (x1 => null)), \
+       (x1 => (x2 => x1))));\n\
+       \n\
+       export {\n  \
+         _sign as \"sign\",\n\
+       };\n"
+    );
+  }
+
+  // Runs `case` on literals with node. `#Nat` and `#Int` are both BigInts, so
+  // this fails if a `case` picks its expansion by the representation.
+  #[test]
+  #[ignore = "needs node"]
+  fn run_case_on_literals() {
+    let prog = program(
+      "def sign (x: #Int): #Bool = (case x) (λ _ => #Bool) (λ s n => s)
+       def mag (x: #Int): #Nat = (case x) (λ _ => #Nat) (λ s n => n)
+       def len (t: #Text): #Nat = (case t) (λ _ => #Nat) 0 (λ c cs => \
+       #Nat.suc (len cs))",
+    );
+    // A directory of its own, so concurrent runs don't clobber each other
+    let dir = std::env::temp_dir()
+      .join(format!("yatima_codegen_js_case_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("case.mjs"), emit(&prog).unwrap()).unwrap();
+    fs::write(
+      dir.join("main.mjs"),
+      "import * as m from \"./case.mjs\";\n\
+       console.log(m.call(m.sign, -3n), m.call(m.mag, -3n), \
+       m.call(m.len, \"h\u{e9}llo\"));\n",
+    )
+    .unwrap();
+    let out = Command::new("node").arg(dir.join("main.mjs")).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "false 3n 5n\n");
+    fs::remove_dir_all(&dir).ok();
+  }
+
+  #[test]
+  fn prelude_implements_every_op() {
+    for op in every_op() {
+      let sym = op.symbol();
+      let found = match op {
+        Op::U8(_)
+        | Op::U16(_)
+        | Op::U32(_)
+        | Op::U64(_)
+        | Op::I8(_)
+        | Op::I16(_)
+        | Op::I32(_)
+        | Op::I64(_) => {
+          let (_, nam) = sym.split_once('.').unwrap();
+          PRELUDE.contains(&format!("\n    {}: [", nam))
+        }
+        _ => PRELUDE.contains(&format!("\n  {}: ", string(&sym))),
+      };
+      assert!(found, "missing primitive {} in the JS prelude", sym);
+    }
+  }
+}
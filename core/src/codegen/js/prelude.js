@@ -0,0 +1,606 @@
+// Yatima runtime. Arguments are passed lazily as memoized thunks, so the
+// generated code keeps the call-by-need semantics of the reference evaluator.
+//
+// Values: `#Nat`, `#Int`, 64 and 128 bit integers are BigInts, smaller fixed
+// width integers are numbers, `#Text` and `#Char` are strings, `#Bytes` is a
+// Uint8Array, `#Bits` is an array of booleans and `#Bool` is a boolean.
+
+class $Thunk {
+  constructor(fn) {
+    this.fn = fn;
+    this.val = undefined;
+  }
+}
+
+const $blackhole = () => {
+  throw new Error("yatima: thunk forced during its own evaluation");
+};
+
+const $lazy = (fn) => new $Thunk(fn);
+
+export function force(x) {
+  while (x instanceof $Thunk) {
+    if (x.fn !== null) {
+      const fn = x.fn;
+      x.fn = $blackhole;
+      x.val = fn();
+      x.fn = null;
+    }
+    x = x.val;
+  }
+  return x;
+}
+
+const $app = (f, x) => force(f)(x);
+
+// Applies a compiled function to arguments and evaluates the result
+export function call(f, ...args) {
+  let res = f;
+  for (const arg of args) {
+    res = $app(res, arg);
+  }
+  return force(res);
+}
+
+// Primitives are curried and only run once fully applied, at which point all
+// of their arguments are forced. An `undefined` result means the application
+// is stuck, e.g. a division by zero.
+function $prim(name, arity, fn) {
+  if (arity === 0) {
+    return fn();
+  }
+  const go = (args) => (x) => {
+    const xs = [...args, x];
+    if (xs.length < arity) {
+      return go(xs);
+    }
+    const res = fn(...xs.map(force));
+    if (res === undefined) {
+      throw new Error(`yatima: stuck primitive application ${name}`);
+    }
+    return res;
+  };
+  return go([]);
+}
+
+// `case` on data is the data itself, which is already its own eliminator
+const $case = (x) => force(x);
+
+// `case` on a literal expands it into its inductive encoding, like
+// `Literal::expand`. `#Nat` and `#Int` are both BigInts, so the emitter picks
+// the expansion by the literal type the checker inferred.
+function $caseSeq(x) {
+  x = force(x);
+  if (x.length === 0) {
+    return (p) => (n) => (c) => n;
+  }
+  const h = x[x.length - 1];
+  return (p) => (n) => (c) => $app($app(c, h), x.slice(0, -1));
+}
+
+const $cases = {
+  "#Nat": (x) => {
+    x = force(x);
+    return x === 0n ? (p) => (z) => (s) => z : (p) => (z) => (s) => $app(s, x - 1n);
+  },
+  "#Int": (x) => {
+    x = force(x);
+    return (p) => (i) => $app($app(i, x >= 0n), x < 0n ? -x : x);
+  },
+  "#Bits": $caseSeq,
+  "#Bytes": $caseSeq,
+  "#Text": (x) => {
+    x = force(x);
+    if (x === "") {
+      return (p) => (n) => (c) => n;
+    }
+    const h = String.fromCodePoint(x.codePointAt(0));
+    return (p) => (n) => (c) => $app($app(c, h), x.slice(h.length));
+  },
+  "#Bool": (x) =>
+    force(x) ? (p) => (t) => (f) => t : (p) => (t) => (f) => f,
+};
+
+const $index = (idx, len) => (idx < BigInt(len) ? Number(idx) : undefined);
+
+// `#Bits` and `#Bytes` share the stack-like operations of `prim/bits.rs` and
+// `prim/bytes.rs`, where the head is the last element.
+const $seq = (concat) => ({
+  cons: [2, (x, xs) => concat(xs, [x])],
+  len: [1, (xs) => BigInt(xs.length)],
+  head: [1, (xs) => (xs.length === 0 ? undefined : xs[xs.length - 1])],
+  tail: [1, (xs) => xs.slice(0, xs.length === 0 ? 0 : -1)],
+  take: [2, (n, xs) => (n <= BigInt(xs.length) ? xs.slice(0, Number(n)) : xs)],
+  drop: [
+    2,
+    (n, xs) => (n <= BigInt(xs.length) ? xs.slice(Number(n)) : xs.slice(0, 0)),
+  ],
+  append: [2, (xs, ys) => concat(xs, ys)],
+  insert: [
+    3,
+    (n, y, xs) => {
+      const i = $index(n, xs.length);
+      return i === undefined
+        ? xs
+        : concat(concat(xs.slice(0, i), [y]), xs.slice(i));
+    },
+  ],
+  remove: [
+    2,
+    (n, xs) => {
+      const i = $index(n, xs.length);
+      return i === undefined ? xs : concat(xs.slice(0, i), xs.slice(i + 1));
+    },
+  ],
+  index: [
+    2,
+    (n, xs) => {
+      const i = $index(n, xs.length);
+      return i === undefined ? undefined : xs[i];
+    },
+  ],
+});
+
+const $concatBits = (xs, ys) => [...xs, ...ys];
+
+function $concatBytes(xs, ys) {
+  const res = new Uint8Array(xs.length + ys.length);
+  res.set(xs, 0);
+  res.set(ys, xs.length);
+  return res;
+}
+
+const $bits = $seq($concatBits);
+
+const $bytes = $seq($concatBytes);
+
+// Bytes are expanded least significant bit first
+function $bytesToBits(len, bytes) {
+  const res = [];
+  for (const byte of bytes) {
+    for (let i = 0; i < 8; i++) {
+      res.push(((byte >> i) & 1) === 1);
+    }
+  }
+  return res.slice(0, len);
+}
+
+function $bitsToBytes(bits) {
+  const res = new Uint8Array(Math.ceil(bits.length / 8));
+  bits.forEach((b, i) => {
+    if (b) {
+      res[i >> 3] |= 1 << (i & 7);
+    }
+  });
+  return res;
+}
+
+// Compares strings by code point, matching the byte order of UTF-8
+function $cmpText(xs, ys) {
+  const a = Array.from(xs, (c) => c.codePointAt(0));
+  const b = Array.from(ys, (c) => c.codePointAt(0));
+  for (let i = 0; i < Math.min(a.length, b.length); i++) {
+    if (a[i] !== b[i]) {
+      return a[i] < b[i] ? -1 : 1;
+    }
+  }
+  return a.length - b.length;
+}
+
+const $utf8 = (xs) => new TextEncoder().encode(xs);
+
+const $utf8Len = (c) => {
+  const x = c.codePointAt(0);
+  return x < 0x80 ? 1 : x < 0x800 ? 2 : x < 0x10000 ? 3 : 4;
+};
+
+// Line breaks recognized by ropey
+const $isBreak = (c) => /^[\n\v\f\r\u0085\u2028\u2029]$/u.test(c);
+
+// The char index at which every line starts
+function $lineStarts(chars) {
+  const res = [0];
+  for (let i = 0; i < chars.length; i++) {
+    if (chars[i] === "\r" && chars[i + 1] === "\n") {
+      continue;
+    }
+    if ($isBreak(chars[i])) {
+      res.push(i + 1);
+    }
+  }
+  return res;
+}
+
+function $lineOf(chars, idx) {
+  const starts = $lineStarts(chars);
+  let line = 0;
+  while (line + 1 < starts.length && starts[line + 1] <= idx) {
+    line++;
+  }
+  return line;
+}
+
+function $charOfByte(chars, idx) {
+  let bytes = 0;
+  for (let i = 0; i < chars.length; i++) {
+    bytes += $utf8Len(chars[i]);
+    if (idx < bytes) {
+      return i;
+    }
+  }
+  return chars.length;
+}
+
+const $byteOfChar = (chars, idx) =>
+  chars.slice(0, idx).reduce((acc, c) => acc + $utf8Len(c), 0);
+
+function $textOp(fn) {
+  return (n, xs) => {
+    const chars = Array.from(xs);
+    return fn(n, chars);
+  };
+}
+
+function $fromCodePoint(x) {
+  if (x > 0x10ffff || (x >= 0xd800 && x <= 0xdfff)) {
+    return undefined;
+  }
+  return String.fromCodePoint(x);
+}
+
+function $digit(c) {
+  const x = c.codePointAt(0);
+  if (x >= 0x30 && x <= 0x39) {
+    return x - 0x30;
+  }
+  const l = x | 0x20;
+  return l >= 0x61 && l <= 0x7a ? l - 0x61 + 10 : Infinity;
+}
+
+const $test = (re) => [1, (c) => re.test(c)];
+
+const $code = (c) => c.codePointAt(0);
+
+const $isAsciiGraphic = (c) => $code(c) >= 0x21 && $code(c) <= 0x7e;
+
+// Checked conversion into a fixed width integer, `undefined` if out of range
+function $conv(x, bits, signed) {
+  const min = signed ? -(1n << BigInt(bits - 1)) : 0n;
+  const max = signed ? (1n << BigInt(bits - 1)) - 1n : (1n << BigInt(bits)) - 1n;
+  if (x < min || x > max) {
+    return undefined;
+  }
+  return bits > 32 ? x : Number(x);
+}
+
+// The operations shared by all fixed width integers, implemented on BigInts
+// and wrapped back into the type's representation
+function $fixed(type, bits, signed) {
+  const width = BigInt(bits);
+  const wrap = (x) =>
+    signed ? BigInt.asIntN(bits, x) : BigInt.asUintN(bits, x);
+  const big = (x) => BigInt(x);
+  const out = (x) => (bits > 32 ? wrap(x) : Number(wrap(x)));
+  const unsigned = (x) => BigInt.asUintN(bits, big(x));
+  const shift = (n) => BigInt(n % bits);
+  function pow(x, n) {
+    let res = 1n;
+    let base = wrap(big(x));
+    while (n > 0) {
+      if (n % 2 === 1) {
+        res = wrap(res * base);
+      }
+      base = wrap(base * base);
+      n = Math.floor(n / 2);
+    }
+    return out(res);
+  }
+  function bytes(x) {
+    const res = new Uint8Array(bits / 8);
+    let u = unsigned(x);
+    for (let i = res.length - 1; i >= 0; i--) {
+      res[i] = Number(u & 0xffn);
+      u >>= 8n;
+    }
+    return res;
+  }
+  function ones(x) {
+    let u = unsigned(x);
+    let res = 0;
+    while (u > 0n) {
+      res += Number(u & 1n);
+      u >>= 1n;
+    }
+    return res;
+  }
+  const ops = {
+    max: [0, () => out(signed ? (1n << (width - 1n)) - 1n : -1n)],
+    min: [0, () => out(signed ? 1n << (width - 1n) : 0n)],
+    abs: [1, (x) => $conv(x < 0 ? -big(x) : big(x), bits, false)],
+    sgn: [1, (x) => x > 0],
+    eql: [2, (x, y) => x === y],
+    lte: [2, (x, y) => x <= y],
+    lth: [2, (x, y) => x < y],
+    gth: [2, (x, y) => x > y],
+    gte: [2, (x, y) => x >= y],
+    not: [1, (x) => out(~big(x))],
+    and: [2, (x, y) => out(big(x) & big(y))],
+    or: [2, (x, y) => out(big(x) | big(y))],
+    xor: [2, (x, y) => out(big(x) ^ big(y))],
+    add: [2, (x, y) => out(big(x) + big(y))],
+    sub: [2, (x, y) => out(big(x) - big(y))],
+    mul: [2, (x, y) => out(big(x) * big(y))],
+    div: [2, (x, y) => (big(y) === 0n ? undefined : out(big(x) / big(y)))],
+    mod: [2, (x, y) => (big(y) === 0n ? undefined : out(big(x) % big(y)))],
+    pow: [2, (x, n) => pow(x, n)],
+    shl: [2, (n, x) => out(big(x) << shift(n))],
+    shr: [2, (n, x) => out(wrap(big(x)) >> shift(n))],
+    rol: [
+      2,
+      (n, x) => out((unsigned(x) << shift(n)) | (unsigned(x) >> (width - shift(n)))),
+    ],
+    ror: [
+      2,
+      (n, x) => out((unsigned(x) >> shift(n)) | (unsigned(x) << (width - shift(n)))),
+    ],
+    count_zeros: [1, (x) => bits - ones(x)],
+    count_ones: [1, (x) => ones(x)],
+    to_U8: [1, (x) => $conv(big(x), 8, false)],
+    to_U16: [1, (x) => $conv(big(x), 16, false)],
+    to_U32: [1, (x) => $conv(big(x), 32, false)],
+    to_U64: [1, (x) => $conv(big(x), 64, false)],
+    to_U128: [1, (x) => $conv(big(x), 128, false)],
+    to_I8: [1, (x) => $conv(big(x), 8, true)],
+    to_I16: [1, (x) => $conv(big(x), 16, true)],
+    to_I32: [1, (x) => $conv(big(x), 32, true)],
+    to_I64: [1, (x) => $conv(big(x), 64, true)],
+    to_I128: [1, (x) => $conv(big(x), 128, true)],
+    to_Nat: [1, (x) => (x < 0 ? undefined : big(x))],
+    to_Int: [1, (x) => big(x)],
+    to_Bits: [1, (x) => $bytesToBits(bits, bytes(x))],
+    to_Bytes: [1, (x) => bytes(x)],
+    to_Char: [1, (x) => $fromCodePoint(Number(x))],
+  };
+  const res = {};
+  for (const [name, op] of Object.entries(ops)) {
+    res[`#${type}.${name}`] = op;
+  }
+  return res;
+}
+
+const $table = {
+  "#Nat.suc": [1, (x) => x + 1n],
+  "#Nat.pre": [1, (x) => (x === 0n ? 0n : x - 1n)],
+  "#Nat.eql": [2, (x, y) => x === y],
+  "#Nat.lte": [2, (x, y) => x <= y],
+  "#Nat.lth": [2, (x, y) => x < y],
+  "#Nat.gte": [2, (x, y) => x >= y],
+  "#Nat.gth": [2, (x, y) => x > y],
+  "#Nat.add": [2, (x, y) => x + y],
+  "#Nat.sub": [2, (x, y) => (x >= y ? x - y : undefined)],
+  "#Nat.mul": [2, (x, y) => x * y],
+  "#Nat.div": [2, (x, y) => (y === 0n ? undefined : x / y)],
+  "#Nat.mod": [2, (x, y) => (y === 0n ? undefined : x % y)],
+
+  "#Int.new": [2, (s, n) => (s ? n : -n)],
+  "#Int.sgn": [1, (x) => x > 0n],
+  "#Int.abs": [1, (x) => (x < 0n ? -x : x)],
+  "#Int.eql": [2, (x, y) => x === y],
+  "#Int.lte": [2, (x, y) => x <= y],
+  "#Int.lth": [2, (x, y) => x < y],
+  "#Int.gte": [2, (x, y) => x >= y],
+  "#Int.gth": [2, (x, y) => x > y],
+  "#Int.add": [2, (x, y) => x + y],
+  "#Int.sub": [2, (x, y) => x - y],
+  "#Int.mul": [2, (x, y) => x * y],
+  "#Int.div": [2, (x, y) => (y === 0n ? undefined : x / y)],
+  "#Int.mod": [2, (x, y) => (y === 0n ? undefined : x % y)],
+
+  "#Bits.cons": $bits.cons,
+  "#Bits.len": $bits.len,
+  "#Bits.head": $bits.head,
+  "#Bits.tail": $bits.tail,
+  "#Bits.take": $bits.take,
+  "#Bits.drop": $bits.drop,
+  "#Bits.append": $bits.append,
+  "#Bits.insert": $bits.insert,
+  "#Bits.remove": $bits.remove,
+  "#Bits.index": $bits.index,
+  "#Bits.to_Bytes": [1, (xs) => $bitsToBytes(xs)],
+
+  "#Bytes.cons": $bytes.cons,
+  "#Bytes.len": $bytes.len,
+  "#Bytes.head": $bytes.head,
+  "#Bytes.tail": $bytes.tail,
+  "#Bytes.take": $bytes.take,
+  "#Bytes.drop": $bytes.drop,
+  "#Bytes.append": $bytes.append,
+  "#Bytes.insert": $bytes.insert,
+  "#Bytes.remove": $bytes.remove,
+  "#Bytes.index": $bytes.index,
+  "#Bytes.to_Bits": [2, (n, xs) => $bytesToBits(Number(n), xs)],
+
+  "#Text.cons": [2, (c, xs) => c + xs],
+  "#Text.append": [2, (xs, ys) => xs + ys],
+  "#Text.insert": [
+    3,
+    (n, ys, xs) => {
+      const chars = Array.from(xs);
+      if (n > BigInt(chars.length)) {
+        return xs;
+      }
+      chars.splice(Number(n), 0, ys);
+      return chars.join("");
+    },
+  ],
+  "#Text.remove": [
+    3,
+    (from, upto, xs) => {
+      const chars = Array.from(xs);
+      const len = BigInt(chars.length);
+      if (from > len || upto > len || upto < from) {
+        return xs;
+      }
+      chars.splice(Number(from), Number(upto - from));
+      return chars.join("");
+    },
+  ],
+  "#Text.take": [
+    2,
+    $textOp((n, cs) =>
+      n <= BigInt(cs.length) ? cs.slice(0, Number(n)).join("") : cs.join(""),
+    ),
+  ],
+  "#Text.drop": [
+    2,
+    $textOp((n, cs) =>
+      n <= BigInt(cs.length) ? cs.slice(Number(n)).join("") : "",
+    ),
+  ],
+  "#Text.eql": [2, (xs, ys) => xs === ys],
+  "#Text.lte": [2, (xs, ys) => $cmpText(xs, ys) <= 0],
+  "#Text.lth": [2, (xs, ys) => $cmpText(xs, ys) < 0],
+  "#Text.gte": [2, (xs, ys) => $cmpText(xs, ys) >= 0],
+  "#Text.gth": [2, (xs, ys) => $cmpText(xs, ys) > 0],
+  "#Text.len_chars": [1, (xs) => BigInt(Array.from(xs).length)],
+  "#Text.len_bytes": [1, (xs) => BigInt($utf8(xs).length)],
+  "#Text.len_lines": [1, (xs) => BigInt($lineStarts(Array.from(xs)).length)],
+  "#Text.char": [
+    2,
+    $textOp((n, cs) => {
+      const i = $index(n, cs.length);
+      return i === undefined ? undefined : cs[i];
+    }),
+  ],
+  "#Text.byte": [
+    2,
+    (n, xs) => {
+      const i = $index(n, Array.from(xs).length);
+      return i === undefined ? undefined : $utf8(xs)[i];
+    },
+  ],
+  "#Text.line": [
+    2,
+    $textOp((n, cs) => {
+      const starts = $lineStarts(cs);
+      const i = $index(n, starts.length);
+      return i === undefined
+        ? undefined
+        : cs.slice(starts[i], starts[i + 1]).join("");
+    }),
+  ],
+  "#Text.char_at_byte": [
+    2,
+    (n, xs) => {
+      const i = $index(n, $utf8(xs).length);
+      return i === undefined ? undefined : BigInt($charOfByte(Array.from(xs), i));
+    },
+  ],
+  "#Text.byte_at_char": [
+    2,
+    $textOp((n, cs) => {
+      const i = $index(n, cs.length);
+      return i === undefined ? undefined : BigInt($byteOfChar(cs, i));
+    }),
+  ],
+  "#Text.line_at_byte": [
+    2,
+    (n, xs) => {
+      const chars = Array.from(xs);
+      const i = $index(n, $utf8(xs).length);
+      return i === undefined
+        ? undefined
+        : BigInt($lineOf(chars, $charOfByte(chars, i)));
+    },
+  ],
+  "#Text.line_at_char": [
+    2,
+    $textOp((n, cs) => {
+      const i = $index(n, cs.length);
+      return i === undefined ? undefined : BigInt($lineOf(cs, i));
+    }),
+  ],
+  "#Text.line_start_byte": [
+    2,
+    $textOp((n, cs) => {
+      const starts = $lineStarts(cs);
+      const i = $index(n, starts.length);
+      return i === undefined ? undefined : BigInt($byteOfChar(cs, starts[i]));
+    }),
+  ],
+  "#Text.line_start_char": [
+    2,
+    $textOp((n, cs) => {
+      const starts = $lineStarts(cs);
+      const i = $index(n, starts.length);
+      return i === undefined ? undefined : BigInt(starts[i]);
+    }),
+  ],
+  "#Text.to_bytes": [1, (xs) => $utf8(xs)],
+
+  "#Char.from_U32": [1, (x) => $fromCodePoint(x)],
+  "#Char.to_U32": [1, (c) => $code(c)],
+  "#Char.is_alphabetic": $test(/^\p{Alphabetic}$/u),
+  "#Char.is_alphanumeric": $test(/^[\p{Alphabetic}\p{N}]$/u),
+  "#Char.is_ascii": [1, (c) => $code(c) < 0x80],
+  "#Char.is_ascii_alphabetic": $test(/^[A-Za-z]$/),
+  "#Char.is_ascii_alphanumeric": $test(/^[A-Za-z0-9]$/),
+  "#Char.is_ascii_control": [1, (c) => $code(c) < 0x20 || $code(c) === 0x7f],
+  "#Char.is_ascii_digit": $test(/^[0-9]$/),
+  "#Char.is_ascii_graphic": [1, (c) => $isAsciiGraphic(c)],
+  "#Char.is_ascii_hexdigit": $test(/^[0-9A-Fa-f]$/),
+  "#Char.is_ascii_lowercase": $test(/^[a-z]$/),
+  "#Char.is_ascii_punctuation": [
+    1,
+    (c) => $isAsciiGraphic(c) && !/^[A-Za-z0-9]$/.test(c),
+  ],
+  "#Char.is_ascii_uppercase": $test(/^[A-Z]$/),
+  "#Char.is_ascii_whitespace": $test(/^[ \t\n\f\r]$/),
+  "#Char.is_control": $test(/^\p{Cc}$/u),
+  "#Char.is_digit": [2, (c, r) => (r > 36 ? undefined : $digit(c) < r)],
+  "#Char.is_lowercase": $test(/^\p{Lowercase}$/u),
+  "#Char.is_numeric": $test(/^\p{N}$/u),
+  "#Char.is_uppercase": $test(/^\p{Uppercase}$/u),
+  "#Char.is_whitespace": $test(/^\p{White_Space}$/u),
+  "#Char.len_utf8": [1, (c) => BigInt($utf8Len(c))],
+  "#Char.len_utf16": [1, (c) => BigInt(c.length)],
+  "#Char.to_ascii_lowercase": [
+    1,
+    (c) => (/^[A-Z]$/.test(c) ? c.toLowerCase() : c),
+  ],
+  "#Char.to_ascii_uppercase": [
+    1,
+    (c) => (/^[a-z]$/.test(c) ? c.toUpperCase() : c),
+  ],
+  "#Char.to_lowercase": [1, (c) => c.toLowerCase()],
+  "#Char.to_uppercase": [1, (c) => c.toUpperCase()],
+  "#Char.eql": [2, (x, y) => x === y],
+  "#Char.lte": [2, (x, y) => $code(x) <= $code(y)],
+  "#Char.lth": [2, (x, y) => $code(x) < $code(y)],
+  "#Char.gth": [2, (x, y) => $code(x) > $code(y)],
+  "#Char.gte": [2, (x, y) => $code(x) >= $code(y)],
+
+  "#Bool.eql": [2, (x, y) => x === y],
+  "#Bool.lte": [2, (x, y) => x <= y],
+  "#Bool.lth": [2, (x, y) => x < y],
+  "#Bool.gte": [2, (x, y) => x >= y],
+  "#Bool.gth": [2, (x, y) => x > y],
+  "#Bool.and": [2, (x, y) => x && y],
+  "#Bool.or": [2, (x, y) => x || y],
+  "#Bool.xor": [2, (x, y) => x !== y],
+  "#Bool.not": [1, (x) => !x],
+
+  ...$fixed("U8", 8, false),
+  ...$fixed("U16", 16, false),
+  ...$fixed("U32", 32, false),
+  ...$fixed("U64", 64, false),
+  ...$fixed("I8", 8, true),
+  ...$fixed("I16", 16, true),
+  ...$fixed("I32", 32, true),
+  ...$fixed("I64", 64, true),
+};
+
+const $ops = {};
+for (const [name, [arity, fn]] of Object.entries($table)) {
+  $ops[name] = $prim(name, arity, fn);
+}
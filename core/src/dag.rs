@@ -2,12 +2,19 @@
 // Mitchel Wand "Bottom-up β-reduction: uplinks and λ-DAGs" (https://www.brics.dk/RS/04/38/BRICS-RS-04-38.pdf)
 
 use crate::{
+  arena::{
+    self,
+    Arena,
+    Node,
+  },
   defs::Def,
   dll::*,
+  gas,
   literal::{
     LitType,
     Literal,
   },
+  memory,
   name::Name,
   position::Pos,
   prim::Op,
@@ -25,13 +32,23 @@ use sp_std::{
   fmt,
   mem,
   boxed::Box,
+  rc::Rc,
 };
 
 use alloc::string::String;
 use sp_cid::Cid;
 
+#[cfg(feature = "debug-arena")]
+use sp_std::vec::Vec;
+
+pub mod hashcons;
+pub mod validate;
+
 pub struct DAG {
   pub head: DAGPtr,
+  // The arena the nodes of the graph are allocated in, which lives at least as
+  // long as the graph
+  arena: Option<Rc<Arena>>,
 }
 
 // A top-down λ-DAG pointer. Keeps track of what kind of node it points to.
@@ -106,6 +123,9 @@ pub struct Lam {
   pub bod: DAGPtr,
   pub bod_ref: Parents,
   pub var: Var,
+  // Whether the argument is reduced to weak head normal form before it is
+  // substituted, as in the λs of a definition marked `strict`
+  pub strict: bool,
   pub parents: Option<NonNull<Parents>>,
 }
 
@@ -180,6 +200,9 @@ pub struct Let {
   pub exp_ref: Parents,
   pub bod_ref: Parents,
   pub copy: Option<NonNull<Let>>,
+  // Whether the expression is reduced to weak head normal form before it is
+  // substituted
+  pub strict: bool,
   pub parents: Option<NonNull<Parents>>,
 }
 
@@ -215,10 +238,21 @@ pub struct Opr {
   pub parents: Option<NonNull<Parents>>,
 }
 
-// Auxiliary allocation functions
+// Auxiliary allocation functions. Nodes are allocated in the current arena
+// (see `arena::enter`).
+#[inline]
+pub fn alloc_val<T: Node>(val: T) -> NonNull<T> {
+  gas::charge(gas::ALLOC);
+  memory::alloc_node();
+  arena::with_slab(|slab| slab.alloc(val))
+}
+
+// Free a value allocated with `alloc_val` in the current arena. Bound
+// variables are part of their binders, and are never freed on their own.
 #[inline]
-pub fn alloc_val<T>(val: T) -> NonNull<T> {
-  NonNull::new(Box::leak(Box::new(val))).unwrap()
+pub fn free_val<T: Node>(link: NonNull<T>) {
+  memory::free_node();
+  arena::with_slab(|slab| unsafe { slab.free(link) })
 }
 
 #[inline]
@@ -226,6 +260,7 @@ pub fn alloc_lam(
   var_nam: Name,
   var_dep: u64,
   bod: DAGPtr,
+  strict: bool,
   parents: Option<NonNull<Parents>>,
 ) -> NonNull<Lam> {
   unsafe {
@@ -239,6 +274,7 @@ pub fn alloc_lam(
       },
       bod,
       bod_ref: mem::zeroed(),
+      strict,
       parents,
     });
     (*lam.as_ptr()).var.binder = BinderPtr::Lam(lam);
@@ -395,6 +431,7 @@ pub fn alloc_let(
   typ: DAGPtr,
   exp: DAGPtr,
   bod: NonNull<Lam>,
+  strict: bool,
   parents: Option<NonNull<Parents>>,
 ) -> NonNull<Let> {
   unsafe {
@@ -404,6 +441,7 @@ pub fn alloc_let(
       exp,
       bod,
       copy: None,
+      strict,
       typ_ref: mem::zeroed(),
       exp_ref: mem::zeroed(),
       bod_ref: mem::zeroed(),
@@ -534,7 +572,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_bod_parents.is_none() {
           free_dead_node(*bod)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Slf(mut link) => {
         let Slf { bod, bod_ref, .. } = &link.as_mut();
@@ -543,7 +581,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_bod_parents.is_none() {
           free_dead_node(*bod)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Fix(mut link) => {
         let Fix { bod, bod_ref, .. } = &link.as_mut();
@@ -552,7 +590,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_bod_parents.is_none() {
           free_dead_node(*bod)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Cse(link) => {
         let Cse { bod, bod_ref, .. } = link.as_ref();
@@ -561,7 +599,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_bod_parents.is_none() {
           free_dead_node(*bod)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Dat(link) => {
         let Dat { bod, bod_ref, .. } = &link.as_ref();
@@ -570,7 +608,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_bod_parents.is_none() {
           free_dead_node(*bod)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::All(link) => {
         let All { dom, img, dom_ref, img_ref, .. } = link.as_ref();
@@ -585,7 +623,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_img_parents.is_none() {
           free_dead_node(img)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::App(link) => {
         let App { fun, arg, fun_ref, arg_ref, .. } = link.as_ref();
@@ -599,7 +637,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_arg_parents.is_none() {
           free_dead_node(*arg)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Ann(link) => {
         let Ann { exp, typ, exp_ref, typ_ref, .. } = link.as_ref();
@@ -613,7 +651,7 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_typ_parents.is_none() {
           free_dead_node(*typ)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Let(link) => {
         let Let { exp, typ, exp_ref, typ_ref, bod, bod_ref, .. } =
@@ -634,46 +672,244 @@ pub fn free_dead_node(node: DAGPtr) {
         if new_bod_parents.is_none() {
           free_dead_node(bod)
         }
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Var(link) => {
         let Var { binder, .. } = link.as_ref();
         // only free Free variables, bound variables are freed with their binder
         if let BinderPtr::Free = binder {
-          Box::from_raw(link.as_ptr());
+          free_val(link);
         }
       }
       DAGPtr::Ref(link) => {
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Typ(link) => {
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Lit(link) => {
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::LTy(link) => {
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
       DAGPtr::Opr(link) => {
-        Box::from_raw(link.as_ptr());
+        free_val(link);
       }
     }
   }
 }
 
+// The node a parent pointer points into, if it isn't a root
+pub fn parent_node(parent: &ParentPtr) -> Option<DAGPtr> {
+  match parent {
+    ParentPtr::Root => None,
+    ParentPtr::LamBod(link) => Some(DAGPtr::Lam(*link)),
+    ParentPtr::SlfBod(link) => Some(DAGPtr::Slf(*link)),
+    ParentPtr::FixBod(link) => Some(DAGPtr::Fix(*link)),
+    ParentPtr::DatBod(link) => Some(DAGPtr::Dat(*link)),
+    ParentPtr::CseBod(link) => Some(DAGPtr::Cse(*link)),
+    ParentPtr::AppFun(link) | ParentPtr::AppArg(link) => {
+      Some(DAGPtr::App(*link))
+    }
+    ParentPtr::AllDom(link) | ParentPtr::AllImg(link) => {
+      Some(DAGPtr::All(*link))
+    }
+    ParentPtr::AnnTyp(link) | ParentPtr::AnnExp(link) => {
+      Some(DAGPtr::Ann(*link))
+    }
+    ParentPtr::LetTyp(link)
+    | ParentPtr::LetExp(link)
+    | ParentPtr::LetBod(link) => Some(DAGPtr::Let(*link)),
+  }
+}
+
+// Whether a node is still allocated in the current arena. Bound variables
+// live in their binders, so they are alive as long as the binder is.
+#[cfg(feature = "debug-arena")]
+pub fn is_live(node: DAGPtr) -> bool {
+  fn live<T: Node>(link: NonNull<T>) -> bool {
+    arena::with_slab(|slab: &mut arena::Slab<T>| slab.is_live(link))
+  }
+  match node {
+    DAGPtr::Var(link) => match unsafe { link.as_ref().binder } {
+      BinderPtr::Free => live(link),
+      BinderPtr::Lam(link) => live(link),
+      BinderPtr::Slf(link) => live(link),
+      BinderPtr::Fix(link) => live(link),
+    },
+    DAGPtr::Lam(link) => live(link),
+    DAGPtr::App(link) => live(link),
+    DAGPtr::All(link) => live(link),
+    DAGPtr::Slf(link) => live(link),
+    DAGPtr::Fix(link) => live(link),
+    DAGPtr::Dat(link) => live(link),
+    DAGPtr::Cse(link) => live(link),
+    DAGPtr::Ref(link) => live(link),
+    DAGPtr::Let(link) => live(link),
+    DAGPtr::Typ(link) => live(link),
+    DAGPtr::Ann(link) => live(link),
+    DAGPtr::Lit(link) => live(link),
+    DAGPtr::LTy(link) => live(link),
+    DAGPtr::Opr(link) => live(link),
+  }
+}
+
+// Collect the nodes reachable from a node, except bound variables
+#[cfg(feature = "debug-arena")]
+fn collect_nodes(node: DAGPtr, nodes: &mut BTreeSet<DAGPtr>) {
+  let mut stack = vec![node];
+  while let Some(node) = stack.pop() {
+    if !nodes.insert(node) {
+      continue;
+    }
+    unsafe {
+      match node {
+        DAGPtr::Var(link) => {
+          if !matches!(link.as_ref().binder, BinderPtr::Free) {
+            nodes.remove(&node);
+          }
+        }
+        DAGPtr::Lam(link) => stack.push(link.as_ref().bod),
+        DAGPtr::Slf(link) => stack.push(link.as_ref().bod),
+        DAGPtr::Fix(link) => stack.push(link.as_ref().bod),
+        DAGPtr::Dat(link) => stack.push(link.as_ref().bod),
+        DAGPtr::Cse(link) => stack.push(link.as_ref().bod),
+        DAGPtr::App(link) => {
+          let App { fun, arg, .. } = link.as_ref();
+          stack.push(*fun);
+          stack.push(*arg);
+        }
+        DAGPtr::All(link) => {
+          let All { dom, img, .. } = link.as_ref();
+          stack.push(*dom);
+          stack.push(DAGPtr::Lam(*img));
+        }
+        DAGPtr::Ann(link) => {
+          let Ann { typ, exp, .. } = link.as_ref();
+          stack.push(*typ);
+          stack.push(*exp);
+        }
+        DAGPtr::Let(link) => {
+          let Let { typ, exp, bod, .. } = link.as_ref();
+          stack.push(*typ);
+          stack.push(*exp);
+          stack.push(DAGPtr::Lam(*bod));
+        }
+        _ => (),
+      }
+    }
+  }
+}
+
+// Check that the nodes of a freed graph are either gone or still have parents
+// outside of it, and panic with the leaked ones otherwise
+#[cfg(feature = "debug-arena")]
+fn check_freed(nodes: &BTreeSet<DAGPtr>) {
+  let mut leaks: Vec<String> = vec![];
+  for node in nodes {
+    if !is_live(*node) {
+      continue;
+    }
+    let parents = get_parents(*node);
+    let dangling = DLL::iter_option(parents)
+      .any(|parent| parent_node(parent).map_or(false, |p| !is_live(p)));
+    if parents.is_none() || dangling {
+      leaks.push(format!("{} at {:p}", node_kind(node), node_addr(node)));
+    }
+  }
+  if !leaks.is_empty() {
+    panic!("DAG::free leaked {} nodes: {}", leaks.len(), leaks.join(", "));
+  }
+}
+
+// The name of the kind of a node, for diagnostics
+pub fn node_kind(node: &DAGPtr) -> &'static str {
+  match node {
+    DAGPtr::Var(_) => "Var",
+    DAGPtr::Lam(_) => "Lam",
+    DAGPtr::App(_) => "App",
+    DAGPtr::All(_) => "All",
+    DAGPtr::Slf(_) => "Slf",
+    DAGPtr::Fix(_) => "Fix",
+    DAGPtr::Dat(_) => "Dat",
+    DAGPtr::Cse(_) => "Cse",
+    DAGPtr::Ref(_) => "Ref",
+    DAGPtr::Let(_) => "Let",
+    DAGPtr::Typ(_) => "Typ",
+    DAGPtr::Ann(_) => "Ann",
+    DAGPtr::Lit(_) => "Lit",
+    DAGPtr::LTy(_) => "LTy",
+    DAGPtr::Opr(_) => "Opr",
+  }
+}
+
+// The address of a node, for diagnostics
+pub fn node_addr(node: &DAGPtr) -> *const u8 {
+  match node {
+    DAGPtr::Var(link) => link.as_ptr() as *const u8,
+    DAGPtr::Lam(link) => link.as_ptr() as *const u8,
+    DAGPtr::App(link) => link.as_ptr() as *const u8,
+    DAGPtr::All(link) => link.as_ptr() as *const u8,
+    DAGPtr::Slf(link) => link.as_ptr() as *const u8,
+    DAGPtr::Fix(link) => link.as_ptr() as *const u8,
+    DAGPtr::Dat(link) => link.as_ptr() as *const u8,
+    DAGPtr::Cse(link) => link.as_ptr() as *const u8,
+    DAGPtr::Ref(link) => link.as_ptr() as *const u8,
+    DAGPtr::Let(link) => link.as_ptr() as *const u8,
+    DAGPtr::Typ(link) => link.as_ptr() as *const u8,
+    DAGPtr::Ann(link) => link.as_ptr() as *const u8,
+    DAGPtr::Lit(link) => link.as_ptr() as *const u8,
+    DAGPtr::LTy(link) => link.as_ptr() as *const u8,
+    DAGPtr::Opr(link) => link.as_ptr() as *const u8,
+  }
+}
+
 impl DAG {
-  pub fn new(head: DAGPtr) -> DAG { DAG { head } }
+  // A graph with the given head, in the current arena
+  pub fn new(head: DAGPtr) -> DAG { DAG { head, arena: arena::current() } }
+
+  // The arena the graph is allocated in, if it was built in one
+  pub fn arena(&self) -> Option<Rc<Arena>> { self.arena.clone() }
+
+  // Run `f` with the arena of the graph as the current arena
+  pub fn enter<A>(&self, f: impl FnOnce() -> A) -> A {
+    arena::enter(self.arena.as_ref(), f)
+  }
 
+  // Free the graph by unlinking its roots from the head. Hash-consing may
+  // share the head with other graphs, and the head of a subgraph has parents
+  // in the graph around it, in which cases it outlives this one.
   pub fn free(self) {
-    match get_parents(self.head) {
-      None => (),
-      Some(pref) => unsafe {
-        Box::from_raw(pref.as_ptr());
-        set_parents(self.head, None);
-      },
-    }
-    free_dead_node(self.head)
+    self.enter(|| {
+      #[cfg(feature = "debug-arena")]
+      let nodes = {
+        let mut nodes = BTreeSet::new();
+        collect_nodes(self.head, &mut nodes);
+        nodes
+      };
+      // Only roots are allocated on their own; the other uplinks are fields of
+      // the parent nodes
+      let mut roots = vec![];
+      let mut iter = DLL::iter_option(get_parents(self.head));
+      while let Some(parent) = iter.next() {
+        if *parent == ParentPtr::Root {
+          roots.extend(iter.this());
+        }
+      }
+      for root in roots {
+        let rest = unsafe { root.as_ref().unlink_node() };
+        if get_parents(self.head) == Some(root) {
+          set_parents(self.head, rest.map(DLL::first));
+        }
+        free_val(root);
+      }
+      if get_parents(self.head).is_none() {
+        free_dead_node(self.head);
+      }
+      #[cfg(feature = "debug-arena")]
+      check_freed(&nodes);
+    })
   }
 
   pub fn dag_ptr_to_term(
@@ -825,23 +1061,29 @@ impl DAG {
     DAG::dag_ptr_to_term(&self.head, &mut map, 0, re_rec)
   }
 
+  // Build a graph in the current arena, or in a new one owned by the graph
   pub fn from_term(tree: &Term) -> Self {
-    let root = alloc_val(DLL::singleton(ParentPtr::Root));
-    DAG::new(DAG::from_term_inner(tree, 0, BTreeMap::new(), Some(root), None))
+    arena::scope(|| {
+      let root = alloc_val(DLL::singleton(ParentPtr::Root));
+      DAG::new(DAG::from_term_inner(tree, 0, BTreeMap::new(), Some(root), None))
+    })
   }
 
   pub fn from_def(def: &Def, name: Name) -> Self {
-    let root = alloc_val(DLL::singleton(ParentPtr::Root));
-    let (d, _, a) = def.embed();
-    let def_cid = d.cid();
-    let ast_cid = a.cid();
-    DAG::new(DAG::from_term_inner(
-      &def.term,
-      0,
-      BTreeMap::new(),
-      Some(root),
-      Some((name, def_cid, ast_cid)),
-    ))
+    arena::scope(|| {
+      let root = alloc_val(DLL::singleton(ParentPtr::Root));
+      let (d, _, a) = def.embed();
+      let def_cid = d.cid();
+      let ast_cid = a.cid();
+      DAG::new(DAG::from_term_strict(
+        &def.term,
+        0,
+        BTreeMap::new(),
+        Some(root),
+        Some((name, def_cid, ast_cid)),
+        def.strict,
+      ))
+    })
   }
 
   pub fn from_ref(
@@ -851,21 +1093,35 @@ impl DAG {
     ast_cid: Cid,
     parents: Option<NonNull<Parents>>,
   ) -> DAGPtr {
-    DAG::from_term_inner(
+    DAG::from_term_strict(
       &def.term,
       0,
       BTreeMap::new(),
       parents,
       Some((name, def_cid, ast_cid)),
+      def.strict,
     )
   }
 
   pub fn from_term_inner(
+    tree: &Term,
+    depth: u64,
+    ctx: BTreeMap<usize, DAGPtr>,
+    parents: Option<NonNull<Parents>>,
+    rec_ref: Option<(Name, Cid, Cid)>,
+  ) -> DAGPtr {
+    DAG::from_term_strict(tree, depth, ctx, parents, rec_ref, false)
+  }
+
+  // Build the graph of a term whose λs and `let`s are strict if `strict` is
+  // set, as those of a definition marked `strict` are
+  pub fn from_term_strict(
     tree: &Term,
     depth: u64,
     mut ctx: BTreeMap<usize, DAGPtr>,
     parents: Option<NonNull<Parents>>,
     rec_ref: Option<(Name, Cid, Cid)>,
+    strict: bool,
   ) -> DAGPtr {
     match tree {
       Term::Rec(_) => match rec_ref {
@@ -923,15 +1179,16 @@ impl DAG {
         parents,
       })),
       Term::Lam(_, nam, bod) => unsafe {
-        let lam = alloc_lam(nam.clone(), 0, mem::zeroed(), parents);
+        let lam = alloc_lam(nam.clone(), 0, mem::zeroed(), strict, parents);
         let Lam { var, bod_ref, .. } = &mut *lam.as_ptr();
         ctx.insert(depth as usize, DAGPtr::Var(NonNull::new(var).unwrap()));
-        let bod = DAG::from_term_inner(
+        let bod = DAG::from_term_strict(
           &**bod,
           depth + 1,
           ctx,
           NonNull::new(bod_ref),
           rec_ref.clone(),
+          strict,
         );
         (*lam.as_ptr()).bod = bod;
         DAGPtr::Lam(lam)
@@ -940,12 +1197,13 @@ impl DAG {
         let slf = alloc_slf(nam.clone(), 0, mem::zeroed(), parents);
         let Slf { var, bod_ref, .. } = &mut *slf.as_ptr();
         ctx.insert(depth as usize, DAGPtr::Var(NonNull::new(var).unwrap()));
-        let bod = DAG::from_term_inner(
+        let bod = DAG::from_term_strict(
           &**bod,
           depth + 1,
           ctx,
           NonNull::new(bod_ref),
           rec_ref.clone(),
+          strict,
         );
         (*slf.as_ptr()).bod = bod;
         DAGPtr::Slf(slf)
@@ -953,12 +1211,13 @@ impl DAG {
       Term::Dat(_, bod) => unsafe {
         let dat = alloc_dat(mem::zeroed(), parents);
         let Dat { bod_ref, .. } = &mut *dat.as_ptr();
-        let bod = DAG::from_term_inner(
+        let bod = DAG::from_term_strict(
           &**bod,
           depth,
           ctx,
           NonNull::new(bod_ref),
           rec_ref.clone(),
+          strict,
         );
         (*dat.as_ptr()).bod = bod;
         DAGPtr::Dat(dat)
@@ -966,12 +1225,13 @@ impl DAG {
       Term::Cse(_, bod) => unsafe {
         let cse = alloc_cse(mem::zeroed(), parents);
         let Cse { bod_ref, .. } = &mut *cse.as_ptr();
-        let bod = DAG::from_term_inner(
+        let bod = DAG::from_term_strict(
           &**bod,
           depth,
           ctx,
           NonNull::new(bod_ref),
           rec_ref.clone(),
+          strict,
         );
         (*cse.as_ptr()).bod = bod;
         DAGPtr::Cse(cse)
@@ -980,24 +1240,31 @@ impl DAG {
         let (dom, img) = &**dom_img;
         let all = alloc_all(*uses, mem::zeroed(), NonNull::dangling(), parents);
         let All { dom_ref, img_ref, .. } = &mut *all.as_ptr();
-        let lam =
-          alloc_lam(nam.clone(), 0, mem::zeroed(), NonNull::new(img_ref));
+        let lam = alloc_lam(
+          nam.clone(),
+          0,
+          mem::zeroed(),
+          false,
+          NonNull::new(img_ref),
+        );
         let Lam { var, bod_ref, .. } = &mut *lam.as_ptr();
         let mut img_ctx = ctx.clone();
-        let dom = DAG::from_term_inner(
+        let dom = DAG::from_term_strict(
           dom,
           depth,
           ctx,
           NonNull::new(dom_ref),
           rec_ref.clone(),
+          strict,
         );
         img_ctx.insert(depth as usize, DAGPtr::Var(NonNull::new(var).unwrap()));
-        let img = DAG::from_term_inner(
+        let img = DAG::from_term_strict(
           img,
           depth + 1,
           img_ctx,
           NonNull::new(bod_ref),
           rec_ref.clone(),
+          strict,
         );
         (*all.as_ptr()).dom = dom;
         (*all.as_ptr()).img = lam;
@@ -1008,19 +1275,21 @@ impl DAG {
         let (fun, arg) = &**fun_arg;
         let app = alloc_app(mem::zeroed(), mem::zeroed(), parents);
         let App { fun_ref, arg_ref, .. } = &mut *app.as_ptr();
-        let fun = DAG::from_term_inner(
+        let fun = DAG::from_term_strict(
           fun,
           depth,
           ctx.clone(),
           NonNull::new(fun_ref),
           rec_ref.clone(),
+          strict,
         );
-        let arg = DAG::from_term_inner(
+        let arg = DAG::from_term_strict(
           arg,
           depth,
           ctx,
           NonNull::new(arg_ref),
           rec_ref.clone(),
+          strict,
         );
         (*app.as_ptr()).fun = fun;
         (*app.as_ptr()).arg = arg;
@@ -1030,19 +1299,21 @@ impl DAG {
         let (typ, exp) = &**typ_exp;
         let ann = alloc_ann(mem::zeroed(), mem::zeroed(), parents);
         let Ann { typ_ref, exp_ref, .. } = &mut *ann.as_ptr();
-        let typ = DAG::from_term_inner(
+        let typ = DAG::from_term_strict(
           typ,
           depth,
           ctx.clone(),
           NonNull::new(typ_ref),
           rec_ref.clone(),
+          strict,
         );
-        let exp = DAG::from_term_inner(
+        let exp = DAG::from_term_strict(
           exp,
           depth,
           ctx,
           NonNull::new(exp_ref),
           rec_ref.clone(),
+          strict,
         );
         (*ann.as_ptr()).typ = typ;
         (*ann.as_ptr()).exp = exp;
@@ -1056,30 +1327,38 @@ impl DAG {
           mem::zeroed(),
           mem::zeroed(),
           NonNull::dangling(),
+          strict,
           parents,
         );
         let Let { typ_ref, exp_ref, bod_ref, .. } = &mut *let_.as_ptr();
-        let lam =
-          alloc_lam(nam.clone(), 0, mem::zeroed(), NonNull::new(bod_ref));
+        let lam = alloc_lam(
+          nam.clone(),
+          0,
+          mem::zeroed(),
+          false,
+          NonNull::new(bod_ref),
+        );
         let Lam { var: lam_var, bod_ref: lam_bod_ref, .. } = &mut *lam.as_ptr();
         // Sets up the context for `typ` and `bod` conversion
         let typ_ctx = ctx.clone();
         let mut bod_ctx = ctx.clone();
         bod_ctx.insert(depth as usize, DAGPtr::Var(NonNull::new(lam_var).unwrap()));
         // Convert `typ` and `bod` to DAG and add it to the newly created `Let` node
-        let typ = DAG::from_term_inner(
+        let typ = DAG::from_term_strict(
           typ,
           depth,
           typ_ctx,
           NonNull::new(typ_ref),
           rec_ref.clone(),
+          strict,
         );
-        let bod = DAG::from_term_inner(
+        let bod = DAG::from_term_strict(
           bod,
           depth + 1,
           bod_ctx,
           NonNull::new(lam_bod_ref),
           rec_ref.clone(),
+          strict,
         );
         (*let_.as_ptr()).typ = typ;
         (*let_.as_ptr()).bod = lam;
@@ -1090,23 +1369,25 @@ impl DAG {
             alloc_fix(nam.clone(), 0, mem::zeroed(), NonNull::new(exp_ref));
           let Fix { var: fix_var, bod_ref: fix_bod_ref, .. } = &mut *fix.as_ptr();
           ctx.insert(depth as usize, DAGPtr::Var(NonNull::new(fix_var).unwrap()));
-          let exp = DAG::from_term_inner(
+          let exp = DAG::from_term_strict(
             exp,
             depth + 1,
             ctx,
             NonNull::new(fix_bod_ref),
             rec_ref.clone(),
+            strict,
           );
           (*let_.as_ptr()).exp = DAGPtr::Fix(fix);
           (*fix.as_ptr()).bod = exp;
         }
         else {
-          let exp = DAG::from_term_inner(
+          let exp = DAG::from_term_strict(
             exp,
             depth,
             ctx,
             NonNull::new(exp_ref),
             rec_ref.clone(),
+            strict,
           );
           (*let_.as_ptr()).exp = exp;
         }
@@ -1154,9 +1435,9 @@ impl DAG {
         DAGPtr::Ref(node)
       },
       DAGPtr::Lam(link) => unsafe {
-        let Lam { var, bod, .. } = &mut *link.as_ptr();
+        let Lam { var, bod, strict, .. } = &mut *link.as_ptr();
         let lam =
-          alloc_lam(var.nam.clone(), var.dep, mem::zeroed(), parents);
+          alloc_lam(var.nam.clone(), var.dep, mem::zeroed(), *strict, parents);
         let Lam { var: new_var, bod: new_bod, bod_ref, .. } =
           &mut *lam.as_ptr();
         map.insert(
@@ -1230,8 +1511,8 @@ impl DAG {
         DAGPtr::All(all)
       },
       DAGPtr::Let(link) => unsafe {
-        let Let { uses, typ, exp, bod, .. } = &mut *link.as_ptr();
-        let let_ = alloc_let(*uses, mem::zeroed(), mem::zeroed(), NonNull::dangling(), parents);
+        let Let { uses, typ, exp, bod, strict, .. } = &mut *link.as_ptr();
+        let let_ = alloc_let(*uses, mem::zeroed(), mem::zeroed(), NonNull::dangling(), *strict, parents);
         let Let {typ: new_typ, typ_ref, exp: new_exp, exp_ref, bod: new_bod, bod_ref, ..} =
           &mut *let_.as_ptr();
         *new_exp = DAG::from_subdag(*exp, map, NonNull::new(exp_ref));
@@ -1343,9 +1624,11 @@ impl DAG {
 
 impl Clone for DAG {
   fn clone(&self) -> Self {
-    let mut map: BTreeMap<DAGPtr, DAGPtr> = BTreeMap::new();
-    let root = alloc_val(DLL::singleton(ParentPtr::Root));
-    DAG::new(DAG::from_subdag(self.head, &mut map, Some(root)))
+    self.enter(|| {
+      let mut map: BTreeMap<DAGPtr, DAGPtr> = BTreeMap::new();
+      let root = alloc_val(DLL::singleton(ParentPtr::Root));
+      DAG::new(DAG::from_subdag(self.head, &mut map, Some(root)))
+    })
   }
 }
 
@@ -1656,4 +1939,66 @@ pub mod test {
     let y = DAG::to_term(&DAG::from_def(&x, Name::from("test")), true);
     x.term == y
   }
+
+  #[test]
+  fn graphs_own_their_arena() {
+    let dag = DAG::from_term(&Term::Typ(Pos::None));
+    let arena = Rc::downgrade(&dag.arena().unwrap());
+    let copy = dag.clone();
+    drop(dag);
+    assert!(arena.upgrade().is_some());
+    drop(copy);
+    assert!(arena.upgrade().is_none());
+  }
+
+  // Freeing a subgraph leaves the uplinks from the graph around it alone
+  #[test]
+  fn free_keeps_subgraphs_with_parents() {
+    let term = Term::App(
+      Pos::None,
+      Box::new((Term::Typ(Pos::None), Term::Typ(Pos::None))),
+    );
+    let dag = DAG::from_term(&term);
+    let arg = match dag.head {
+      DAGPtr::App(link) => unsafe { link.as_ref().arg },
+      _ => unreachable!(),
+    };
+    dag.enter(|| DAG::new(arg).free());
+    assert_eq!(dag.validate(), Ok(()));
+    assert_eq!(dag.to_term(true), term);
+    dag.free();
+  }
+
+  #[cfg(feature = "debug-arena")]
+  #[test]
+  fn free_normalized() {
+    use crate::{
+      defs::Defs,
+      eval::test::parse,
+    };
+    let (_, mut dag) =
+      parse("(λ s z => s (s z)) (λ s z => s (s z)) (λ x => x) Type").unwrap();
+    dag.norm(&Defs::new(), false);
+    assert_eq!(format!("{}", dag), "Type");
+    dag.free();
+  }
+
+  #[cfg(feature = "debug-arena")]
+  #[test]
+  #[should_panic(expected = "DAG::free leaked")]
+  fn free_reports_dangling_parents() {
+    arena::scope(|| {
+      let dag = DAG::from_term(&Term::App(
+        Pos::None,
+        Box::new((Term::Typ(Pos::None), Term::Typ(Pos::None))),
+      ));
+      // Give the argument a second uplink into the application, which doesn't
+      // get unlinked when the application is freed
+      if let DAGPtr::App(link) = dag.head {
+        let arg = unsafe { link.as_ref().arg };
+        add_to_parents(arg, alloc_val(DLL::singleton(ParentPtr::AppArg(link))));
+      }
+      dag.free();
+    })
+  }
 }
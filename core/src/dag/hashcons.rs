@@ -0,0 +1,425 @@
+// Hash-consing of λ-DAGs. Large proofs repeat the same closed subterms many
+// times, each as a separate subgraph. `HashCons::share` keys every closed node
+// by a content identifier of its kind and of the keys of its children, which is
+// the content address of its anonymous term computed bottom-up, and merges it
+// into the first node with the same key by moving its uplinks over and freeing
+// it. Graphs shared with the same table end up sharing nodes with each other.
+//
+// The checker keeps one table for each definition it checks, when asked to,
+// and shares the types it builds and the nodes it reduces while comparing
+// types, so conversion checking can tell that two subterms are equal by
+// comparing pointers.
+//
+// Only closed nodes are merged, since a node with free variables means
+// something else under other binders. The images of `∀` and the bodies of
+// `let` are keyed as part of their parents, and nodes hanging from a root are
+// never merged, so that `DAG::free` still owns the head of its graph.
+//
+// The table holds on to the node of every key through a `Dat` node without
+// parents, so that freeing the graphs it came from doesn't free it, and on to
+// the arena the graphs it shares are allocated in. Reducing
+// the node moves the holder to its reduct like any other parent, so a node is
+// only merged into the one of its key if that still has the same key.
+
+use crate::{
+  arena::{
+    self,
+    Arena,
+  },
+  dag::*,
+  dll::*,
+};
+
+use core::ptr::NonNull;
+
+use sp_cid::Cid;
+use sp_ipld::{
+  dag_cbor::cid,
+  Ipld,
+};
+use sp_std::{
+  collections::btree_map::BTreeMap,
+  rc::Rc,
+  vec::Vec,
+};
+
+// The level of the binder of free variables, below every node
+const FREE: u64 = 0;
+
+pub struct HashCons {
+  // The holder of the node every closed key was merged into
+  table: BTreeMap<Cid, NonNull<Dat>>,
+  // The arena of the graphs shared with the table
+  arena: Option<Rc<Arena>>,
+}
+
+impl HashCons {
+  // A table for graphs in the current arena
+  pub fn new() -> Self {
+    HashCons { table: BTreeMap::new(), arena: arena::current() }
+  }
+
+  // Merge the closed subgraphs of the graph below `node` with identical ones
+  // seen before
+  pub fn share(&mut self, node: DAGPtr) {
+    let arena = self.arena.clone();
+    arena::enter(arena.as_ref(), || {
+      self.visit(node, &mut BTreeMap::new(), &mut BTreeMap::new(), FREE + 1);
+    })
+  }
+
+  // Key a node `depth` binders deep and merge it if it's closed. Returns its
+  // key and the lowest level of a binder it refers to. The keys of the closed
+  // nodes visited by one call to `share` are kept in `seen`, including the
+  // ones merged away, since their parents may still be about to visit them.
+  fn visit(
+    &mut self,
+    node: DAGPtr,
+    levels: &mut BTreeMap<*mut Var, u64>,
+    seen: &mut BTreeMap<DAGPtr, Cid>,
+    depth: u64,
+  ) -> (Cid, u64) {
+    if let Some(key) = seen.get(&node) {
+      return (*key, u64::MAX);
+    }
+    let (key, low) = key_with(node, levels, depth, &mut |node, levels, depth| {
+      self.visit(node, levels, seen, depth)
+    });
+    if low < depth {
+      return (key, low);
+    }
+    seen.insert(node, key);
+    if is_rooted(node) {
+      return (key, u64::MAX);
+    }
+    match self.table.get(&key).map(|holder| unsafe { holder.as_ref().bod }) {
+      Some(canon) if canon == node => (),
+      Some(canon) if key_of(canon, seen) == key => {
+        replace_child(node, canon);
+        free_dead_node(node);
+      }
+      // The node of the key was reduced since, so this one takes its place
+      _ => self.hold(key, node),
+    }
+    (key, u64::MAX)
+  }
+
+  // Make `node` the node of `key`
+  fn hold(&mut self, key: Cid, node: DAGPtr) {
+    let holder = alloc_dat(node, None);
+    let Dat { bod_ref, .. } = unsafe { &mut *holder.as_ptr() };
+    add_to_parents(node, NonNull::from(bod_ref));
+    if let Some(old) = self.table.insert(key, holder) {
+      free_dead_node(DAGPtr::Dat(old));
+    }
+  }
+}
+
+impl Default for HashCons {
+  fn default() -> Self { Self::new() }
+}
+
+impl Drop for HashCons {
+  fn drop(&mut self) {
+    let arena = self.arena.take();
+    arena::enter(arena.as_ref(), || {
+      for holder in self.table.values() {
+        free_dead_node(DAGPtr::Dat(*holder));
+      }
+    })
+  }
+}
+
+// Key a node `depth` binders deep, keying its children with `child`. Returns
+// its key and the lowest level of a binder it refers to.
+fn key_with<F>(
+  node: DAGPtr,
+  levels: &mut BTreeMap<*mut Var, u64>,
+  depth: u64,
+  child: &mut F,
+) -> (Cid, u64)
+where
+  F: FnMut(DAGPtr, &mut BTreeMap<*mut Var, u64>, u64) -> (Cid, u64),
+{
+  let (tag, fields, low) = unsafe {
+    match node {
+      DAGPtr::Var(link) => match levels.get(&link.as_ptr()) {
+        Some(level) => {
+          let idx = Ipld::Integer((depth - level - 1) as i128);
+          (0, vec![idx], *level)
+        }
+        None => (0, vec![], FREE),
+      },
+      // Strict and lazy λs only differ in how they reduce, but merging them
+      // would change that for one of them
+      DAGPtr::Lam(link) => {
+        let Lam { var, bod, strict, .. } = &mut *link.as_ptr();
+        levels.insert(var, depth);
+        let (bod, low) = child(*bod, levels, depth + 1);
+        (1, vec![Ipld::Link(bod), Ipld::Bool(*strict)], low)
+      }
+      DAGPtr::App(link) => {
+        let App { fun, arg, .. } = link.as_ref();
+        let (fun, fun_low) = child(*fun, levels, depth);
+        let (arg, arg_low) = child(*arg, levels, depth);
+        (2, vec![Ipld::Link(fun), Ipld::Link(arg)], fun_low.min(arg_low))
+      }
+      DAGPtr::All(link) => {
+        let All { uses, dom, img, .. } = link.as_ref();
+        let Lam { var, bod, .. } = &mut *img.as_ptr();
+        let (dom, dom_low) = child(*dom, levels, depth);
+        levels.insert(var, depth);
+        let (img, img_low) = child(*bod, levels, depth + 1);
+        let fields = vec![uses.to_ipld(), Ipld::Link(dom), Ipld::Link(img)];
+        (3, fields, dom_low.min(img_low))
+      }
+      DAGPtr::Slf(link) => {
+        let Slf { var, bod, .. } = &mut *link.as_ptr();
+        levels.insert(var, depth);
+        let (bod, low) = child(*bod, levels, depth + 1);
+        (4, vec![Ipld::Link(bod)], low)
+      }
+      DAGPtr::Dat(link) => {
+        let (bod, low) = child(link.as_ref().bod, levels, depth);
+        (5, vec![Ipld::Link(bod)], low)
+      }
+      DAGPtr::Cse(link) => {
+        let (bod, low) = child(link.as_ref().bod, levels, depth);
+        (6, vec![Ipld::Link(bod)], low)
+      }
+      DAGPtr::Ref(link) => {
+        let Ref { exp, rec, .. } = link.as_ref();
+        (7, vec![Ipld::Link(*exp), Ipld::Bool(*rec)], u64::MAX)
+      }
+      DAGPtr::Let(link) => {
+        let Let { uses, typ, exp, bod, strict, .. } = link.as_ref();
+        let Lam { var, bod, .. } = &mut *bod.as_ptr();
+        let (typ, typ_low) = child(*typ, levels, depth);
+        let (exp, exp_low) = child(*exp, levels, depth);
+        levels.insert(var, depth);
+        let (bod, bod_low) = child(*bod, levels, depth + 1);
+        let fields = vec![
+          uses.to_ipld(),
+          Ipld::Link(typ),
+          Ipld::Link(exp),
+          Ipld::Link(bod),
+          Ipld::Bool(*strict),
+        ];
+        (8, fields, typ_low.min(exp_low).min(bod_low))
+      }
+      DAGPtr::Typ(_) => (9, vec![], u64::MAX),
+      DAGPtr::Ann(link) => {
+        let Ann { typ, exp, .. } = link.as_ref();
+        let (typ, typ_low) = child(*typ, levels, depth);
+        let (exp, exp_low) = child(*exp, levels, depth);
+        (10, vec![Ipld::Link(typ), Ipld::Link(exp)], typ_low.min(exp_low))
+      }
+      DAGPtr::Lit(link) => (11, vec![link.as_ref().lit.to_ipld()], u64::MAX),
+      DAGPtr::LTy(link) => (12, vec![link.as_ref().lty.to_ipld()], u64::MAX),
+      DAGPtr::Opr(link) => (13, vec![link.as_ref().opr.to_ipld()], u64::MAX),
+      // Fixpoints only exist in graphs, as the expressions of `letrec`
+      DAGPtr::Fix(link) => {
+        let Fix { var, bod, .. } = &mut *link.as_ptr();
+        levels.insert(var, depth);
+        let (bod, low) = child(*bod, levels, depth + 1);
+        (14, vec![Ipld::Link(bod)], low)
+      }
+    }
+  };
+  let key = cid(&Ipld::List(
+    Some(Ipld::Integer(tag)).into_iter().chain(fields).collect::<Vec<_>>(),
+  ));
+  (key, low)
+}
+
+// The key of a closed node, without merging anything
+fn key_of(node: DAGPtr, seen: &mut BTreeMap<DAGPtr, Cid>) -> Cid {
+  fn go(
+    node: DAGPtr,
+    levels: &mut BTreeMap<*mut Var, u64>,
+    seen: &mut BTreeMap<DAGPtr, Cid>,
+    depth: u64,
+  ) -> (Cid, u64) {
+    if let Some(key) = seen.get(&node) {
+      return (*key, u64::MAX);
+    }
+    let (key, low) = key_with(node, levels, depth, &mut |node, levels, depth| {
+      go(node, levels, seen, depth)
+    });
+    if low >= depth {
+      seen.insert(node, key);
+    }
+    (key, low)
+  }
+  go(node, &mut BTreeMap::new(), seen, FREE + 1).0
+}
+
+// Whether a node is the head of a graph
+fn is_rooted(node: DAGPtr) -> bool {
+  DLL::iter_option(get_parents(node)).any(|parent| *parent == ParentPtr::Root)
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    check::equal_dag,
+    dag::validate::roots_of,
+    defs::Defs,
+    eval::test::parse,
+    term::{
+      tests::{
+        affine,
+        test_defs,
+      },
+      Term,
+    },
+  };
+  use quickcheck::TestResult;
+
+  fn app_args(node: DAGPtr) -> (DAGPtr, DAGPtr) {
+    match node {
+      DAGPtr::App(link) => unsafe {
+        let App { fun, arg, .. } = link.as_ref();
+        (*fun, *arg)
+      },
+      _ => panic!("Not an application"),
+    }
+  }
+
+  fn lam_bod(node: DAGPtr) -> DAGPtr {
+    match node {
+      DAGPtr::Lam(link) => unsafe { link.as_ref().bod },
+      _ => panic!("Not a lambda"),
+    }
+  }
+
+  #[test]
+  fn shares_closed_subgraphs() {
+    arena::scope(|| {
+      let (_, dag) = parse("λ f => f (λ x => x) (λ y => y)").unwrap();
+      HashCons::new().share(dag.head);
+      assert_eq!(dag.validate(), Ok(()));
+      let (fun, snd) = app_args(lam_bod(dag.head));
+      let (_, fst) = app_args(fun);
+      assert!(fst == snd);
+      assert_eq!(format!("{}", dag), "λ f => f (λ x => x) (λ x => x)");
+      dag.free();
+    })
+  }
+
+  #[test]
+  fn keeps_open_subgraphs_apart() {
+    arena::scope(|| {
+      let (_, dag) = parse("λ x => (x x) (x x)").unwrap();
+      HashCons::new().share(dag.head);
+      let (fst, snd) = app_args(lam_bod(dag.head));
+      assert!(fst != snd);
+      dag.free();
+    })
+  }
+
+  #[test]
+  fn shares_between_graphs() {
+    arena::scope(|| {
+      let (_, a) = parse("λ f => f (λ x => x)").unwrap();
+      let (_, b) = parse("λ g => g (λ y => y)").unwrap();
+      let mut table = HashCons::new();
+      table.share(a.head);
+      table.share(b.head);
+      let (_, a_id) = app_args(lam_bod(a.head));
+      let (_, b_id) = app_args(lam_bod(b.head));
+      assert!(a_id == b_id);
+      // Each graph on its own has an uplink from outside of it, and so does the
+      // shared node, from the table
+      assert!(a.validate().is_err());
+      assert_eq!(roots_of(a_id).len(), 3);
+      assert_eq!(DAG::validate_shared(&roots_of(a_id)), Ok(()));
+      drop(table);
+      assert_eq!(DAG::validate_shared(&[a.head, b.head]), Ok(()));
+      a.free();
+      assert_eq!(b.validate(), Ok(()));
+      assert_eq!(format!("{}", b), "λ g => g (λ x => x)");
+      b.free();
+    })
+  }
+
+  #[test]
+  fn replaces_reduced_nodes() {
+    arena::scope(|| {
+      let (_, a) = parse("λ f => f ((λ x => x) Type)").unwrap();
+      let mut table = HashCons::new();
+      table.share(a.head);
+      let (_, redex) = app_args(lam_bod(a.head));
+      DAG::new(redex).whnf(&Defs::new(), false);
+      let (_, a_typ) = app_args(lam_bod(a.head));
+      assert!(matches!(a_typ, DAGPtr::Typ(_)));
+      // The redex of `b` has the key the reduced one had, but isn't merged into
+      // its reduct, while its `Type` is
+      let (_, b) = parse("λ g => g ((λ y => y) Type)").unwrap();
+      table.share(b.head);
+      let (_, b_redex) = app_args(lam_bod(b.head));
+      let (_, b_typ) = app_args(b_redex);
+      assert!(b_typ == a_typ);
+      assert_eq!(DAG::validate_shared(&roots_of(a_typ)), Ok(()));
+      drop(table);
+      assert_eq!(format!("{}", b), "λ g => g ((λ x => x) Type)");
+      a.free();
+      b.free();
+    })
+  }
+
+  // Comparing two graphs built separately shares their closed subterms after
+  // reducing them, so the arguments of `f` and `g` end up as one node
+  #[test]
+  fn comparing_shares_between_graphs() {
+    arena::scope(|| {
+      let defs = Defs::new();
+      let (_, mut a) = parse("λ f => f ((λ x => λ y => y) Type)").unwrap();
+      let (_, mut b) = parse("λ g => g (λ z => z)").unwrap();
+      let mut cons = Some(HashCons::new());
+      assert!(equal_dag(&defs, &mut a, &mut b, 0, false, &mut cons));
+      let (_, a_arg) = app_args(lam_bod(a.head));
+      let (_, b_arg) = app_args(lam_bod(b.head));
+      assert!(a_arg == b_arg);
+      assert_eq!(DAG::validate_shared(&roots_of(a_arg)), Ok(()));
+      a.free();
+      b.free();
+      drop(cons);
+    })
+  }
+
+  #[quickcheck]
+  fn sharing_preserves_terms(x: Term) -> bool {
+    arena::scope(|| {
+      let dag = DAG::from_term(&x);
+      HashCons::new().share(dag.head);
+      // Merged nodes keep the names of the first copy, so only the anonymous
+      // terms agree
+      let res =
+        dag.validate().is_ok() && dag.to_term(true).embed().0 == x.embed().0;
+      dag.free();
+      res
+    })
+  }
+
+  #[quickcheck]
+  fn sharing_preserves_normal_forms(x: Term) -> TestResult {
+    arena::scope(|| {
+      if !affine(&x, &mut vec![]) {
+        return TestResult::discard();
+      }
+      let defs: Defs = test_defs();
+      let mut plain = DAG::from_term(&x);
+      plain.norm(&defs, false);
+      let mut shared = DAG::from_term(&x);
+      HashCons::new().share(shared.head);
+      shared.norm(&defs, false);
+      let res =
+        plain.to_term(true).embed().0 == shared.to_term(true).embed().0;
+      plain.free();
+      shared.free();
+      TestResult::from_bool(res)
+    })
+  }
+}
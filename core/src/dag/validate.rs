@@ -0,0 +1,415 @@
+// Consistency checks for λ-DAGs. Reduction rewires parent pointers in place,
+// so a bug in `upcopy`, `subst` or `replace_child` usually shows up much later
+// as a wrong result or a crash. `DAG::validate` walks a graph and checks that:
+//
+// - every downlink is mirrored by an uplink in the parents of the child, and
+//   every uplink by a downlink
+// - the doubly-linked lists of parents are well formed
+// - bound variables are the variables of binders in the same graph
+// - no uplink points outside of the graph
+// - the graph is acyclic, except through fixpoints
+//
+// With the `debug-dag` feature, the evaluator validates the graph it reduces
+// after every step.
+
+use core::{
+  fmt,
+  ptr::NonNull,
+};
+
+use crate::{
+  dag::*,
+  dll::*,
+};
+
+use sp_std::{
+  collections::{
+    btree_map::BTreeMap,
+    btree_set::BTreeSet,
+  },
+  vec::Vec,
+};
+
+use alloc::string::String;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum DAGError {
+  // A parent whose child doesn't list the parent's uplink
+  MissingUplink(String, String),
+  // A child listing an uplink from a parent that doesn't point to it
+  StrayUplink(String, String),
+  // A node whose list of parents is not a well formed doubly-linked list
+  BrokenParents(String),
+  // A node listing an uplink from a node outside of the graph
+  DanglingUplink(String, String),
+  // A bound variable that isn't the variable of its binder, or whose binder is
+  // outside of the graph
+  EscapedVar(String),
+  // A cycle that doesn't go through a fixpoint
+  Cycle(String),
+  // A node of the graph that was already freed
+  FreedNode(String),
+}
+
+impl fmt::Display for DAGError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::MissingUplink(parent, child) => {
+        write!(f, "{} is missing the uplink from its parent {}", child, parent)
+      }
+      Self::StrayUplink(child, parent) => {
+        write!(f, "{} has an uplink from {}, which isn't its parent", child, parent)
+      }
+      Self::BrokenParents(node) => {
+        write!(f, "The parents of {} are not a doubly-linked list", node)
+      }
+      Self::DanglingUplink(child, parent) => {
+        write!(f, "{} has an uplink from {}, outside of the graph", child, parent)
+      }
+      Self::EscapedVar(var) => {
+        write!(f, "{} is not bound by a binder in the graph", var)
+      }
+      Self::Cycle(node) => {
+        write!(f, "{} is part of a cycle without a fixpoint", node)
+      }
+      Self::FreedNode(node) => write!(f, "{} was already freed", node),
+    }
+  }
+}
+
+pub fn describe(node: &DAGPtr) -> String {
+  format!("{} at {:p}", node_kind(node), node_addr(node))
+}
+
+// The children of a node, each with the uplink the child should list
+pub fn children(node: DAGPtr) -> Vec<(DAGPtr, NonNull<Parents>)> {
+  unsafe {
+    match node {
+      DAGPtr::Lam(link) => {
+        let Lam { bod, bod_ref, .. } = &mut *link.as_ptr();
+        vec![(*bod, NonNull::from(bod_ref))]
+      }
+      DAGPtr::Slf(link) => {
+        let Slf { bod, bod_ref, .. } = &mut *link.as_ptr();
+        vec![(*bod, NonNull::from(bod_ref))]
+      }
+      DAGPtr::Fix(link) => {
+        let Fix { bod, bod_ref, .. } = &mut *link.as_ptr();
+        vec![(*bod, NonNull::from(bod_ref))]
+      }
+      DAGPtr::Dat(link) => {
+        let Dat { bod, bod_ref, .. } = &mut *link.as_ptr();
+        vec![(*bod, NonNull::from(bod_ref))]
+      }
+      DAGPtr::Cse(link) => {
+        let Cse { bod, bod_ref, .. } = &mut *link.as_ptr();
+        vec![(*bod, NonNull::from(bod_ref))]
+      }
+      DAGPtr::App(link) => {
+        let App { fun, arg, fun_ref, arg_ref, .. } = &mut *link.as_ptr();
+        vec![(*fun, NonNull::from(fun_ref)), (*arg, NonNull::from(arg_ref))]
+      }
+      DAGPtr::All(link) => {
+        let All { dom, img, dom_ref, img_ref, .. } = &mut *link.as_ptr();
+        vec![
+          (*dom, NonNull::from(dom_ref)),
+          (DAGPtr::Lam(*img), NonNull::from(img_ref)),
+        ]
+      }
+      DAGPtr::Ann(link) => {
+        let Ann { typ, exp, typ_ref, exp_ref, .. } = &mut *link.as_ptr();
+        vec![(*typ, NonNull::from(typ_ref)), (*exp, NonNull::from(exp_ref))]
+      }
+      DAGPtr::Let(link) => {
+        let Let { typ, exp, bod, typ_ref, exp_ref, bod_ref, .. } =
+          &mut *link.as_ptr();
+        vec![
+          (*typ, NonNull::from(typ_ref)),
+          (*exp, NonNull::from(exp_ref)),
+          (DAGPtr::Lam(*bod), NonNull::from(bod_ref)),
+        ]
+      }
+      _ => vec![],
+    }
+  }
+}
+
+// The nodes of a list of parents, in order, or `None` if the list is broken
+fn parent_nodes(
+  parents: Option<NonNull<Parents>>,
+) -> Option<Vec<NonNull<Parents>>> {
+  let mut res = vec![];
+  let mut seen = BTreeSet::new();
+  let mut prev = None;
+  let mut next = parents.map(DLL::first);
+  while let Some(node) = next {
+    if !seen.insert(node) {
+      return None;
+    }
+    let DLL { prev: node_prev, next: node_next, .. } = unsafe { node.as_ref() };
+    if *node_prev != prev {
+      return None;
+    }
+    res.push(node);
+    prev = Some(node);
+    next = *node_next;
+  }
+  Some(res)
+}
+
+// The heads of the graphs a node is connected to. Hash-consed graphs share
+// nodes, so a node can be reachable from the heads of several graphs, which
+// are found by walking both uplinks and downlinks.
+pub fn roots_of(node: DAGPtr) -> Vec<DAGPtr> {
+  let mut seen = BTreeSet::new();
+  let mut roots = vec![];
+  let mut todo = vec![node];
+  while let Some(node) = todo.pop() {
+    if !seen.insert(node) {
+      continue;
+    }
+    let uplinks = parent_nodes(get_parents(node)).unwrap_or_default();
+    let mut rooted = uplinks.is_empty();
+    for uplink in uplinks {
+      match parent_node(unsafe { &(*uplink.as_ptr()).elem }) {
+        Some(parent) => todo.push(parent),
+        None => rooted = true,
+      }
+    }
+    if rooted {
+      roots.push(node);
+    }
+    todo.extend(children(node).into_iter().map(|(child, _)| child));
+  }
+  roots
+}
+
+impl DAG {
+  // Check the invariants of the graph below the head
+  pub fn validate(&self) -> Result<(), DAGError> {
+    self.enter(|| DAG::validate_shared(&[self.head]))
+  }
+
+  // Check the invariants of the graphs below several heads, which may share
+  // nodes with each other, in the current arena
+  pub fn validate_shared(heads: &[DAGPtr]) -> Result<(), DAGError> {
+    // Collect the nodes of the graphs
+    let mut nodes = BTreeSet::new();
+    let mut todo = heads.to_vec();
+    while let Some(node) = todo.pop() {
+      if nodes.insert(node) {
+        for (child, _) in children(node) {
+          todo.push(child);
+        }
+      }
+    }
+    for node in &nodes {
+      #[cfg(feature = "debug-arena")]
+      if !is_live(*node) {
+        return Err(DAGError::FreedNode(describe(node)));
+      }
+      let uplinks = parent_nodes(get_parents(*node))
+        .ok_or_else(|| DAGError::BrokenParents(describe(node)))?;
+      // Every uplink must come from a parent that points to this node
+      for uplink in uplinks {
+        let elem = unsafe { &(*uplink.as_ptr()).elem };
+        if let Some(parent) = parent_node(elem) {
+          if !nodes.contains(&parent) {
+            return Err(DAGError::DanglingUplink(
+              describe(node),
+              describe(&parent),
+            ));
+          }
+          let mirrored = children(parent)
+            .iter()
+            .any(|(child, slot)| *child == *node && *slot == uplink);
+          if !mirrored {
+            return Err(DAGError::StrayUplink(describe(node), describe(&parent)));
+          }
+        }
+      }
+      // Every downlink must be listed by the child
+      for (child, slot) in children(*node) {
+        let listed = parent_nodes(get_parents(child))
+          .map_or(false, |uplinks| uplinks.contains(&slot));
+        if !listed {
+          return Err(DAGError::MissingUplink(describe(node), describe(&child)));
+        }
+      }
+      if let DAGPtr::Var(link) = node {
+        let binder = match unsafe { link.as_ref().binder } {
+          BinderPtr::Free => None,
+          BinderPtr::Lam(lam) => unsafe {
+            Some((DAGPtr::Lam(lam), &mut (*lam.as_ptr()).var as *mut Var))
+          },
+          BinderPtr::Slf(slf) => unsafe {
+            Some((DAGPtr::Slf(slf), &mut (*slf.as_ptr()).var as *mut Var))
+          },
+          BinderPtr::Fix(fix) => unsafe {
+            Some((DAGPtr::Fix(fix), &mut (*fix.as_ptr()).var as *mut Var))
+          },
+        };
+        if let Some((binder, var)) = binder {
+          if var != link.as_ptr() || !nodes.contains(&binder) {
+            return Err(DAGError::EscapedVar(describe(node)));
+          }
+        }
+      }
+    }
+    DAG::check_acyclic(heads)
+  }
+
+  // Depth-first search for a back edge that doesn't lead to a fixpoint
+  fn check_acyclic(heads: &[DAGPtr]) -> Result<(), DAGError> {
+    #[derive(PartialEq)]
+    enum Mark {
+      Open,
+      Done,
+    }
+    let mut marks: BTreeMap<DAGPtr, Mark> = BTreeMap::new();
+    let mut stack: Vec<_> = heads.iter().map(|head| (*head, false)).collect();
+    while let Some((node, exiting)) = stack.pop() {
+      if exiting {
+        marks.insert(node, Mark::Done);
+        continue;
+      }
+      match marks.get(&node) {
+        Some(Mark::Done) => continue,
+        Some(Mark::Open) => {
+          if let DAGPtr::Fix(_) = node {
+            continue;
+          }
+          return Err(DAGError::Cycle(describe(&node)));
+        }
+        None => (),
+      }
+      marks.insert(node, Mark::Open);
+      stack.push((node, true));
+      for (child, _) in children(node) {
+        match marks.get(&child) {
+          Some(Mark::Done) => (),
+          Some(Mark::Open) => {
+            if let DAGPtr::Fix(_) = child {
+              continue;
+            }
+            return Err(DAGError::Cycle(describe(&child)));
+          }
+          None => stack.push((child, false)),
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// Validate the whole graphs a node belongs to, panicking on a broken invariant
+#[cfg(feature = "debug-dag")]
+pub fn assert_valid(node: DAGPtr) {
+  if let Err(err) = DAG::validate_shared(&roots_of(node)) {
+    panic!("Invalid DAG after a reduction step: {}", err);
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    arena,
+    defs::Defs,
+    eval::test::parse,
+    position::Pos,
+    term::{
+      tests::{
+        affine,
+        test_defs,
+      },
+      Term,
+    },
+  };
+  use quickcheck::TestResult;
+
+  #[quickcheck]
+  fn norm_preserves_invariants(x: Term) -> TestResult {
+    if !affine(&x, &mut vec![]) {
+      return TestResult::discard();
+    }
+    let mut dag = DAG::from_term(&x);
+    if dag.validate().is_err() {
+      return TestResult::failed();
+    }
+    dag.norm(&test_defs(), false);
+    let res = dag.validate();
+    dag.free();
+    TestResult::from_bool(res.is_ok())
+  }
+
+  #[test]
+  fn valid_after_norm() {
+    let (_, mut dag) = parse("λ y => (λ z => z z) ((λ x => x) y)").unwrap();
+    assert_eq!(dag.validate(), Ok(()));
+    dag.norm(&Defs::new(), false);
+    assert_eq!(format!("{}", dag), "λ y => y y");
+    assert_eq!(dag.validate(), Ok(()));
+  }
+
+  #[test]
+  fn detects_missing_uplink() {
+    let dag = DAG::from_term(&Term::App(
+      Pos::None,
+      Box::new((Term::Typ(Pos::None), Term::Typ(Pos::None))),
+    ));
+    if let DAGPtr::App(link) = dag.head {
+      let arg = unsafe { link.as_ref().arg };
+      set_parents(arg, None);
+    }
+    match dag.validate() {
+      Err(DAGError::MissingUplink(..)) => (),
+      res => panic!("unexpected {:?}", res),
+    }
+  }
+
+  #[test]
+  fn detects_escaped_var() {
+    arena::scope(|| {
+      let (_, outer) = parse("λ x => x").unwrap();
+      let var = match outer.head {
+        DAGPtr::Lam(link) => unsafe { link.as_ref().bod },
+        _ => unreachable!(),
+      };
+      // A graph whose only node is a variable bound in another graph
+      let root = alloc_val(DLL::singleton(ParentPtr::Root));
+      let parents = get_parents(var);
+      set_parents(var, Some(root));
+      let res = DAG::new(var).validate();
+      set_parents(var, parents);
+      free_val(root);
+      match res {
+        Err(DAGError::EscapedVar(..)) => (),
+        res => panic!("unexpected {:?}", res),
+      }
+    })
+  }
+
+  #[test]
+  fn detects_cycles() {
+    let dag = DAG::from_term(&Term::Dat(
+      Pos::None,
+      Box::new(Term::Dat(Pos::None, Box::new(Term::Typ(Pos::None)))),
+    ));
+    if let DAGPtr::Dat(outer) = dag.head {
+      let inner = unsafe { outer.as_ref().bod };
+      if let DAGPtr::Dat(inner) = inner {
+        // Point the inner body back to the outer node
+        unsafe {
+          let bod_ref = &mut (*inner.as_ptr()).bod_ref;
+          (*inner.as_ptr()).bod = dag.head;
+          add_to_parents(dag.head, NonNull::from(bod_ref));
+        }
+      }
+    }
+    match dag.validate() {
+      Err(DAGError::Cycle(..)) => (),
+      res => panic!("unexpected {:?}", res),
+    }
+  }
+}
@@ -31,6 +31,8 @@ pub struct Def {
   pub ast_cid: Cid,
   pub typ_: Term,
   pub term: Term,
+  /// Whether the definition is evaluated strictly, declared with `strict def`
+  pub strict: bool,
 }
 
 impl PartialEq for Def {
@@ -39,6 +41,7 @@ impl PartialEq for Def {
       && self.ast_cid == other.ast_cid
       && self.typ_ == other.typ_
       && self.term == other.term
+      && self.strict == other.strict
   }
 }
 
@@ -51,6 +54,16 @@ pub struct Defs {
 
 impl Def {
   pub fn make(pos: Pos, typ_: Term, term: Term) -> (Self, Entry) {
+    Def::build(pos, typ_, term, false)
+  }
+
+  /// Make a definition whose arguments and `let` bindings are reduced before
+  /// they are substituted, see `eval::Strategy`
+  pub fn make_strict(pos: Pos, typ_: Term, term: Term) -> (Self, Entry) {
+    Def::build(pos, typ_, term, true)
+  }
+
+  fn build(pos: Pos, typ_: Term, term: Term, strict: bool) -> (Self, Entry) {
     let (type_anon, type_meta) = typ_.embed();
     let (term_anon, term_meta) = term.embed();
     let ast_cid = term_anon.cid();
@@ -60,8 +73,9 @@ impl Def {
       type_meta,
       term_anon: ast_cid,
       term_meta,
+      strict,
     };
-    let def = Def { pos, def_cid: defn.cid(), ast_cid, typ_, term };
+    let def = Def { pos, def_cid: defn.cid(), ast_cid, typ_, term, strict };
     (def, defn)
   }
 
@@ -74,6 +88,7 @@ impl Def {
       term_anon: self.ast_cid,
       type_meta,
       term_meta,
+      strict: self.strict,
     };
     (d, type_anon, term_anon)
   }
@@ -91,12 +106,14 @@ impl Def {
       ast_cid: def.term_anon,
       typ_,
       term,
+      strict: def.strict,
     })
   }
 
   pub fn pretty(&self, name: String, ind: bool) -> String {
     format!(
-      "def {} : {} = {}",
+      "{}def {} : {} = {}",
+      if self.strict { "strict " } else { "" },
       name,
       self.typ_.pretty(Some(&name), ind),
       self.term.pretty(Some(&name), ind)
@@ -133,19 +150,32 @@ impl Defs {
     self.defs.get(&def_cid)
   }
 
-  /// Merge Defs from an Import
-  pub fn merge(self, other: Defs, import: &Import) -> Self {
+  /// Keep only the names in `exports` in scope. The definitions themselves
+  /// stay, so that the exported ones can still refer to the others by CID.
+  pub fn export(mut self, exports: &[Name]) -> Self {
+    self.names.retain(|n, _| exports.contains(n));
+    self
+  }
+
+  /// Merge Defs from an Import. Only the names in scope in `other` are
+  /// brought into scope, so private definitions of the imported package stay
+  /// out of it. Fails with the first name of `import.with` that isn't in
+  /// scope in `other`, since it is either unknown or private.
+  pub fn merge(self, other: Defs, import: &Import) -> Result<Self, Name> {
+    let mut names = self.names;
+    for k in import.with.iter() {
+      match other.names.get(k) {
+        Some(v) => {
+          names.insert(import_alias(k.clone(), import), *v);
+        }
+        None => return Err(k.clone()),
+      }
+    }
     let mut defs = self.defs;
     for (k, v) in other.defs {
       defs.insert(k, v);
     }
-    let mut names = self.names;
-    for k in import.with.iter() {
-      let k = k.clone();
-      let v = other.names.get(&k).unwrap();
-      names.insert(import_alias(k, import), *v);
-    }
-    Defs { defs, names }
+    Ok(Defs { defs, names })
   }
 
   /// Merge Defs mutably at the same level like in a REPL env
@@ -188,7 +218,8 @@ impl fmt::Display for Defs {
       writeln!(f, "{}:", def.def_cid)?;
       writeln!(
         f,
-        "def {} : {} = {}",
+        "{}def {} : {} = {}",
+        if def.strict { "strict " } else { "" },
         k.clone(),
         def.typ_.pretty(Some(&k.to_string()), false),
         def.term.pretty(Some(&k.to_string()), false),
@@ -216,7 +247,12 @@ pub mod tests {
     let typ_: Term = Arbitrary::arbitrary(g);
     let term =
       arbitrary_term(g, true, test_defs(), Vector::new());
-    Def::make(Pos::None, typ_, term)
+    if Arbitrary::arbitrary(g) {
+      Def::make_strict(Pos::None, typ_, term)
+    }
+    else {
+      Def::make(Pos::None, typ_, term)
+    }
   }
 
   impl Arbitrary for Def {
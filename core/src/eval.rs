@@ -1,12 +1,21 @@
 use core::ptr::NonNull;
 
 use crate::{
+  arena,
   dag::*,
   defs::Defs,
   dll::*,
+  gas,
+  memory,
   upcopy::*,
 };
 
+use cache::{
+  CacheKey,
+  Form,
+  NormCache,
+};
+
 use sp_std::{
   collections::btree_map::BTreeMap,
   vec::Vec,
@@ -15,8 +24,25 @@ use sp_std::{
 
 use alloc::string::String;
 
+pub mod cache;
+pub mod reference;
+
+// How arguments and `let` bindings are evaluated. Lazy evaluation substitutes
+// them as they are and relies on the graph to share their reduction. Strict
+// evaluation first reduces them to weak head normal form, so that loops which
+// thread an accumulator don't build up a chain of suspended applications.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+  Lazy,
+  Strict,
+}
+
+impl Default for Strategy {
+  fn default() -> Self { Strategy::Lazy }
+}
+
 enum Single {
-  Lam(Var),
+  Lam(Var, bool),
   Slf(Var),
   Fix(Var),
   Dat,
@@ -40,9 +66,9 @@ pub fn subst(bod: DAGPtr, var: &Var, arg: DAGPtr, fix: bool, should_count: bool)
   loop {
     match input {
       DAGPtr::Lam(link) => {
-        let Lam { var, bod, .. } = unsafe { link.as_ref() };
+        let Lam { var, bod, strict, .. } = unsafe { link.as_ref() };
         input = *bod;
-        spine.push(Single::Lam(var.clone()));
+        spine.push(Single::Lam(var.clone(), *strict));
       }
       DAGPtr::Slf(link) => {
         let Slf { var, bod, .. } = unsafe { link.as_ref() };
@@ -104,8 +130,8 @@ pub fn subst(bod: DAGPtr, var: &Var, arg: DAGPtr, fix: bool, should_count: bool)
         break;
       }
       DAGPtr::Let(link) => {
-        let Let { uses, typ, exp, bod, .. } = unsafe { link.as_ref() };
-        let new_let = alloc_let(*uses, *typ, *exp, *bod, None);
+        let Let { uses, typ, exp, bod, strict, .. } = unsafe { link.as_ref() };
+        let new_let = alloc_let(*uses, *typ, *exp, *bod, *strict, None);
         unsafe {
           (*link.as_ptr()).copy = Some(new_let);
         }
@@ -126,9 +152,9 @@ pub fn subst(bod: DAGPtr, var: &Var, arg: DAGPtr, fix: bool, should_count: bool)
   }
   while let Some(single) = spine.pop() {
     match single {
-      Single::Lam(var) => {
+      Single::Lam(var, strict) => {
         let Var { nam, dep, parents: var_parents, .. } = var;
-        let new_lam = alloc_lam(nam, dep, result, None);
+        let new_lam = alloc_lam(nam, dep, result, strict, None);
         let ptr: *mut Parents = unsafe { &mut (*new_lam.as_ptr()).bod_ref };
         add_to_parents(result, NonNull::new(ptr).unwrap());
         let ptr: *mut Var = unsafe { &mut (*new_lam.as_ptr()).var };
@@ -303,9 +329,44 @@ pub fn print_trail(trail: &Vec<NonNull<App>>) -> Vec<String> {
 impl DAG {
   // Reduce term to its weak head normal form
   pub fn whnf(&mut self, defs: &Defs, should_count: bool) {
+    self.whnf_with(defs, None, Strategy::Lazy, should_count)
+  }
+
+  // Reduce term to its weak head normal form, memoizing the unfolding of
+  // references in `cache`
+  pub fn whnf_with(
+    &mut self,
+    defs: &Defs,
+    cache: Option<&dyn NormCache>,
+    strategy: Strategy,
+    should_count: bool,
+  ) {
+    let arena = self.arena();
+    arena::enter(arena.as_ref(), || {
+      self.whnf_in(defs, cache, strategy, should_count)
+    })
+  }
+
+  // Reduce term to its weak head normal form. Strictness is lexical, as on the
+  // runtime: the λs and `let`s of a definition marked `strict` reduce their
+  // arguments first wherever they end up, and so do all of them under
+  // `Strategy::Strict`.
+  fn whnf_in(
+    &mut self,
+    defs: &Defs,
+    cache: Option<&dyn NormCache>,
+    strategy: Strategy,
+    should_count: bool,
+  ) {
+    let strict = strategy == Strategy::Strict;
     let mut node = self.head;
     let mut trail: Vec<NonNull<App>> = vec![];
     loop {
+      #[cfg(feature = "debug-dag")]
+      crate::dag::validate::assert_valid(node);
+      if gas::exhausted() || memory::exceeded() {
+        break;
+      }
       match node {
         DAGPtr::App(link) => {
           let App { fun, .. } = unsafe { link.as_ref() };
@@ -314,6 +375,11 @@ impl DAG {
         }
         DAGPtr::Lam(link) => {
           if let Some(app_link) = trail.pop() {
+            if strict || unsafe { link.as_ref().strict } {
+              let mut arg = unsafe { DAG::new((*app_link.as_ptr()).arg) };
+              arg.whnf_with(defs, cache, strategy, should_count);
+            }
+            gas::charge(gas::BETA);
             node = reduce_lam(app_link, link, should_count);
           }
           else {
@@ -328,9 +394,10 @@ impl DAG {
         }
         DAGPtr::Cse(link) => {
           let mut body = unsafe { DAG::new((*link.as_ptr()).bod) };
-          body.whnf(defs, should_count);
+          body.whnf_with(defs, cache, strategy, should_count);
           match body.head {
             DAGPtr::Dat(body_link) => {
+              gas::charge(gas::BETA);
               let bod = unsafe { body_link.as_ref().bod };
               replace_child(node, bod);
               free_dead_node(node);
@@ -341,6 +408,7 @@ impl DAG {
               match &lit.clone().expand() {
                 None => break,
                 Some(expand) => {
+                  gas::charge(gas::BETA);
                   let expand = DAG::from_term_inner(
                     expand,
                     0,
@@ -358,10 +426,16 @@ impl DAG {
           }
         }
         DAGPtr::Let(link) => {
+          if strict || unsafe { link.as_ref().strict } {
+            let mut exp = unsafe { DAG::new((*link.as_ptr()).exp) };
+            exp.whnf_with(defs, cache, strategy, should_count);
+          }
+          gas::charge(gas::BETA);
           node = reduce_let(link, should_count);
         }
         DAGPtr::Fix(link) => unsafe {
           let Fix { var, bod, .. } = &mut *link.as_ptr();
+          gas::charge(gas::UNFOLD);
           replace_child(node, *bod);
           if !var.parents.is_none() {
             let new_fix =
@@ -389,10 +463,24 @@ impl DAG {
           let Ref { nam, exp, ast, parents: ref_parents, .. } =
             unsafe { &mut *link.as_ptr() };
           if let Some(def) = defs.defs.get(exp) {
+            gas::charge(gas::UNFOLD);
             let parents = *ref_parents;
             *ref_parents = None;
             let ref_node = node;
-            node = DAG::from_ref(&def, nam.clone(), *exp, *ast, parents);
+            node = match cache {
+              Some(cache) => cache::unfold(
+                cache,
+                defs,
+                &def,
+                nam.clone(),
+                *exp,
+                *ast,
+                parents,
+                strategy,
+                should_count,
+              ),
+              None => DAG::from_ref(&def, nam.clone(), *exp, *ast, parents),
+            };
             free_dead_node(ref_node);
             for parent in DLL::iter_option(parents) {
               install_child(parent, node);
@@ -405,10 +493,18 @@ impl DAG {
         DAGPtr::Opr(link) => {
           let opr = unsafe { (*link.as_ptr()).opr };
           let len = trail.len();
-          if len == 0 && opr.arity() == 0 {
+          if opr.arity() == 0 {
+            gas::charge_op(&[]);
             let res = opr.apply0();
             if let Some(res) = res {
-              node = DAGPtr::Lit(alloc_val(Lit { lit: res, parents: None }));
+              if !memory::record_lit(&res) {
+                break;
+              }
+              let new_node =
+                DAGPtr::Lit(alloc_val(Lit { lit: res, parents: None }));
+              replace_child(node, new_node);
+              free_dead_node(node);
+              node = new_node;
             }
             else {
               break;
@@ -416,12 +512,16 @@ impl DAG {
           }
           else if len >= 1 && opr.arity() == 1 {
             let mut arg = unsafe { DAG::new((*trail[len - 1].as_ptr()).arg) };
-            arg.whnf(defs, should_count);
+            arg.whnf_with(defs, cache, strategy, should_count);
             match arg.head {
               DAGPtr::Lit(link) => {
                 let x = unsafe { &(*link.as_ptr()).lit };
+                gas::charge_op(&[x]);
                 let res = opr.apply1(x);
                 if let Some(res) = res {
+                  if !memory::record_lit(&res) {
+                    break;
+                  }
                   let top = DAGPtr::App(trail.pop().unwrap());
                   let new_node =
                     DAGPtr::Lit(alloc_val(Lit { lit: res, parents: None }));
@@ -439,14 +539,18 @@ impl DAG {
           else if len >= 2 && opr.arity() == 2 {
             let mut arg1 = unsafe { DAG::new((*trail[len - 1].as_ptr()).arg) };
             let mut arg2 = unsafe { DAG::new((*trail[len - 2].as_ptr()).arg) };
-            arg1.whnf(defs, should_count);
-            arg2.whnf(defs, should_count);
+            arg1.whnf_with(defs, cache, strategy, should_count);
+            arg2.whnf_with(defs, cache, strategy, should_count);
             match (arg1.head, arg2.head) {
               (DAGPtr::Lit(x_link), DAGPtr::Lit(y_link)) => {
                 let x = unsafe { &(*x_link.as_ptr()).lit };
                 let y = unsafe { &(*y_link.as_ptr()).lit };
+                gas::charge_op(&[x, y]);
                 let res = opr.apply2(x, y);
                 if let Some(res) = res {
+                  if !memory::record_lit(&res) {
+                    break;
+                  }
                   trail.pop();
                   let top = DAGPtr::App(trail.pop().unwrap());
                   let new_node =
@@ -466,9 +570,9 @@ impl DAG {
             let mut arg1 = unsafe { DAG::new((*trail[len - 1].as_ptr()).arg) };
             let mut arg2 = unsafe { DAG::new((*trail[len - 2].as_ptr()).arg) };
             let mut arg3 = unsafe { DAG::new((*trail[len - 3].as_ptr()).arg) };
-            arg1.whnf(defs, should_count);
-            arg2.whnf(defs, should_count);
-            arg3.whnf(defs, should_count);
+            arg1.whnf_with(defs, cache, strategy, should_count);
+            arg2.whnf_with(defs, cache, strategy, should_count);
+            arg3.whnf_with(defs, cache, strategy, should_count);
             match (arg1.head, arg2.head, arg3.head) {
               (
                 DAGPtr::Lit(x_link),
@@ -478,8 +582,12 @@ impl DAG {
                 let x = unsafe { &(*x_link.as_ptr()).lit };
                 let y = unsafe { &(*y_link.as_ptr()).lit };
                 let z = unsafe { &(*z_link.as_ptr()).lit };
+                gas::charge_op(&[x, y, z]);
                 let res = opr.apply3(x, y, z);
                 if let Some(res) = res {
+                  if !memory::record_lit(&res) {
+                    break;
+                  }
                   trail.pop();
                   trail.pop();
                   let top = DAGPtr::App(trail.pop().unwrap());
@@ -513,7 +621,88 @@ impl DAG {
 
   // Reduce term to its normal form
   pub fn norm(&mut self, defs: &Defs, should_count: bool) {
-    self.whnf(defs, should_count);
+    self.norm_with(defs, None, Strategy::Lazy, should_count)
+  }
+
+  // Reduce term to its normal form, memoizing the normal forms of references
+  // in `cache`
+  pub fn norm_with(
+    &mut self,
+    defs: &Defs,
+    cache: Option<&dyn NormCache>,
+    strategy: Strategy,
+    should_count: bool,
+  ) {
+    let arena = self.arena();
+    arena::enter(arena.as_ref(), || {
+      self.norm_in(defs, cache, strategy, should_count)
+    })
+  }
+
+  fn norm_in(
+    &mut self,
+    defs: &Defs,
+    cache: Option<&dyn NormCache>,
+    strategy: Strategy,
+    should_count: bool,
+  ) {
+    let key = match (cache, self.head) {
+      (Some(cache), DAGPtr::Ref(link)) => {
+        let Ref { exp, ast, parents, .. } = unsafe { &mut *link.as_ptr() };
+        if defs.defs.contains_key(exp) {
+          if let Some(term) = cache::lookup(cache, defs, *ast, Form::Norm) {
+            let node = DAG::from_term_inner(
+              &term,
+              0,
+              BTreeMap::new(),
+              *parents,
+              None,
+            );
+            for parent in DLL::iter_option(*parents) {
+              install_child(parent, node);
+            }
+            *parents = None;
+            free_dead_node(self.head);
+            self.head = node;
+            return;
+          }
+          Some(CacheKey::new(*ast, Form::Norm))
+        }
+        else {
+          None
+        }
+      }
+      _ => None,
+    };
+    self.normalize(defs, cache, strategy, should_count);
+    if let (Some(cache), Some(key)) = (cache, key) {
+      if cache::is_closed(&self.head) {
+        cache.put(&key, &self.to_term(false));
+      }
+    }
+  }
+
+  // Normalize for at most `limit` gas, returning whether the term reached its
+  // normal form or stopped on a memory limit. A term that ran out of gas is
+  // left part way through its reduction, and normalizing it again continues
+  // from there, so an evaluation can be run in slices with other work done in
+  // between. Replaces any meter already set on this thread.
+  pub fn norm_slice(&mut self, defs: &Defs, limit: u64) -> bool {
+    gas::meter(limit);
+    self.norm(defs, false);
+    let paused = gas::exhausted();
+    gas::unmeter();
+    !paused
+  }
+
+  fn normalize(
+    &mut self,
+    defs: &Defs,
+    cache: Option<&dyn NormCache>,
+    strategy: Strategy,
+    should_count: bool,
+  ) {
+    self.whnf_with(defs, cache, strategy, should_count);
     let mut trail = vec![self.head];
     while let Some(node) = trail.pop() {
       match node {
@@ -521,8 +710,8 @@ impl DAG {
           let app = link.as_ptr();
           let mut fun = DAG::new((*app).fun);
           let mut arg = DAG::new((*app).arg);
-          fun.whnf(defs, should_count);
-          arg.whnf(defs, should_count);
+          fun.whnf_with(defs, cache, strategy, should_count);
+          arg.whnf_with(defs, cache, strategy, should_count);
           trail.push(fun.head);
           trail.push(arg.head);
         },
@@ -530,33 +719,33 @@ impl DAG {
           let all = link.as_ptr();
           let mut dom = DAG::new((*all).dom);
           let mut img = DAG::new(DAGPtr::Lam((*all).img));
-          dom.whnf(defs, should_count);
-          img.whnf(defs, should_count);
+          dom.whnf_with(defs, cache, strategy, should_count);
+          img.whnf_with(defs, cache, strategy, should_count);
           trail.push(dom.head);
           trail.push(img.head);
         },
         DAGPtr::Lam(link) => unsafe {
           let lam = link.as_ptr();
           let mut body = DAG::new((*lam).bod);
-          body.whnf(defs, should_count);
+          body.whnf_with(defs, cache, strategy, should_count);
           trail.push(body.head);
         },
         DAGPtr::Slf(link) => unsafe {
           let slf = link.as_ptr();
           let mut body = DAG::new((*slf).bod);
-          body.whnf(defs, should_count);
+          body.whnf_with(defs, cache, strategy, should_count);
           trail.push(body.head);
         },
         DAGPtr::Cse(link) => unsafe {
           let cse = link.as_ptr();
           let mut body = DAG::new((*cse).bod);
-          body.whnf(defs, should_count);
+          body.whnf_with(defs, cache, strategy, should_count);
           trail.push(body.head);
         },
         DAGPtr::Dat(link) => unsafe {
           let dat = link.as_ptr();
           let mut body = DAG::new((*dat).bod);
-          body.whnf(defs, should_count);
+          body.whnf_with(defs, cache, strategy, should_count);
           trail.push(body.head);
         },
         _ => (),
@@ -567,7 +756,10 @@ impl DAG {
 
 //#[cfg(test)]
 pub mod test {
-  use super::DAG;
+  use super::{
+    Strategy,
+    DAG,
+  };
   use crate::{
     defs::Defs,
     parse::{
@@ -713,4 +905,67 @@ pub mod test {
     // assert_eq!(true, false);
     norm_assert(trm_str, id);
   }
+
+  fn whnf_assert(input: &str, result: &str, strategy: Strategy, defs: &Defs) {
+    let (_, tree) = crate::parse::term::parse(input, defs.clone()).unwrap();
+    let mut dag = DAG::from_term(&tree);
+    dag.whnf_with(defs, None, strategy, false);
+    assert_eq!(format!("{}", dag), result);
+    dag.free();
+  }
+
+  #[test]
+  pub fn strict_test() {
+    let defs = Defs::new();
+    let app = "(λ x y => x) (#Nat.add 1 2)";
+    whnf_assert(app, "λ y => #Nat.add 1 2", Strategy::Lazy, &defs);
+    whnf_assert(app, "λ y => 3", Strategy::Strict, &defs);
+    let bind = "let x: #Nat = #Nat.add 1 2; λ y => x";
+    whnf_assert(bind, "λ y => #Nat.add 1 2", Strategy::Lazy, &defs);
+    whnf_assert(bind, "λ y => 3", Strategy::Strict, &defs);
+  }
+
+  #[test]
+  pub fn strict_test_defs() {
+    let (_, defs) = parse_defs(
+      "strict def konst (x: #Nat): ∀ #Nat -> #Nat = λ y => x
+       def lazy_konst (x: #Nat): ∀ #Nat -> #Nat = λ y => x
+       strict def sum (acc: #Nat) (n: #Nat): #Nat =
+         (case n) (λ _ => #Nat) acc (λ p => sum (#Nat.add acc n) p)",
+    )
+    .unwrap();
+    let konst = defs.get(&"konst".into()).unwrap();
+    assert!(konst.strict);
+    assert!(konst.pretty("konst".into(), false).starts_with("strict def"));
+    assert!(!defs.get(&"lazy_konst".into()).unwrap().strict);
+    let app = "konst (#Nat.add 1 2)";
+    whnf_assert(app, "λ y => 3", Strategy::Lazy, &defs);
+    let app = "lazy_konst (#Nat.add 1 2)";
+    whnf_assert(app, "λ y => #Nat.add 1 2", Strategy::Lazy, &defs);
+    for strategy in &[Strategy::Lazy, Strategy::Strict] {
+      let (_, tree) = crate::parse::term::parse("sum 0 10", defs.clone())
+        .unwrap();
+      let mut dag = DAG::from_term(&tree);
+      dag.norm_with(&defs, None, *strategy, false);
+      assert_eq!(format!("{}", dag), "55");
+    }
+  }
+
+  #[test]
+  pub fn norm_slice_test() {
+    let (_, defs) = parse_defs(
+      "def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+         #Nat.mul x (fact x'))",
+    )
+    .unwrap();
+    let (_, tree) = crate::parse::term::parse("fact 6", defs.clone()).unwrap();
+    let mut dag = DAG::from_term(&tree);
+    let mut slices = 1;
+    while !dag.norm_slice(&defs, 20) {
+      slices += 1;
+    }
+    assert!(slices > 1);
+    assert_eq!(format!("{}", dag), "720");
+    dag.free();
+  }
 }
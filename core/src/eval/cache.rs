@@ -0,0 +1,300 @@
+use core::ptr::NonNull;
+
+use crate::{
+  dag::*,
+  defs::{
+    Def,
+    Defs,
+  },
+  dll::*,
+  eval::Strategy,
+  name::Name,
+  term::Term,
+};
+
+use sp_cid::Cid;
+use sp_ipld::{
+  dag_cbor::cid,
+  Ipld,
+};
+
+use sp_std::collections::btree_map::BTreeMap;
+
+use alloc::string::ToString;
+
+// The version of the evaluator. Cached results are keyed by this number, so
+// any change to the reduction rules that can alter a normal form (including
+// changes to primitive operations) must bump it, which invalidates every
+// entry written by older evaluators.
+pub const EVAL_VERSION: u64 = 2;
+
+// The form of a cached result
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Form {
+  Whnf,
+  Norm,
+}
+
+// The key of a cached result: the anonymous term of a closed definition,
+// together with the form it was reduced to and the evaluator version
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct CacheKey {
+  pub anon: Cid,
+  pub form: Form,
+  pub version: u64,
+}
+
+impl CacheKey {
+  pub fn new(anon: Cid, form: Form) -> Self {
+    CacheKey { anon, form, version: EVAL_VERSION }
+  }
+
+  pub fn to_ipld(&self) -> Ipld {
+    let form = match self.form {
+      Form::Whnf => 0,
+      Form::Norm => 1,
+    };
+    Ipld::List(vec![
+      Ipld::String("yatima/eval".to_string()),
+      Ipld::Integer(self.version as i128),
+      Ipld::Integer(form),
+      Ipld::Link(self.anon),
+    ])
+  }
+
+  pub fn cid(&self) -> Cid { cid(&self.to_ipld()) }
+}
+
+// A memo table of reduced definitions. Implementations may persist entries
+// anywhere (see `yatima_utils::store::StoreCache`); a failed lookup or write
+// only costs the work of reducing the definition again.
+pub trait NormCache {
+  fn get(&self, key: &CacheKey) -> Option<Term>;
+  fn put(&self, key: &CacheKey, term: &Term);
+}
+
+// Whether a reduced graph can be read back as a closed term. Fixpoints can only
+// be read back as the expression of a recursive `let`, and free variables can't
+// be replayed into another context.
+pub fn is_closed(node: &DAGPtr) -> bool {
+  unsafe {
+    match node {
+      DAGPtr::Var(link) => !matches!(link.as_ref().binder, BinderPtr::Free),
+      DAGPtr::Fix(_) => false,
+      DAGPtr::Lam(link) => is_closed(&link.as_ref().bod),
+      DAGPtr::Slf(link) => is_closed(&link.as_ref().bod),
+      DAGPtr::Dat(link) => is_closed(&link.as_ref().bod),
+      DAGPtr::Cse(link) => is_closed(&link.as_ref().bod),
+      DAGPtr::App(link) => {
+        let App { fun, arg, .. } = link.as_ref();
+        is_closed(fun) && is_closed(arg)
+      }
+      DAGPtr::Ann(link) => {
+        let Ann { typ, exp, .. } = link.as_ref();
+        is_closed(typ) && is_closed(exp)
+      }
+      DAGPtr::All(link) => {
+        let All { dom, img, .. } = link.as_ref();
+        is_closed(dom) && is_closed(&img.as_ref().bod)
+      }
+      DAGPtr::Let(link) => {
+        let Let { typ, exp, bod, .. } = link.as_ref();
+        let exp = match exp {
+          DAGPtr::Fix(fix) => &fix.as_ref().bod,
+          exp => exp,
+        };
+        is_closed(typ) && is_closed(exp) && is_closed(&bod.as_ref().bod)
+      }
+      _ => true,
+    }
+  }
+}
+
+// Whether every reference in a term points to a definition in `defs`. Entries
+// are keyed by anonymous terms only, so a cached result may mention
+// definitions from a package that isn't loaded.
+pub fn refs_defined(term: &Term, defs: &Defs) -> bool {
+  match term {
+    Term::Ref(_, _, def, _) => defs.defs.contains_key(def),
+    Term::Rec(_) => false,
+    Term::Lam(_, _, bod)
+    | Term::Slf(_, _, bod)
+    | Term::Dat(_, bod)
+    | Term::Cse(_, bod) => refs_defined(bod, defs),
+    Term::App(_, x) | Term::Ann(_, x) | Term::All(_, _, _, x) => {
+      refs_defined(&x.0, defs) && refs_defined(&x.1, defs)
+    }
+    Term::Let(_, _, _, _, x) => {
+      refs_defined(&x.0, defs)
+        && refs_defined(&x.1, defs)
+        && refs_defined(&x.2, defs)
+    }
+    _ => true,
+  }
+}
+
+// Look up a cached result, treating unusable entries as misses
+pub fn lookup(
+  cache: &dyn NormCache,
+  defs: &Defs,
+  anon: Cid,
+  form: Form,
+) -> Option<Term> {
+  let key = CacheKey::new(anon, form);
+  cache.get(&key).filter(|term| refs_defined(term, defs))
+}
+
+// Unfold a reference to a definition into its weak head normal form, reusing
+// the cached result if there is one and caching it otherwise. The returned node
+// is installed under `parents` by the caller. Terms don't record strictness,
+// so the λs of a result read back from a term are as strict as the definition.
+#[allow(clippy::too_many_arguments)]
+pub fn unfold(
+  cache: &dyn NormCache,
+  defs: &Defs,
+  def: &Def,
+  nam: Name,
+  exp: Cid,
+  ast: Cid,
+  parents: Option<NonNull<Parents>>,
+  strategy: Strategy,
+  should_count: bool,
+) -> DAGPtr {
+  let cached = lookup(cache, defs, ast, Form::Norm)
+    .or_else(|| lookup(cache, defs, ast, Form::Whnf));
+  if let Some(term) = cached {
+    return DAG::from_term_strict(
+      &term,
+      0,
+      BTreeMap::new(),
+      parents,
+      None,
+      def.strict,
+    );
+  }
+  let root = alloc_val(DLL::singleton(ParentPtr::Root));
+  let mut dag = DAG::new(DAG::from_ref(def, nam.clone(), exp, ast, Some(root)));
+  dag.whnf_in(defs, Some(cache), strategy, should_count);
+  if is_closed(&dag.head) {
+    let term = dag.to_term(false);
+    dag.free();
+    cache.put(&CacheKey::new(ast, Form::Whnf), &term);
+    DAG::from_term_strict(&term, 0, BTreeMap::new(), parents, None, def.strict)
+  }
+  else {
+    // The result can't be stored, so hand the reduced graph itself over to the
+    // parents of the reference
+    let head = dag.head;
+    let rest = unsafe { (*root.as_ptr()).unlink_node() };
+    free_val(root);
+    match rest {
+      Some(rest) => {
+        DLL::concat(rest, parents);
+        set_parents(head, Some(rest));
+      }
+      None => set_parents(head, parents),
+    }
+    head
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    eval::test::parse_defs,
+    literal::Literal,
+    position::Pos,
+  };
+  use core::cell::RefCell;
+
+  // An in-memory cache that counts its hits
+  #[derive(Default)]
+  pub struct MemCache {
+    pub entries: RefCell<BTreeMap<Cid, Term>>,
+    pub hits: RefCell<usize>,
+  }
+
+  impl NormCache for MemCache {
+    fn get(&self, key: &CacheKey) -> Option<Term> {
+      let res = self.entries.borrow().get(&key.cid()).cloned();
+      if res.is_some() {
+        *self.hits.borrow_mut() += 1;
+      }
+      res
+    }
+
+    fn put(&self, key: &CacheKey, term: &Term) {
+      self.entries.borrow_mut().insert(key.cid(), term.clone());
+    }
+  }
+
+  fn defs(src: &str) -> Defs {
+    let (_, defs) = parse_defs(src).unwrap();
+    defs
+  }
+
+  fn eval(defs: &Defs, name: &str, cache: Option<&dyn NormCache>) -> Term {
+    let def = defs.get(&Name::from(name)).unwrap();
+    let mut dag = DAG::from_term(&Term::Ref(
+      Pos::None,
+      Name::from(name),
+      def.def_cid,
+      def.ast_cid,
+    ));
+    dag.norm_with(defs, cache, Strategy::Lazy, false);
+    let term = dag.to_term(false);
+    dag.free();
+    term
+  }
+
+  const SRC: &str = "
+    def id (A: Type) (x: A): A = x
+    def three: #Nat = (#Nat.add 1 2)
+    def six: #Nat = (#Nat.mul (id #Nat three) 2)
+  ";
+
+  #[test]
+  fn cached_matches_uncached() {
+    let defs = defs(SRC);
+    let cache = MemCache::default();
+    let uncached = eval(&defs, "six", None);
+    assert_eq!(eval(&defs, "six", Some(&cache)), uncached);
+    assert_eq!(eval(&defs, "six", Some(&cache)), uncached);
+    assert!(*cache.hits.borrow() > 0);
+  }
+
+  #[test]
+  fn cache_is_consulted() {
+    let defs = defs(SRC);
+    let cache = MemCache::default();
+    let three = defs.get(&Name::from("three")).unwrap().ast_cid;
+    let seven = Term::Lit(Pos::None, Literal::Nat(7u64.into()));
+    cache.put(&CacheKey::new(three, Form::Whnf), &seven);
+    assert_eq!(format!("{}", eval(&defs, "six", Some(&cache))), "14");
+  }
+
+  #[test]
+  fn stale_entries_are_ignored() {
+    let defs = defs(SRC);
+    let cache = MemCache::default();
+    let three = defs.get(&Name::from("three")).unwrap().ast_cid;
+    let seven = Term::Lit(Pos::None, Literal::Nat(7u64.into()));
+    let key =
+      CacheKey { version: EVAL_VERSION + 1, ..CacheKey::new(three, Form::Whnf) };
+    cache.put(&key, &seven);
+    assert_eq!(format!("{}", eval(&defs, "six", Some(&cache))), "6");
+  }
+
+  #[test]
+  fn undefined_refs_are_ignored() {
+    let defs = defs(SRC);
+    let cache = MemCache::default();
+    let three = defs.get(&Name::from("three")).unwrap().ast_cid;
+    // Any CID that isn't the CID of a loaded definition
+    let missing = CacheKey::new(three, Form::Norm).cid();
+    let bogus = Term::Ref(Pos::None, Name::from("bogus"), missing, missing);
+    cache.put(&CacheKey::new(three, Form::Whnf), &bogus);
+    assert_eq!(format!("{}", eval(&defs, "six", Some(&cache))), "6");
+  }
+}
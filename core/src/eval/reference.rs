@@ -0,0 +1,545 @@
+// A reference evaluator, used as an oracle for the graph reducer in tests. It
+// is an environment machine over `Term`: variables are bound to suspended
+// computations (thunks) in persistent environments, and normal forms are read
+// back by evaluating under binders with fresh variables. It is much slower
+// than `DAG::norm`, but it has no sharing to get wrong and no unsafe code.
+//
+// It follows the reduction rules of `DAG::whnf` exactly, including the stuck
+// forms (a `case` of a neutral term, a primitive applied to non-literals, a
+// literal applied to arguments), so the two must agree on every term where
+// both terminate.
+
+use crate::{
+  defs::Defs,
+  literal::{
+    LitType,
+    Literal,
+  },
+  name::Name,
+  position::Pos,
+  prim::Op,
+  term::Term,
+  uses::Uses,
+};
+
+use sp_cid::Cid;
+
+use sp_std::{
+  boxed::Box,
+  cell::RefCell,
+  fmt,
+  rc::Rc,
+  vec::Vec,
+};
+
+// The evaluator recurses to force thunks and to read back under binders, so
+// the nesting is bounded as well as the number of steps, to fail before the
+// stack overflows
+pub const MAX_DEPTH: u64 = 64;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum EvalError {
+  OutOfFuel,
+  TooDeep,
+  UndefinedReference(Name, Cid),
+  FreeVariable(Name, u64),
+}
+
+impl fmt::Display for EvalError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::OutOfFuel => write!(f, "Evaluation ran out of fuel"),
+      Self::TooDeep => {
+        write!(f, "Evaluation nested deeper than {} levels", MAX_DEPTH)
+      }
+      Self::UndefinedReference(nam, cid) => {
+        write!(f, "Undefined reference {} ({})", nam, cid)
+      }
+      Self::FreeVariable(nam, idx) => {
+        write!(f, "Free variable {}^{} in evaluated term", nam, idx)
+      }
+    }
+  }
+}
+
+// Weak head normal forms
+#[derive(Clone)]
+pub enum Value {
+  // A variable introduced while reading back under a binder, by level
+  Var(Name, u64),
+  Lam(Name, Term, Env),
+  Slf(Name, Term, Env),
+  All(Uses, Name, Box<(Term, Term)>, Env),
+  Dat(Term, Env),
+  // A `case` of a value that is neither data nor an expandable literal
+  Cse(Box<Value>),
+  // A value that can't be applied, with its arguments in application order
+  App(Box<Value>, Vec<Thunk>),
+  Typ,
+  LTy(LitType),
+  Lit(Literal),
+  Opr(Op),
+}
+
+enum Suspended {
+  Delayed(Term, Env),
+  // The expression of a recursive `let`, which is bound to itself
+  Fix(Term, Env),
+  Forced(Value),
+}
+
+#[derive(Clone)]
+pub struct Thunk(Rc<RefCell<Suspended>>);
+
+impl Thunk {
+  pub fn new(term: Term, env: Env) -> Self {
+    Thunk(Rc::new(RefCell::new(Suspended::Delayed(term, env))))
+  }
+
+  pub fn fix(term: Term, env: Env) -> Self {
+    Thunk(Rc::new(RefCell::new(Suspended::Fix(term, env))))
+  }
+
+  pub fn value(val: Value) -> Self {
+    Thunk(Rc::new(RefCell::new(Suspended::Forced(val))))
+  }
+}
+
+struct Bind {
+  val: Thunk,
+  next: Option<Rc<Bind>>,
+}
+
+// The variables in scope, innermost first, and the definition `Term::Rec`
+// refers to
+#[derive(Clone, Default)]
+pub struct Env {
+  vars: Option<Rc<Bind>>,
+  rec: Option<(Name, Cid, Cid)>,
+}
+
+impl Env {
+  pub fn bind(&self, val: Thunk) -> Self {
+    Env {
+      vars: Some(Rc::new(Bind { val, next: self.vars.clone() })),
+      rec: self.rec.clone(),
+    }
+  }
+
+  pub fn get(&self, idx: u64) -> Option<Thunk> {
+    let mut vars = self.vars.as_ref();
+    for _ in 0..idx {
+      vars = vars?.next.as_ref();
+    }
+    vars.map(|bind| bind.val.clone())
+  }
+}
+
+// The result of applying a value to pending arguments
+enum Step {
+  Done(Value),
+  Continue(Term, Env, Vec<Thunk>),
+}
+
+pub struct Evaluator<'a> {
+  defs: &'a Defs,
+  fuel: u64,
+  depth: u64,
+}
+
+impl<'a> Evaluator<'a> {
+  pub fn new(defs: &'a Defs, fuel: u64) -> Self {
+    Evaluator { defs, fuel, depth: 0 }
+  }
+
+  fn nested<A>(
+    &mut self,
+    f: impl FnOnce(&mut Self) -> Result<A, EvalError>,
+  ) -> Result<A, EvalError> {
+    if self.depth >= MAX_DEPTH {
+      return Err(EvalError::TooDeep);
+    }
+    self.depth += 1;
+    let res = f(self);
+    self.depth -= 1;
+    res
+  }
+
+  fn tick(&mut self) -> Result<(), EvalError> {
+    match self.fuel.checked_sub(1) {
+      Some(fuel) => {
+        self.fuel = fuel;
+        Ok(())
+      }
+      None => Err(EvalError::OutOfFuel),
+    }
+  }
+
+  pub fn force(&mut self, thunk: &Thunk) -> Result<Value, EvalError> {
+    let suspended = match &*thunk.0.borrow() {
+      Suspended::Forced(val) => return Ok(val.clone()),
+      Suspended::Delayed(term, env) => (term.clone(), env.clone(), false),
+      Suspended::Fix(term, env) => (term.clone(), env.clone(), true),
+    };
+    let val = match suspended {
+      (term, env, false) => self.whnf(term, env)?,
+      (term, env, true) => {
+        let env = env.bind(Thunk::fix(term.clone(), env.clone()));
+        self.whnf(term, env)?
+      }
+    };
+    *thunk.0.borrow_mut() = Suspended::Forced(val.clone());
+    Ok(val)
+  }
+
+  pub fn whnf(&mut self, term: Term, env: Env) -> Result<Value, EvalError> {
+    self.nested(|this| this.eval(term, env, Vec::new()))
+  }
+
+  // Evaluate a term applied to a stack of arguments, the first argument on top
+  fn eval(
+    &mut self,
+    mut term: Term,
+    mut env: Env,
+    mut args: Vec<Thunk>,
+  ) -> Result<Value, EvalError> {
+    loop {
+      self.tick()?;
+      let head = match term {
+        Term::App(_, fun_arg) => {
+          let (fun, arg) = *fun_arg;
+          args.push(Thunk::new(arg, env.clone()));
+          term = fun;
+          continue;
+        }
+        Term::Lam(_, nam, bod) => match args.pop() {
+          Some(arg) => {
+            env = env.bind(arg);
+            term = *bod;
+            continue;
+          }
+          None => Value::Lam(nam, *bod, env),
+        },
+        Term::Var(_, nam, idx) => match env.get(idx) {
+          Some(thunk) => self.force(&thunk)?,
+          None => return Err(EvalError::FreeVariable(nam, idx)),
+        },
+        Term::Rec(pos) => match env.rec.clone() {
+          Some((nam, def, ast)) => {
+            term = Term::Ref(pos, nam, def, ast);
+            continue;
+          }
+          None => return Err(EvalError::FreeVariable(Name::from("#^"), 0)),
+        },
+        Term::Ref(_, nam, def, ast) => match self.defs.defs.get(&def) {
+          Some(d) => {
+            term = d.term.clone();
+            env = Env { vars: None, rec: Some((nam, def, ast)) };
+            continue;
+          }
+          None => return Err(EvalError::UndefinedReference(nam, def)),
+        },
+        Term::Ann(_, typ_exp) => {
+          term = typ_exp.1;
+          continue;
+        }
+        Term::Let(_, rec, _, _, typ_exp_bod) => {
+          let (_, exp, bod) = *typ_exp_bod;
+          let exp = if rec {
+            Thunk::fix(exp, env.clone())
+          }
+          else {
+            Thunk::new(exp, env.clone())
+          };
+          env = env.bind(exp);
+          term = bod;
+          continue;
+        }
+        Term::Cse(_, bod) => match self.whnf(*bod, env.clone())? {
+          Value::Dat(bod, dat_env) => {
+            term = bod;
+            env = dat_env;
+            continue;
+          }
+          Value::Lit(lit) => match lit.clone().expand() {
+            Some(expand) => {
+              term = expand;
+              env = Env::default();
+              continue;
+            }
+            None => Value::Cse(Box::new(Value::Lit(lit))),
+          },
+          val => Value::Cse(Box::new(val)),
+        },
+        Term::Dat(_, bod) => Value::Dat(*bod, env),
+        Term::Slf(_, nam, bod) => Value::Slf(nam, *bod, env),
+        Term::All(_, uses, nam, dom_img) => Value::All(uses, nam, dom_img, env),
+        Term::Typ(_) => Value::Typ,
+        Term::LTy(_, typ) => Value::LTy(typ),
+        Term::Lit(_, lit) => Value::Lit(lit),
+        Term::Opr(_, opr) => Value::Opr(opr),
+      };
+      match self.apply(head, args)? {
+        Step::Done(val) => return Ok(val),
+        Step::Continue(next, next_env, rest) => {
+          term = next;
+          env = next_env;
+          args = rest;
+        }
+      }
+    }
+  }
+
+  fn apply(
+    &mut self,
+    head: Value,
+    mut args: Vec<Thunk>,
+  ) -> Result<Step, EvalError> {
+    match head {
+      Value::Lam(_, bod, env) if !args.is_empty() => {
+        let arg = args.pop().unwrap();
+        Ok(Step::Continue(bod, env.bind(arg), args))
+      }
+      // A stuck application may be unstuck by more arguments, as when a
+      // primitive operation is partially applied
+      Value::App(head, xs) if !args.is_empty() => {
+        args.extend(xs.into_iter().rev());
+        self.apply(*head, args)
+      }
+      Value::Opr(opr) => {
+        let arity = opr.arity() as usize;
+        let len = args.len();
+        if arity == 0 {
+          if let Some(res) = opr.apply0() {
+            return self.apply(Value::Lit(res), args);
+          }
+        }
+        else if len >= arity {
+          let mut lits = Vec::new();
+          for arg in args[len - arity..].iter().rev() {
+            if let Value::Lit(lit) = self.force(arg)? {
+              lits.push(lit);
+            }
+          }
+          let res = match lits.as_slice() {
+            [x] if arity == 1 => opr.apply1(x),
+            [x, y] if arity == 2 => opr.apply2(x, y),
+            [x, y, z] if arity == 3 => opr.apply3(x, y, z),
+            _ => None,
+          };
+          if let Some(res) = res {
+            args.truncate(len - arity);
+            return self.apply(Value::Lit(res), args);
+          }
+        }
+        Ok(Step::Done(stuck(Value::Opr(opr), args)))
+      }
+      head => Ok(Step::Done(stuck(head, args))),
+    }
+  }
+
+  // Read a value back as a term in normal form, `depth` binders deep
+  pub fn quote(&mut self, val: Value, depth: u64) -> Result<Term, EvalError> {
+    self.tick()?;
+    self.nested(|this| this.quote_value(val, depth))
+  }
+
+  fn quote_value(
+    &mut self,
+    val: Value,
+    depth: u64,
+  ) -> Result<Term, EvalError> {
+    match val {
+      Value::Var(nam, lvl) => Ok(Term::Var(Pos::None, nam, depth - lvl - 1)),
+      Value::Lam(nam, bod, env) => {
+        let bod = self.under(nam.clone(), bod, env, depth)?;
+        Ok(Term::Lam(Pos::None, nam, Box::new(bod)))
+      }
+      Value::Slf(nam, bod, env) => {
+        let bod = self.under(nam.clone(), bod, env, depth)?;
+        Ok(Term::Slf(Pos::None, nam, Box::new(bod)))
+      }
+      Value::All(uses, nam, dom_img, env) => {
+        let (dom, img) = *dom_img;
+        let dom = self.norm_in(dom, env.clone(), depth)?;
+        let img = self.under(nam.clone(), img, env, depth)?;
+        Ok(Term::All(Pos::None, uses, nam, Box::new((dom, img))))
+      }
+      Value::Dat(bod, env) => {
+        Ok(Term::Dat(Pos::None, Box::new(self.norm_in(bod, env, depth)?)))
+      }
+      Value::Cse(val) => {
+        Ok(Term::Cse(Pos::None, Box::new(self.quote(*val, depth)?)))
+      }
+      Value::App(head, args) => {
+        let mut term = self.quote(*head, depth)?;
+        for arg in args {
+          let arg = self.force(&arg)?;
+          let arg = self.quote(arg, depth)?;
+          term = Term::App(Pos::None, Box::new((term, arg)));
+        }
+        Ok(term)
+      }
+      Value::Typ => Ok(Term::Typ(Pos::None)),
+      Value::LTy(typ) => Ok(Term::LTy(Pos::None, typ)),
+      Value::Lit(lit) => Ok(Term::Lit(Pos::None, lit)),
+      Value::Opr(opr) => Ok(Term::Opr(Pos::None, opr)),
+    }
+  }
+
+  fn under(
+    &mut self,
+    nam: Name,
+    bod: Term,
+    env: Env,
+    depth: u64,
+  ) -> Result<Term, EvalError> {
+    let var = Thunk::value(Value::Var(nam, depth));
+    self.norm_in(bod, env.bind(var), depth + 1)
+  }
+
+  fn norm_in(
+    &mut self,
+    term: Term,
+    env: Env,
+    depth: u64,
+  ) -> Result<Term, EvalError> {
+    let val = self.whnf(term, env)?;
+    self.quote(val, depth)
+  }
+}
+
+// A value applied to arguments it can't consume
+fn stuck(head: Value, mut args: Vec<Thunk>) -> Value {
+  if args.is_empty() {
+    head
+  }
+  else {
+    args.reverse();
+    Value::App(Box::new(head), args)
+  }
+}
+
+// Reduce a closed term to normal form, giving up after `fuel` steps or
+// `MAX_DEPTH` nested evaluations
+pub fn norm(defs: &Defs, term: &Term, fuel: u64) -> Result<Term, EvalError> {
+  let mut evaluator = Evaluator::new(defs, fuel);
+  evaluator.norm_in(term.clone(), Env::default(), 0)
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    dag::DAG,
+    eval::test::parse_defs,
+    parse::term::parse,
+    term::tests::{
+      affine,
+      test_defs,
+    },
+  };
+  use quickcheck::TestResult;
+
+  const FUEL: u64 = 100_000;
+
+  // A package exercising datatypes, recursion and primitives
+  const SRC: &str = "
+    type Bool { True, False }
+    type Nat { Z, S Nat }
+    type List (A: Type) { Nil, Cons A (List A) }
+    def Bool.not (b: Bool): Bool = (case b) (λ _ => Bool) Bool.False Bool.True
+    def Bool.and (a: Bool) (b: Bool): Bool =
+      (case a) (λ _ => Bool) b Bool.False
+    def yes: Bool = Bool.and (Bool.not Bool.False) Bool.True
+    def two: Nat = Nat.S (Nat.S Nat.Z)
+    def List.map (A: Type) (B: Type) (f: ∀ A -> B) (xs: List A): List B =
+      (case xs) (λ _ => List B) (List.Nil B)
+      (λ x xs => List.Cons B (f x) (List.map A B f xs))
+    def List.sum (xs: List #Nat): #Nat =
+      (case xs) (λ _ => #Nat) 0 (λ x xs => #Nat.add x (List.sum xs))
+    def nums: List #Nat = List.map #Nat #Nat (#Nat.mul 2)
+      (List.Cons #Nat 1 (List.Cons #Nat 2 (List.Nil #Nat)))
+    def total: #Nat = List.sum nums
+    def fact (x: #Nat): #Nat =
+      (case x) (λ _ => #Nat) 1 (λ x' => #Nat.mul x (fact x'))
+    def fact5: #Nat = fact 5
+    def countdown: #Nat = letrec go: ∀ #Nat -> #Nat = λ n =>
+      (case n) (λ _ => #Nat) 0 (λ p => #Nat.add 2 (go p)); go 4
+    def max: #I64 = #I64.max
+    def text: #Text = #Text.insert 1 \"ac\" \"b\"
+    def lits (A: Type) (f: ∀ #I64 -> A): A = f #I64.max
+    def stuck (n: #Nat): #Nat =
+      #Nat.add n ((case n) (λ _ => #Nat) 0 (λ p => p))
+  ";
+
+  // The normal form of a term on the graph reducer
+  fn dag_norm(defs: &Defs, term: &Term) -> Term {
+    let mut dag = DAG::from_term(term);
+    dag.norm(defs, false);
+    let term = dag.to_term(false);
+    dag.free();
+    term
+  }
+
+  fn run(src: &str) -> Result<Term, EvalError> {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    let (_, term) = parse(src, defs.clone()).unwrap();
+    norm(&defs, &term, FUEL)
+  }
+
+  #[test]
+  fn evaluates_package() {
+    assert_eq!(format!("{}", run("fact5").unwrap()), "120");
+    assert_eq!(format!("{}", run("total").unwrap()), "6");
+    assert_eq!(format!("{}", run("countdown").unwrap()), "8");
+    assert_eq!(
+      format!("{}", run("λ x => #Nat.add ((λ y => y) x)").unwrap()),
+      "λ x => #Nat.add x"
+    );
+  }
+
+  #[test]
+  fn gives_up_on_divergence() {
+    assert_eq!(run("(λ x => x x) (λ x => x x)"), Err(EvalError::OutOfFuel));
+    assert_eq!(run("λ x => fact x"), Err(EvalError::TooDeep));
+  }
+
+  #[quickcheck]
+  fn norm_matches_dag(x: Term) -> TestResult {
+    if !affine(&x, &mut vec![]) {
+      return TestResult::discard();
+    }
+    let defs = test_defs();
+    match norm(&defs, &x, FUEL) {
+      Ok(expected) => TestResult::from_bool(dag_norm(&defs, &x) == expected),
+      Err(_) => TestResult::discard(),
+    }
+  }
+
+  // Every definition whose normal form the reference evaluator finds must
+  // normalize to the same term on the graph reducer. Definitions that don't
+  // terminate, like recursive functions and datatypes, are skipped.
+  #[test]
+  fn package_defs_match_dag() {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    let mut compared = 0;
+    for (nam, cid) in &defs.names {
+      let def = defs.defs.get(cid).unwrap();
+      let term = Term::Ref(Pos::None, nam.clone(), def.def_cid, def.ast_cid);
+      match norm(&defs, &term, FUEL) {
+        Ok(expected) => {
+          assert_eq!(dag_norm(&defs, &term), expected, "{}", nam);
+          compared += 1;
+        }
+        Err(e) => assert!(
+          matches!(e, EvalError::OutOfFuel | EvalError::TooDeep),
+          "{}: {}",
+          nam,
+          e
+        ),
+      }
+    }
+    assert!(compared >= 15, "only {} definitions compared", compared);
+  }
+}
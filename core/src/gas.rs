@@ -0,0 +1,276 @@
+// Gas metering for deterministic evaluation. Packages fetched by CID may come
+// from anyone, so running them needs a budget that doesn't depend on the
+// machine: instead of time or memory, evaluation is charged a fixed cost for
+// each step it takes. Both the typed evaluator (`DAG::whnf`) and the runtime
+// (`runtime::whnf`) charge
+//
+// - `BETA` for every β-reduction, `let` substitution and case on data
+// - `UNFOLD` for every unfolding of a reference or of a fixpoint
+// - `ALLOC` for every node they allocate
+// - `OP` for every primitive operation, plus `WORD` for every machine word of
+//   its operands, so that arithmetic on a large `#Nat` or splitting a long
+//   `#Text` costs as much as the work it does
+//
+// Evaluation stops at the first step after the gas used goes over the limit,
+// leaving the graph as it was after that step. The same term with the same
+// definitions always takes the same steps, so it always uses the same gas.
+// Reusing cached normal forms skips steps, so a metered evaluation shouldn't
+// use a cache.
+//
+// The meter is per thread, so that evaluations on other threads don't charge
+// it. Without `std` there is only one.
+
+use crate::literal::Literal;
+
+use core::fmt;
+
+pub const BETA: u64 = 4;
+pub const UNFOLD: u64 = 16;
+pub const ALLOC: u64 = 1;
+pub const OP: u64 = 2;
+pub const WORD: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Meter {
+  limit: Option<u64>,
+  used: u64,
+}
+
+const UNMETERED: Meter = Meter { limit: None, used: 0 };
+
+#[cfg(any(feature = "std", test))]
+std::thread_local! {
+  static METER: core::cell::Cell<Meter> = core::cell::Cell::new(UNMETERED);
+}
+
+#[cfg(any(feature = "std", test))]
+fn get() -> Meter { METER.with(|meter| meter.get()) }
+
+#[cfg(any(feature = "std", test))]
+fn set(meter: Meter) { METER.with(|cell| cell.set(meter)) }
+
+#[cfg(not(any(feature = "std", test)))]
+mod global {
+  use super::Meter;
+  use core::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    Ordering,
+  };
+
+  static METERED: AtomicBool = AtomicBool::new(false);
+  static LIMIT: AtomicU64 = AtomicU64::new(0);
+  static USED: AtomicU64 = AtomicU64::new(0);
+
+  pub fn get() -> Meter {
+    let limit = if METERED.load(Ordering::SeqCst) {
+      Some(LIMIT.load(Ordering::SeqCst))
+    }
+    else {
+      None
+    };
+    Meter { limit, used: USED.load(Ordering::SeqCst) }
+  }
+
+  pub fn set(meter: Meter) {
+    METERED.store(meter.limit.is_some(), Ordering::SeqCst);
+    LIMIT.store(meter.limit.unwrap_or(0), Ordering::SeqCst);
+    USED.store(meter.used, Ordering::SeqCst);
+  }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+use global::{
+  get,
+  set,
+};
+
+// The gas used by a metered evaluation that went over its limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfGas {
+  pub limit: u64,
+  pub used: u64,
+}
+
+impl fmt::Display for OutOfGas {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Out of gas: used {} with a limit of {}", self.used, self.limit)
+  }
+}
+
+// Start metering evaluation on this thread, with no gas used yet
+pub fn meter(limit: u64) { set(Meter { limit: Some(limit), used: 0 }) }
+
+// Stop metering, returning the gas used
+pub fn unmeter() -> u64 {
+  let used = get().used;
+  set(UNMETERED);
+  used
+}
+
+pub fn used() -> u64 { get().used }
+
+pub fn limit() -> Option<u64> { get().limit }
+
+// Whether a metered evaluation went over its limit, and must stop
+#[inline]
+pub fn exhausted() -> bool {
+  let meter = get();
+  meter.limit.map_or(false, |limit| meter.used > limit)
+}
+
+#[inline]
+pub fn charge(cost: u64) {
+  let meter = get();
+  if meter.limit.is_some() {
+    set(Meter { used: meter.used.saturating_add(cost), ..meter })
+  }
+}
+
+// Charge a primitive operation on its operands
+pub fn charge_op(args: &[&Literal]) {
+  charge(args.iter().fold(OP, |cost, arg| cost + WORD * words(arg)))
+}
+
+// The size of a literal in machine words
+pub fn words(lit: &Literal) -> u64 {
+  match lit {
+    Literal::Nat(x) => x.bits() / 64 + 1,
+    Literal::Int(x) => x.bits() / 64 + 1,
+    Literal::Bits(x) => x.len() as u64 / 64 + 1,
+    Literal::Bytes(x) => x.len() as u64 / 8 + 1,
+    Literal::Text(x) => x.len_bytes() as u64 / 8 + 1,
+    Literal::U128(_) | Literal::I128(_) => 2,
+    _ => 1,
+  }
+}
+
+// Report the gas used so far, or that the limit was exceeded
+pub fn report() -> Result<u64, OutOfGas> {
+  let Meter { limit, used } = get();
+  match limit {
+    Some(limit) if used > limit => Err(OutOfGas { limit, used }),
+    _ => Ok(used),
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    dag::DAG,
+    defs::Defs,
+    dll::DLL,
+    eval::{
+      test::parse_defs,
+      Strategy,
+    },
+    parse::term::parse,
+    runtime,
+  };
+
+  const SRC: &str = "
+    def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+      #Nat.mul x (fact x'))
+  ";
+
+  fn defs() -> Defs {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    defs
+  }
+
+  // The normal form of an expression and the gas used to reach it
+  type Eval = fn(&Defs, &str, u64) -> (String, Result<u64, OutOfGas>);
+
+  // Evaluate on the typed evaluator
+  fn eval(
+    defs: &Defs,
+    src: &str,
+    limit: u64,
+  ) -> (String, Result<u64, OutOfGas>) {
+    let (_, term) = parse(src, defs.clone()).unwrap();
+    meter(limit);
+    let mut dag = DAG::from_term(&term);
+    dag.norm(defs, false);
+    let res = (format!("{}", dag), report());
+    unmeter();
+    dag.free();
+    res
+  }
+
+  // Evaluate on the runtime
+  fn run(
+    defs: &Defs,
+    src: &str,
+    limit: u64,
+  ) -> (String, Result<u64, OutOfGas>) {
+    let (_, term) = parse(src, defs.clone()).unwrap();
+    meter(limit);
+    let root = runtime::alloc_val(DLL::singleton(runtime::ParentPtr::Root));
+    let mut dag = runtime::from_term(defs, &term, Strategy::Lazy, Some(root));
+    runtime::norm(&mut dag, false);
+    let res = (format!("{}", runtime::to_term(dag)), report());
+    unmeter();
+    res
+  }
+
+  #[test]
+  fn uses_the_same_gas_every_time() {
+    let defs = defs();
+    for eval in &[eval as Eval, run] {
+      let (res, gas) = eval(&defs, "fact 6", u64::MAX);
+      assert_eq!(res, "720");
+      let gas = gas.unwrap();
+      assert!(gas > 0);
+      assert_eq!(eval(&defs, "fact 6", u64::MAX).1, Ok(gas));
+      // More steps use more gas
+      assert!(eval(&defs, "fact 7", u64::MAX).1.unwrap() > gas);
+    }
+  }
+
+  #[test]
+  fn stops_out_of_gas() {
+    let defs = defs();
+    for eval in &[eval as Eval, run] {
+      let gas = eval(&defs, "fact 6", u64::MAX).1.unwrap();
+      let (res, out) = eval(&defs, "fact 6", gas / 2);
+      assert_ne!(res, "720");
+      match out {
+        Err(OutOfGas { limit, used }) => {
+          assert_eq!(limit, gas / 2);
+          assert!(used > limit && used < gas);
+        }
+        Ok(used) => panic!("{} gas used with a limit of {}", used, gas / 2),
+      }
+      // Running out of gas is deterministic too
+      assert_eq!(eval(&defs, "fact 6", gas / 2).1, out);
+      assert_eq!(eval(&defs, "fact 6", gas).1, Ok(gas));
+    }
+  }
+
+  #[test]
+  fn charges_operations_by_size() {
+    let defs = defs();
+    let small = eval(&defs, "#Nat.add 1 2", u64::MAX).1.unwrap();
+    let big = eval(
+      &defs,
+      "#Nat.add 100000000000000000000000000000000000000000000 2",
+      u64::MAX,
+    );
+    assert!(big.1.unwrap() > small);
+  }
+
+  #[test]
+  fn doesnt_charge_unmetered() {
+    let defs = defs();
+    meter(0);
+    unmeter();
+    let (_, term) = parse("fact 3", defs.clone()).unwrap();
+    let mut dag = DAG::from_term(&term);
+    dag.norm(&defs, false);
+    assert_eq!(used(), 0);
+    assert_eq!(report(), Ok(0));
+    assert!(!exhausted());
+    dag.free();
+  }
+}
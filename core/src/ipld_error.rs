@@ -42,6 +42,7 @@ pub enum IpldError {
   Import(Ipld),
   ImportEntry(Ipld),
   Package(Ipld),
+  Snapshot(Ipld),
 }
 
 impl From<IpldError> for String {
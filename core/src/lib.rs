@@ -12,14 +12,18 @@ extern crate quickcheck_macros;
 extern crate rand;
 
 pub mod anon;
+pub mod arena;
 pub mod check;
+pub mod codegen;
 pub mod dag;
 pub mod defs;
 pub mod dll;
 pub mod embed_error;
 pub mod eval;
+pub mod gas;
 pub mod ipld_error;
 pub mod literal;
+pub mod memory;
 pub mod meta;
 pub mod name;
 pub mod package;
@@ -177,7 +177,11 @@ impl Literal {
           ))
         }
       }
-      Self::Int(_) => Some(yatima!("λ P i => i")),
+      Self::Int(x) => Some(yatima!(
+        "λ P i => i #$0 #$1",
+        Term::Lit(Pos::None, Literal::Bool(x.sign() != Sign::Minus)),
+        Term::Lit(Pos::None, Literal::Nat(x.magnitude().clone()))
+      )),
       Self::Bits(mut t) => {
         let c = t.pop();
         match c {
@@ -0,0 +1,319 @@
+// Memory limits for evaluation. A long chain of `#Nat.mul` or a runaway
+// `#Bytes.append` can use up all the memory there is, and aborting on a failed
+// allocation takes down the whole process, or the browser tab running the web
+// REPL. Under `limit`, the evaluators count
+//
+// - the live nodes they allocate in `alloc_val`, net of the ones they free
+// - the bytes of the literals built by primitive operations, in total
+//
+// and stop at the next evaluation step once a limit is exceeded, so that
+// `report` can return an error instead. The literal a primitive operation
+// builds is measured once it is built, and evaluation stops without using it
+// if it doesn't fit.
+//
+// Like the gas meter, the counts are kept per thread.
+
+use crate::{
+  gas,
+  literal::Literal,
+};
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+  // The most nodes that can be live at once
+  pub nodes: Option<u64>,
+  // The most bytes of literals that primitive operations can build
+  pub lit_bytes: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutOfMemory {
+  Nodes { limit: u64, live: u64 },
+  LitBytes { limit: u64, used: u64 },
+}
+
+impl fmt::Display for OutOfMemory {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Nodes { limit, live } => write!(
+        f,
+        "Out of memory: {} live nodes with a limit of {}",
+        live, limit
+      ),
+      Self::LitBytes { limit, used } => write!(
+        f,
+        "Out of memory: {} bytes of literals with a limit of {}",
+        used, limit
+      ),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Usage {
+  limits: Limits,
+  live: u64,
+  lit_bytes: u64,
+  exceeded: Option<OutOfMemory>,
+}
+
+const UNLIMITED: Usage = Usage {
+  limits: Limits { nodes: None, lit_bytes: None },
+  live: 0,
+  lit_bytes: 0,
+  exceeded: None,
+};
+
+#[cfg(any(feature = "std", test))]
+std::thread_local! {
+  static USAGE: core::cell::Cell<Usage> = core::cell::Cell::new(UNLIMITED);
+}
+
+#[cfg(any(feature = "std", test))]
+fn get() -> Usage { USAGE.with(|usage| usage.get()) }
+
+#[cfg(any(feature = "std", test))]
+fn set(usage: Usage) { USAGE.with(|cell| cell.set(usage)) }
+
+#[cfg(not(any(feature = "std", test)))]
+mod global {
+  use super::Usage;
+  use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    sync::atomic::{
+      AtomicBool,
+      Ordering,
+    },
+  };
+
+  struct Global {
+    lock: AtomicBool,
+    usage: UnsafeCell<Usage>,
+  }
+
+  // The usage is only read or written while holding the lock
+  unsafe impl Sync for Global {}
+
+  static USAGE: Global = Global {
+    lock: AtomicBool::new(false),
+    usage: UnsafeCell::new(super::UNLIMITED),
+  };
+
+  fn with<A>(f: impl FnOnce(&mut Usage) -> A) -> A {
+    while USAGE
+      .lock
+      .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .is_err()
+    {
+      spin_loop();
+    }
+    let res = f(unsafe { &mut *USAGE.usage.get() });
+    USAGE.lock.store(false, Ordering::Release);
+    res
+  }
+
+  pub fn get() -> Usage { with(|usage| *usage) }
+
+  pub fn set(usage: Usage) { with(|cell| *cell = usage) }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+use global::{
+  get,
+  set,
+};
+
+// Start enforcing limits on this thread, with nothing used yet
+pub fn limit(limits: Limits) { set(Usage { limits, ..UNLIMITED }) }
+
+// Stop enforcing limits
+pub fn unlimit() { set(UNLIMITED) }
+
+// Whether evaluation went over a limit, and must stop
+#[inline]
+pub fn exceeded() -> bool { get().exceeded.is_some() }
+
+// Count a newly allocated node
+#[inline]
+pub fn alloc_node() {
+  let mut usage = get();
+  if let Some(limit) = usage.limits.nodes {
+    usage.live += 1;
+    if usage.live > limit && usage.exceeded.is_none() {
+      usage.exceeded = Some(OutOfMemory::Nodes { limit, live: usage.live });
+    }
+    set(usage)
+  }
+}
+
+// Count a freed node. Nodes allocated before the limits were set are freed
+// without having been counted, so the count stops at zero.
+#[inline]
+pub fn free_node() {
+  let mut usage = get();
+  if usage.limits.nodes.is_some() {
+    usage.live = usage.live.saturating_sub(1);
+    set(usage)
+  }
+}
+
+// Count the nodes left in a dropped arena as freed
+pub fn free_nodes(nodes: u64) {
+  let mut usage = get();
+  if usage.limits.nodes.is_some() {
+    usage.live = usage.live.saturating_sub(nodes);
+    set(usage)
+  }
+}
+
+// The bytes a literal takes up. Bits are stored one byte per bit, the other
+// literals in words.
+pub fn bytes(lit: &Literal) -> u64 {
+  match lit {
+    Literal::Bits(x) => x.len() as u64 + 8,
+    _ => gas::words(lit) * 8,
+  }
+}
+
+// Count a literal built by a primitive operation, and whether it still fits in
+// the limit on literals. Marks the limit as exceeded if it doesn't.
+pub fn record_lit(lit: &Literal) -> bool {
+  let mut usage = get();
+  match usage.limits.lit_bytes {
+    Some(limit) => {
+      let used = usage.lit_bytes.saturating_add(bytes(lit));
+      usage.lit_bytes = used;
+      if used > limit {
+        usage.exceeded = usage
+          .exceeded
+          .or(Some(OutOfMemory::LitBytes { limit, used }));
+      }
+      set(usage);
+      used <= limit
+    }
+    None => true,
+  }
+}
+
+// Report whether evaluation went over a limit
+pub fn report() -> Result<(), OutOfMemory> {
+  match get().exceeded {
+    Some(err) => Err(err),
+    None => Ok(()),
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    dag::DAG,
+    defs::Defs,
+    dll::DLL,
+    eval::{
+      test::parse_defs,
+      Strategy,
+    },
+    parse::term::parse,
+    runtime,
+  };
+
+  const SRC: &str = "
+    def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+      #Nat.mul x (fact x'))
+    def square (n: #Nat) (x: #Nat): #Nat = (case n) (λ _ => #Nat) x \
+      (λ p => square p (#Nat.mul x x))
+  ";
+
+  fn defs() -> Defs {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    defs
+  }
+
+  type Eval = fn(&Defs, &str, Limits) -> (String, Result<(), OutOfMemory>);
+
+  // Evaluate on the typed evaluator
+  fn eval(
+    defs: &Defs,
+    src: &str,
+    limits: Limits,
+  ) -> (String, Result<(), OutOfMemory>) {
+    let (_, term) = parse(src, defs.clone()).unwrap();
+    limit(limits);
+    let mut dag = DAG::from_term(&term);
+    dag.norm(defs, false);
+    let res = (format!("{}", dag), report());
+    unlimit();
+    dag.free();
+    res
+  }
+
+  // Evaluate on the runtime
+  fn run(
+    defs: &Defs,
+    src: &str,
+    limits: Limits,
+  ) -> (String, Result<(), OutOfMemory>) {
+    let (_, term) = parse(src, defs.clone()).unwrap();
+    limit(limits);
+    let root = runtime::alloc_val(DLL::singleton(runtime::ParentPtr::Root));
+    let mut dag = runtime::from_term(defs, &term, Strategy::Lazy, Some(root));
+    runtime::norm(&mut dag, false);
+    let res = (format!("{}", runtime::to_term(dag)), report());
+    unlimit();
+    res
+  }
+
+  #[test]
+  fn evaluates_within_limits() {
+    let defs = defs();
+    let limits = Limits { nodes: Some(100_000), lit_bytes: Some(1 << 16) };
+    for eval in &[eval as Eval, run] {
+      assert_eq!(eval(&defs, "fact 6", limits), ("720".to_owned(), Ok(())));
+    }
+  }
+
+  #[test]
+  fn stops_on_too_many_nodes() {
+    let defs = defs();
+    let limits = Limits { nodes: Some(10), lit_bytes: None };
+    for eval in &[eval as Eval, run] {
+      match eval(&defs, "fact 6", limits).1 {
+        Err(OutOfMemory::Nodes { limit: 10, live }) => assert!(live > 10),
+        res => panic!("unexpected {:?}", res),
+      }
+    }
+  }
+
+  #[test]
+  fn stops_before_building_huge_literals() {
+    let defs = defs();
+    // Squaring 2 twenty times builds a literal of a million bits
+    let limits = Limits { nodes: None, lit_bytes: Some(4096) };
+    for eval in &[eval as Eval, run] {
+      match eval(&defs, "square 20 2", limits).1 {
+        Err(OutOfMemory::LitBytes { limit: 4096, used }) => {
+          assert!(used > 4096)
+        }
+        res => panic!("unexpected {:?}", res),
+      }
+    }
+  }
+
+  #[test]
+  fn measures_results_larger_than_their_operands() {
+    let defs = defs();
+    // Converting 128 bytes to bits builds 1024 bits, a byte each
+    let src = format!("#Bytes.to_Bits x'{}'", "ff".repeat(128));
+    let limits = Limits { nodes: None, lit_bytes: Some(512) };
+    for eval in &[eval as Eval, run] {
+      match eval(&defs, &src, limits).1 {
+        Err(OutOfMemory::LitBytes { limit: 512, used: 1032 }) => (),
+        res => panic!("unexpected {:?}", res),
+      }
+    }
+  }
+}
@@ -1,3 +1,8 @@
+// Packages, their imports and the entries of their definitions are stored by
+// content id. The fields added to them since are left out of their IPLD
+// unless they're set, so that everything stored before those fields existed
+// keeps its content id.
+
 use core::fmt;
 
 use crate::{
@@ -28,6 +33,10 @@ pub struct Package {
   pub name: Name,
   pub imports: Vec<Import>,
   pub index: Index,
+  // The names other packages can import, if not every name in the index. The
+  // index still lists every definition, since the exported ones can refer to
+  // the others.
+  pub exports: Option<Vec<Name>>,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -36,6 +45,7 @@ pub struct Import {
   pub name: Name,
   pub alias: Name,
   pub with: Vec<Name>,
+  pub renaming: Vec<(Name, Name)>,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -48,17 +58,23 @@ pub struct Entry {
   pub term_anon: Cid,
   pub type_meta: Meta,
   pub term_meta: Meta,
+  pub strict: bool,
 }
 
 impl Entry {
   pub fn to_ipld(&self) -> Ipld {
-    Ipld::List(vec![
+    let mut xs = vec![
       self.pos.to_ipld(),
       Ipld::Link(self.type_anon),
       Ipld::Link(self.term_anon),
       self.type_meta.to_ipld(),
       self.term_meta.to_ipld(),
-    ])
+    ];
+    // Lazy entries leave the flag out
+    if self.strict {
+      xs.push(Ipld::Bool(true));
+    }
+    Ipld::List(xs)
   }
 
   pub fn from_ipld(ipld: &Ipld) -> Result<Self, IpldError> {
@@ -70,7 +86,13 @@ impl Entry {
           Ipld::Link(term_anon),
           type_meta,
           term_meta,
+          strict @ ..,
         ] => {
+          let strict = match strict {
+            [] => false,
+            [Ipld::Bool(true)] => true,
+            _ => return Err(IpldError::Entry(Ipld::List(xs.to_owned()))),
+          };
           let pos = Pos::from_ipld(pos)?;
           let type_meta = Meta::from_ipld(type_meta)?;
           let term_meta = Meta::from_ipld(term_meta)?;
@@ -79,7 +101,8 @@ impl Entry {
             type_anon: *type_anon,
             term_anon: *term_anon,
             type_meta,
-            term_meta
+            term_meta,
+            strict,
             })
         }
         xs => Err(IpldError::Entry(Ipld::List(xs.to_owned()))),
@@ -93,7 +116,7 @@ impl Entry {
 
 impl fmt::Display for Entry {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    writeln!(f, "Entry")?;
+    writeln!(f, "Entry{}", if self.strict { " (strict)" } else { "" })?;
     writeln!(f, "  Type ({}):", self.type_anon)?;
     writeln!(f, "  {}", self.type_meta)?;
     writeln!(f, "  Term ({}):", self.term_anon)?;
@@ -161,21 +184,60 @@ impl fmt::Display for Index {
 
 impl Import {
   pub fn to_ipld(&self) -> Ipld {
-    Ipld::List(vec![
+    let mut xs = vec![
       Ipld::Link(self.cid),
       Ipld::String(self.name.to_string()),
       Ipld::String(self.alias.to_string()),
       Ipld::List(
         self.with.iter().map(|x| Ipld::String(x.to_string())).collect(),
       ),
-    ])
+    ];
+    // Imports that rename nothing leave the renaming out
+    if !self.renaming.is_empty() {
+      xs.push(Ipld::List(
+        self
+          .renaming
+          .iter()
+          .map(|(from, to)| {
+            Ipld::List(vec![
+              Ipld::String(from.to_string()),
+              Ipld::String(to.to_string()),
+            ])
+          })
+          .collect(),
+      ));
+    }
+    Ipld::List(xs)
   }
 
   pub fn from_ipld(ipld: &Ipld) -> Result<Self, IpldError> {
     match ipld {
       Ipld::List(xs) => match xs.as_slice() {
-        [Ipld::Link(cid), Ipld::String(name), Ipld::String(alias), Ipld::List(with)] =>
-        {
+        #[rustfmt::skip]
+        [ Ipld::Link(cid),
+          Ipld::String(name),
+          Ipld::String(alias),
+          Ipld::List(with),
+          renaming @ ..,
+        ] => {
+          let renaming = match renaming {
+            [] => Vec::new(),
+            [Ipld::List(rs)] => {
+              let mut res = Vec::new();
+              for r in rs {
+                let (from, to) = match r {
+                  Ipld::List(ft) => match ft.as_slice() {
+                    [Ipld::String(from), Ipld::String(to)] => (from, to),
+                    _ => return Err(IpldError::ImportEntry(r.to_owned())),
+                  },
+                  r => return Err(IpldError::ImportEntry(r.to_owned())),
+                };
+                res.push((Name::from(from.clone()), Name::from(to.clone())));
+              }
+              res
+            }
+            _ => return Err(IpldError::Import(Ipld::List(xs.to_owned()))),
+          };
           let mut res: Vec<String> = Vec::new();
           for w in with {
             match w {
@@ -190,6 +252,7 @@ impl Import {
             name: Name::from(name.clone()),
             alias: Name::from(alias.clone()),
             with: res.iter().cloned().map(Name::from).collect(),
+            renaming,
           })
         }
         xs => Err(IpldError::Import(Ipld::List(xs.to_owned()))),
@@ -212,7 +275,10 @@ impl fmt::Display for Import {
       )?;
     }
     for withIdent in self.with.clone() {
-      writeln!(f, "  {}", withIdent)?;
+      match self.renaming.iter().find(|(from, _)| *from == withIdent) {
+        Some((_, to)) => writeln!(f, "  {} to {}", withIdent, to)?,
+        None => writeln!(f, "  {}", withIdent)?,
+      }
     }
     Ok(())
   }
@@ -220,6 +286,10 @@ impl fmt::Display for Import {
 
 pub fn import_alias(name: Name, import: &Import) -> Name {
   if import.with.iter().any(|x| *x == name) {
+    let name = match import.renaming.iter().find(|(from, _)| *from == name) {
+      Some((_, to)) => to.clone(),
+      None => name,
+    };
     if import.alias.is_empty() {
       name
     }
@@ -234,18 +304,25 @@ pub fn import_alias(name: Name, import: &Import) -> Name {
 
 impl Package {
   pub fn to_ipld(&self) -> Ipld {
-    Ipld::List(vec![
+    let mut xs = vec![
       self.pos.to_ipld(),
       Ipld::String(self.name.to_string()),
       Ipld::List(self.imports.iter().map(Import::to_ipld).collect()),
       self.index.to_ipld(),
-    ])
+    ];
+    // Packages that export every definition leave the exports out
+    if let Some(exports) = &self.exports {
+      xs.push(Ipld::List(
+        exports.iter().map(|x| Ipld::String(x.to_string())).collect(),
+      ));
+    }
+    Ipld::List(xs)
   }
 
   pub fn from_ipld(ipld: &Ipld) -> Result<Self, IpldError> {
     match ipld {
       Ipld::List(xs) => match xs.as_slice() {
-        [pos, Ipld::String(name), Ipld::List(is), index] => {
+        [pos, Ipld::String(name), Ipld::List(is), index, exports @ ..] => {
           let pos: Pos = Pos::from_ipld(pos)?;
           let mut imports: Vec<Import> = Vec::new();
           for i in is {
@@ -253,7 +330,29 @@ impl Package {
             imports.push(i);
           }
           let index = Index::from_ipld(index)?;
-          Ok(Package { pos, name: Name::from(name.clone()), imports, index })
+          let exports = match exports {
+            [] => None,
+            [Ipld::List(exports)] => {
+              let mut names = Vec::new();
+              for x in exports {
+                match x {
+                  Ipld::String(x) => names.push(Name::from(x.clone())),
+                  _ => {
+                    return Err(IpldError::Package(Ipld::List(xs.to_owned())));
+                  }
+                }
+              }
+              Some(names)
+            }
+            _ => return Err(IpldError::Package(Ipld::List(xs.to_owned()))),
+          };
+          Ok(Package {
+            pos,
+            name: Name::from(name.clone()),
+            imports,
+            index,
+            exports,
+          })
         }
         xs => Err(IpldError::Package(Ipld::List(xs.to_owned()))),
       },
@@ -262,6 +361,18 @@ impl Package {
   }
 
   pub fn cid(&self) -> Cid { cid(&self.to_ipld()) }
+
+  // The names other packages can import
+  pub fn exported(&self) -> Vec<Name> {
+    self.exports.clone().unwrap_or_else(|| self.index.keys())
+  }
+
+  pub fn is_exported(&self, name: &Name) -> bool {
+    match &self.exports {
+      Some(exports) => exports.contains(name),
+      None => self.index.0.iter().any(|(n, _)| n == name),
+    }
+  }
 }
 
 impl fmt::Display for Package {
@@ -271,6 +382,12 @@ impl fmt::Display for Package {
       writeln!(f, "{}", i)?;
     }
     writeln!(f, "{}", self.index)?;
+    if let Some(exports) = &self.exports {
+      writeln!(f, "Exported")?;
+      for n in exports {
+        writeln!(f, " {}", n)?;
+      }
+    }
     Ok(())
   }
 }
@@ -313,6 +430,7 @@ pub mod tests {
         name: Name::from("Test"),
         cid: arbitrary_cid(g),
         alias: arbitrary_name(g),
+        renaming: vec.iter().map(|n| (n.clone(), arbitrary_name(g))).collect(),
         with: vec,
       }
     }
@@ -325,6 +443,13 @@ pub mod tests {
         name: arbitrary_name(g),
         imports: Arbitrary::arbitrary(g),
         index: Arbitrary::arbitrary(g),
+        exports: if Arbitrary::arbitrary(g) {
+          let vec: Vec<()> = Arbitrary::arbitrary(g);
+          Some(vec.into_iter().map(|_| arbitrary_name(g)).collect())
+        }
+        else {
+          None
+        },
       }
     }
   }
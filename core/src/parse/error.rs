@@ -43,6 +43,10 @@ use alloc::string::String;
 pub enum ParseErrorKind {
   UndefinedReference(Name, ConsList<Name>),
   TopLevelRedefinition(Name),
+  UndefinedExport(Name),
+  PrivateExport(Name),
+  UnknownImport(Name),
+  ClashingImport(Name),
   UnknownLiteralType(String),
   InvalidBaseEncoding(base::LitBase),
   UnknownBaseCode,
@@ -84,6 +88,18 @@ impl<'a> fmt::Display for ParseErrorKind {
       Self::UndefinedReference(name, _) => {
         write!(f, "Undefined reference {}", name)
       }
+      Self::UndefinedExport(name) => {
+        write!(f, "Exported name \"{}\" is not defined in this package", name)
+      }
+      Self::PrivateExport(name) => {
+        write!(f, "Exported name \"{}\" is defined as private", name)
+      }
+      Self::UnknownImport(name) => {
+        write!(f, "Imported name \"{}\" is not exported by the package", name)
+      }
+      Self::ClashingImport(name) => {
+        write!(f, "Import renames more than one name to \"{}\"", name)
+      }
       Self::TopLevelRedefinition(name) => {
         write!(
           f,
@@ -37,8 +37,14 @@ use nom::{
     eof,
     opt,
   },
-  multi::separated_list0,
-  sequence::terminated,
+  multi::{
+    many0,
+    separated_list0,
+  },
+  sequence::{
+    terminated,
+    tuple,
+  },
   Err,
   IResult,
 };
@@ -71,6 +77,101 @@ pub fn parse_with(i: Span) -> IResult<Span, Vec<Name>, ParseError<Span>> {
   Ok((i, ns))
 }
 
+// An `export (a, b, c)` clause, listing the only names of a package other
+// packages can import
+pub fn parse_export(i: Span) -> IResult<Span, Vec<Name>, ParseError<Span>> {
+  let (i, _) = tag("export")(i)?;
+  let (i, _) = parse_space(i)?;
+  parse_with(i)
+}
+
+// A `hiding (a, b)` clause, leaving names of an import out of scope
+pub fn parse_hiding(i: Span) -> IResult<Span, Vec<Name>, ParseError<Span>> {
+  let (i, _) = tag("hiding")(i)?;
+  let (i, _) = parse_space(i)?;
+  parse_with(i)
+}
+
+pub fn parse_rename(i: Span) -> IResult<Span, (Name, Name), ParseError<Span>> {
+  let (i, from) = parse_name(i)?;
+  let (i, _) = parse_space1(i)?;
+  let (i, _) = tag("to")(i)?;
+  let (i, _) = parse_space1(i)?;
+  let (i, to) = parse_name(i)?;
+  Ok((i, (from, to)))
+}
+
+// A `renaming (a to b, c to d)` clause, bringing names of an import into
+// scope under other names
+pub fn parse_renaming(
+  i: Span,
+) -> IResult<Span, Vec<(Name, Name)>, ParseError<Span>> {
+  let (i, _) = tag("renaming")(i)?;
+  let (i, _) = parse_space(i)?;
+  let (i, _) = tag("(")(i)?;
+  let (i, _) = parse_space(i)?;
+  let (i, rs) = separated_list0(
+    terminated(tag(","), parse_space),
+    terminated(parse_rename, parse_space),
+  )(i)?;
+  let (i, _) = tag(")")(i)?;
+  Ok((i, rs))
+}
+
+// The names an import brings into scope out of the `names` it can import,
+// along with the ones it renames. Names in the `with`, `hiding` or `renaming`
+// clauses that can't be imported are errors at the clause, as is renaming
+// two names to the same one.
+pub fn import_scope<'a>(
+  names: &[Name],
+  with: Option<(Span<'a>, Vec<Name>)>,
+  hiding: Option<(Span<'a>, Vec<Name>)>,
+  renaming: Option<(Span<'a>, Vec<(Name, Name)>)>,
+) -> Result<(Vec<Name>, Vec<(Name, Name)>), (Span<'a>, ParseErrorKind)> {
+  let mut scope = match with {
+    Some((from, with)) => {
+      if let Some(n) = with.iter().find(|n| !names.contains(n)) {
+        return Err((from, ParseErrorKind::UnknownImport(n.clone())));
+      }
+      with
+    }
+    None => names.to_vec(),
+  };
+  if let Some((from, hiding)) = hiding {
+    if let Some(n) = hiding.iter().find(|n| !scope.contains(n)) {
+      return Err((from, ParseErrorKind::UnknownImport(n.clone())));
+    }
+    scope.retain(|n| !hiding.contains(n));
+  }
+  let renaming = match renaming {
+    Some((from, renaming)) => {
+      if let Some((n, _)) = renaming.iter().find(|(n, _)| !scope.contains(n)) {
+        return Err((from, ParseErrorKind::UnknownImport(n.clone())));
+      }
+      let mut renamed: Vec<Name> = Vec::new();
+      for n in &scope {
+        let n = match renaming.iter().find(|(r, _)| r == n) {
+          Some((_, to)) => to,
+          None => n,
+        };
+        if renamed.contains(n) {
+          return Err((from, ParseErrorKind::ClashingImport(n.clone())));
+        }
+        renamed.push(n.clone());
+      }
+      // A name renamed twice clashes with itself
+      for (i, (n, _)) in renaming.iter().enumerate() {
+        if renaming[..i].iter().any(|(r, _)| r == n) {
+          return Err((from, ParseErrorKind::ClashingImport(n.clone())));
+        }
+      }
+      renaming
+    }
+    None => Vec::new(),
+  };
+  Ok((scope, renaming))
+}
+
 pub fn parse_import(i: Span) -> IResult<Span, Import, ParseError<Span>> {
   let (i, _) = tag("import")(i)?;
   let (i, _) = parse_space(i)?;
@@ -79,8 +180,14 @@ pub fn parse_import(i: Span) -> IResult<Span, Import, ParseError<Span>> {
   let (i, alias) = opt(terminated(parse_alias, parse_space))(i)?;
   let alias = alias.unwrap_or_else(|| Name::from(""));
   let (i, with) = terminated(parse_with, parse_space)(i)?;
-  let (i, from) = terminated(parse_link, parse_space)(i)?;
-  Ok((i, Import { cid: from, name, alias, with }))
+  let (i2, hiding) = opt(terminated(parse_hiding, parse_space))(i)?;
+  let hiding = hiding.map(|h| (i, h));
+  let (i3, renaming) = opt(terminated(parse_renaming, parse_space))(i2)?;
+  let renaming = renaming.map(|r| (i2, r));
+  let (with, renaming) = import_scope(&with, None, hiding, renaming)
+    .map_err(|(from, e)| Err::Error(ParseError::new(from, e)))?;
+  let (i, from) = terminated(parse_link, parse_space)(i3)?;
+  Ok((i, Import { cid: from, name, alias, with, renaming }))
 }
 
 pub fn parse_entry(
@@ -88,31 +195,64 @@ pub fn parse_entry(
   defs: Rc<RefCell<Defs>>,
 ) -> impl Fn(Span) -> IResult<Span, Vec<(Name, Def, Entry)>, ParseError<Span>> {
   move |from: Span| {
-    let (i, _) = tag("def")(from)?;
+    let (i, strict) = opt(terminated(tag("strict"), parse_space1))(from)?;
+    parse_def(input, &defs, from, i, strict.is_some())
+  }
+}
+
+/// Parse the keywords of a definition up to its name, returning the name and
+/// where it starts
+pub fn parse_def_name(
+  from: Span,
+) -> IResult<Span, (Span, Name), ParseError<Span>> {
+  let (i, _) = many0(terminated(
+    alt((tag("strict"), tag("private"))),
+    parse_space1,
+  ))(from)?;
+  let (i, _) = tag("def")(i)?;
+  let (at, _) = parse_space(i)?;
+  let (i, nam) = parse_name(at)?;
+  Ok((i, (at, nam)))
+}
+
+// Parse a definition at `i`, from its `def` keyword on. Its position starts
+// at `from`, before any keywords marking it.
+fn parse_def<'a>(
+  input: Cid,
+  defs: &Rc<RefCell<Defs>>,
+  from: Span<'a>,
+  i: Span<'a>,
+  strict: bool,
+) -> IResult<Span<'a>, Vec<(Name, Def, Entry)>, ParseError<Span<'a>>> {
+  let (i, _) = tag("def")(i)?;
+  let (i, _) = parse_space(i)?;
+  let (i, nam) = parse_name(i)?;
+  if defs.borrow().names.get(&nam.clone()).is_some() {
+    Err(Err::Error(ParseError::new(
+      from,
+      ParseErrorKind::TopLevelRedefinition(nam),
+    )))
+  }
+  else {
     let (i, _) = parse_space(i)?;
-    let (i, nam) = parse_name(i)?;
-    if defs.borrow().names.get(&nam.clone()).is_some() {
-      Err(Err::Error(ParseError::new(
-        from,
-        ParseErrorKind::TopLevelRedefinition(nam),
-      )))
+    let (upto, (typ_, term)) = parse_bound_expression(
+      input,
+      defs.clone(),
+      None,
+      Some(nam.clone()),
+      ConsList::new(),
+      Rc::new(VecDeque::new()),
+      nam.clone(),
+      false,
+    )(i)?;
+    let pos = Pos::from_upto(input, from, upto);
+    let (def, entry) = if strict {
+      Def::make_strict(pos, typ_, term)
     }
     else {
-      let (i, _) = parse_space(i)?;
-      let (upto, (typ_, term)) = parse_bound_expression(
-        input,
-        defs.clone(),
-        None,
-        Some(nam.clone()),
-        ConsList::new(),
-        Rc::new(VecDeque::new()),
-        nam.clone(),
-        false,
-      )(i)?;
-      let pos = Pos::from_upto(input, from, upto);
-      let (def, entry) = Def::make(pos, typ_, term);
-      Ok((upto, vec![(nam, def, entry)]))
-    }
+      Def::make(pos, typ_, term)
+    };
+    Ok((upto, vec![(nam, def, entry)]))
   }
 }
 
@@ -120,23 +260,95 @@ pub fn parse_defs(
   input: Cid,
   import_defs: Defs,
 ) -> impl Fn(Span) -> IResult<Span, (Defs, Index), ParseError<Span>> {
+  move |i: Span| {
+    let (i, (defs, index, _)) =
+      parse_defs_exports(input, import_defs.clone())(i)?;
+    Ok((i, (defs, index)))
+  }
+}
+
+// Parse the definitions of a package along with the names it exports, if not
+// all of them. Definitions marked `private` aren't exported, and neither are
+// the ones left out of an `export` clause.
+pub fn parse_defs_exports(
+  input: Cid,
+  import_defs: Defs,
+) -> impl Fn(
+  Span,
+) -> IResult<Span, (Defs, Index, Option<Vec<Name>>), ParseError<Span>> {
   move |i: Span| {
     let defs = Rc::new(RefCell::new(import_defs.clone()));
     let mut ind: Vec<(Name, Cid)> = Vec::new();
+    let mut private: Vec<Name> = Vec::new();
+    let mut export: Option<(Span, Vec<Name>)> = None;
     let mut i = i;
     loop {
       let (i2, _) = parse_space(i)?;
       i = i2;
       let end: IResult<Span, Span, ParseError<Span>> = eof(i);
       if end.is_ok() {
-        return Ok((i2, (defs.as_ref().clone().into_inner(), Index(ind))));
+        let exports = match export {
+          Some((from, names)) => {
+            for name in &names {
+              if private.contains(name) {
+                return Err(Err::Error(ParseError::new(
+                  from,
+                  ParseErrorKind::PrivateExport(name.clone()),
+                )));
+              }
+              if !ind.iter().any(|(n, _)| n == name) {
+                return Err(Err::Error(ParseError::new(
+                  from,
+                  ParseErrorKind::UndefinedExport(name.clone()),
+                )));
+              }
+            }
+            Some(names)
+          }
+          None if !private.is_empty() => Some(
+            ind
+              .iter()
+              .map(|(n, _)| n.clone())
+              .filter(|n| !private.contains(n))
+              .collect(),
+          ),
+          None => None,
+        };
+        let defs = defs.as_ref().clone().into_inner();
+        return Ok((i2, (defs, Index(ind), exports)));
+      }
+      else if export.is_none() && parse_export(i).is_ok() {
+        let (i2, names) = parse_export(i)?;
+        export = Some((i, names));
+        i = i2;
       }
       else {
-        let (i2, entries) = alt((
-          parse_entry(input, defs.clone()),
-          parse_typedef_elaborated(input, defs.clone()),
-        ))(i)?;
+        // `strict` and `private` can come in either order
+        let strict_private: IResult<Span, _, ParseError<Span>> = tuple((
+          tag("strict"),
+          parse_space1,
+          tag("private"),
+          parse_space1,
+        ))(i);
+        let (i2, is_private, entries) = match strict_private {
+          Ok((i2, _)) => {
+            let (i2, entries) = parse_def(input, &defs, i, i2, true)?;
+            (i2, true, entries)
+          }
+          Err(_) => {
+            let (i2, is_private) =
+              opt(terminated(tag("private"), parse_space1))(i)?;
+            let (i2, entries) = alt((
+              parse_entry(input, defs.clone()),
+              parse_typedef_elaborated(input, defs.clone()),
+            ))(i2)?;
+            (i2, is_private.is_some(), entries)
+          }
+        };
         for (name, def, _) in entries {
+          if is_private {
+            private.push(name.clone());
+          }
           ind.push((name.clone(), def.def_cid));
           defs.borrow_mut().insert(name, def);
         }
@@ -146,6 +358,80 @@ pub fn parse_defs(
   }
 }
 
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+
+  fn exports(src: &str) -> Result<Option<Vec<Name>>, ()> {
+    match parse_defs_exports(input_cid(src), Defs::new())(Span::new(src)) {
+      Ok((_, (_, _, exports))) => Ok(exports),
+      Err(_) => Err(()),
+    }
+  }
+
+  fn names(ns: &[&str]) -> Option<Vec<Name>> {
+    Some(ns.iter().map(|n| Name::from(*n)).collect())
+  }
+
+  fn import(src: &str) -> Result<(Vec<Name>, Vec<(Name, Name)>), ()> {
+    let cid = input_cid("");
+    match parse_import(Span::new(&format!("{} {}", src, cid))) {
+      Ok((_, import)) => Ok((import.with, import.renaming)),
+      Err(_) => Err(()),
+    }
+  }
+
+  #[test]
+  fn hides_and_renames_imports() {
+    assert_eq!(
+      import("import bool (and, or, not) hiding (or)"),
+      Ok((names(&["and", "not"]).unwrap(), vec![]))
+    );
+    assert_eq!(
+      import("import bool (and, not) renaming (not to neg)"),
+      Ok((
+        names(&["and", "not"]).unwrap(),
+        vec![(Name::from("not"), Name::from("neg"))]
+      ))
+    );
+    assert!(import("import bool (and) hiding (or)").is_err());
+    assert!(
+      import("import bool (and, or) hiding (or) renaming (or to either)")
+        .is_err()
+    );
+    assert!(import("import bool (and, or) renaming (or to and)").is_err());
+    assert!(
+      import("import bool (and, or) renaming (or to a, or to b)").is_err()
+    );
+  }
+
+  #[test]
+  fn exports_public_defs() {
+    assert_eq!(exports("def a: Type = Type def b: Type = a"), Ok(None));
+    assert_eq!(
+      exports("def a: Type = Type private def b: Type = a def c: Type = b"),
+      Ok(names(&["a", "c"]))
+    );
+    assert_eq!(
+      exports("export (b) def a: Type = Type def b: Type = a"),
+      Ok(names(&["b"]))
+    );
+    assert_eq!(
+      exports("def a: Type = Type def b: Type = a export (a, b)"),
+      Ok(names(&["a", "b"]))
+    );
+    assert_eq!(
+      exports(
+        "private strict def a: Type = Type \
+         strict private def b: Type = a"
+      ),
+      Ok(names(&[]))
+    );
+    assert!(exports("export (a) private def a: Type = Type").is_err());
+    assert!(exports("export (c) def a: Type = Type").is_err());
+  }
+}
+
 // #[cfg(test)]
 // pub mod tests {
 //  use super::*;
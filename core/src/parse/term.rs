@@ -858,6 +858,7 @@ pub fn parse_expression(
 pub fn parse_app_end(i: Span) -> IResult<Span, (), ParseError<Span>> {
   let (i, _) = alt((
     peek(tag("def")),
+    peek(terminated(tag("strict"), preceded(parse_space1, tag("def")))),
     peek(tag("type")),
     peek(tag("::")),
     peek(tag("=")),
@@ -0,0 +1,439 @@
+// Snapshots of runtime graphs, so that an evaluation stopped part way (by
+// running out of gas, say) can be stored and resumed elsewhere. A snapshot
+// lists the nodes of the graph children first, each referring to its children
+// by their position in the list, so shared subgraphs are written once and stay
+// shared when read back. Variables refer to the λ or fixpoint that binds them,
+// which comes after them in the list. The root is the last node.
+//
+// Normalizing the graph read back from a snapshot takes the same steps as
+// normalizing the graph it was taken from, so a resumed evaluation ends with
+// the same result as one that was never stopped.
+
+use crate::{
+  dll::*,
+  ipld_error::IpldError,
+  literal::Literal,
+  prim::Op,
+  runtime::*,
+};
+
+use core::ptr::NonNull;
+
+use sp_ipld::Ipld;
+use sp_std::{
+  collections::btree_map::BTreeMap,
+  convert::TryFrom,
+  vec::Vec,
+};
+
+use alloc::string::ToString;
+
+pub const SNAPSHOT_VERSION: i128 = 1;
+
+// Nodes are told apart by kind as well as by address, since a variable lives
+// inside its binder
+fn key(node: DAG) -> (u8, usize) {
+  match node {
+    DAG::Var(link) => (0, link.as_ptr() as usize),
+    DAG::Lam(link) => (1, link.as_ptr() as usize),
+    DAG::App(link) => (2, link.as_ptr() as usize),
+    DAG::Fix(link) => (3, link.as_ptr() as usize),
+    DAG::Lit(link) => (4, link.as_ptr() as usize),
+    DAG::Opr(link) => (5, link.as_ptr() as usize),
+    DAG::Irr(link) => (6, link.as_ptr() as usize),
+    DAG::Con(link) => (7, link.as_ptr() as usize),
+  }
+}
+
+// Encode the graph below a node
+pub fn to_ipld(dag: DAG) -> Ipld {
+  let mut nodes: Vec<Ipld> = vec![];
+  let mut index: BTreeMap<(u8, usize), usize> = BTreeMap::new();
+  // The binder of every variable, and the variables waiting for the index of
+  // their binders
+  let mut binders: BTreeMap<usize, DAG> = BTreeMap::new();
+  let mut vars: Vec<(usize, usize)> = vec![];
+  let mut stack = vec![(dag, false)];
+  while let Some((node, expanded)) = stack.pop() {
+    if index.contains_key(&key(node)) {
+      continue;
+    }
+    if !expanded {
+      stack.push((node, true));
+      unsafe {
+        match node {
+          DAG::Lam(link) => {
+            let Lam { var, bod, .. } = &mut *link.as_ptr();
+            binders.insert(var as *mut Var as usize, node);
+            stack.push((*bod, false));
+          }
+          DAG::Fix(link) => {
+            let Fix { var, bod, .. } = &mut *link.as_ptr();
+            binders.insert(var as *mut Var as usize, node);
+            stack.push((*bod, false));
+          }
+          DAG::App(link) => {
+            let App { fun, arg, .. } = link.as_ref();
+            stack.push((*arg, false));
+            stack.push((*fun, false));
+          }
+          DAG::Con(link) => {
+            let Con { fields, .. } = link.as_ref();
+            for field in fields.iter().rev() {
+              stack.push((*field, false));
+            }
+          }
+          _ => (),
+        }
+      }
+      continue;
+    }
+    let at = |child: &DAG| Ipld::Integer(index[&key(*child)] as i128);
+    let entry = unsafe {
+      match node {
+        DAG::Var(link) => {
+          vars.push((nodes.len(), link.as_ptr() as usize));
+          Ipld::Null
+        }
+        DAG::Lam(link) => {
+          let Lam { bod, strict, .. } = link.as_ref();
+          Ipld::List(vec![Ipld::Integer(1), at(bod), Ipld::Bool(*strict)])
+        }
+        DAG::App(link) => {
+          let App { fun, arg, .. } = link.as_ref();
+          Ipld::List(vec![Ipld::Integer(2), at(fun), at(arg)])
+        }
+        DAG::Fix(link) => {
+          Ipld::List(vec![Ipld::Integer(3), at(&link.as_ref().bod)])
+        }
+        DAG::Lit(link) => {
+          Ipld::List(vec![Ipld::Integer(4), link.as_ref().lit.to_ipld()])
+        }
+        DAG::Opr(link) => {
+          Ipld::List(vec![Ipld::Integer(5), link.as_ref().opr.to_ipld()])
+        }
+        DAG::Irr(_) => Ipld::List(vec![Ipld::Integer(6)]),
+        DAG::Con(link) => {
+          let Con { tag, variants, fields, .. } = link.as_ref();
+          Ipld::List(vec![
+            Ipld::Integer(7),
+            Ipld::Integer(*tag as i128),
+            Ipld::Integer(*variants as i128),
+            Ipld::List(fields.iter().map(at).collect()),
+          ])
+        }
+      }
+    };
+    index.insert(key(node), nodes.len());
+    nodes.push(entry);
+  }
+  for (entry, var) in vars {
+    let binder = match binders.get(&var) {
+      Some(binder) => index[&key(*binder)],
+      None => panic!("Snapshot of a variable whose binder is not in the graph"),
+    };
+    nodes[entry] =
+      Ipld::List(vec![Ipld::Integer(0), Ipld::Integer(binder as i128)]);
+  }
+  Ipld::List(vec![
+    Ipld::String("yatima/snapshot".to_string()),
+    Ipld::Integer(SNAPSHOT_VERSION),
+    Ipld::List(nodes),
+  ])
+}
+
+// Decode a graph, giving its root `parents`
+pub fn from_ipld(
+  ipld: &Ipld,
+  parents: Option<NonNull<Parents>>,
+) -> Result<DAG, IpldError> {
+  let error = || IpldError::Snapshot(ipld.clone());
+  let entries = match ipld {
+    Ipld::List(xs) => match xs.as_slice() {
+      [Ipld::String(tag), Ipld::Integer(SNAPSHOT_VERSION), Ipld::List(nodes)]
+        if tag == "yatima/snapshot" && !nodes.is_empty() =>
+      {
+        nodes
+      }
+      _ => return Err(error()),
+    },
+    _ => return Err(error()),
+  };
+  let mut nodes: Vec<Option<DAG>> = vec![None; entries.len()];
+  match decode(ipld, entries, &mut nodes) {
+    Ok(root) => {
+      set_parents(root, parents);
+      Ok(root)
+    }
+    Err(err) => {
+      // Nothing outside links to the nodes decoded so far, so each is freed
+      // on its own. Variables live inside their binders.
+      for node in nodes.into_iter().flatten() {
+        match node {
+          DAG::Lam(link) => free_val(link),
+          DAG::App(link) => free_val(link),
+          DAG::Fix(link) => free_val(link),
+          DAG::Lit(link) => free_val(link),
+          DAG::Opr(link) => free_val(link),
+          DAG::Irr(link) => free_val(link),
+          DAG::Con(link) => free_val(link),
+          DAG::Var(_) => (),
+        }
+      }
+      Err(err)
+    }
+  }
+}
+
+// Decode the nodes of a snapshot into `nodes`, returning the root
+fn decode(
+  ipld: &Ipld,
+  entries: &[Ipld],
+  nodes: &mut [Option<DAG>],
+) -> Result<DAG, IpldError> {
+  let error = || IpldError::Snapshot(ipld.clone());
+  let fields = |entry: &Ipld| match entry {
+    Ipld::List(fields) => Ok(fields.clone()),
+    _ => Err(error()),
+  };
+  // Binders come after their variables, so they are allocated up front, with
+  // their bodies filled in once they're decoded
+  let hole = DAG::Irr(NonNull::dangling());
+  for (i, entry) in entries.iter().enumerate() {
+    match fields(entry)?.as_slice() {
+      [Ipld::Integer(1), _, Ipld::Bool(strict)] => {
+        nodes[i] = Some(DAG::Lam(alloc_lam(hole, *strict, None)));
+      }
+      [Ipld::Integer(3), _] => {
+        nodes[i] = Some(DAG::Fix(alloc_fix(hole, None)));
+      }
+      _ => (),
+    }
+  }
+  // Children always come before their parents
+  let child = |nodes: &[Option<DAG>], i: usize, child: &Ipld| match child {
+    Ipld::Integer(j) => match usize::try_from(*j) {
+      Ok(j) if j < i => nodes[j].ok_or_else(error),
+      _ => Err(error()),
+    },
+    _ => Err(error()),
+  };
+  for (i, entry) in entries.iter().enumerate() {
+    let node = unsafe {
+      match fields(entry)?.as_slice() {
+        [Ipld::Integer(0), Ipld::Integer(binder)] => {
+          let binder = usize::try_from(*binder).map_err(|_| error())?;
+          match nodes.get(binder) {
+            Some(Some(DAG::Lam(link))) => {
+              DAG::Var(NonNull::new_unchecked(&mut (*link.as_ptr()).var))
+            }
+            Some(Some(DAG::Fix(link))) => {
+              DAG::Var(NonNull::new_unchecked(&mut (*link.as_ptr()).var))
+            }
+            _ => return Err(error()),
+          }
+        }
+        [Ipld::Integer(1), bod, _] => {
+          let bod = child(nodes, i, bod)?;
+          match nodes[i] {
+            Some(DAG::Lam(link)) => {
+              let Lam { bod: slot, bod_ref, .. } = &mut *link.as_ptr();
+              *slot = bod;
+              add_to_parents(bod, NonNull::new_unchecked(bod_ref));
+              DAG::Lam(link)
+            }
+            _ => return Err(error()),
+          }
+        }
+        [Ipld::Integer(2), fun, arg] => {
+          let fun = child(nodes, i, fun)?;
+          let arg = child(nodes, i, arg)?;
+          let app = alloc_app(fun, arg, None);
+          let App { fun_ref, arg_ref, .. } = &mut *app.as_ptr();
+          add_to_parents(fun, NonNull::new_unchecked(fun_ref));
+          add_to_parents(arg, NonNull::new_unchecked(arg_ref));
+          DAG::App(app)
+        }
+        [Ipld::Integer(3), bod] => {
+          let bod = child(nodes, i, bod)?;
+          match nodes[i] {
+            Some(DAG::Fix(link)) => {
+              let Fix { bod: slot, bod_ref, .. } = &mut *link.as_ptr();
+              *slot = bod;
+              add_to_parents(bod, NonNull::new_unchecked(bod_ref));
+              DAG::Fix(link)
+            }
+            _ => return Err(error()),
+          }
+        }
+        [Ipld::Integer(4), lit] => {
+          let lit = Literal::from_ipld(lit)?;
+          DAG::Lit(alloc_val(Lit { lit, parents: None }))
+        }
+        [Ipld::Integer(5), opr] => {
+          let opr = Op::from_ipld(opr)?;
+          DAG::Opr(alloc_val(Opr { opr, parents: None }))
+        }
+        [Ipld::Integer(6)] => DAG::Irr(alloc_val(Irr { parents: None })),
+        [Ipld::Integer(7), Ipld::Integer(tag), Ipld::Integer(variants), Ipld::List(xs)] => {
+          let tag = usize::try_from(*tag).map_err(|_| error())?;
+          let variants = usize::try_from(*variants).map_err(|_| error())?;
+          // Any number of fields reads back as a λ-encoding, but the tag has
+          // to pick one of the variants for `case` to find its branch
+          if tag >= variants {
+            return Err(error());
+          }
+          let xs = xs
+            .iter()
+            .map(|x| child(nodes, i, x))
+            .collect::<Result<Vec<_>, _>>()?;
+          let con = alloc_con(tag, variants, xs, None);
+          let Con { fields, field_refs, .. } = &mut *con.as_ptr();
+          for (field, field_ref) in fields.iter().zip(field_refs.iter_mut()) {
+            add_to_parents(*field, NonNull::new_unchecked(field_ref));
+          }
+          DAG::Con(con)
+        }
+        _ => return Err(error()),
+      }
+    };
+    nodes[i] = Some(node);
+  }
+  nodes[entries.len() - 1].ok_or_else(error)
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+  use crate::{
+    defs::Defs,
+    eval::{
+      test::parse_defs,
+      Strategy,
+    },
+    gas,
+    parse::term::parse,
+  };
+
+  const SRC: &str = "
+    type List (A: Type) { Nil, Cons A (List A) }
+    def List.sum (xs: List #Nat): #Nat =
+      (case xs) (λ _ => #Nat) 0 (λ y ys => #Nat.add y (List.sum ys))
+    def fact (x: #Nat): #Nat = (case x) (λ _ => #Nat) 1 (λ x' => \
+      #Nat.mul x (fact x'))
+  ";
+
+  fn defs() -> Defs {
+    let (_, defs) = parse_defs(SRC).unwrap();
+    defs
+  }
+
+  fn build(defs: &Defs, src: &str) -> DAG {
+    let (_, term) = parse(src, defs.clone()).unwrap();
+    let root = alloc_val(DLL::singleton(ParentPtr::Root));
+    from_term(defs, &term, Strategy::Lazy, Some(root))
+  }
+
+  // Read a snapshot of a graph back
+  fn restore(dag: DAG) -> DAG {
+    let root = alloc_val(DLL::singleton(ParentPtr::Root));
+    from_ipld(&to_ipld(dag), Some(root)).unwrap()
+  }
+
+  #[test]
+  fn roundtrips_graphs() {
+    let defs = defs();
+    for src in &[
+      "fact 4",
+      "List.sum (List.Cons #Nat 1 (List.Cons #Nat 2 (List.Nil #Nat)))",
+      "λ x => (λ y => #Nat.add y y) (fact x)",
+    ] {
+      let dag = build(&defs, src);
+      let ipld = to_ipld(dag);
+      assert_eq!(to_ipld(restore(dag)), ipld, "{}", src);
+    }
+  }
+
+  #[test]
+  fn keeps_sharing() {
+    let defs = defs();
+    let mut dag = build(&defs, "(λ x => λ f => f x x) (fact 3)");
+    whnf(&mut dag, false);
+    let dag = restore(dag);
+    match dag {
+      DAG::Lam(link) => unsafe {
+        match link.as_ref().bod {
+          DAG::App(app) => {
+            let App { fun, arg, .. } = app.as_ref();
+            match fun {
+              DAG::App(fun) => assert!(fun.as_ref().arg == *arg),
+              _ => panic!("not an application"),
+            }
+          }
+          _ => panic!("not an application"),
+        }
+      },
+      _ => panic!("not a lambda"),
+    }
+  }
+
+  #[test]
+  fn resumes_stopped_runs() {
+    let defs = defs();
+    let mut dag = build(&defs, "fact 6");
+    norm(&mut dag, false);
+    let expected = format!("{}", to_term(dag));
+    for limit in &[0, 50, 200, 500] {
+      gas::meter(*limit);
+      let mut dag = build(&defs, "fact 6");
+      norm(&mut dag, false);
+      gas::unmeter();
+      let mut dag = restore(dag);
+      norm(&mut dag, false);
+      assert_eq!(format!("{}", to_term(dag)), expected);
+    }
+  }
+
+  #[test]
+  fn rejects_bad_snapshots() {
+    let bad = |nodes: Vec<Ipld>| {
+      from_ipld(
+        &Ipld::List(vec![
+          Ipld::String("yatima/snapshot".to_string()),
+          Ipld::Integer(SNAPSHOT_VERSION),
+          Ipld::List(nodes),
+        ]),
+        None,
+      )
+      .is_err()
+    };
+    assert!(bad(vec![]));
+    // A child after its parent
+    assert!(bad(vec![Ipld::List(vec![
+      Ipld::Integer(1),
+      Ipld::Integer(0),
+      Ipld::Bool(false)
+    ])]));
+    // A variable bound by an application
+    assert!(bad(vec![
+      Ipld::List(vec![Ipld::Integer(0), Ipld::Integer(1)]),
+      Ipld::List(vec![Ipld::Integer(2), Ipld::Integer(0), Ipld::Integer(0)]),
+    ]));
+    // Constructors whose tag picks none of their variants
+    let con = |tag: i128, variants: i128| {
+      vec![
+        Ipld::List(vec![Ipld::Integer(6)]),
+        Ipld::List(vec![
+          Ipld::Integer(7),
+          Ipld::Integer(tag),
+          Ipld::Integer(variants),
+          Ipld::List(vec![Ipld::Integer(0)]),
+        ]),
+      ]
+    };
+    assert!(!bad(con(1, 2)));
+    assert!(bad(con(0, 0)));
+    assert!(bad(con(2, 2)));
+    assert!(bad(con(-1, 2)));
+  }
+}
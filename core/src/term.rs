@@ -867,6 +867,41 @@ pub mod tests {
     arena[0].into_term(&arena)
   }
 
+  // Whether every variable of a term is used at most once. Affine terms
+  // without recursion always normalize, since every reduction step makes them
+  // smaller.
+  pub fn affine(term: &Term, ctx: &mut Vec<usize>) -> bool {
+    fn bind(bod: &Term, ctx: &mut Vec<usize>) -> bool {
+      ctx.push(0);
+      let res = affine(bod, ctx);
+      ctx.pop();
+      res
+    }
+    match term {
+      Term::Var(_, _, idx) => {
+        let len = ctx.len();
+        match ctx.get_mut(len.wrapping_sub(*idx as usize + 1)) {
+          Some(uses) => {
+            *uses += 1;
+            *uses <= 1
+          }
+          None => false,
+        }
+      }
+      Term::Rec(_) | Term::Let(_, true, ..) => false,
+      Term::Lam(_, _, bod) | Term::Slf(_, _, bod) => bind(bod, ctx),
+      Term::Dat(_, bod) | Term::Cse(_, bod) => affine(bod, ctx),
+      Term::App(_, x) | Term::Ann(_, x) => {
+        affine(&x.0, ctx) && affine(&x.1, ctx)
+      }
+      Term::All(_, _, _, x) => affine(&x.0, ctx) && bind(&x.1, ctx),
+      Term::Let(_, false, _, _, x) => {
+        affine(&x.0, ctx) && affine(&x.1, ctx) && bind(&x.2, ctx)
+      }
+      _ => true,
+    }
+  }
+
   impl Arbitrary for Term {
     fn arbitrary(g: &mut Gen) -> Self {
       arbitrary_term(g, false, test_defs(), Vector::new())
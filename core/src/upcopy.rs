@@ -113,9 +113,9 @@ pub fn upcopy(new_child: DAGPtr, cc: ParentPtr, should_count: bool) {
   unsafe {
     match cc {
       ParentPtr::LamBod(link) => {
-        let Lam { var, parents, .. } = link.as_ref();
+        let Lam { var, strict, parents, .. } = link.as_ref();
         let Var { nam, dep, parents: var_parents, .. } = var;
-        let new_lam = alloc_lam(nam.clone(), *dep, new_child, None);
+        let new_lam = alloc_lam(nam.clone(), *dep, new_child, *strict, None);
         let ptr: *mut Parents = &mut (*new_lam.as_ptr()).bod_ref;
         add_to_parents(new_child, NonNull::new(ptr).unwrap());
         let ptr: *mut Var = &mut (*new_lam.as_ptr()).var;
@@ -267,13 +267,14 @@ pub fn upcopy(new_child: DAGPtr, cc: ParentPtr, should_count: bool) {
         }
       }
       ParentPtr::LetTyp(link) => {
-        let Let { copy, uses, exp, bod, parents, .. } = link.as_ref();
+        let Let { copy, uses, exp, bod, strict, parents, .. } = link.as_ref();
         match copy {
           Some(cache) => {
             (*cache.as_ptr()).typ = new_child;
           }
           None => {
-            let new_let = alloc_let(*uses, new_child, *exp, *bod, None);
+            let new_let =
+              alloc_let(*uses, new_child, *exp, *bod, *strict, None);
             (*link.as_ptr()).copy = Some(new_let);
             for parent in DLL::iter_option(*parents) {
               upcopy(DAGPtr::Let(new_let), *parent, should_count)
@@ -282,13 +283,14 @@ pub fn upcopy(new_child: DAGPtr, cc: ParentPtr, should_count: bool) {
         }
       }
       ParentPtr::LetExp(link) => {
-        let Let { copy, uses, typ, bod, parents, .. } = link.as_ref();
+        let Let { copy, uses, typ, bod, strict, parents, .. } = link.as_ref();
         match copy {
           Some(cache) => {
             (*cache.as_ptr()).exp = new_child;
           }
           None => {
-            let new_let = alloc_let(*uses, *typ, new_child, *bod, None);
+            let new_let =
+              alloc_let(*uses, *typ, new_child, *bod, *strict, None);
             (*link.as_ptr()).copy = Some(new_let);
             for parent in DLL::iter_option(*parents) {
               upcopy(DAGPtr::Let(new_let), *parent, should_count)
@@ -297,7 +299,7 @@ pub fn upcopy(new_child: DAGPtr, cc: ParentPtr, should_count: bool) {
         }
       }
       ParentPtr::LetBod(link) => {
-        let Let { copy, uses, typ, exp, parents, .. } = link.as_ref();
+        let Let { copy, uses, typ, exp, strict, parents, .. } = link.as_ref();
         let new_child = match new_child {
           DAGPtr::Lam(link) => link,
           _ => panic!("Cannot install a non-lambda node as image"),
@@ -307,7 +309,8 @@ pub fn upcopy(new_child: DAGPtr, cc: ParentPtr, should_count: bool) {
             (*cache.as_ptr()).bod = new_child;
           }
           None => {
-            let new_let = alloc_let(*uses, *typ, *exp, new_child, None);
+            let new_let =
+              alloc_let(*uses, *typ, *exp, new_child, *strict, None);
             (*link.as_ptr()).copy = Some(new_let);
             for parent in DLL::iter_option(*parents) {
               upcopy(DAGPtr::Let(new_let), *parent, should_count)
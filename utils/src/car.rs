@@ -0,0 +1,296 @@
+use crate::{
+  fsck::verify_block,
+  gc::{
+    self,
+    reachable,
+  },
+  store::Store,
+};
+use sp_cid::Cid;
+use sp_ipld::{
+  dag_cbor::DagCborCodec,
+  Codec,
+};
+use std::convert::TryFrom;
+
+// The CBOR tag of a CID
+const CID_TAG: u64 = 42;
+
+/// Write the blocks reachable from `roots` as a CARv1 archive: a dag-cbor
+/// header listing the roots, followed by every block prefixed by its CID.
+pub fn write_car(store: &dyn Store, roots: &[Cid]) -> Result<Vec<u8>, String> {
+  let mut header = vec![];
+  cbor_head(&mut header, 5, 2);
+  cbor_text(&mut header, "roots");
+  cbor_head(&mut header, 4, roots.len() as u64);
+  for root in roots {
+    let bytes = root.to_bytes();
+    cbor_head(&mut header, 6, CID_TAG);
+    // Binary CIDs in dag-cbor are prefixed by the identity multibase
+    cbor_head(&mut header, 2, bytes.len() as u64 + 1);
+    header.push(0x00);
+    header.extend(bytes);
+  }
+  cbor_text(&mut header, "version");
+  cbor_head(&mut header, 0, 1);
+
+  let mut car = vec![];
+  varint(&mut car, header.len() as u64);
+  car.extend(header);
+  for link in reachable(store, roots)? {
+    let ipld = store
+      .get(link)
+      .ok_or_else(|| format!("{} not found in the store", link))?;
+    let bytes = link.to_bytes();
+    let data = DagCborCodec.encode(&ipld).unwrap().into_inner();
+    varint(&mut car, (bytes.len() + data.len()) as u64);
+    car.extend(bytes);
+    car.extend(data);
+  }
+  Ok(car)
+}
+
+/// Read a CARv1 archive into the store, returning its roots and the number of
+/// blocks read. Every block is checked against its CID before any is put in
+/// the store, so a corrupted archive leaves the store untouched. The roots
+/// are pinned if `pin` is set, so that garbage collection keeps what was
+/// imported.
+pub fn read_car(
+  store: &dyn Store,
+  car: &[u8],
+  pin: bool,
+) -> Result<(Vec<Cid>, usize), String> {
+  let mut reader = Reader { bytes: car, at: 0 };
+  let len = reader.varint()?;
+  let header = reader.take(len)?;
+  let roots = parse_header(header)?;
+  let mut blocks = vec![];
+  while !reader.done() {
+    let len = reader.varint()?;
+    let section = reader.take(len)?;
+    let (link, data) = split_block(section)?;
+    let ipld =
+      verify_block(link, data).map_err(|e| format!("Block {} {}", link, e))?;
+    blocks.push(ipld);
+  }
+  let count = blocks.len();
+  for ipld in blocks {
+    store.put(ipld);
+  }
+  if pin {
+    // An archive may name roots it doesn't contain, which can't be pinned
+    for root in &roots {
+      gc::pin(store, *root).ok();
+    }
+  }
+  Ok((roots, count))
+}
+
+// Split a block section into its CID and its data
+fn split_block(section: &[u8]) -> Result<(Cid, &[u8]), String> {
+  let mut reader = Reader { bytes: section, at: 0 };
+  // A CIDv0 is a bare sha2-256 multihash
+  if section.starts_with(&[0x12, 0x20]) {
+    reader.take(34)?;
+  }
+  else {
+    reader.varint()?;
+    reader.varint()?;
+    reader.varint()?;
+    let len = reader.varint()?;
+    reader.take(len)?;
+  }
+  let (link, data) = section.split_at(reader.at);
+  let link = Cid::try_from(link.to_vec())
+    .map_err(|e| format!("Invalid block CID: {}", e))?;
+  Ok((link, data))
+}
+
+// Parse the roots out of the dag-cbor header of an archive
+fn parse_header(header: &[u8]) -> Result<Vec<Cid>, String> {
+  let mut reader = Reader { bytes: header, at: 0 };
+  let mut roots = None;
+  let mut version = None;
+  for _ in 0..reader.cbor_expect(5)? {
+    let len = reader.cbor_expect(3)?;
+    match reader.take(len)? {
+      b"roots" => {
+        let mut cids = vec![];
+        for _ in 0..reader.cbor_expect(4)? {
+          if reader.cbor_expect(6)? != CID_TAG {
+            return Err("Expected a CID in the CAR roots".to_owned());
+          }
+          let len = reader.cbor_expect(2)?;
+          let bytes = reader.take(len)?;
+          let link = match bytes.split_first() {
+            Some((0x00, link)) => Cid::try_from(link.to_vec())
+              .map_err(|e| format!("Invalid CAR root: {}", e))?,
+            _ => return Err("Invalid CAR root".to_owned()),
+          };
+          cids.push(link);
+        }
+        roots = Some(cids);
+      }
+      b"version" => version = Some(reader.cbor_expect(0)?),
+      _ => return Err("Unexpected field in the CAR header".to_owned()),
+    }
+  }
+  match (version, roots) {
+    (Some(1), Some(roots)) => Ok(roots),
+    (Some(version), _) if version != 1 => {
+      Err(format!("Unsupported CAR version {}", version))
+    }
+    _ => Err("Invalid CAR header".to_owned()),
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  at: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn done(&self) -> bool { self.at >= self.bytes.len() }
+
+  fn take(&mut self, len: u64) -> Result<&'a [u8], String> {
+    let end = usize::try_from(len)
+      .ok()
+      .and_then(|len| self.at.checked_add(len))
+      .filter(|end| *end <= self.bytes.len())
+      .ok_or_else(|| "Unexpected end of the CAR file".to_owned())?;
+    let bytes = &self.bytes[self.at..end];
+    self.at = end;
+    Ok(bytes)
+  }
+
+  fn byte(&mut self) -> Result<u8, String> { Ok(self.take(1)?[0]) }
+
+  // An unsigned LEB128 varint, as used for lengths and inside CIDs
+  fn varint(&mut self) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+      let byte = self.byte()?;
+      value |= u64::from(byte & 0x7f) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    Err("Varint too long in the CAR file".to_owned())
+  }
+
+  // The argument of a CBOR item of the given major type
+  fn cbor_expect(&mut self, major: u8) -> Result<u64, String> {
+    let byte = self.byte()?;
+    if byte >> 5 != major {
+      return Err("Unexpected item in the CAR header".to_owned());
+    }
+    let size = match byte & 0x1f {
+      n @ 0..=23 => return Ok(u64::from(n)),
+      24 => 1,
+      25 => 2,
+      26 => 4,
+      27 => 8,
+      _ => return Err("Unsupported item in the CAR header".to_owned()),
+    };
+    Ok(self.take(size)?.iter().fold(0, |n, b| (n << 8) | u64::from(*b)))
+  }
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+  while value >= 0x80 {
+    out.push((value as u8 & 0x7f) | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
+
+fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+  let major = major << 5;
+  if value < 24 {
+    out.push(major | value as u8);
+  }
+  else if value <= 0xff {
+    out.push(major | 24);
+    out.push(value as u8);
+  }
+  else if value <= 0xffff {
+    out.push(major | 25);
+    out.extend(&(value as u16).to_be_bytes());
+  }
+  else if value <= 0xffff_ffff {
+    out.push(major | 26);
+    out.extend(&(value as u32).to_be_bytes());
+  }
+  else {
+    out.push(major | 27);
+    out.extend(&value.to_be_bytes());
+  }
+}
+
+fn cbor_text(out: &mut Vec<u8>, text: &str) {
+  cbor_head(out, 3, text.len() as u64);
+  out.extend(text.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::diff::test::{
+    package,
+    MemStore,
+  };
+  use sp_ipld::Ipld;
+  use std::collections::BTreeSet;
+
+  #[test]
+  fn roundtrips_package_closures() {
+    let store = MemStore::default();
+    let pack = package(
+      &store,
+      "def a: Type = Type
+       def b: Type = a",
+    );
+    let root = store.put(pack.to_ipld());
+    store.put(Ipld::String("unreachable".to_owned()));
+    let car = write_car(&store, &[root]).unwrap();
+
+    let imported = MemStore::default();
+    let closure = reachable(&store, &[root]).unwrap();
+    assert_eq!(
+      read_car(&imported, &car, false),
+      Ok((vec![root], closure.len()))
+    );
+    assert_eq!(imported.list().into_iter().collect::<BTreeSet<_>>(), closure);
+    assert!(closure.iter().all(|cid| imported.get(*cid) == store.get(*cid)));
+
+    // Flip a bit in the last block
+    let mut corrupted = car.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    let empty = MemStore::default();
+    assert!(read_car(&empty, &corrupted, true).is_err());
+    assert!(empty.list().is_empty());
+    assert!(read_car(&empty, &car[..car.len() - 1], true).is_err());
+  }
+
+  #[test]
+  fn keeps_imported_closures_through_gc() {
+    let store = MemStore::default();
+    let pack = package(&store, "def a: Type = Type");
+    let root = store.put(pack.to_ipld());
+    let car = write_car(&store, &[root]).unwrap();
+    let closure = reachable(&store, &[root]).unwrap();
+
+    let imported = MemStore::default();
+    read_car(&imported, &car, true).unwrap();
+    assert_eq!(imported.get_pins(), vec![root]);
+    assert!(gc::collect(&imported, false, false).unwrap().garbage.is_empty());
+    assert!(closure.iter().all(|cid| imported.get(*cid) == store.get(*cid)));
+
+    // Without pinning, the next collection deletes the import
+    let unpinned = MemStore::default();
+    read_car(&unpinned, &car, false).unwrap();
+    let collection = gc::collect(&unpinned, false, false).unwrap();
+    assert_eq!(collection.garbage.len(), closure.len());
+    assert!(unpinned.list().is_empty());
+  }
+}
@@ -0,0 +1,353 @@
+use crate::{
+  diff::load_entries,
+  store::{
+    load_package_defs,
+    Store,
+  },
+};
+use sp_cid::Cid;
+use std::{
+  collections::{
+    BTreeMap,
+    BTreeSet,
+  },
+  fmt,
+  rc::Rc,
+};
+use yatima_core::{
+  check::{
+    check_def,
+    Conversion,
+  },
+  defs::{
+    Def,
+    Defs,
+  },
+  name::Name,
+  package::{
+    Index,
+    Package,
+  },
+  term::Term,
+};
+
+/// How an exported definition changed for the packages importing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+  /// Same type with another body, so importers still check
+  Compatible,
+  /// Another type, so importers may no longer check
+  TypeChanged,
+  /// No longer exported, whether removed, renamed or made private
+  Removed,
+  /// Newly exported
+  Added,
+}
+
+impl Change {
+  pub fn is_breaking(&self) -> bool {
+    matches!(self, Self::TypeChanged | Self::Removed)
+  }
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Compatible => write!(f, "compatible (body changed)"),
+      Self::TypeChanged => write!(f, "breaking (type changed)"),
+      Self::Removed => write!(f, "breaking (removed)"),
+      Self::Added => write!(f, "additive"),
+    }
+  }
+}
+
+/// The changes to the exported definitions of a package between two of its
+/// versions. Only the anonymous type of a definition matters to the packages
+/// importing it, so a definition whose type anon CID stayed the same can be
+/// upgraded to safely.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Compat {
+  pub changes: Vec<(Name, Change)>,
+}
+
+impl Compat {
+  /// Compare two packages, whose entries are in `store`
+  pub fn new(
+    old: &Package,
+    new: &Package,
+    store: &dyn Store,
+  ) -> Result<Self, String> {
+    let olds = load_entries(old, store)?;
+    let news = load_entries(new, store)?;
+    let mut changes = vec![];
+    for name in old.exported() {
+      let (_, old_entry) = match olds.get(&name) {
+        Some(old) => old,
+        None => continue,
+      };
+      match news.get(&name).filter(|_| new.is_exported(&name)) {
+        None => changes.push((name, Change::Removed)),
+        Some((_, entry)) if entry.type_anon != old_entry.type_anon => {
+          changes.push((name, Change::TypeChanged))
+        }
+        Some((_, entry)) if entry.term_anon != old_entry.term_anon => {
+          changes.push((name, Change::Compatible))
+        }
+        Some(_) => (),
+      }
+    }
+    for name in new.exported() {
+      if !old.is_exported(&name) {
+        changes.push((name, Change::Added));
+      }
+    }
+    Ok(Compat { changes })
+  }
+
+  pub fn is_breaking(&self) -> bool {
+    self.changes.iter().any(|(_, change)| change.is_breaking())
+  }
+}
+
+impl fmt::Display for Compat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (name, change) in &self.changes {
+      writeln!(f, "{} {}", change, name)?;
+    }
+    Ok(())
+  }
+}
+
+/// The packages in the store that import the package at `cid`, directly or
+/// through other packages. Each comes after the packages it imports it
+/// through, so that they can be rechecked in order.
+pub fn dependents(store: &dyn Store, cid: Cid) -> Vec<(Cid, Package)> {
+  let packages: Vec<(Cid, Package)> = store
+    .list()
+    .into_iter()
+    .filter_map(|link| {
+      Some((link, Package::from_ipld(&store.get(link)?).ok()?))
+    })
+    .collect();
+  let imports = |package: &Package, cids: &BTreeSet<Cid>| {
+    package.imports.iter().any(|import| cids.contains(&import.cid))
+  };
+  // Every package importing one already found is a dependent too
+  let mut found = BTreeSet::new();
+  found.insert(cid);
+  loop {
+    let more: Vec<Cid> = packages
+      .iter()
+      .filter(|(link, package)| {
+        !found.contains(link) && imports(package, &found)
+      })
+      .map(|(link, _)| *link)
+      .collect();
+    if more.is_empty() {
+      break;
+    }
+    found.extend(more);
+  }
+  found.remove(&cid);
+  // Packages import each other by content id, so they form no cycles, and
+  // some dependent left always imports none of the others left
+  let mut left: Vec<(Cid, Package)> =
+    packages.into_iter().filter(|(link, _)| found.contains(link)).collect();
+  let mut ordered = vec![];
+  while !left.is_empty() {
+    let pending: BTreeSet<Cid> = left.iter().map(|(link, _)| *link).collect();
+    let (ready, rest): (Vec<_>, Vec<_>) =
+      left.into_iter().partition(|(_, package)| !imports(package, &pending));
+    ordered.extend(ready);
+    left = rest;
+  }
+  ordered
+}
+
+/// Point the references of a term to the definitions of the same names in
+/// `defs`, as if it had been parsed against them
+pub fn relink(term: &Term, defs: &Defs) -> Term {
+  let go = |term: &Term| Box::new(relink(term, defs));
+  let go2 = |x: &(Term, Term)| {
+    Box::new((relink(&x.0, defs), relink(&x.1, defs)))
+  };
+  match term {
+    Term::Ref(pos, name, def, ast) => match defs.get(name) {
+      Some(d) => Term::Ref(*pos, name.clone(), d.def_cid, d.ast_cid),
+      None => Term::Ref(*pos, name.clone(), *def, *ast),
+    },
+    Term::Lam(pos, name, bod) => Term::Lam(*pos, name.clone(), go(bod)),
+    Term::Slf(pos, name, bod) => Term::Slf(*pos, name.clone(), go(bod)),
+    Term::Dat(pos, bod) => Term::Dat(*pos, go(bod)),
+    Term::Cse(pos, bod) => Term::Cse(*pos, go(bod)),
+    Term::App(pos, x) => Term::App(*pos, go2(x)),
+    Term::Ann(pos, x) => Term::Ann(*pos, go2(x)),
+    Term::All(pos, uses, name, x) => {
+      Term::All(*pos, *uses, name.clone(), go2(x))
+    }
+    Term::Let(pos, rec, uses, name, x) => Term::Let(
+      *pos,
+      *rec,
+      *uses,
+      name.clone(),
+      Box::new((relink(&x.0, defs), relink(&x.1, defs), relink(&x.2, defs))),
+    ),
+    term => term.clone(),
+  }
+}
+
+/// Check the definitions of a package against the packages in `upgrades`
+/// instead of the ones they upgrade, returning the error of every definition
+/// that no longer checks. The package is also rebuilt against them and put
+/// in the store, under the CID returned, so that the packages importing it
+/// can be rechecked against it in turn.
+pub fn recheck(
+  store: Rc<dyn Store>,
+  package: &Package,
+  upgrades: &BTreeMap<Cid, Cid>,
+  conversion: Conversion,
+  hash_cons: bool,
+) -> Result<(Cid, Vec<(Name, String)>), String> {
+  let mut package = package.clone();
+  for import in package.imports.iter_mut() {
+    if let Some(new) = upgrades.get(&import.cid) {
+      import.cid = *new;
+    }
+  }
+  let mut index = vec![];
+  let mut defs = load_package_defs(store, Rc::new(package.clone()))?;
+  // Definitions only refer to the ones before them, so relinking them in
+  // order relinks the references to other definitions of the package too
+  for (name, _) in &package.index.0 {
+    let def = defs
+      .get(name)
+      .cloned()
+      .ok_or_else(|| format!("Definition {} not found", name))?;
+    let typ_ = relink(&def.typ_, &defs);
+    let term = relink(&def.term, &defs);
+    let (def, entry) = if def.strict {
+      Def::make_strict(def.pos, typ_, term)
+    }
+    else {
+      Def::make(def.pos, typ_, term)
+    };
+    let (_, type_anon, term_anon) = def.embed();
+    store.put(type_anon.to_ipld());
+    store.put(term_anon.to_ipld());
+    index.push((name.clone(), store.put(entry.to_ipld())));
+    defs.insert(name.clone(), def);
+  }
+  let package = Package { index: Index(index), ..package };
+  let cid = store.put(package.to_ipld());
+  let defs = Rc::new(defs);
+  let mut errors = vec![];
+  for (name, _) in &package.index.0 {
+    let checked = check_def(defs.clone(), name, false, conversion, hash_cons);
+    if let Err(e) = checked {
+      errors.push((name.clone(), e.to_string()));
+    }
+  }
+  Ok((cid, errors))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::diff::test::{
+    package,
+    package_with,
+    MemStore,
+  };
+  use yatima_core::package::Import;
+
+  #[test]
+  fn rechecks_dependents() {
+    let store = Rc::new(MemStore::default());
+    let lib = package(&store, "def a: Type = Type");
+    let lib_cid = store.put(lib.to_ipld());
+    let import = Import {
+      cid: lib_cid,
+      name: Name::from("lib"),
+      alias: Name::from(""),
+      with: vec![Name::from("a")],
+      renaming: vec![],
+    };
+    let lib_defs = load_package_defs(store.clone(), Rc::new(lib.clone()));
+    let defs = Defs::new().merge(lib_defs.unwrap(), &import).unwrap();
+    let dependent =
+      package_with(&store, vec![import], defs, "def d: Type = a");
+    let dependent_cid = store.put(dependent.to_ipld());
+    assert_eq!(dependents(&*store, lib_cid), vec![(
+      dependent_cid,
+      dependent.clone()
+    )]);
+
+    let compatible = package(&store, "def a: Type = ∀ (A: Type) -> A");
+    let compatible_cid = store.put(compatible.to_ipld());
+    let compat = Compat::new(&lib, &compatible, &*store).unwrap();
+    assert_eq!(compat.changes, vec![(Name::from("a"), Change::Compatible)]);
+    assert!(!compat.is_breaking());
+    let upgrade = |new: Cid| {
+      let mut upgrades = BTreeMap::new();
+      upgrades.insert(lib_cid, new);
+      recheck(store.clone(), &dependent, &upgrades, Conversion::Nbe, false)
+        .map(|(_, errors)| errors)
+    };
+    assert_eq!(upgrade(compatible_cid), Ok(vec![]));
+
+    let breaking = package(
+      &store,
+      "def a: ∀ (A: Type) -> Type = λ A => A
+       def b: Type = Type",
+    );
+    let breaking_cid = store.put(breaking.to_ipld());
+    let compat = Compat::new(&lib, &breaking, &*store).unwrap();
+    assert_eq!(compat.changes, vec![
+      (Name::from("a"), Change::TypeChanged),
+      (Name::from("b"), Change::Added),
+    ]);
+    assert!(compat.is_breaking());
+    assert_eq!(upgrade(breaking_cid).map(|es| es.len()), Ok(1));
+  }
+
+  #[test]
+  fn rechecks_indirect_dependents() {
+    let store = Rc::new(MemStore::default());
+    let import = |cid: Cid, name: &str| Import {
+      cid,
+      name: Name::from("lib"),
+      alias: Name::from(""),
+      with: vec![Name::from(name)],
+      renaming: vec![],
+    };
+    let dependent = |imported: &Package, name: &str, src: &str| {
+      let cid = store.put(imported.to_ipld());
+      let defs = load_package_defs(store.clone(), Rc::new(imported.clone()));
+      let import = import(cid, name);
+      let defs = Defs::new().merge(defs.unwrap(), &import).unwrap();
+      let package = package_with(&store, vec![import], defs, src);
+      (cid, package)
+    };
+    let lib = package(&store, "def a: Type = Type");
+    let (lib_cid, middle) = dependent(&lib, "a", "def b: Type = a");
+    let (middle_cid, top) = dependent(&middle, "b", "def c: b = Type");
+    let top_cid = store.put(top.to_ipld());
+    let found: Vec<Cid> =
+      dependents(&*store, lib_cid).into_iter().map(|(cid, _)| cid).collect();
+    assert_eq!(found, vec![middle_cid, top_cid]);
+
+    // The middle package still checks against the upgrade, but the package
+    // importing it doesn't once it is rebuilt against the upgrade
+    let upgrade = package(&store, "def a: Type = ∀ (A: Type) -> A");
+    let mut upgrades = BTreeMap::new();
+    upgrades.insert(lib_cid, store.put(upgrade.to_ipld()));
+    let (upgraded, errors) =
+      recheck(store.clone(), &middle, &upgrades, Conversion::Nbe, false)
+        .unwrap();
+    assert_eq!(errors, vec![]);
+    upgrades.insert(middle_cid, upgraded);
+    let (_, errors) =
+      recheck(store.clone(), &top, &upgrades, Conversion::Nbe, false).unwrap();
+    assert_eq!(errors.len(), 1);
+  }
+}
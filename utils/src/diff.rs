@@ -0,0 +1,430 @@
+use crate::store::Store;
+use serde_json::{
+  json,
+  Value,
+};
+use sp_cid::Cid;
+use std::{
+  collections::{
+    BTreeMap,
+    BTreeSet,
+  },
+  fmt,
+};
+use yatima_core::{
+  anon::Anon,
+  meta::Meta,
+  name::Name,
+  package::{
+    Entry,
+    Package,
+  },
+};
+
+/// A definition whose meaning changed, and whether in its type, its term or
+/// both
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Changed {
+  pub name: Name,
+  pub typ: bool,
+  pub term: bool,
+}
+
+/// How the definitions of a package changed between two of its versions.
+/// Entries keep the anonymous terms of a definition apart from its names and
+/// positions, so a definition that was only renamed or reformatted is told
+/// apart from one that means something else.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+  pub added: Vec<Name>,
+  pub removed: Vec<Name>,
+  /// Definitions with the same type and term under another name
+  pub renamed: Vec<(Name, Name)>,
+  pub changed: Vec<Changed>,
+  /// Definitions that mean the same but whose names of variables and
+  /// references, positions or strictness changed
+  pub metadata: Vec<Name>,
+  /// Definitions that only changed through the definitions they depend on,
+  /// since references carry the anonymous term they point to
+  pub affected: Vec<Name>,
+}
+
+pub(crate) fn load_entries(
+  package: &Package,
+  store: &dyn Store,
+) -> Result<BTreeMap<Name, (Cid, Entry)>, String> {
+  let mut entries = BTreeMap::new();
+  for (name, cid) in &package.index.0 {
+    let ipld = store
+      .get(*cid)
+      .ok_or_else(|| format!("Entry {} of {} not found", cid, name))?;
+    let entry = Entry::from_ipld(&ipld).map_err(|e| format!("{:?}", e))?;
+    entries.insert(name.clone(), (*cid, entry));
+  }
+  Ok(entries)
+}
+
+fn load_anon(cid: Cid, store: &dyn Store) -> Result<Anon, String> {
+  let ipld =
+    store.get(cid).ok_or_else(|| format!("Anon {} not found", cid))?;
+  Anon::from_ipld(&ipld).map_err(|e| format!("{:?}", e))
+}
+
+/// Whether two anonymous terms are the same but for the terms their
+/// references point to
+pub fn same_modulo_refs(a: &Anon, b: &Anon) -> bool {
+  use Anon::*;
+  match (a, b) {
+    (Ref(_), Ref(_)) => true,
+    (Lam(a), Lam(b))
+    | (Slf(a), Slf(b))
+    | (Dat(a), Dat(b))
+    | (Cse(a), Cse(b)) => same_modulo_refs(a, b),
+    (App(a), App(b)) | (Ann(a), Ann(b)) => {
+      same_modulo_refs(&a.0, &b.0) && same_modulo_refs(&a.1, &b.1)
+    }
+    (All(ua, a), All(ub, b)) => {
+      ua == ub && same_modulo_refs(&a.0, &b.0) && same_modulo_refs(&a.1, &b.1)
+    }
+    (Let(ra, ua, a), Let(rb, ub, b)) => {
+      ra == rb
+        && ua == ub
+        && same_modulo_refs(&a.0, &b.0)
+        && same_modulo_refs(&a.1, &b.1)
+        && same_modulo_refs(&a.2, &b.2)
+    }
+    (a, b) => a == b,
+  }
+}
+
+/// The names a term refers to, in order of appearance
+pub fn meta_refs(meta: &Meta, acc: &mut Vec<Name>) {
+  use Meta::*;
+  match meta {
+    Ref(_, name, _) => acc.push(name.clone()),
+    Lam(_, _, bod) | Slf(_, _, bod) | Dat(_, bod) | Cse(_, bod) => {
+      meta_refs(bod, acc)
+    }
+    App(_, xs) | All(_, _, xs) | Ann(_, xs) => {
+      meta_refs(&xs.0, acc);
+      meta_refs(&xs.1, acc);
+    }
+    Let(_, _, xs) => {
+      meta_refs(&xs.0, acc);
+      meta_refs(&xs.1, acc);
+      meta_refs(&xs.2, acc);
+    }
+    Var(..) | Typ(_) | Lit(_) | LTy(_) | Opr(_) | Rec(_) => (),
+  }
+}
+
+fn entry_refs(entry: &Entry) -> Vec<Name> {
+  let mut refs = vec![];
+  meta_refs(&entry.type_meta, &mut refs);
+  meta_refs(&entry.term_meta, &mut refs);
+  refs
+}
+
+impl Diff {
+  /// Compare two packages, whose entries and anonymous terms are in `store`
+  pub fn new(
+    old: &Package,
+    new: &Package,
+    store: &dyn Store,
+  ) -> Result<Self, String> {
+    let olds = load_entries(old, store)?;
+    let news = load_entries(new, store)?;
+    let mut diff = Diff::default();
+    let mut added: Vec<&Name> =
+      news.keys().filter(|n| !olds.contains_key(n)).collect();
+    for (name, (_, entry)) in &olds {
+      if news.contains_key(name) {
+        continue;
+      }
+      let same = |n: &&Name| {
+        let (_, e) = &news[*n];
+        e.type_anon == entry.type_anon && e.term_anon == entry.term_anon
+      };
+      match added.iter().position(same) {
+        Some(i) => diff.renamed.push((name.clone(), added.remove(i).clone())),
+        None => diff.removed.push(name.clone()),
+      }
+    }
+    diff.added = added.into_iter().cloned().collect();
+    let renames: BTreeMap<&Name, &Name> =
+      diff.renamed.iter().map(|(from, to)| (from, to)).collect();
+    // Definitions whose anonymous terms changed only in their references,
+    // which are affected if they still refer to the same names
+    let mut through_refs = BTreeSet::new();
+    for (name, (cid, entry)) in &news {
+      let (old_cid, old_entry) = match olds.get(name) {
+        Some(old) => old,
+        None => continue,
+      };
+      let typ = old_entry.type_anon != entry.type_anon;
+      let term = old_entry.term_anon != entry.term_anon;
+      if !typ && !term {
+        if old_cid != cid {
+          diff.metadata.push(name.clone());
+        }
+        continue;
+      }
+      let old_refs: Vec<Name> = entry_refs(old_entry)
+        .into_iter()
+        .map(|n| renames.get(&n).map_or(n.clone(), |to| (*to).clone()))
+        .collect();
+      let same = |old: Cid, new: Cid| -> Result<bool, String> {
+        Ok(old == new
+          || same_modulo_refs(&load_anon(old, store)?, &load_anon(new, store)?))
+      };
+      if old_refs == entry_refs(entry)
+        && same(old_entry.type_anon, entry.type_anon)?
+        && same(old_entry.term_anon, entry.term_anon)?
+      {
+        through_refs.insert(name.clone());
+      }
+      else {
+        diff.changed.push(Changed { name: name.clone(), typ, term });
+      }
+    }
+    // Everything that depends on a changed or removed definition is affected
+    // as well, whether or not its anonymous terms changed, unless it's new
+    let mut dependents: BTreeMap<Name, Vec<Name>> = BTreeMap::new();
+    for (name, (_, entry)) in &news {
+      for dep in entry_refs(entry) {
+        dependents.entry(dep).or_default().push(name.clone());
+      }
+    }
+    let mut todo: Vec<Name> =
+      diff.changed.iter().map(|c| c.name.clone()).collect();
+    todo.extend(diff.removed.iter().cloned());
+    todo.extend(through_refs.iter().cloned());
+    let mut affected = through_refs;
+    while let Some(name) = todo.pop() {
+      for dependent in dependents.get(&name).into_iter().flatten() {
+        if affected.insert(dependent.clone()) {
+          todo.push(dependent.clone());
+        }
+      }
+    }
+    let changed: BTreeSet<&Name> =
+      diff.changed.iter().map(|c| &c.name).collect();
+    diff.affected = affected
+      .into_iter()
+      .filter(|n| olds.contains_key(n) && !changed.contains(n))
+      .collect();
+    Ok(diff)
+  }
+
+  pub fn is_empty(&self) -> bool { *self == Diff::default() }
+
+  pub fn to_json(&self) -> Value {
+    let names =
+      |ns: &[Name]| ns.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    let renamed: Vec<Value> = self
+      .renamed
+      .iter()
+      .map(|(from, to)| {
+        json!({ "from": from.to_string(), "to": to.to_string() })
+      })
+      .collect();
+    let changed: Vec<Value> = self
+      .changed
+      .iter()
+      .map(|Changed { name, typ, term }| {
+        json!({ "name": name.to_string(), "type": typ, "term": term })
+      })
+      .collect();
+    json!({
+      "added": names(&self.added),
+      "removed": names(&self.removed),
+      "renamed": renamed,
+      "changed": changed,
+      "metadata": names(&self.metadata),
+      "affected": names(&self.affected),
+    })
+  }
+}
+
+impl fmt::Display for Diff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for name in &self.added {
+      writeln!(f, "added {}", name)?;
+    }
+    for name in &self.removed {
+      writeln!(f, "removed {}", name)?;
+    }
+    for (from, to) in &self.renamed {
+      writeln!(f, "renamed {} to {}", from, to)?;
+    }
+    for Changed { name, typ, term } in &self.changed {
+      let what = match (typ, term) {
+        (true, true) => "type and term",
+        (true, false) => "type",
+        _ => "term",
+      };
+      writeln!(f, "changed {} ({})", name, what)?;
+    }
+    for name in &self.metadata {
+      writeln!(f, "metadata {}", name)?;
+    }
+    for name in &self.affected {
+      writeln!(f, "affected {}", name)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+pub mod test {
+  use super::*;
+  use multiaddr::Multiaddr;
+  use sp_ipld::{
+    dag_cbor::cid,
+    Ipld,
+  };
+  use std::{
+    cell::RefCell,
+    collections::HashMap,
+  };
+  use yatima_core::{
+    defs::Defs,
+    package::Import,
+    parse::{
+      package::parse_defs,
+      span::Span,
+    },
+    position::Pos,
+  };
+
+  #[derive(Debug, Default)]
+  pub struct MemStore {
+    blocks: RefCell<HashMap<Cid, Ipld>>,
+    memos: RefCell<HashMap<Cid, Cid>>,
+    registry: RefCell<Option<Cid>>,
+    pins: RefCell<Vec<Cid>>,
+  }
+
+  impl Store for MemStore {
+    fn get_by_multiaddr(&self, _addr: Multiaddr) -> Result<Ipld, String> {
+      Err("Not supported".to_owned())
+    }
+
+    fn load_by_name(&self, _path: Vec<&str>) -> Result<Ipld, String> {
+      Err("Not supported".to_owned())
+    }
+
+    fn put(&self, expr: Ipld) -> Cid {
+      let link = cid(&expr);
+      self.blocks.borrow_mut().insert(link, expr);
+      link
+    }
+
+    fn get(&self, link: Cid) -> Option<Ipld> {
+      self.blocks.borrow().get(&link).cloned()
+    }
+
+    fn get_memo(&self, key: Cid) -> Option<Cid> {
+      self.memos.borrow().get(&key).copied()
+    }
+
+    fn put_memo(&self, key: Cid, value: Cid) {
+      self.memos.borrow_mut().insert(key, value);
+    }
+
+    fn list_memos(&self) -> Vec<(Cid, Cid)> {
+      self.memos.borrow().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    fn delete_memo(&self, key: Cid) -> bool {
+      self.memos.borrow_mut().remove(&key).is_some()
+    }
+
+    fn get_registry(&self) -> Option<Cid> { *self.registry.borrow() }
+
+    fn put_registry(&self, registry: Cid) {
+      *self.registry.borrow_mut() = Some(registry)
+    }
+
+    fn list(&self) -> Vec<Cid> {
+      self.blocks.borrow().keys().copied().collect()
+    }
+
+    fn delete(&self, link: Cid) -> bool {
+      self.blocks.borrow_mut().remove(&link).is_some()
+    }
+
+    fn get_pins(&self) -> Vec<Cid> { self.pins.borrow().clone() }
+
+    fn put_pins(&self, pins: &[Cid]) { *self.pins.borrow_mut() = pins.to_vec() }
+  }
+
+  // Parse the definitions of a package against imported ones, putting their
+  // entries and anonymous terms into the store
+  pub fn package_with(
+    store: &MemStore,
+    imports: Vec<Import>,
+    defs: Defs,
+    src: &str,
+  ) -> Package {
+    let input = store.put(Ipld::String(src.to_owned()));
+    let (upto, (defs, index)) =
+      parse_defs(input, defs)(Span::new(src)).unwrap();
+    for (n, _) in &index.0 {
+      let (entry, typ, trm) = defs.get(n).unwrap().embed();
+      store.put(typ.to_ipld());
+      store.put(trm.to_ipld());
+      store.put(entry.to_ipld());
+    }
+    Package {
+      pos: Pos::from_upto(input, Span::new(src), upto),
+      name: Name::from("test"),
+      imports,
+      index,
+      exports: None,
+    }
+  }
+
+  pub fn package(store: &MemStore, src: &str) -> Package {
+    package_with(store, vec![], Defs::new(), src)
+  }
+
+  fn names(ns: &[&str]) -> Vec<Name> {
+    ns.iter().map(|n| Name::from(*n)).collect()
+  }
+
+  #[test]
+  fn diffs_packages() {
+    let store = MemStore::default();
+    let old = package(
+      &store,
+      "def a: Type = Type
+       def b: Type = a
+       def c: Type = b
+       def d: Type = Type
+       def e (A: Type): Type = A
+       def f: Type = Type",
+    );
+    let new = package(
+      &store,
+      "def a: Type = ∀ (A: Type) -> A
+       def b: Type = a
+       def c: Type = b
+       def renamed: Type = Type
+       def e (B: Type): Type = B
+       def g: Type = a",
+    );
+    let diff = Diff::new(&old, &new, &store).unwrap();
+    assert_eq!(diff.added, names(&["g"]));
+    assert_eq!(diff.removed, names(&["f"]));
+    assert_eq!(diff.renamed, vec![(Name::from("d"), Name::from("renamed"))]);
+    assert_eq!(diff.changed, vec![Changed {
+      name: Name::from("a"),
+      typ: false,
+      term: true
+    }]);
+    assert_eq!(diff.metadata, names(&["e"]));
+    assert_eq!(diff.affected, names(&["b", "c"]));
+    assert!(Diff::new(&new, &new, &store).unwrap().is_empty());
+  }
+}
@@ -9,7 +9,10 @@ use std::{
   rc::Rc,
 };
 use yatima_core::{
-  check::error::CheckError,
+  check::{
+    error::CheckError,
+    Conversion,
+  },
   defs::Defs,
   package::Package,
   position::Pos,
@@ -21,19 +24,27 @@ use crate::store::{
 };
 
 pub mod error;
+pub mod lock;
 pub mod parse;
 
+/// Type check all in a file, checking the packages its imports resolved to
+/// against the lockfile of `root`, as `lock::check_lock` does
 pub fn check_all_in_file(
   root: PathBuf,
   path: PathBuf,
   store: Rc<dyn Store>,
+  frozen: bool,
+  update: bool,
+  conversion: Conversion,
+  hash_cons: bool,
 ) -> io::Result<Rc<Defs>> {
-  let env = parse::PackageEnv::new(root, path, store.clone());
-  let (_, p, ds) =
-    parse::parse_file(env).map_err(|e| Error::new(ErrorKind::Other, e))?;
+  let env = parse::PackageEnv::new(root.clone(), path, store.clone());
+  let (_, p, ds) = parse::parse_file(env.clone())
+    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+  lock::check_lock(&root, env.resolved(), frozen, update)?;
   let cid = store.put(p.to_ipld());
   println!("Checking package {} at {}", p.name, cid);
-  check_all(Rc::new(p), Rc::new(ds), store)
+  check_all(Rc::new(p), Rc::new(ds), store, conversion, hash_cons)
     .map_err(|e| Error::new(ErrorKind::Other, e))
 }
 
@@ -41,25 +52,33 @@ pub fn check_all_in_file(
 pub fn check_all_in_ipld(
   ipld: Ipld,
   store: Rc<dyn Store>,
+  conversion: Conversion,
+  hash_cons: bool,
 ) -> Result<(Rc<Package>, Rc<Defs>), String> {
   let p = Rc::new(Package::from_ipld(&ipld)?);
   let ds = store::load_package_defs(store.clone(), p.clone())?;
   println!("Checking package {} at {}", p.name, p.cid());
-  check_all(p.clone(), Rc::new(ds), store).map(|defs| (p, defs))
+  check_all(p.clone(), Rc::new(ds), store, conversion, hash_cons)
+    .map(|defs| (p, defs))
 }
 
 pub fn check_all(
   p: Rc<Package>,
   ds: Rc<Defs>,
   store: Rc<dyn Store>,
+  conversion: Conversion,
+  hash_cons: bool,
 ) -> Result<Rc<Defs>, String> {
   for i in &p.imports {
     println!("Checking import {} at {}", i.name, i.cid);
     for n in &i.with {
+      let alias = yatima_core::package::import_alias(n.to_owned(), &i);
       match yatima_core::check::check_def(
         ds.clone(),
-        &yatima_core::package::import_alias(n.to_owned(), &i),
+        &alias,
         false,
+        conversion,
+        hash_cons,
       ) {
         Ok(ty) => {
           println!("✓ {}: {}", n, ty.pretty(Some(&n.to_string()), false))
@@ -68,7 +87,7 @@ pub fn check_all(
           println!("✕ {}: {}", n, e);
         }
         Err(err) => {
-          let def = ds.get(n).unwrap();
+          let def = ds.get(&alias).unwrap();
           println!("✕ {}: {}", n, def.typ_.pretty(Some(&n.to_string()), false));
           if let Pos::Some(pos) = err.pos() {
             if let Some(Ipld::String(input)) = store.get(pos.input) {
@@ -82,7 +101,13 @@ pub fn check_all(
   }
   println!("Checking definitions:");
   for (n, _) in &p.index.0 {
-    match yatima_core::check::check_def(ds.clone(), n, false) {
+    match yatima_core::check::check_def(
+      ds.clone(),
+      n,
+      false,
+      conversion,
+      hash_cons,
+    ) {
       Ok(ty) => println!("✓ {}: {}", n, ty.pretty(Some(&n.to_string()), false)),
       Err(e @ CheckError::UndefinedReference(Pos::None, _)) => {
         println!("✕ {}: {}", n, e);
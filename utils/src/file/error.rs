@@ -34,6 +34,10 @@ pub enum FileErrorKind {
   ImportCollision(String, Cid, String),
   MisnamedImport(String, Cid, String),
   ImportCycle(PathBuf),
+  /// An import of a name the package doesn't export
+  PrivateImport(Name, Name),
+  /// A package and version not found in the registry
+  UnregisteredPackage(String),
   IpldError(IpldError),
   EmbedError(Box<yatima_core::embed_error::EmbedError>),
   Nom(ErrorKind),
@@ -96,6 +100,21 @@ impl<'a> fmt::Display for FileErrorKind {
           path
         )
       }
+      Self::PrivateImport(package, name) => {
+        write!(
+          f,
+          "Cannot import {} from {}, which doesn't export it",
+          name, package
+        )
+      }
+      Self::UnregisteredPackage(reference) => {
+        write!(
+          f,
+          "Package {} is not in the registry. Register it with `yatima \
+           registry add`",
+          reference
+        )
+      }
       Self::ImportCollision(imp_name, _cid, def_name) => {
         writeln!(
           f,
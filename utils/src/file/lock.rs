@@ -0,0 +1,246 @@
+use sp_cid::Cid;
+use std::{
+  collections::BTreeMap,
+  convert::TryFrom,
+  fmt,
+  fs,
+  io::{
+    self,
+    Error,
+    ErrorKind,
+  },
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+/// The name of the lockfile, in the root directory imports resolve from
+pub const LOCKFILE: &str = "yatima.lock";
+
+const HEADER: &str =
+  "# Written by `yatima check` to pin the packages imports resolve to.";
+
+/// The package an import resolved to, and the file it was read from if it
+/// was imported by path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Locked {
+  pub path: Option<PathBuf>,
+  pub cid: Cid,
+}
+
+// The path comes last, so that it can contain spaces
+impl fmt::Display for Locked {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.path {
+      Some(path) => write!(f, "{} {}", self.cid, path.to_string_lossy()),
+      None => write!(f, "{}", self.cid),
+    }
+  }
+}
+
+/// The packages the imports of a root directory resolved to, by the name they
+/// were imported with, like `bool` or `bool@1.2`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lock(pub BTreeMap<String, Locked>);
+
+/// An import that resolved differently from its lock
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+  Added(String, Locked),
+  Changed(String, Locked, Locked),
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Added(name, new) => {
+        write!(f, "import {} is not locked, and resolved to {}", name, new.cid)
+      }
+      Self::Changed(name, old, new) => write!(
+        f,
+        "import {} is locked to {}, but resolved to {}",
+        name, old.cid, new.cid
+      ),
+    }
+  }
+}
+
+impl Lock {
+  pub fn new() -> Self { Self::default() }
+
+  pub fn insert(&mut self, name: String, path: Option<PathBuf>, cid: Cid) {
+    self.0.insert(name, Locked { path, cid });
+  }
+
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let mut lock = Lock::new();
+    for line in text.lines() {
+      // Only the start is trimmed, since a path may end in a space
+      let line = line.trim_start();
+      if line.trim_end().is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let error = || format!("Malformed line in {}: {}", LOCKFILE, line);
+      let mut fields = line.splitn(3, ' ');
+      match (fields.next(), fields.next(), fields.next()) {
+        (Some(name), Some(cid), path) if !name.is_empty() => {
+          let cid = Cid::try_from(cid.trim_end()).map_err(|_| error())?;
+          let path = path.filter(|p| !p.is_empty()).map(PathBuf::from);
+          lock.insert(name.to_owned(), path, cid);
+        }
+        _ => return Err(error()),
+      }
+    }
+    Ok(lock)
+  }
+
+  /// Read the lockfile of a root directory, if it has one
+  pub fn read(root: &Path) -> io::Result<Option<Self>> {
+    match fs::read_to_string(root.join(LOCKFILE)) {
+      Ok(text) => Lock::parse(&text)
+        .map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e),
+    }
+  }
+
+  pub fn write(&self, root: &Path) -> io::Result<()> {
+    fs::write(root.join(LOCKFILE), self.to_string())
+  }
+
+  /// The imports of `resolved` that resolved differently from this lock
+  pub fn changes(&self, resolved: &Lock) -> Vec<Change> {
+    let mut changes = vec![];
+    for (name, new) in &resolved.0 {
+      match self.0.get(name) {
+        None => changes.push(Change::Added(name.clone(), new.clone())),
+        Some(old) if old != new => {
+          changes.push(Change::Changed(name.clone(), old.clone(), new.clone()))
+        }
+        Some(_) => (),
+      }
+    }
+    changes
+  }
+
+  /// Lock the imports of `resolved` as they resolved. The lock is shared by
+  /// every package of the root directory, and doesn't record which of them
+  /// made an import, so the other locked imports are kept on purpose, even
+  /// ones no package imports anymore. Delete their lines to unlock them.
+  pub fn update(&mut self, resolved: Lock) { self.0.extend(resolved.0) }
+}
+
+impl fmt::Display for Lock {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{}", HEADER)?;
+    for (name, locked) in &self.0 {
+      writeln!(f, "{} {}", name, locked)?;
+    }
+    Ok(())
+  }
+}
+
+/// Check the imports a package resolved to against the lockfile of its root
+/// directory. Imports that aren't locked yet are reported and locked. Imports
+/// that resolved to a different package than they lock are errors, unless
+/// `update` is set, in which case they're reported and locked anew. If
+/// `frozen` is set, any import that isn't locked as it resolved is an error,
+/// as is a missing lockfile.
+pub fn check_lock(
+  root: &Path,
+  resolved: Lock,
+  frozen: bool,
+  update: bool,
+) -> io::Result<()> {
+  let lock = Lock::read(root)?;
+  if frozen && lock.is_none() {
+    return Err(Error::new(
+      ErrorKind::NotFound,
+      format!("No {} to check imports against", LOCKFILE),
+    ));
+  }
+  let mut lock = lock.unwrap_or_default();
+  let changes = lock.changes(&resolved);
+  let errors: Vec<String> = changes
+    .iter()
+    .filter(|c| frozen || (!update && matches!(c, Change::Changed(..))))
+    .map(|c| c.to_string())
+    .collect();
+  if !errors.is_empty() {
+    let hint = if frozen { "" } else { "\nCheck with --update to lock anew" };
+    return Err(Error::new(
+      ErrorKind::Other,
+      format!("{} is out of date:\n{}{}", LOCKFILE, errors.join("\n"), hint),
+    ));
+  }
+  for change in &changes {
+    println!("Locking {}", change);
+  }
+  lock.update(resolved);
+  lock.write(root)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use sp_ipld::{
+    dag_cbor::cid,
+    Ipld,
+  };
+
+  fn package(n: i128) -> Cid { cid(&Ipld::Integer(n)) }
+
+  fn file(name: &str) -> Option<PathBuf> {
+    Some(PathBuf::from(format!("{}.ya", name)))
+  }
+
+  #[test]
+  fn roundtrips_lockfiles() {
+    let mut lock = Lock::new();
+    lock.insert("bool".to_owned(), file("bool"), package(0));
+    lock.insert("Nat.Bits@1.2".to_owned(), None, package(1));
+    lock.insert("nat".to_owned(), file("My Packages/nat"), package(2));
+    assert_eq!(Lock::parse(&lock.to_string()), Ok(lock));
+    assert!(Lock::parse("bool bool.ya").is_err());
+    assert!(Lock::parse("bool").is_err());
+  }
+
+  #[test]
+  fn finds_changed_imports() {
+    let mut lock = Lock::new();
+    lock.insert("bool".to_owned(), file("bool"), package(0));
+    lock.insert("nat".to_owned(), file("nat"), package(1));
+    let mut resolved = Lock::new();
+    resolved.insert("bool".to_owned(), file("bool"), package(0));
+    assert_eq!(lock.changes(&resolved), vec![]);
+    resolved.insert("nat".to_owned(), file("nat"), package(2));
+    resolved.insert("text".to_owned(), None, package(3));
+    assert_eq!(lock.changes(&resolved).len(), 2);
+    lock.update(resolved.clone());
+    assert_eq!(lock.changes(&resolved), vec![]);
+  }
+  #[test]
+  fn relocks_changed_imports_only_on_update() {
+    let root = std::env::temp_dir()
+      .join(format!("yatima-lock-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let resolve = |n: i128| {
+      let mut resolved = Lock::new();
+      resolved.insert("bool".to_owned(), file("bool"), package(n));
+      resolved
+    };
+    // Without a lockfile, new imports are locked unless frozen
+    assert!(check_lock(&root, resolve(0), true, false).is_err());
+    check_lock(&root, resolve(0), false, false).unwrap();
+    check_lock(&root, resolve(0), true, false).unwrap();
+    // A changed import is an error until it is locked anew
+    assert!(check_lock(&root, resolve(1), false, false).is_err());
+    assert!(check_lock(&root, resolve(1), true, false).is_err());
+    assert_eq!(Lock::read(&root).unwrap(), Some(resolve(0)));
+    check_lock(&root, resolve(1), false, true).unwrap();
+    assert_eq!(Lock::read(&root).unwrap(), Some(resolve(1)));
+    fs::remove_dir_all(&root).unwrap();
+  }
+}
@@ -5,6 +5,11 @@ use crate::{
       FileError,
       FileErrorKind,
     },
+    lock::Lock,
+  },
+  registry::{
+    parse_reference,
+    Registry,
   },
   store::Store,
 };
@@ -26,8 +31,11 @@ use yatima_core::{
   parse::{
     package::{
       parse_alias,
-      parse_defs,
+      import_scope,
+      parse_defs_exports,
+      parse_hiding,
       parse_link,
+      parse_renaming,
       parse_with,
     },
     span::Span,
@@ -69,6 +77,8 @@ pub struct PackageEnv {
   store: Rc<dyn Store>,
   open: Rc<RefCell<HashSet<PathBuf>>>,
   done: Rc<RefCell<HashMap<PathBuf, Cid>>>,
+  // The package every import resolved to, for the lockfile
+  resolved: Rc<RefCell<Lock>>,
   // sources: Rc<RefCell<HashMap<Cid, PathBuf>>>,
 }
 
@@ -81,6 +91,7 @@ impl PackageEnv {
       store: store.clone(),
       open: Rc::new(RefCell::new(HashSet::new())),
       done: Rc::new(RefCell::new(HashMap::new())),
+      resolved: Rc::new(RefCell::new(Lock::new())),
     }
   }
 
@@ -104,6 +115,18 @@ impl PackageEnv {
     let cid = done.get(path);
     cid.cloned()
   }
+
+  // Record the package an import resolved to, and the file it was read from,
+  // relative to the root
+  pub fn insert_resolved(&self, name: String, path: Option<PathBuf>, cid: Cid) {
+    let path = path.map(|path| {
+      path.strip_prefix(&self.root).map(PathBuf::from).unwrap_or(path)
+    });
+    self.resolved.borrow_mut().insert(name, path, cid);
+  }
+
+  // The packages the imports of this package and of its imports resolved to
+  pub fn resolved(&self) -> Lock { self.resolved.borrow().clone() }
 }
 
 pub fn parse_file(env: PackageEnv) -> Result<(Cid, Package, Defs), String> {
@@ -171,12 +194,24 @@ pub fn parse_import(
     let (i, _) = tag("import")(i)?;
     let (i, _) = parse_space(i).map_err(error::convert)?;
     let (i, name) = parse_name(i).map_err(error::convert)?;
+    // A name like `bool@1.2` asks for a version of a registered package
+    let (name, version) = parse_reference(&name.to_string()).map_err(|e| {
+      Err::Error(FileError::new(i, FileErrorKind::SystemError(e)))
+    })?;
+    let name = Name::from(name);
     let (i, _) = parse_space(i).map_err(error::convert)?;
     let (i, alias) =
       opt(terminated(parse_alias, parse_space))(i).map_err(error::convert)?;
     let alias = alias.unwrap_or_else(|| Name::from(""));
-    let (i, with) =
+    let (i2, with) =
       opt(terminated(parse_with, parse_space))(i).map_err(error::convert)?;
+    let with = with.map(|w| (i, w));
+    let (i3, hiding) =
+      opt(terminated(parse_hiding, parse_space))(i2).map_err(error::convert)?;
+    let hiding = hiding.map(|h| (i2, h));
+    let (i, renaming) = opt(terminated(parse_renaming, parse_space))(i3)
+      .map_err(error::convert)?;
+    let renaming = renaming.map(|r| (i3, r));
     let (i, from) =
       opt(terminated(parse_link, parse_space))(i).map_err(error::convert)?;
 
@@ -186,9 +221,35 @@ pub fn parse_import(
     }
     import_path.set_extension("ya");
 
-    let from = match from {
-      Some(cid) => Some(cid),
-      None => env.get_done_cid(&import_path.clone()),
+    let from = match (from, version) {
+      (Some(cid), _) => Some(cid),
+      (None, Some(version)) => {
+        let registry = Registry::load(&*env.store).map_err(|e| {
+          Err::Error(FileError::new(i, FileErrorKind::SystemError(e)))
+        })?;
+        match registry.resolve(&name.to_string(), Some(&version)) {
+          Some((_, cid)) => {
+            env.insert_resolved(format!("{}@{}", name, version), None, *cid);
+            Some(*cid)
+          }
+          None => {
+            return Err(Err::Error(FileError::new(
+              i,
+              FileErrorKind::UnregisteredPackage(format!(
+                "{}@{}",
+                name, version
+              )),
+            )));
+          }
+        }
+      }
+      (None, None) => {
+        let done = env.get_done_cid(&import_path.clone());
+        if let Some(cid) = done {
+          env.insert_resolved(name.to_string(), Some(import_path.clone()), cid);
+        }
+        done
+      }
     };
     if let Some(from) = from {
       use FileErrorKind::*;
@@ -204,8 +265,10 @@ pub fn parse_import(
         |e| Err(Err::Error(FileError::new(i, e))),
         |v| Ok((i, v)),
       )?;
-      let with: Vec<Name> = with.unwrap_or_else(|| defs.names());
-      Ok((i, (from, Import { cid: from, name, alias, with }, defs)))
+      let (with, renaming, defs) =
+        exported(&name, &pack, with, hiding, renaming, defs)?;
+      let import = Import { cid: from, name, alias, with, renaming };
+      Ok((i, (from, import, defs)))
     }
     else {
       let has_path = env.insert_open(import_path.clone());
@@ -221,20 +284,54 @@ pub fn parse_import(
           path: import_path.clone(),
           open: env.open.clone(),
           done: env.done.clone(),
+          resolved: env.resolved.clone(),
           store: env.store.clone(),
         };
         let (from, pack, defs) = parse_file(env.clone())
             .map_err(|e| nom::Err::Error(error::FileError::new(i, error::FileErrorKind::SystemError(e))))?;
         env.remove_open(import_path.clone());
-        env.insert_done(import_path, from);
-        let names = pack.index.keys();
-        let with = with.unwrap_or_else(|| names);
-        Ok((i, (from, Import { cid: from, name, alias, with }, defs)))
+        env.insert_done(import_path.clone(), from);
+        env.insert_resolved(name.to_string(), Some(import_path), from);
+        let (with, renaming, defs) =
+          exported(&name, &pack, with, hiding, renaming, defs)?;
+        let import = Import { cid: from, name, alias, with, renaming };
+        Ok((i, (from, import, defs)))
       }
     }
   }
 }
 
+// The names an import brings into scope and renames, which must be exported
+// by the package, and the definitions of the package with only those exported
+// in scope
+#[allow(clippy::type_complexity)]
+fn exported<'a>(
+  name: &Name,
+  pack: &Package,
+  with: Option<(Span<'a>, Vec<Name>)>,
+  hiding: Option<(Span<'a>, Vec<Name>)>,
+  renaming: Option<(Span<'a>, Vec<(Name, Name)>)>,
+  defs: Defs,
+) -> Result<(Vec<Name>, Vec<(Name, Name)>, Defs), Err<FileError<Span<'a>>>> {
+  if let Some((i, with)) = &with {
+    let private = with.iter().find(|n| {
+      !pack.is_exported(n) && pack.index.0.iter().any(|(m, _)| m == *n)
+    });
+    if let Some(private) = private {
+      return Err(Err::Error(FileError::new(
+        *i,
+        FileErrorKind::PrivateImport(name.clone(), private.clone()),
+      )));
+    }
+  }
+  let exports = pack.exported();
+  let (with, renaming) = import_scope(&exports, with, hiding, renaming)
+    .map_err(|(i, e)| {
+      Err::Error(FileError::new(i, FileErrorKind::CoreError(e)))
+    })?;
+  Ok((with, renaming, defs.export(&exports)))
+}
+
 pub fn parse_imports(
   env: PackageEnv,
 ) -> impl Fn(Span) -> IResult<Span, (Vec<Import>, Defs), FileError<Span>> {
@@ -266,7 +363,12 @@ pub fn parse_imports(
               }
             }
           }
-          defs = defs.merge(imp_defs, &imp);
+          defs = defs.merge(imp_defs, &imp).map_err(|name| {
+            Err::Error(FileError::new(
+              i,
+              FileErrorKind::PrivateImport(imp.name.clone(), name),
+            ))
+          })?;
           imps.push(imp);
           i = i2;
         }
@@ -296,8 +398,8 @@ pub fn parse_package(
     }
     let (i, (imports, defs)) = parse_imports(env.clone())(i)?;
     let (i, _) = parse_space(i).map_err(error::convert)?;
-    let (upto, (defs, index)) =
-      parse_defs(input, defs)(i).map_err(error::convert)?;
+    let (upto, (defs, index, exports)) =
+      parse_defs_exports(input, defs)(i).map_err(error::convert)?;
     for (n, _) in index.0.iter() {
       let d = defs.get(n).unwrap();
       let (entry, typ, trm) = d.clone().embed();
@@ -309,7 +411,7 @@ pub fn parse_package(
       }
     }
     let pos = Pos::from_upto(input, from, upto);
-    let package = Package { pos, name, imports, index };
+    let package = Package { pos, name, imports, index, exports };
     let pack_cid = env.store.put(package.to_ipld());
     Ok((from, (pack_cid, package, defs)))
   }
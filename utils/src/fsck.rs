@@ -0,0 +1,209 @@
+use crate::gc::links;
+use bytecursor::ByteCursor;
+use sp_cid::Cid;
+use sp_ipld::{
+  dag_cbor::{
+    cid,
+    DagCborCodec,
+  },
+  Codec,
+  Ipld,
+};
+use sp_multihash::{
+  Code,
+  MultihashDigest,
+};
+use std::{
+  collections::{
+    BTreeMap,
+    BTreeSet,
+  },
+  convert::TryFrom,
+  fmt,
+};
+
+// The multicodec of dag-cbor, the only codec blocks are stored in
+const DAG_CBOR: u64 = 0x71;
+
+/// Why the bytes of a block aren't the block its CID addresses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+  /// The bytes couldn't be read
+  Unreadable,
+  /// The CID isn't for dag-cbor
+  UnsupportedCodec(u64),
+  /// The CID uses a hash function we don't know
+  UnsupportedHash(u64),
+  /// The bytes don't hash to the CID
+  HashMismatch,
+  /// The bytes aren't dag-cbor
+  Undecodable,
+  /// The bytes decode, but re-encode to another CID, so putting the block
+  /// back into a store would address it differently
+  NotCanonical,
+}
+
+impl fmt::Display for BlockError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Unreadable => write!(f, "can't be read"),
+      Self::UnsupportedCodec(codec) => {
+        write!(f, "has unsupported codec {:#x}", codec)
+      }
+      Self::UnsupportedHash(code) => {
+        write!(f, "has unsupported hash function {:#x}", code)
+      }
+      Self::HashMismatch => write!(f, "doesn't match its hash"),
+      Self::Undecodable => write!(f, "is not valid dag-cbor"),
+      Self::NotCanonical => write!(f, "is not canonical dag-cbor"),
+    }
+  }
+}
+
+/// Check that the bytes of a block hash to its CID and decode them
+pub fn verify_block(link: Cid, bytes: &[u8]) -> Result<Ipld, BlockError> {
+  if link.codec() != DAG_CBOR {
+    return Err(BlockError::UnsupportedCodec(link.codec()));
+  }
+  let code = link.hash().code();
+  let code =
+    Code::try_from(code).map_err(|_| BlockError::UnsupportedHash(code))?;
+  if code.digest(bytes) != *link.hash() {
+    return Err(BlockError::HashMismatch);
+  }
+  let ipld = DagCborCodec
+    .decode(ByteCursor::new(bytes.to_vec()))
+    .map_err(|_| BlockError::Undecodable)?;
+  if cid(&ipld) != link {
+    return Err(BlockError::NotCanonical);
+  }
+  Ok(ipld)
+}
+
+/// A problem found by checking the blocks of a store
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+  /// A block that isn't what its CID addresses
+  Corrupt(Cid, BlockError),
+  /// A block linking to one missing from the store
+  Dangling(Cid, Cid),
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Corrupt(link, error) => write!(f, "Block {} {}", link, error),
+      Self::Dangling(from, to) => {
+        write!(f, "Block {} links to missing block {}", from, to)
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+  pub checked: usize,
+  pub problems: Vec<Problem>,
+}
+
+impl Report {
+  /// The corrupt blocks, which quarantining moves out of the store
+  pub fn corrupt(&self) -> Vec<Cid> {
+    self
+      .problems
+      .iter()
+      .filter_map(|problem| match problem {
+        Problem::Corrupt(link, _) => Some(*link),
+        Problem::Dangling(..) => None,
+      })
+      .collect()
+  }
+
+  pub fn is_clean(&self) -> bool { self.problems.is_empty() }
+}
+
+/// Check every block of a store, given as its CID and its bytes, if they
+/// could be read. A link to a corrupt block isn't reported as dangling, since
+/// the block itself is.
+pub fn fsck<I: IntoIterator<Item = (Cid, Option<Vec<u8>>)>>(
+  blocks: I,
+) -> Report {
+  let mut report = Report::default();
+  let mut listed = BTreeSet::new();
+  let mut linked = BTreeMap::new();
+  for (link, bytes) in blocks {
+    report.checked += 1;
+    listed.insert(link);
+    let block = bytes
+      .ok_or(BlockError::Unreadable)
+      .and_then(|bytes| verify_block(link, &bytes));
+    match block {
+      Ok(ipld) => {
+        linked.insert(link, links(&ipld));
+      }
+      Err(error) => report.problems.push(Problem::Corrupt(link, error)),
+    }
+  }
+  for (from, tos) in linked {
+    for to in tos {
+      if !listed.contains(&to) {
+        report.problems.push(Problem::Dangling(from, to));
+      }
+    }
+  }
+  report
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    diff::test::{
+      package,
+      MemStore,
+    },
+    store::Store,
+  };
+  use yatima_core::position::Pos;
+
+  #[test]
+  fn finds_corrupt_and_dangling_blocks() {
+    let store = MemStore::default();
+    let pack = package(&store, "def a: Type = Type");
+    let root = store.put(pack.to_ipld());
+    let bytes = |link: Cid| {
+      DagCborCodec.encode(&store.get(link).unwrap()).unwrap().into_inner()
+    };
+    let mut blocks: Vec<_> =
+      store.list().into_iter().map(|link| (link, Some(bytes(link)))).collect();
+    let report = fsck(blocks.clone());
+    assert_eq!(report, Report { checked: blocks.len(), problems: vec![] });
+
+    // Flip a bit of the package, drop its source and add undecodable bytes
+    let source = match pack.pos {
+      Pos::Some(pos) => pos.input,
+      Pos::None => panic!("package without source"),
+    };
+    let garbage = vec![0xff];
+    let undecodable = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&garbage));
+    blocks.retain(|(link, _)| *link != source);
+    for (link, bytes) in blocks.iter_mut() {
+      if *link == root {
+        let mut corrupted = bytes.take().unwrap();
+        corrupted[0] ^= 1;
+        *bytes = Some(corrupted);
+      }
+    }
+    blocks.push((undecodable, Some(garbage)));
+    let report = fsck(blocks);
+    let problems = report.problems.clone();
+    let corrupt =
+      |link, error| problems.contains(&Problem::Corrupt(link, error));
+    assert!(corrupt(root, BlockError::HashMismatch));
+    assert!(corrupt(undecodable, BlockError::Undecodable));
+    assert!(problems.iter().any(|problem| {
+      matches!(problem, Problem::Dangling(_, to) if *to == source)
+    }));
+    assert_eq!(report.corrupt().len(), 2);
+  }
+}
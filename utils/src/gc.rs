@@ -0,0 +1,270 @@
+use crate::store::Store;
+use sp_cid::Cid;
+use sp_ipld::Ipld;
+use std::collections::BTreeSet;
+
+/// Pin a CID in the store, returning whether it wasn't pinned already
+pub fn pin(store: &dyn Store, cid: Cid) -> Result<bool, String> {
+  if store.get(cid).is_none() {
+    return Err(format!("{} not found in the store", cid));
+  }
+  let mut pins = store.get_pins();
+  if pins.contains(&cid) {
+    return Ok(false);
+  }
+  pins.push(cid);
+  store.put_pins(&pins);
+  Ok(true)
+}
+
+/// Unpin a CID, returning whether it was pinned
+pub fn unpin(store: &dyn Store, cid: Cid) -> bool {
+  let mut pins = store.get_pins();
+  let len = pins.len();
+  pins.retain(|pin| *pin != cid);
+  store.put_pins(&pins);
+  pins.len() != len
+}
+
+/// The roots garbage collection starts from: the pins and the current
+/// registry, which links to every registered package
+pub fn roots(store: &dyn Store) -> Vec<Cid> {
+  let mut roots = store.get_pins();
+  roots.extend(store.get_registry());
+  roots
+}
+
+/// Every CID reachable from `roots` by following links. The CIDs in packages,
+/// entries, anonymous terms and positions are all links, to the imported
+/// packages, the entries and terms of definitions and the source text. Fails
+/// on a reachable block that can't be decoded, since the blocks it links to
+/// can't be told apart from garbage.
+pub fn reachable(
+  store: &dyn Store,
+  roots: &[Cid],
+) -> Result<BTreeSet<Cid>, String> {
+  let mut seen = BTreeSet::new();
+  let mut todo = roots.to_vec();
+  while let Some(cid) = todo.pop() {
+    if !seen.insert(cid) {
+      continue;
+    }
+    // A link to something missing from the store keeps nothing else alive
+    if let Some(ipld) = store.try_get(cid)? {
+      todo.extend(links(&ipld));
+    }
+  }
+  Ok(seen)
+}
+
+/// The CIDs an IPLD expression links to directly
+pub fn links(ipld: &Ipld) -> Vec<Cid> {
+  let mut links = vec![];
+  let mut todo = vec![ipld];
+  while let Some(node) = todo.pop() {
+    match node {
+      Ipld::Link(link) => links.push(*link),
+      Ipld::List(xs) => todo.extend(xs),
+      Ipld::StringMap(map) => todo.extend(map.values()),
+      _ => (),
+    }
+  }
+  links
+}
+
+/// The blocks of a store that aren't reachable from its roots, deleted unless
+/// it's a dry run, and the memoized keys whose values are among them, which
+/// are forgotten with them. Cached normal forms are kept as roots too, unless
+/// the cache is dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Collection {
+  pub live: usize,
+  pub garbage: Vec<Cid>,
+  pub memos: Vec<Cid>,
+}
+
+/// Collect the garbage of a store. Fails before deleting anything if a live
+/// block can't be decoded.
+pub fn collect(
+  store: &dyn Store,
+  dry_run: bool,
+  drop_cache: bool,
+) -> Result<Collection, String> {
+  let mut roots = roots(store);
+  if !drop_cache {
+    roots.extend(store.list_memos().into_iter().map(|(_, value)| value));
+  }
+  let live = reachable(store, &roots)?;
+  let mut collection = Collection::default();
+  for cid in store.list() {
+    if live.contains(&cid) {
+      collection.live += 1;
+    }
+    else {
+      if !dry_run {
+        store.delete(cid);
+      }
+      collection.garbage.push(cid);
+    }
+  }
+  // Collecting a dropped normal form leaves its memo pointing nowhere unless
+  // it's forgotten too
+  for (key, value) in store.list_memos() {
+    if !live.contains(&value) {
+      if !dry_run {
+        store.delete_memo(key);
+      }
+      collection.memos.push(key);
+    }
+  }
+  Ok(collection)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    diff::test::{
+      package,
+      MemStore,
+    },
+    registry::Registry,
+    store::StoreCache,
+  };
+  use yatima_core::{
+    eval::cache::{
+      CacheKey,
+      Form,
+      NormCache,
+    },
+    position::Pos,
+    term::Term,
+  };
+  use multiaddr::Multiaddr;
+  use sp_ipld::dag_cbor::cid;
+  use std::{
+    collections::BTreeMap,
+    rc::Rc,
+  };
+
+  #[test]
+  fn collects_unreachable_blocks() {
+    let store = MemStore::default();
+    let kept = package(&store, "def a: Type = Type");
+    let kept = store.put(kept.to_ipld());
+    let dropped = package(&store, "def b: Type = ∀ (A: Type) -> A");
+    let dropped = store.put(dropped.to_ipld());
+    let registered = package(&store, "def c: Type = Type def d: Type = c");
+    let registered = store.put(registered.to_ipld());
+    let mut registry = Registry::new();
+    let version = "1.0".parse().unwrap();
+    registry.add("c".to_owned(), version, registered).unwrap();
+    registry.save(&store);
+    assert_eq!(pin(&store, kept), Ok(true));
+    assert_eq!(pin(&store, kept), Ok(false));
+    let before = store.list().len();
+    let dry = collect(&store, true, false).unwrap();
+    assert_eq!(store.list().len(), before);
+    assert!(dry.garbage.contains(&dropped));
+    assert_eq!(collect(&store, false, false), Ok(dry));
+    assert_eq!(store.list().len(), dry.live);
+    // Everything a kept package links to survives
+    let live = reachable(&store, &roots(&store)).unwrap();
+    assert!(live.contains(&kept) && live.contains(&registered));
+    assert!(live.iter().all(|cid| store.get(*cid).is_some()));
+    assert!(store.get(dropped).is_none());
+    assert!(unpin(&store, kept));
+    assert!(!unpin(&store, kept));
+    assert!(collect(&store, false, false).unwrap().garbage.contains(&kept));
+    assert!(store.get(registered).is_some());
+  }
+
+  #[test]
+  fn keeps_memos_unless_dropped() {
+    let store = Rc::new(MemStore::default());
+    let cache = StoreCache(store.clone());
+    let key = CacheKey::new(cid(&Ipld::Integer(0)), Form::Norm);
+    cache.put(&key, &Term::Typ(Pos::None));
+    assert!(cache.get(&key).is_some());
+    assert_eq!(store.list_memos().len(), 1);
+    // Cached normal forms survive collection
+    let kept = collect(&*store, false, false).unwrap();
+    assert!(kept.garbage.is_empty() && kept.memos.is_empty());
+    assert!(cache.get(&key).is_some());
+    // and go with their memos once the cache is dropped
+    let dry = collect(&*store, true, true).unwrap();
+    assert_eq!(dry.memos.len(), 1);
+    assert!(cache.get(&key).is_some());
+    assert_eq!(collect(&*store, false, true), Ok(dry));
+    assert!(store.list_memos().is_empty());
+    assert!(cache.get(&key).is_none());
+  }
+
+  // A store in which one block can't be decoded
+  #[derive(Debug)]
+  struct Corrupt(MemStore, Cid);
+
+  impl Store for Corrupt {
+    fn get_by_multiaddr(&self, addr: Multiaddr) -> Result<Ipld, String> {
+      self.0.get_by_multiaddr(addr)
+    }
+
+    fn load_by_name(&self, path: Vec<&str>) -> Result<Ipld, String> {
+      self.0.load_by_name(path)
+    }
+
+    fn put(&self, expr: Ipld) -> Cid { self.0.put(expr) }
+
+    fn get(&self, link: Cid) -> Option<Ipld> { self.try_get(link).ok()? }
+
+    fn try_get(&self, link: Cid) -> Result<Option<Ipld>, String> {
+      if link == self.1 {
+        Err(format!("Block {} is not valid dag-cbor", link))
+      }
+      else {
+        Ok(self.0.get(link))
+      }
+    }
+
+    fn list(&self) -> Vec<Cid> { self.0.list() }
+
+    fn delete(&self, link: Cid) -> bool { self.0.delete(link) }
+
+    fn get_pins(&self) -> Vec<Cid> { self.0.get_pins() }
+
+    fn put_pins(&self, pins: &[Cid]) { self.0.put_pins(pins) }
+  }
+
+  #[test]
+  fn aborts_on_corrupt_live_blocks() {
+    // A store with a pinned package and a block of garbage
+    let build = || {
+      let store = MemStore::default();
+      let kept = package(&store, "def a: Type = Type");
+      let entry = kept.index.0[0].1;
+      let kept = store.put(kept.to_ipld());
+      pin(&store, kept).unwrap();
+      let garbage = store.put(Ipld::String("garbage".to_owned()));
+      (store, kept, entry, garbage)
+    };
+    let (store, kept, entry, garbage) = build();
+    let blocks = store.list().len();
+    // A corrupt root or block linked from one keeps everything
+    for corrupt in &[kept, entry] {
+      let store = Corrupt(build().0, *corrupt);
+      assert!(collect(&store, false, false).is_err());
+      assert_eq!(store.list().len(), blocks);
+    }
+    // while corrupt garbage is collected like any other
+    let store = Corrupt(store, garbage);
+    assert_eq!(collect(&store, false, false).unwrap().garbage, vec![garbage]);
+  }
+
+  #[test]
+  fn follows_links_in_maps() {
+    let link = cid(&Ipld::Integer(0));
+    let mut map = BTreeMap::new();
+    map.insert("x".to_owned(), Ipld::List(vec![Ipld::Link(link)]));
+    assert_eq!(links(&Ipld::StringMap(map)), vec![link]);
+  }
+}
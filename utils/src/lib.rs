@@ -1,4 +1,11 @@
+pub mod car;
+pub mod compat;
+pub mod diff;
 pub mod file;
+pub mod fsck;
+pub mod gc;
+pub mod registry;
+pub mod rename;
 pub mod repl;
 pub mod store;
 #[macro_use]
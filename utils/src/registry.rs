@@ -0,0 +1,239 @@
+use crate::store::Store;
+use sp_cid::Cid;
+use sp_ipld::Ipld;
+use std::{
+  collections::BTreeMap,
+  fmt,
+  str::FromStr,
+};
+
+/// The tag and version of the IPLD encoding of a registry
+pub const REGISTRY_TAG: &str = "yatima/registry";
+pub const REGISTRY_VERSION: i128 = 1;
+
+/// A version of a package, as numbers separated by dots like `1.2.3`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub Vec<u64>);
+
+impl Version {
+  /// Whether this version is `prefix` or one of its refinements, so that
+  /// asking for `1.2` finds `1.2.3`
+  pub fn matches(&self, prefix: &Version) -> bool {
+    self.0.starts_with(&prefix.0)
+  }
+}
+
+impl FromStr for Version {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    s.split('.')
+      .map(|n| n.parse::<u64>())
+      .collect::<Result<Vec<_>, _>>()
+      .map(Version)
+      .map_err(|_| format!("Invalid version {}", s))
+  }
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let ns: Vec<String> = self.0.iter().map(|n| n.to_string()).collect();
+    write!(f, "{}", ns.join("."))
+  }
+}
+
+/// Split a reference like `bool@1.2` into a package name and a version
+pub fn parse_reference(s: &str) -> Result<(String, Option<Version>), String> {
+  match s.find('@') {
+    Some(at) => {
+      let version = s[at + 1..].parse()?;
+      Ok((s[..at].to_owned(), Some(version)))
+    }
+    None => Ok((s.to_owned(), None)),
+  }
+}
+
+/// A local registry of packages by name and version, so that imports can ask
+/// for `bool@1.2` instead of a CID. The registry is itself put into the store,
+/// which keeps the CID of the current one: registering a package makes a new
+/// registry and leaves the old one as it was.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Registry {
+  /// The CID of every version of every package
+  pub packages: BTreeMap<String, BTreeMap<Version, Cid>>,
+}
+
+impl Registry {
+  pub fn new() -> Self { Self::default() }
+
+  /// Register a version of a package. A version that was already registered
+  /// can't be changed, since packages importing it expect what it was.
+  pub fn add(
+    &mut self,
+    name: String,
+    version: Version,
+    cid: Cid,
+  ) -> Result<(), String> {
+    let versions = self.packages.entry(name.clone()).or_default();
+    match versions.get(&version) {
+      Some(old) if *old != cid => Err(format!(
+        "Package {}@{} is already registered as {}",
+        name, version, old
+      )),
+      _ => {
+        versions.insert(version, cid);
+        Ok(())
+      }
+    }
+  }
+
+  /// The latest version of a package matching `version`, or of any version
+  pub fn resolve(
+    &self,
+    name: &str,
+    version: Option<&Version>,
+  ) -> Option<(&Version, &Cid)> {
+    self
+      .packages
+      .get(name)?
+      .iter()
+      .rev()
+      .find(|(v, _)| version.map_or(true, |version| v.matches(version)))
+  }
+
+  pub fn to_ipld(&self) -> Ipld {
+    let packages = self
+      .packages
+      .iter()
+      .map(|(name, versions)| {
+        let versions = versions
+          .iter()
+          .map(|(version, cid)| {
+            Ipld::List(vec![
+              Ipld::String(version.to_string()),
+              Ipld::Link(*cid),
+            ])
+          })
+          .collect();
+        Ipld::List(vec![Ipld::String(name.clone()), Ipld::List(versions)])
+      })
+      .collect();
+    Ipld::List(vec![
+      Ipld::String(REGISTRY_TAG.to_owned()),
+      Ipld::Integer(REGISTRY_VERSION),
+      Ipld::List(packages),
+    ])
+  }
+
+  pub fn from_ipld(ipld: &Ipld) -> Result<Self, String> {
+    let error = || format!("Invalid registry {:?}", ipld);
+    let packages = match ipld {
+      Ipld::List(xs) => match xs.as_slice() {
+        [Ipld::String(tag), Ipld::Integer(REGISTRY_VERSION), Ipld::List(ps)]
+          if tag == REGISTRY_TAG =>
+        {
+          ps
+        }
+        _ => return Err(error()),
+      },
+      _ => return Err(error()),
+    };
+    let mut registry = Registry::new();
+    for package in packages {
+      match package {
+        Ipld::List(xs) => match xs.as_slice() {
+          [Ipld::String(name), Ipld::List(versions)] => {
+            for version in versions {
+              match version {
+                Ipld::List(xs) => match xs.as_slice() {
+                  [Ipld::String(version), Ipld::Link(cid)] => {
+                    registry.add(name.clone(), version.parse()?, *cid)?;
+                  }
+                  _ => return Err(error()),
+                },
+                _ => return Err(error()),
+              }
+            }
+          }
+          _ => return Err(error()),
+        },
+        _ => return Err(error()),
+      }
+    }
+    Ok(registry)
+  }
+
+  /// Load the current registry of a store, or an empty one if it has none
+  pub fn load(store: &dyn Store) -> Result<Self, String> {
+    match store.get_registry() {
+      Some(cid) => {
+        let ipld = store
+          .get(cid)
+          .ok_or_else(|| format!("Registry {} not found in the store", cid))?;
+        Registry::from_ipld(&ipld)
+      }
+      None => Ok(Registry::new()),
+    }
+  }
+
+  /// Put the registry into a store and make it the current one
+  pub fn save(&self, store: &dyn Store) -> Cid {
+    let cid = store.put(self.to_ipld());
+    store.put_registry(cid);
+    cid
+  }
+}
+
+impl fmt::Display for Registry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (name, versions) in &self.packages {
+      for (version, cid) in versions {
+        writeln!(f, "{}@{} {}", name, version, cid)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use sp_ipld::dag_cbor::cid;
+
+  fn package(n: i128) -> Cid { cid(&Ipld::Integer(n)) }
+
+  #[test]
+  fn parses_references() {
+    assert_eq!(parse_reference("bool"), Ok(("bool".to_owned(), None)));
+    assert_eq!(
+      parse_reference("Nat.Bits@1.2"),
+      Ok(("Nat.Bits".to_owned(), Some(Version(vec![1, 2]))))
+    );
+    assert!(parse_reference("bool@").is_err());
+    assert!(parse_reference("bool@1.x").is_err());
+  }
+
+  #[test]
+  fn resolves_latest_matching_version() {
+    let mut registry = Registry::new();
+    for (version, n) in &[("1.2", 0), ("1.2.1", 1), ("1.10", 2), ("2.0", 3)] {
+      let version = version.parse().unwrap();
+      registry.add("bool".to_owned(), version, package(*n)).unwrap();
+    }
+    let resolve = |version: &str| {
+      let version = version.parse().unwrap();
+      registry.resolve("bool", Some(&version)).map(|(_, cid)| *cid)
+    };
+    assert_eq!(resolve("1.2"), Some(package(1)));
+    assert_eq!(resolve("1.2.0"), None);
+    assert_eq!(resolve("1"), Some(package(2)));
+    let latest = registry.resolve("bool", None).map(|(_, cid)| *cid);
+    assert_eq!(latest, Some(package(3)));
+    assert_eq!(registry.resolve("nat", None), None);
+    // Versions can't be changed once registered
+    let two = Version(vec![2, 0]);
+    assert!(registry.add("bool".to_owned(), two.clone(), package(4)).is_err());
+    assert!(registry.add("bool".to_owned(), two, package(3)).is_ok());
+    assert_eq!(Registry::from_ipld(&registry.to_ipld()), Ok(registry));
+  }
+}
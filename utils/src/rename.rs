@@ -0,0 +1,429 @@
+use crate::store::Store;
+use sp_cid::Cid;
+use sp_ipld::Ipld;
+use std::collections::BTreeMap;
+use yatima_core::{
+  anon::Anon,
+  defs::Def,
+  meta::Meta,
+  name::Name,
+  package::{
+    import_alias,
+    Entry,
+    Index,
+    Package,
+  },
+  parse::{
+    package::parse_def_name,
+    span::Span,
+    term::{
+      is_valid_symbol_char,
+      is_valid_symbol_string,
+    },
+  },
+  position::{
+    Pos,
+    Position,
+  },
+};
+
+/// An occurrence of the renamed definition's name in the source text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Edit {
+  offset: u64,
+  line: u64,
+}
+
+/// Rebuild a term's metadata with other positions and references
+pub fn map_meta(
+  meta: &Meta,
+  pos: &dyn Fn(Pos) -> Pos,
+  refs: &dyn Fn(&Name, Cid) -> (Name, Cid),
+) -> Meta {
+  let go = |m: &Meta| Box::new(map_meta(m, pos, refs));
+  let go2 = |x: &(Meta, Meta)| {
+    Box::new((map_meta(&x.0, pos, refs), map_meta(&x.1, pos, refs)))
+  };
+  match meta {
+    Meta::Var(p, n) => Meta::Var(pos(*p), n.clone()),
+    Meta::Lam(p, n, bod) => Meta::Lam(pos(*p), n.clone(), go(bod)),
+    Meta::App(p, x) => Meta::App(pos(*p), go2(x)),
+    Meta::All(p, n, x) => Meta::All(pos(*p), n.clone(), go2(x)),
+    Meta::Slf(p, n, bod) => Meta::Slf(pos(*p), n.clone(), go(bod)),
+    Meta::Dat(p, bod) => Meta::Dat(pos(*p), go(bod)),
+    Meta::Cse(p, bod) => Meta::Cse(pos(*p), go(bod)),
+    Meta::Ref(p, n, cid) => {
+      let (n, cid) = refs(n, *cid);
+      Meta::Ref(pos(*p), n, cid)
+    }
+    Meta::Let(p, n, x) => Meta::Let(
+      pos(*p),
+      n.clone(),
+      Box::new((
+        map_meta(&x.0, pos, refs),
+        map_meta(&x.1, pos, refs),
+        map_meta(&x.2, pos, refs),
+      )),
+    ),
+    Meta::Typ(p) => Meta::Typ(pos(*p)),
+    Meta::Ann(p, x) => Meta::Ann(pos(*p), go2(x)),
+    Meta::Lit(p) => Meta::Lit(pos(*p)),
+    Meta::LTy(p) => Meta::LTy(pos(*p)),
+    Meta::Opr(p) => Meta::Opr(pos(*p)),
+    Meta::Rec(p) => Meta::Rec(pos(*p)),
+  }
+}
+
+// The positions of the references to the definition at `cid`, and of the
+// recursive references if `rec`, along with the names bound in the term
+fn occurrences(
+  meta: &Meta,
+  cid: Cid,
+  rec: bool,
+  refs: &mut Vec<Pos>,
+  binders: &mut Vec<Name>,
+) {
+  match meta {
+    Meta::Ref(p, _, c) if *c == cid => refs.push(*p),
+    Meta::Rec(p) if rec => refs.push(*p),
+    Meta::Lam(_, n, bod) | Meta::Slf(_, n, bod) => {
+      binders.push(n.clone());
+      occurrences(bod, cid, rec, refs, binders)
+    }
+    Meta::Dat(_, bod) | Meta::Cse(_, bod) => {
+      occurrences(bod, cid, rec, refs, binders)
+    }
+    Meta::All(_, n, x) => {
+      binders.push(n.clone());
+      occurrences(&x.0, cid, rec, refs, binders);
+      occurrences(&x.1, cid, rec, refs, binders);
+    }
+    Meta::App(_, x) | Meta::Ann(_, x) => {
+      occurrences(&x.0, cid, rec, refs, binders);
+      occurrences(&x.1, cid, rec, refs, binders);
+    }
+    Meta::Let(_, n, x) => {
+      binders.push(n.clone());
+      occurrences(&x.0, cid, rec, refs, binders);
+      occurrences(&x.1, cid, rec, refs, binders);
+      occurrences(&x.2, cid, rec, refs, binders);
+    }
+    _ => (),
+  }
+}
+
+// Whether a name can't go on before or after an offset of the text
+fn boundary_before(text: &str, offset: usize) -> bool {
+  text[..offset].chars().next_back().map_or(true, |c| !is_valid_symbol_char(c))
+}
+
+fn boundary_after(text: &str, offset: usize) -> bool {
+  text[offset..].chars().next().map_or(true, |c| !is_valid_symbol_char(c))
+}
+
+// The offset of the name of a definition in the text of its entry, as the
+// definition parses
+fn def_name(text: &str, from: usize, upto: usize, name: &str) -> Option<usize> {
+  let entry = text.get(from..upto)?;
+  let (_, (at, nam)) = parse_def_name(Span::new(entry)).ok()?;
+  if &*nam == name { Some(from + at.location_offset()) } else { None }
+}
+
+// The offsets of a name in the `export (...)` clause of a package, if it has
+// one
+fn export_names(text: &str, name: &str) -> Vec<usize> {
+  let mut offsets = vec![];
+  let mut at = 0;
+  while let Some(found) = text[at..].find("export") {
+    let start = at + found;
+    at = start + "export".len();
+    let rest = text[at..].trim_start();
+    let open = text.len() - rest.len();
+    if !boundary_before(text, start) || !rest.starts_with('(') {
+      continue;
+    }
+    let close = match rest.find(')') {
+      Some(close) => open + close,
+      None => break,
+    };
+    let mut i = open + 1;
+    while let Some(found) = text[i..close].find(name) {
+      let offset = i + found;
+      let end = offset + name.len();
+      if boundary_before(text, offset) && boundary_after(text, end) {
+        offsets.push(offset);
+      }
+      i = end;
+    }
+    break;
+  }
+  offsets
+}
+
+fn line_of(text: &str, offset: usize) -> u64 {
+  text[..offset].matches('\n').count() as u64 + 1
+}
+
+/// Rename a definition of a package. Names only live in the metadata of
+/// entries, so the index, the references to the definition and its source
+/// text are rewritten without changing the anonymous term of any definition,
+/// which is checked before the renamed package is put into the store.
+pub fn rename(
+  store: &dyn Store,
+  package: &Package,
+  old: &Name,
+  new: &Name,
+) -> Result<(Cid, Package), String> {
+  if !is_valid_symbol_string(new) {
+    return Err(format!("{} is not a valid name", new));
+  }
+  let renamed = package
+    .index
+    .0
+    .iter()
+    .find(|(n, _)| n == old)
+    .map(|(_, cid)| *cid)
+    .ok_or_else(|| format!("{} is not defined in {}", old, package.name))?;
+  let imported = package.imports.iter().any(|import| {
+    import.with.iter().any(|n| import_alias(n.clone(), import) == *new)
+  });
+  if imported || package.index.0.iter().any(|(n, _)| n == new) {
+    return Err(format!("{} is already defined in {}", new, package.name));
+  }
+  let input = match package.pos {
+    Pos::Some(pos) => pos.input,
+    Pos::None => return Err(format!("{} has no source", package.name)),
+  };
+  let text = match store.get(input) {
+    Some(Ipld::String(text)) => text,
+    _ => return Err(format!("Source {} not found", input)),
+  };
+  let load = |cid: Cid| -> Result<Entry, String> {
+    let ipld = store.get(cid).ok_or_else(|| format!("{} not found", cid))?;
+    Entry::from_ipld(&ipld).map_err(|e| format!("{:?}", e))
+  };
+  let mut entries = vec![];
+  for (name, cid) in &package.index.0 {
+    entries.push((name.clone(), *cid, load(*cid)?));
+  }
+
+  // Find every occurrence of the name in the source
+  let mut edits = vec![];
+  let mut edit = |offset: usize| -> Result<(), String> {
+    if text.get(offset..offset + old.len()) != Some(&old[..]) {
+      return Err(format!("Source {} doesn't match {}", input, package.name));
+    }
+    edits.push(Edit { offset: offset as u64, line: line_of(&text, offset) });
+    Ok(())
+  };
+  for (name, cid, entry) in &entries {
+    let (mut refs, mut binders) = (vec![], vec![]);
+    let rec = *cid == renamed;
+    occurrences(&entry.type_meta, renamed, rec, &mut refs, &mut binders);
+    occurrences(&entry.term_meta, renamed, rec, &mut refs, &mut binders);
+    if !refs.is_empty() && binders.contains(new) {
+      return Err(format!("{} would be captured by a binder in {}", new, name));
+    }
+    for pos in refs {
+      if let Pos::Some(pos) = pos {
+        edit(pos.from_offset as usize)?;
+      }
+    }
+    if rec {
+      let (from, upto) = match entry.pos {
+        Pos::Some(pos) => (pos.from_offset as usize, pos.upto_offset as usize),
+        Pos::None => {
+          return Err(format!(
+            "{} has no source of its own, like the definitions of a type",
+            old
+          ));
+        }
+      };
+      let offset = def_name(&text, from, upto, old)
+        .ok_or_else(|| format!("Only a def like {} can be renamed", old))?;
+      edit(offset)?;
+    }
+  }
+  if package.exports.is_some() {
+    for offset in export_names(&text, old) {
+      edit(offset)?;
+    }
+  }
+  edits.sort_by_key(|e| e.offset);
+  edits.dedup();
+
+  // Replace them, and shift the positions after them
+  let mut new_text = String::new();
+  let mut last = 0;
+  for e in &edits {
+    new_text.push_str(&text[last..e.offset as usize]);
+    new_text.push_str(new);
+    last = e.offset as usize + old.len();
+  }
+  new_text.push_str(&text[last..]);
+  let new_input = store.put(Ipld::String(new_text));
+  let bytes = new.len() as i64 - old.len() as i64;
+  let chars = new.chars().count() as i64 - old.chars().count() as i64;
+  let end = |e: &Edit| e.offset + old.len() as u64;
+  let offset = |o: u64| {
+    let n = edits.iter().filter(|e| end(e) <= o).count() as i64;
+    (o as i64 + n * bytes) as u64
+  };
+  let column = |o: u64, line: u64, col: u64| {
+    let n =
+      edits.iter().filter(|e| end(e) <= o && e.line == line).count() as i64;
+    (col as i64 + n * chars) as u64
+  };
+  let pos = |p: Pos| match p {
+    Pos::Some(p) if p.input == input => Pos::Some(Position {
+      input: new_input,
+      from_offset: offset(p.from_offset),
+      from_line: p.from_line,
+      from_column: column(p.from_offset, p.from_line, p.from_column),
+      upto_offset: offset(p.upto_offset),
+      upto_line: p.upto_line,
+      upto_column: column(p.upto_offset, p.upto_line, p.upto_column),
+    }),
+    p => p,
+  };
+
+  // Rewrite the entries in order, since every entry after a rewritten one
+  // refers to it by its new CID
+  let mut cids: BTreeMap<Cid, Cid> = BTreeMap::new();
+  let mut index = vec![];
+  for (name, cid, entry) in entries {
+    let refs = |n: &Name, c: Cid| {
+      let n = if c == renamed { new.clone() } else { n.clone() };
+      (n, cids.get(&c).copied().unwrap_or(c))
+    };
+    let new_entry = Entry {
+      pos: pos(entry.pos),
+      type_meta: map_meta(&entry.type_meta, &pos, &refs),
+      term_meta: map_meta(&entry.term_meta, &pos, &refs),
+      ..entry
+    };
+    verify(store, &name, &new_entry)?;
+    let new_cid = store.put(new_entry.to_ipld());
+    cids.insert(cid, new_cid);
+    index.push((if name == *old { new.clone() } else { name }, new_cid));
+  }
+  let exports = package.exports.as_ref().map(|exports| {
+    exports
+      .iter()
+      .map(|n| if n == old { new.clone() } else { n.clone() })
+      .collect()
+  });
+  let package = Package {
+    pos: pos(package.pos),
+    name: package.name.clone(),
+    imports: package.imports.clone(),
+    index: Index(index),
+    exports,
+  };
+  let cid = store.put(package.to_ipld());
+  Ok((cid, package))
+}
+
+// Check that the terms of a rewritten entry still embed to its anonymous terms
+fn verify(store: &dyn Store, name: &Name, entry: &Entry) -> Result<(), String> {
+  let anon = |cid: Cid| -> Result<Anon, String> {
+    let ipld = store.get(cid).ok_or_else(|| format!("{} not found", cid))?;
+    Anon::from_ipld(&ipld).map_err(|e| format!("{:?}", e))
+  };
+  let (typ, term) = (anon(entry.type_anon)?, anon(entry.term_anon)?);
+  let def =
+    Def::unembed(entry.clone(), typ, term).map_err(|e| format!("{:?}", e))?;
+  let (_, typ, term) = def.embed();
+  if typ.cid() != entry.type_anon || term.cid() != entry.term_anon {
+    return Err(format!("Renaming changed the anonymous terms of {}", name));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::diff::test::{
+    package,
+    MemStore,
+  };
+
+  #[test]
+  fn renames_definitions() {
+    let store = MemStore::default();
+    let old = package(
+      &store,
+      "def a: Type = Type
+def b: Type = a
+def f (x: Type): Type = f (a)
+strict def g: Type = a a",
+    );
+    let (_, new) =
+      rename(&store, &old, &Name::from("a"), &Name::from("alpha")).unwrap();
+    // The renamed package is the one its rewritten source parses to
+    let parsed = package(
+      &store,
+      "def alpha: Type = Type
+def b: Type = alpha
+def f (x: Type): Type = f (alpha)
+strict def g: Type = alpha alpha",
+    );
+    assert_eq!(new, parsed);
+    let (_, new) =
+      rename(&store, &new, &Name::from("f"), &Name::from("fun")).unwrap();
+    let parsed = package(
+      &store,
+      "def alpha: Type = Type
+def b: Type = alpha
+def fun (x: Type): Type = fun (alpha)
+strict def g: Type = alpha alpha",
+    );
+    assert_eq!(new, parsed);
+    // Anonymous terms stay the same
+    let anons = |p: &Package| -> Vec<(Cid, Cid)> {
+      p.index
+        .0
+        .iter()
+        .map(|(_, cid)| {
+          let entry = Entry::from_ipld(&store.get(*cid).unwrap()).unwrap();
+          (entry.type_anon, entry.term_anon)
+        })
+        .collect()
+    };
+    assert_eq!(anons(&old), anons(&new));
+    let renames = |old: &str, new: &str| {
+      rename(&store, &parsed, &Name::from(old), &Name::from(new)).is_ok()
+    };
+    // Names can't clash with definitions or be captured by binders
+    assert!(!renames("b", "alpha"));
+    assert!(!renames("alpha", "x"));
+    assert!(!renames("missing", "y"));
+    assert!(renames("b", "beta"));
+  }
+
+  #[test]
+  fn renames_marked_definitions() {
+    let store = MemStore::default();
+    let old = package(
+      &store,
+      "private strict def a: Type = Type
+strict private def b: Type = a
+def c: Type = b",
+    );
+    let (_, new) =
+      rename(&store, &old, &Name::from("b"), &Name::from("beta")).unwrap();
+    let parsed = package(
+      &store,
+      "private strict def a: Type = Type
+strict private def beta: Type = a
+def c: Type = beta",
+    );
+    assert_eq!(new, parsed);
+    // The definitions of a type have no source of their own
+    let typed = package(&store, "type Bool { True, False }");
+    assert!(
+      rename(&store, &typed, &Name::from("Bool.True"), &Name::from("Bool.Yes"))
+        .is_err()
+    );
+  }
+}
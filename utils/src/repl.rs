@@ -21,13 +21,20 @@ use yatima_core::{
   check::{
     check_def,
     infer_term,
+    Conversion,
   },
   dag::DAG,
   defs::Defs,
+  memory::{
+    self,
+    Limits,
+    OutOfMemory,
+  },
   parse::{
     span::Span,
     term::input_cid,
   },
+  term::Term,
 };
 
 use command::{
@@ -46,6 +53,8 @@ pub struct ReplEnv {
 pub enum LineResult {
   Success,
   Quit,
+  /// The line started an evaluation that finishes later
+  Pending,
 }
 
 impl Default for ReplEnv {
@@ -54,6 +63,66 @@ impl Default for ReplEnv {
   }
 }
 
+/// The memory limits of evaluating in the REPL, so that a runaway term stops
+/// with an error instead of taking down the process or the browser tab
+pub const EVAL_LIMITS: Limits =
+  Limits { nodes: Some(1 << 22), lit_bytes: Some(1 << 26) };
+
+/// The gas an evaluation uses in each slice before an interface gets to do
+/// other work
+pub const EVAL_SLICE: u64 = 1 << 16;
+
+/// An evaluation of a term in the REPL within `EVAL_LIMITS`, run in slices of
+/// gas so that an interface can keep responding, or cancel it, in between.
+/// Dropping it ends the limits and frees its term.
+pub struct Evaluation {
+  defs: Defs,
+  dag: DAG,
+  typ: Option<Term>,
+}
+
+impl Evaluation {
+  /// Start evaluating a term, with the type it was inferred to have, if any
+  pub fn new(defs: Defs, dag: DAG, typ: Option<Term>) -> Self {
+    memory::limit(EVAL_LIMITS);
+    Evaluation { defs, dag, typ }
+  }
+
+  /// Evaluate for a slice of `gas`. Returns the lines to print once the
+  /// evaluation is over, or the error it stopped on.
+  pub fn step(
+    &mut self,
+    gas: u64,
+  ) -> Option<Result<Vec<String>, OutOfMemory>> {
+    if !self.dag.norm_slice(&self.defs, gas) {
+      return None;
+    }
+    Some(memory::report().map(|()| {
+      let mut lines = vec![format!("{}", self.dag)];
+      if let Some(typ) = &self.typ {
+        lines.push(format!(": {}", typ));
+      }
+      lines
+    }))
+  }
+
+  /// Evaluate until the evaluation is over
+  pub fn run(&mut self) -> Result<Vec<String>, OutOfMemory> {
+    loop {
+      if let Some(res) = self.step(EVAL_SLICE) {
+        return res;
+      }
+    }
+  }
+}
+
+impl Drop for Evaluation {
+  fn drop(&mut self) {
+    memory::unlimit();
+    self.dag.free();
+  }
+}
+
 /// Read evaluate print loop - REPL
 /// A common interface for both the CLI REPL and the web REPL.
 /// The design is currently based on rustyline.
@@ -80,6 +149,23 @@ pub trait Repl {
   /// Get store for this Repl
   fn get_store(&self) -> Rc<dyn Store>;
 
+  /// Run an evaluation and print its result. Interfaces that can't block
+  /// while it runs drive it slice by slice instead, and return `Pending`.
+  fn eval(&mut self, mut evaluation: Evaluation) -> Result<LineResult, ()> {
+    match evaluation.run() {
+      Ok(lines) => {
+        for line in lines {
+          self.println(line);
+        }
+        Ok(LineResult::Success)
+      }
+      Err(e) => {
+        self.println(format!("Error: {}", e));
+        Err(())
+      }
+    }
+  }
+
   /// Run a single line of input from the user
   /// This will mutably update the shell_state
   fn handle_line(
@@ -110,7 +196,9 @@ pub trait Repl {
               }
               .map_err(|e| log!("{}", e))?;
 
-              if let Ok((_package, ds)) = file::check_all_in_ipld(ipld, store) {
+              if let Ok((_package, ds)) =
+                file::check_all_in_ipld(ipld, store, Conversion::Nbe, false)
+              {
                 env.defs.flat_merge_mut(ds);
                 Ok(LineResult::Success)
               }
@@ -154,30 +242,26 @@ pub trait Repl {
               }
             },
             Command::Eval(term) => {
-              let mut dag = DAG::from_term(&term);
-              if env.type_system {
-                let res = infer_term(&env.defs, *term, false);
-                match res {
-                  Ok(typ) => {
-                    dag.norm(&env.defs, false);
-                    self.println(format!("{}", dag));
-                    self.println(format!(": {}", typ));
-                    Ok(LineResult::Success)
-                  }
+              let dag = DAG::from_term(&term);
+              let typ = if env.type_system {
+                match infer_term(&env.defs, *term, false, Conversion::Nbe) {
+                  Ok(typ) => Some(typ),
                   Err(e) => {
                     self.println(format!("Type Error: {}", e));
-                    Err(())
+                    return Err(());
                   }
                 }
               }
               else {
-                dag.norm(&env.defs, false);
-                self.println(format!("{}", dag));
-                Ok(LineResult::Success)
-              }
+                None
+              };
+              let evaluation = Evaluation::new(env.defs.clone(), dag, typ);
+              drop(env);
+              self.eval(evaluation)
             }
             Command::Type(term) => {
-              let res = infer_term(&env.defs, *term, false);
+              let res =
+                infer_term(&env.defs, *term, false, Conversion::Nbe);
               match res {
                 Ok(term) => self.println(format!("{}", term)),
                 Err(e) => self.println(format!("Error: {}", e)),
@@ -189,7 +273,8 @@ pub trait Repl {
               let mut tmp_defs = env.defs.clone();
               tmp_defs.insert(n.clone(), def);
               let re = Rc::new(tmp_defs);
-              let res = check_def(re.clone(), &n, false);
+              let res =
+                check_def(re.clone(), &n, false, Conversion::Nbe, false);
               match res {
                 Ok(res) => {
                   env.defs.flat_merge_mut(re);
@@ -253,6 +338,7 @@ pub fn run_repl(rl: &mut dyn Repl) {
     match rl.handle_line(readline) {
       Ok(LineResult::Success) => continue,
       Ok(LineResult::Quit) => break,
+      Ok(LineResult::Pending) => continue,
       Err(()) => continue,
     }
   }
@@ -7,14 +7,24 @@ use yatima_core::{
     Def,
     Defs,
   },
+  eval::cache::{
+    CacheKey,
+    NormCache,
+  },
+  meta::Meta,
   package::{
     Entry,
     Index,
     Package,
   },
+  term::Term,
 };
 use sp_cid::Cid;
-use sp_ipld::Ipld;
+use sp_ipld::{
+  dag_cbor::DagCborCodec,
+  Codec,
+  Ipld,
+};
 use crate::graph::PackageGraph;
 
 /// This trait describes the interactions with
@@ -31,6 +41,85 @@ pub trait Store: std::fmt::Debug {
 
   /// Get an IPLD expression from the store
   fn get(&self, link: Cid) -> Option<Ipld>;
+
+  /// Get an IPLD expression from the store, telling one that is missing,
+  /// `Ok(None)`, from one that is there but can't be decoded, an error. Stores
+  /// that can't hold undecodable blocks only ever miss.
+  fn try_get(&self, link: Cid) -> Result<Option<Ipld>, String> {
+    Ok(self.get(link))
+  }
+
+  /// Read the bytes of a block as the store keeps them, which may not decode
+  fn read(&self, link: Cid) -> Option<Vec<u8>> {
+    let ipld = self.get(link)?;
+    Some(DagCborCodec.encode(&ipld).ok()?.into_inner())
+  }
+
+  /// Get the value a key was memoized to. Unlike `get`, the key is not the
+  /// address of the value, so stores that don't keep a memo table can always
+  /// miss.
+  fn get_memo(&self, _key: Cid) -> Option<Cid> { None }
+
+  /// Memoize a key to a value that was put into the store
+  fn put_memo(&self, _key: Cid, _value: Cid) {}
+
+  /// List the memoized keys and their values, for stores that can
+  fn list_memos(&self) -> Vec<(Cid, Cid)> { vec![] }
+
+  /// Forget the value a key was memoized to, returning whether it had one
+  fn delete_memo(&self, _key: Cid) -> bool { false }
+
+  /// Get the CID of the current package registry, if there is one
+  fn get_registry(&self) -> Option<Cid> { None }
+
+  /// Make a registry that was put into the store the current one
+  fn put_registry(&self, _registry: Cid) {}
+
+  /// List the CIDs of everything put into the store, for stores that can
+  fn list(&self) -> Vec<Cid> { vec![] }
+
+  /// Delete an IPLD expression from the store, returning whether it was there
+  fn delete(&self, _link: Cid) -> bool { false }
+
+  /// Move a corrupt block out of the store, returning whether it was there
+  fn quarantine(&self, _link: Cid) -> bool { false }
+
+  /// Get the pinned CIDs, which garbage collection keeps along with
+  /// everything they link to
+  fn get_pins(&self) -> Vec<Cid> { vec![] }
+
+  /// Replace the pinned CIDs
+  fn put_pins(&self, _pins: &[Cid]) {}
+}
+
+/// A normal form cache kept in a store. The anonymous term of a cached result
+/// is put into the store like the terms of definitions, next to an IPLD pair
+/// of its link and metadata, which is memoized under the CID of the cache key.
+#[derive(Debug, Clone)]
+pub struct StoreCache(pub Rc<dyn Store>);
+
+impl NormCache for StoreCache {
+  fn get(&self, key: &CacheKey) -> Option<Term> {
+    let link = self.0.get_memo(key.cid())?;
+    match self.0.get(link)? {
+      Ipld::List(xs) => match xs.as_slice() {
+        [Ipld::Link(anon), meta] => {
+          let anon = anon::Anon::from_ipld(&self.0.get(*anon)?).ok()?;
+          let meta = Meta::from_ipld(meta).ok()?;
+          Term::unembed(&anon, &meta).ok()
+        }
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  fn put(&self, key: &CacheKey, term: &Term) {
+    let (anon, meta) = term.embed();
+    let anon = self.0.put(anon.to_ipld());
+    let value = self.0.put(Ipld::List(vec![Ipld::Link(anon), meta.to_ipld()]));
+    self.0.put_memo(key.cid(), value);
+  }
 }
 
 /// Load all the package defs from the store
@@ -45,8 +134,16 @@ pub fn load_package_defs(
     if let Some(package_ipld) = store.get(import.cid.clone()) {
       let imported_package = Package::from_ipld(&package_ipld)
         .map_err(|e| format!("{:?}", e))?;
-      let imported_defs = load_package_defs(store.clone(), Rc::new(imported_package))?;
-      defs = defs.merge(imported_defs, &import);
+      let exports = imported_package.exported();
+      let imported_defs =
+        load_package_defs(store.clone(), Rc::new(imported_package))?
+          .export(&exports);
+      defs = defs.merge(imported_defs, &import).map_err(|name| {
+        format!(
+          "Cannot import {} from {}, which doesn't export it",
+          name, import.name
+        )
+      })?;
     }
     else {
       return Err(format!("Failed to load {} at {}", import.name, import.cid));
@@ -121,3 +218,41 @@ pub fn show(
     Err(format!("cannot find {}", cid))
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::diff::test::{
+    package,
+    MemStore,
+  };
+  use yatima_core::{
+    name::Name,
+    package::Import,
+  };
+
+  #[test]
+  fn imports_only_exported_names() {
+    let store = Rc::new(MemStore::default());
+    let mut lib = package(&store, "def a: Type = Type def b: Type = a");
+    lib.exports = Some(vec![Name::from("a")]);
+    let lib_cid = store.put(lib.to_ipld());
+    let dependent = |with: &[&str]| {
+      let import = Import {
+        cid: lib_cid,
+        name: Name::from("lib"),
+        alias: Name::from(""),
+        with: with.iter().map(|n| Name::from(*n)).collect(),
+        renaming: vec![],
+      };
+      let dependent = package(&store, "def d: Type = Type");
+      Rc::new(Package { imports: vec![import], ..dependent })
+    };
+    let defs = load_package_defs(store.clone(), dependent(&["a"])).unwrap();
+    assert!(defs.get(&Name::from("a")).is_some());
+    assert!(defs.get(&Name::from("b")).is_none());
+    // A private name, and one the package doesn't have
+    assert!(load_package_defs(store.clone(), dependent(&["a", "b"])).is_err());
+    assert!(load_package_defs(store.clone(), dependent(&["c"])).is_err());
+  }
+}
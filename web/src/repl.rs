@@ -1,4 +1,5 @@
 use std::{
+  cell::Cell,
   path::PathBuf,
   rc::Rc,
   sync::{
@@ -16,12 +17,18 @@ use yatima_utils::{
   },
   repl::{
     error::ReplError,
+    Evaluation,
+    LineResult,
     Repl,
     ReplEnv,
+    EVAL_SLICE,
   },
   store::Store,
 };
-// use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_futures::{
+  spawn_local,
+  JsFuture,
+};
 
 use crate::{
   store::WebStore,
@@ -73,7 +80,20 @@ const BACKSPACE: &str = "\x1b[H";
 const RETURN: &str = "\x1b[M";
 const LINEFEED: &str = "\x1b[J";
 const DELETE: &str = "\x1b[3";
+const CTRL_C: &str = "\x03";
+
 
+// Wait for the browser to handle the events that came in meanwhile, such as key
+// presses
+async fn next_tick() {
+  let promise = js_sys::Promise::new(&mut |resolve, _| {
+    web_sys::window()
+      .expect("should have a window in this context")
+      .set_timeout_with_callback(&resolve)
+      .unwrap();
+  });
+  let _ = JsFuture::from(promise).await;
+}
 
 fn set_column(term: &Terminal, col: u32) {
   term.write(&format!("\x1b[{}G", col));
@@ -90,6 +110,12 @@ struct WebRepl {
   shell_state: Arc<Mutex<ShellState>>,
   store: Rc<WebStore>,
   history: VecDeque<String>,
+  // Whether an evaluation is running, which is only cleared once its task has
+  // exited, so that no new input starts another one alongside it
+  evaluating: Rc<Cell<bool>>,
+  // The cancel token of the running evaluation. Every evaluation gets its
+  // own, so cancelling one can't affect the next.
+  cancelled: Rc<Cell<bool>>,
 }
 
 #[derive(Debug, Clone)]
@@ -118,6 +144,44 @@ impl Repl for WebRepl {
 
   fn get_store(&self) -> Rc<dyn Store> { self.store.clone() }
 
+  // Evaluate a slice at a time, letting the browser handle events in between,
+  // so that the page doesn't freeze and Ctrl-C can cancel the evaluation
+  fn eval(&mut self, mut evaluation: Evaluation) -> Result<LineResult, ()> {
+    let term = self.get_terminal();
+    let evaluating = self.evaluating.clone();
+    let cancelled = Rc::new(Cell::new(false));
+    self.cancelled = cancelled.clone();
+    evaluating.set(true);
+    spawn_local(async move {
+      let res = loop {
+        if cancelled.get() {
+          break None;
+        }
+        match evaluation.step(EVAL_SLICE) {
+          Some(res) => break Some(res),
+          None => next_tick().await,
+        }
+      };
+      drop(evaluation);
+      match res {
+        Some(Ok(lines)) => {
+          for line in lines {
+            term.writeln(&line.replace("\n", "\r"));
+          }
+          term.writeln("Ok");
+        }
+        Some(Err(e)) => {
+          term.writeln(&format!("Error: {}", e));
+          term.writeln("Error");
+        }
+        None => term.writeln("Interrupted"),
+      }
+      evaluating.set(false);
+      prompt(&term);
+    });
+    Ok(LineResult::Pending)
+  }
+
   fn println(&self, s: String) { 
     // The term needs \r to move the cursor back to the start of the line
     let m = s.replace("\n", "\r");
@@ -211,13 +275,30 @@ impl WebRepl {
     terminal.focus();
     let store = Rc::new(WebStore::new());
     let env = Arc::new(Mutex::new(ReplEnv::default()));
-    WebRepl { terminal, env, shell_state, store, history: VecDeque::new() }
+    WebRepl {
+      terminal,
+      env,
+      shell_state,
+      store,
+      history: VecDeque::new(),
+      evaluating: Rc::new(Cell::new(false)),
+      cancelled: Rc::new(Cell::new(false)),
+    }
   }
 
   pub fn get_terminal(&self) -> Terminal {
     self.terminal.clone().dyn_into().unwrap()
   }
 
+  // Cancel the running evaluation, which stops before its next slice. Input
+  // stays blocked until it has.
+  pub fn cancel(&mut self) {
+    if self.evaluating.get() && !self.cancelled.get() {
+      self.get_terminal().writeln("^C");
+      self.cancelled.set(true);
+    }
+  }
+
   pub fn handle_event(&mut self, e: OnKeyEvent) {
     let shell_state = self.shell_state.lock().unwrap();
     let mut cursor_col = shell_state.cursor_col.clone();
@@ -228,11 +309,20 @@ impl WebRepl {
     drop(shell_state);
     let term: Terminal = self.get_terminal();
     let event = e.dom_event();
+    // Only Ctrl-C is handled while evaluating
+    if self.evaluating.get() {
+      if event.key_code() == KEY_C && event.ctrl_key() {
+        self.cancel();
+      }
+      return;
+    }
     match event.key_code() {
       KEY_ENTER => {
+        let mut pending = false;
         if !line.is_empty() {
           term.writeln("");
           match self.handle_line(Ok(line.clone())) {
+            Ok(LineResult::Pending) => pending = true,
             Ok(_) => term.writeln("Ok"),
             Err(()) => term.writeln("Error"),
           }
@@ -240,7 +330,9 @@ impl WebRepl {
           cursor_col = 0;
           history_index = 0;
         }
-        prompt(&term);
+        if !pending {
+          prompt(&term);
+        }
       }
       KEY_BACKSPACE => {
         if cursor_col > 0 {
@@ -327,6 +419,14 @@ impl WebRepl {
     log!("line: {:X?}", &ss.line);
     log!("data: {:X?}", &data);
 
+    // Only Ctrl-C is handled while evaluating
+    if self.evaluating.get() {
+      if data == CTRL_C {
+        self.cancel();
+      }
+      return;
+    }
+
     match data.as_str() {
       CURSOR_RIGHT => {
         if ss.cursor_col < ss.line.len() {
@@ -373,9 +473,11 @@ impl WebRepl {
         }
       } 
       RETURN | LINEFEED | "\n" | "\r" => {
+        let mut pending = false;
         if !ss.line.is_empty() {
           self.println("".to_owned());
           match self.handle_line(Ok(ss.line.clone())) {
+            Ok(LineResult::Pending) => pending = true,
             Ok(_) => term.writeln("Ok"),
             Err(()) => term.writeln("Error"),
           }
@@ -383,7 +485,14 @@ impl WebRepl {
           ss.cursor_col = 0;
           ss.history_index = 0;
         }
+        if !pending {
+          prompt(&term);
+        }
+      }
+      CTRL_C => {
         prompt(&term);
+        ss.line.clear();
+        ss.cursor_col = 0;
       }
       DELETE => {
         if ss.cursor_col > 0 {
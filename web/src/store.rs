@@ -20,6 +20,7 @@ use sp_ipld::{
   Ipld,
 };
 use bytecursor::ByteCursor;
+use std::convert::TryFrom;
 
 #[derive(Debug, Clone)]
 pub struct WebStore {
@@ -93,4 +94,18 @@ impl Store for WebStore {
 
     link
   }
+
+  fn get_memo(&self, key: Cid) -> Option<Cid> {
+    match self.storage.get(&format!("memo/{}", key)) {
+      Ok(Some(s)) => Cid::try_from(s.as_str()).ok(),
+      _ => None,
+    }
+  }
+
+  fn put_memo(&self, key: Cid, value: Cid) {
+    match self.storage.set(&format!("memo/{}", key), &value.to_string()) {
+      Ok(()) => (),
+      Err(_) => log!("Failed to put memo to local_storage"),
+    }
+  }
 }